@@ -18,6 +18,29 @@ fn extract_inner_token(s: &str) -> Option<String> {
 	None
 }
 
+/// Extract the function name from a `kernel_test! { fn name() ... }` body
+fn extract_fn_name(s: &str) -> Option<String> {
+	let pos = s.find("fn ")?;
+	let name: String = s[pos + 3..]
+		.trim_start()
+		.chars()
+		.take_while(|c| c.is_alphanumeric() || *c == '_')
+		.collect();
+	(!name.is_empty()).then_some(name)
+}
+
+/// Whether the match at `pos` is on a comment line (the macro docs mention
+/// the macros too)
+fn in_comment(file: &str, pos: usize) -> bool {
+	let line_start = file[..pos].rfind('\n').map_or(0, |i| i + 1);
+	file[line_start..pos].trim_start().starts_with("//")
+}
+
+/// Line number of `pos`, which is what `line!()` gives inside the macros
+fn line_of(file: &str, pos: usize) -> usize {
+	file[..pos].chars().filter(|c| *c == '\n').count() + 1
+}
+
 fn search_files_recursively(path: &Path) -> Vec<String> {
 	let mut symbols: Vec<String> = Vec::new();
 
@@ -31,13 +54,15 @@ fn search_files_recursively(path: &Path) -> Vec<String> {
 
 					let needle = "create_test!(";
 					for (pos, _) in file.match_indices(needle) {
+						if in_comment(&file, pos) {
+							continue;
+						}
 						let start = pos + needle.len();
 						let rest = &file[start..];
 
 						if let Some(inner) = extract_inner_token(rest) {
 							// compute line number where create_test appears
-							let prefix = &file[..pos];
-							let line_number = prefix.chars().filter(|c| *c == '\n').count() + 1;
+							let line_number = line_of(&file, pos);
 
 							let token = inner.trim();
 
@@ -54,6 +79,18 @@ fn search_files_recursively(path: &Path) -> Vec<String> {
 							symbols.push(sym);
 						}
 					}
+
+					// `kernel_test!` registers through the identifier form of
+					// `create_test`, with the line of the outer invocation
+					let needle = "kernel_test!";
+					for (pos, _) in file.match_indices(needle) {
+						if in_comment(&file, pos) {
+							continue;
+						}
+						if let Some(name) = extract_fn_name(&file[pos + needle.len()..]) {
+							symbols.push(format!("__kernel_test_{}_{}", name, line_of(&file, pos)));
+						}
+					}
 				}
 		if path.is_dir() {
 			symbols.extend(search_files_recursively(path.as_path()));
@@ -11,10 +11,13 @@ static void _exit(int code) {
     __builtin_unreachable(); // like unreachable!()
 }
 
-extern int main(void);
+extern int main(int argc, char** argv, char** envp);
 
-__attribute__((noreturn)) // same as -> !
-void _start(void) {
-    int ret = main();
+// the kernel enters here with argc, argv and envp in rdi, rsi and rdx (they
+// are also on the stack, see setup_user_stack). rsp is 16-byte aligned rather
+// than just below it like after a call, so gcc has to realign the stack.
+__attribute__((noreturn, force_align_arg_pointer)) // same as -> !
+void _start(int argc, char** argv, char** envp) {
+    int ret = main(argc, argv, envp);
     _exit(ret);
 }
\ No newline at end of file
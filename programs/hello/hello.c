@@ -1,10 +1,14 @@
 #include "../include/nullex.h"
 
-int main(void) {
+int main(int argc, char** argv) {
     for (int i = 0; i < 10; i++) {
         say("Hello!");
     }
 
+    for (int i = 0; i < argc; i++) {
+        say("argv[%d] = %s", (long)i, argv[i]);
+    }
+
     size_t bytes_read;
     int fd;
 
@@ -33,6 +33,23 @@ typedef signed long long int64_t;
 #define SYS_STOP   9
 #define SYS_NAP    10
 #define SYS_SIZEF  11
+#define SYS_GETPID 12
+#define SYS_GETTID 13
+#define SYS_GETCWD 14
+#define SYS_CHDIR  15
+
+// console descriptors: reads from STDIN come from the keyboard a line at a
+// time, writes to STDOUT and STDERR go to the screen. openf starts at 3.
+#define STDIN  0
+#define STDOUT 1
+#define STDERR 2
+
+// returned when a file is accessed in a way its permissions don't allow
+#define FS_FILE_INVALID_PERMISSION -1
+
+// openf_mode access bits, openf asks for whatever the file permits
+#define OPENF_READ  1
+#define OPENF_WRITE 2
 
 /*
  * x86_64 syscall wrapper using the Linux-style syscall register convention:
@@ -153,12 +170,16 @@ static inline int32_t halt(int64_t exit_code) {
     return ksyscall(SYS_HALT, (uint64_t)exit_code, 0, 0, 0, 0, 0);
 }
 
+// forks the calling process; user programs can't be forked yet, so from a
+// program this always fails with -1
 static inline int32_t split() {
     return ksyscall(SYS_SPLIT, 0, 0, 0, 0, 0, 0);
 }
 
-static inline int32_t waiton() {
-    return ksyscall(SYS_WAITON, 0, 0, 0, 0, 0, 0);
+// checks on a child without blocking: returns its pid once it has ended
+// (storing the exit code in status), 0 while it runs and -1 if there is none
+static inline int32_t waiton(uint64_t pid, int32_t* status) {
+    return ksyscall(SYS_WAITON, pid, (uint64_t)status, 0, 0, 0, 0);
 }
 
 static inline int32_t openf(const char* path) {
@@ -166,6 +187,11 @@ static inline int32_t openf(const char* path) {
     return ksyscall(SYS_OPENF, (uint64_t)path, (uint64_t)len, 0, 0, 0, 0);
 }
 
+static inline int32_t openf_mode(const char* path, uint64_t mode) {
+    size_t len = strlen(path);
+    return ksyscall(SYS_OPENF, (uint64_t)path, (uint64_t)len, mode, 0, 0, 0);
+}
+
 static inline int32_t closef(uint64_t fd) {
     return ksyscall(SYS_CLOSEF, fd, 0, 0, 0, 0, 0);
 }
@@ -196,10 +222,16 @@ static inline int32_t writef_str(uint64_t fd, const char* to_write) {
     const uint8_t*: writef_buf          \
 )(fd, arg)
 
+// replaces this program with the one at path, only returns (-1) on failure
 static inline int32_t run(const char* path, unsigned len) {
     return ksyscall(SYS_RUN, (uint64_t)path, (uint64_t)len, 0, 0, 0, 0);
 }
 
+// like run, passing argv (NULL-terminated) instead of just the path
+static inline int32_t run_args(const char* path, unsigned len, char* const* argv) {
+    return ksyscall(SYS_RUN, (uint64_t)path, (uint64_t)len, (uint64_t)argv, 0, 0, 0);
+}
+
 static inline int32_t stop(uint64_t pid) {
     return ksyscall(SYS_STOP, pid, 0, 0, 0, 0, 0);
 }
@@ -210,4 +242,25 @@ static inline int32_t nap(/* todo */) {
 
 static inline int32_t sizef(uint64_t fd) {
     return ksyscall(SYS_SIZEF, fd, 0, 0, 0, 0, 0);
-}   
\ No newline at end of file
+}
+
+static inline int32_t getpid() {
+    return ksyscall(SYS_GETPID, 0, 0, 0, 0, 0, 0);
+}
+
+// processes are single threaded, so this is the same as getpid()
+static inline int32_t gettid() {
+    return ksyscall(SYS_GETTID, 0, 0, 0, 0, 0, 0);
+}
+
+// copies the working directory into buf, NUL-terminated. returns its length,
+// or -1 if buf is too small
+static inline int32_t getcwd(char* buf, size_t len) {
+    return ksyscall(SYS_GETCWD, (uint64_t)buf, (uint64_t)len, 0, 0, 0, 0);
+}
+
+// changes the working directory, relative paths resolve from the current one
+static inline int32_t chdir(const char* path) {
+    size_t len = strlen(path);
+    return ksyscall(SYS_CHDIR, (uint64_t)path, (uint64_t)len, 0, 0, 0, 0);
+}
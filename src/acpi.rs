@@ -10,7 +10,7 @@ use core::ptr::{addr_of, read_unaligned};
 use x86_64::VirtAddr;
 
 use crate::{
-	PHYS_MEM_OFFSET, apic::{PIC1_DATA, PIC2_DATA}, common::ports::outb, error::NullexError, gsi::{GSI_TABLE, program_gsi_vector}, interrupts::allocate_and_register_vector, io::pci::{pci_find_index_from_gsi, try_bind_device}, lazy_static, serial_println, utils::mutex::SpinMutex
+	PHYS_MEM_OFFSET, apic::{PIC1_DATA, PIC2_DATA}, common::ports::{inw, outb}, error::NullexError, gsi::{GSI_TABLE, program_gsi_vector}, interrupts::allocate_and_register_vector, io::pci::{pci_find_index_from_gsi, try_bind_device}, lazy_static, serial_println, utils::mutex::SpinMutex
 };
 
 // https://wiki.osdev.org/RSDT
@@ -37,6 +37,44 @@ const SRAT_TABLE_SIGNATURE: &'static str = "SRAT";
 const SSDT_TABLE_SIGNATURE: &'static str = "SSDT";
 const XSDT_TABLE_SIGNATURE: &'static str = "XSDT";
 
+/// Offset of the DSDT's 32-bit physical address in the FADT.
+const FADT_DSDT_OFFSET: usize = 40;
+/// Offset of the SCI interrupt number in the FADT.
+const FADT_SCI_INTERRUPT_OFFSET: usize = 46;
+/// Offset of the SMI command port, written to switch ACPI mode on or off.
+const FADT_SMI_COMMAND_OFFSET: usize = 48;
+/// Offset of the values written to the SMI command port to enable and
+/// disable ACPI mode.
+const FADT_ACPI_ENABLE_OFFSET: usize = 52;
+const FADT_ACPI_DISABLE_OFFSET: usize = 53;
+/// Offsets of the PM1a and PM1b event block I/O ports in the FADT.
+const FADT_PM1A_EVENT_OFFSET: usize = 56;
+const FADT_PM1B_EVENT_OFFSET: usize = 60;
+/// Offsets of the PM1a and PM1b control block I/O ports in the FADT.
+const FADT_PM1A_CONTROL_OFFSET: usize = 64;
+const FADT_PM1B_CONTROL_OFFSET: usize = 68;
+/// Offset of the `century` field (the CMOS century register) in the FADT.
+const FADT_CENTURY_OFFSET: usize = 108;
+/// Offset of the FADT feature flags.
+const FADT_FLAGS_OFFSET: usize = 112;
+/// FADT flag set when the reset register is supported.
+const FADT_RESET_REG_SUP: u32 = 1 << 10;
+/// Offset of the reset register (a Generic Address Structure) in the FADT.
+const FADT_RESET_REG_OFFSET: usize = 116;
+/// Offset of the value to write to the reset register.
+const FADT_RESET_VALUE_OFFSET: usize = 128;
+/// SCI_EN bit of the PM1 control registers, set while in ACPI mode.
+const PM1_SCI_ENABLE: u16 = 1 << 0;
+/// Generic Address Structure address space for system I/O ports.
+const GAS_SYSTEM_IO: u8 = 1;
+
+/// AML opcodes used by the `\_S5` package.
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+const AML_PACKAGE_OP: u8 = 0x12;
+
 lazy_static! {
 	/// Static reference to the Root System Descriptor Table (RSDT)
 	pub static ref RSDT: SpinMutex<VirtAddr> = SpinMutex::new(VirtAddr::zero());
@@ -180,6 +218,24 @@ struct InterruptSourceOverride {
 	flags: u16
 }
 
+#[repr(C, packed)]
+#[derive(Debug)]
+struct ProcessorLocalApic {
+	header: MadtTableEntry,
+	processor_id: u8,
+	apic_id: u8,
+	flags: u32
+}
+
+/// MADT entry type of a processor's local APIC.
+const MADT_PROCESSOR_LOCAL_APIC: u8 = 0;
+/// MADT entry type of an interrupt source override.
+const MADT_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+/// Processor local APIC flags: the processor is usable, or can be brought
+/// online.
+const MADT_PROCESSOR_ENABLED: u32 = 1 << 0;
+const MADT_PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
 /// Finds and returns the specified ACPI table.
 pub unsafe fn find_acpi_table(
 	root_sdt: VirtAddr,
@@ -203,6 +259,249 @@ pub unsafe fn find_acpi_table(
 	}
 }
 
+/// The FADT fields the kernel uses, mostly power management ports. Ports
+/// the firmware leaves out are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+	/// Table revision.
+	pub revision: u8,
+	/// Physical address of the DSDT.
+	pub dsdt: u32,
+	/// Interrupt the SCI is wired to, in 8259 terms.
+	pub sci_interrupt: u16,
+	/// Port written to switch ACPI mode on or off, `None` if the machine is
+	/// always in ACPI mode.
+	pub smi_command: Option<u16>,
+	/// Value to write to `smi_command` to enable ACPI mode.
+	pub acpi_enable: u8,
+	/// Value to write to `smi_command` to disable ACPI mode.
+	pub acpi_disable: u8,
+	/// PM1a event block port.
+	pub pm1a_event: Option<u16>,
+	/// PM1b event block port.
+	pub pm1b_event: Option<u16>,
+	/// PM1a control block port.
+	pub pm1a_control: Option<u16>,
+	/// PM1b control block port.
+	pub pm1b_control: Option<u16>,
+	/// CMOS register holding the century.
+	pub century: Option<u8>,
+	/// Feature flags.
+	pub flags: u32,
+	/// I/O port and value of the reset register.
+	pub reset_register: Option<(u16, u8)>
+}
+
+impl Fadt {
+	/// Parses the FADT in `table`, which has to hold the whole table. The
+	/// checksum is checked before any field is read.
+	pub fn parse(table: &[u8]) -> Result<Fadt, NullexError> {
+		if table.len() < size_of::<AcpiSdtHeader>()
+			|| table[..4] != *FADT_TABLE_SIGNATURE.as_bytes()
+		{
+			return Err(NullexError::InvalidAcpiSignature("Incorrect FADT Signature."));
+		}
+		let length = u32::from_le_bytes(table[4..8].try_into().unwrap()) as usize;
+		let table = table.get(..length).ok_or(NullexError::BufferTooSmall)?;
+		if length <= FADT_CENTURY_OFFSET {
+			return Err(NullexError::BufferTooSmall);
+		}
+		if table.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+			return Err(NullexError::ChecksumMismatch);
+		}
+
+		let byte = |offset: usize| table[offset];
+		let word = |offset: usize| u16::from_le_bytes([table[offset], table[offset + 1]]);
+		let dword = |offset: usize| {
+			u32::from_le_bytes(table[offset..offset + 4].try_into().unwrap())
+		};
+		let port = |offset: usize| u16::try_from(dword(offset)).ok().filter(|port| *port != 0);
+
+		let flags = dword(FADT_FLAGS_OFFSET);
+		// revision 1 tables end before the reset register
+		let reset_register = if length > FADT_RESET_VALUE_OFFSET
+			&& flags & FADT_RESET_REG_SUP != 0
+			&& byte(FADT_RESET_REG_OFFSET) == GAS_SYSTEM_IO
+		{
+			let address = u64::from(dword(FADT_RESET_REG_OFFSET + 4))
+				| (u64::from(dword(FADT_RESET_REG_OFFSET + 8)) << 32);
+			u16::try_from(address).ok().map(|port| (port, byte(FADT_RESET_VALUE_OFFSET)))
+		} else {
+			None
+		};
+
+		Ok(Fadt {
+			revision: byte(8),
+			dsdt: dword(FADT_DSDT_OFFSET),
+			sci_interrupt: word(FADT_SCI_INTERRUPT_OFFSET),
+			smi_command: port(FADT_SMI_COMMAND_OFFSET),
+			acpi_enable: byte(FADT_ACPI_ENABLE_OFFSET),
+			acpi_disable: byte(FADT_ACPI_DISABLE_OFFSET),
+			pm1a_event: port(FADT_PM1A_EVENT_OFFSET),
+			pm1b_event: port(FADT_PM1B_EVENT_OFFSET),
+			pm1a_control: port(FADT_PM1A_CONTROL_OFFSET),
+			pm1b_control: port(FADT_PM1B_CONTROL_OFFSET),
+			century: Some(byte(FADT_CENTURY_OFFSET)).filter(|register| *register != 0),
+			flags,
+			reset_register
+		})
+	}
+
+	/// Whether the chipset is in ACPI mode, i.e. SCI_EN is set in the PM1a
+	/// control register. Machines without an SMI command port always are.
+	pub fn acpi_mode_enabled(&self) -> bool {
+		match (self.smi_command, self.pm1a_control) {
+			(None, _) => true,
+			(Some(_), Some(port)) => unsafe { inw(port) & PM1_SCI_ENABLE != 0 },
+			(Some(_), None) => false
+		}
+	}
+
+	/// Reads `SLP_TYPa` and `SLP_TYPb` for S5 from the DSDT's `\_S5` object.
+	pub fn s5_sleep_types(&self) -> Option<(u8, u8)> {
+		if self.dsdt == 0 {
+			return None;
+		}
+
+		unsafe {
+			let dsdt = self.dsdt as usize as *const AcpiSdtHeader;
+			let table = core::slice::from_raw_parts(dsdt as *const u8, (*dsdt).length as usize);
+			parse_s5(table.get(size_of::<AcpiSdtHeader>()..)?)
+		}
+	}
+}
+
+/// Finds and parses the FADT through the RSDT at `rsdt`.
+fn find_fadt(rsdt: VirtAddr) -> Result<Fadt, NullexError> {
+	if rsdt.is_null() {
+		return Err(NullexError::DeviceNotFound);
+	}
+
+	unsafe {
+		let fadt = find_acpi_table(rsdt, AcpiTableType::Fadt).ok_or(NullexError::DeviceNotFound)?;
+		let table = core::slice::from_raw_parts(fadt as *const u8, (*fadt).length as usize);
+		Fadt::parse(table)
+	}
+}
+
+/// Returns the parsed FADT.
+pub fn fadt() -> Result<Fadt, NullexError> {
+	find_fadt(*RSDT.lock())
+}
+
+/// Returns the CMOS register holding the century, as reported by the FADT,
+/// or `None` if the firmware doesn't provide one.
+pub fn century_register() -> Option<u8> {
+	fadt().ok()?.century
+}
+
+/// Returns the I/O port and value of the FADT reset register, or `None` if
+/// the firmware doesn't provide one in I/O space.
+///
+/// Meant for fatal error paths, so it gives up instead of spinning if the
+/// RSDT is locked.
+pub fn reset_register() -> Option<(u16, u8)> {
+	let rsdt = *RSDT.try_lock()?;
+	find_fadt(rsdt).ok()?.reset_register
+}
+
+/// The PM1 control ports and the sleep types that put the machine in S5
+/// (soft off), from the FADT and the DSDT's `\_S5` object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoftOff {
+	/// PM1a control block port.
+	pub pm1a_control: u16,
+	/// PM1b control block port, if the chipset has one.
+	pub pm1b_control: Option<u16>,
+	/// `SLP_TYPa` for S5.
+	pub sleep_type_a: u8,
+	/// `SLP_TYPb` for S5.
+	pub sleep_type_b: u8
+}
+
+/// Reads the `SLP_TYPa` and `SLP_TYPb` values of the `Name(_S5, Package ..)`
+/// object in `aml`. Only the constant encodings firmware uses for them are
+/// understood.
+fn parse_s5(aml: &[u8]) -> Option<(u8, u8)> {
+	(0..aml.len().saturating_sub(4))
+		.filter(|&at| &aml[at..at + 4] == b"_S5_")
+		.find_map(|at| parse_s5_package(aml, at))
+}
+
+fn parse_s5_package(aml: &[u8], at: usize) -> Option<(u8, u8)> {
+	let before = &aml[..at];
+	if !before.ends_with(&[AML_NAME_OP]) && !before.ends_with(&[AML_NAME_OP, b'\\']) {
+		return None;
+	}
+
+	let package = aml.get(at + 4..)?;
+	if *package.first()? != AML_PACKAGE_OP {
+		return None;
+	}
+	// bits 6-7 of PkgLength's lead byte count the bytes that follow it
+	let pkg_length_size = 1 + (*package.get(1)? >> 6) as usize;
+	// skip the opcode, PkgLength and NumElements
+	let mut elements = package.get(1 + pkg_length_size + 1..)?.iter();
+	let mut next = || match *elements.next()? {
+		AML_ZERO_OP => Some(0),
+		AML_ONE_OP => Some(1),
+		AML_BYTE_PREFIX => elements.next().copied(),
+		_ => None
+	};
+	Some((next()?, next()?))
+}
+
+/// Returns the ports and sleep types for entering S5, or `None` if the FADT
+/// or the DSDT's `\_S5` object can't be read.
+pub fn soft_off() -> Option<SoftOff> {
+	let fadt = fadt().ok()?;
+	let (sleep_type_a, sleep_type_b) = fadt.s5_sleep_types()?;
+	Some(SoftOff {
+		pm1a_control: fadt.pm1a_control?,
+		pm1b_control: fadt.pm1b_control,
+		sleep_type_a,
+		sleep_type_b
+	})
+}
+
+/// Returns the local APIC IDs of all usable processors listed in the MADT,
+/// including the one running this.
+pub fn processor_apic_ids() -> Vec<u8> {
+	let mut ids = Vec::new();
+	let rsdt = *RSDT.lock();
+	if rsdt.is_null() {
+		return ids;
+	}
+
+	unsafe {
+		let Some(madt) = find_acpi_table(rsdt, AcpiTableType::Madt) else {
+			return ids;
+		};
+		let base = madt as *const u8;
+		let end = base.add((*madt).length as usize);
+		let mut entry_ptr = base.add(size_of::<MadtTable>());
+
+		while entry_ptr.add(size_of::<MadtTableEntry>()) <= end {
+			let entry = read_unaligned(entry_ptr as *const MadtTableEntry);
+			if entry.length == 0 {
+				break;
+			}
+
+			if entry.r#type == MADT_PROCESSOR_LOCAL_APIC
+				&& entry.length as usize >= size_of::<ProcessorLocalApic>()
+			{
+				let processor = read_unaligned(entry_ptr as *const ProcessorLocalApic);
+				if processor.flags & (MADT_PROCESSOR_ENABLED | MADT_PROCESSOR_ONLINE_CAPABLE) != 0 {
+					ids.push(processor.apic_id);
+				}
+			}
+			entry_ptr = entry_ptr.add(entry.length as usize);
+		}
+	}
+
+	ids
+}
+
 /// Finds and links all Interrupt Source Overrides (ISO) 
 pub unsafe fn link_isos() {
 	serial_println!("[ACPI] Starting ISO (Interrupt Source Override) linking...");
@@ -239,7 +538,7 @@ pub unsafe fn link_isos() {
 			let entry = read_unaligned(entry_hdr);
 
 			match entry.r#type {
-				2 => {
+				MADT_INTERRUPT_SOURCE_OVERRIDE => {
 					let iso_ptr = entry_ptr as *const InterruptSourceOverride;
 					let iso = read_unaligned(iso_ptr);
 					let gsi = iso.gsi as usize;
@@ -376,4 +675,72 @@ pub unsafe fn link_isos() {
 			programmed_count
 		);
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{acpi::*, utils::ktest::TestError};
+
+	pub fn test_parse_s5() -> Result<(), TestError> {
+		// Name (_S5, Package (0x04) { Zero, Zero, Zero, Zero }), as QEMU has it
+		let qemu = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x00, 0x00, 0x00, 0x00];
+		assert_eq!(parse_s5(&qemu), Some((0, 0)));
+
+		// Name (\_S5, Package (0x02) { 0x07, One }) after an unrelated _S5_
+		let aml = [
+			b'_', b'S', b'5', b'_', 0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x07, 0x02, 0x0a,
+			0x07, 0x01
+		];
+		assert_eq!(parse_s5(&aml), Some((7, 1)));
+
+		assert_eq!(parse_s5(b"_S4_"), None);
+		assert_eq!(parse_s5(&qemu[..8]), None);
+		Ok(())
+	}
+	crate::create_test!(test_parse_s5);
+
+	pub fn test_parse_fadt() -> Result<(), TestError> {
+		let mut table = [0u8; 132];
+		table[..4].copy_from_slice(b"FACP");
+		table[4..8].copy_from_slice(&132u32.to_le_bytes());
+		table[8] = 3;
+		table[46] = 9;
+		table[48] = 0xb2;
+		table[52] = 0xf1;
+		table[56..58].copy_from_slice(&0x600u16.to_le_bytes());
+		table[64..66].copy_from_slice(&0x604u16.to_le_bytes());
+		table[108] = 0x32;
+		table[112..116].copy_from_slice(&FADT_RESET_REG_SUP.to_le_bytes());
+		table[116] = GAS_SYSTEM_IO;
+		table[120..122].copy_from_slice(&0xcf9u16.to_le_bytes());
+		table[128] = 0x06;
+		let sum = table.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+		table[9] = sum.wrapping_neg();
+
+		let fadt = Fadt::parse(&table).map_err(|_| TestError::Error)?;
+		assert_eq!(fadt.revision, 3);
+		assert_eq!(fadt.sci_interrupt, 9);
+		assert_eq!((fadt.smi_command, fadt.acpi_enable), (Some(0xb2), 0xf1));
+		assert_eq!((fadt.pm1a_event, fadt.pm1b_event), (Some(0x600), None));
+		assert_eq!((fadt.pm1a_control, fadt.pm1b_control), (Some(0x604), None));
+		assert_eq!(fadt.century, Some(0x32));
+		assert_eq!(fadt.reset_register, Some((0xcf9, 0x06)));
+
+		table[46] = 10;
+		assert_eq!(Fadt::parse(&table), Err(NullexError::ChecksumMismatch));
+		assert_eq!(Fadt::parse(&table[..100]), Err(NullexError::BufferTooSmall));
+		table[0] = b'X';
+		assert!(matches!(Fadt::parse(&table), Err(NullexError::InvalidAcpiSignature(_))));
+		Ok(())
+	}
+	crate::create_test!(test_parse_fadt);
+
+	pub fn test_madt_lists_this_cpu() -> Result<(), TestError> {
+		let bsp = unsafe { (crate::apic::read_register(crate::apic::APIC_ID) >> 24) as u8 };
+		let ids = processor_apic_ids();
+		assert!(ids.contains(&bsp));
+		assert!(crate::smp::cpus_online() <= ids.len());
+		Ok(())
+	}
+	crate::create_test!(test_madt_lists_this_cpu);
+}
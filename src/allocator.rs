@@ -6,11 +6,14 @@
 
 use core::{
 	alloc::{self, GlobalAlloc},
+	fmt::{self, Write},
 	marker::PhantomData,
-	ptr::null_mut
+	ptr::null_mut,
+	sync::atomic::{AtomicUsize, Ordering}
 };
 
 use linked_list::LinkedListAllocator;
+use x86_64::instructions::interrupts;
 
 // allow missing documentation because otherwise
 // it will also be unused as there is only one type of 
@@ -76,6 +79,39 @@ lazy_static! {
 			size: PhantomData
 		};
 }
+/// Bytes currently handed out by the global allocator.
+static HEAP_USED: AtomicUsize = AtomicUsize::new(0);
+/// Number of live allocations made through the global allocator.
+static HEAP_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Usage of the kernel heap, as seen by the global allocator.
+pub struct HeapStats {
+	/// Total size of the heap in bytes.
+	pub size: usize,
+	/// Bytes currently allocated. This is the sum of the requested layouts,
+	/// so it doesn't include the strategy's own overhead or fragmentation.
+	pub used: usize,
+	/// Number of live allocations.
+	pub allocations: usize
+}
+
+impl HeapStats {
+	/// Bytes of the heap not currently allocated.
+	pub fn free(&self) -> usize {
+		self.size.saturating_sub(self.used)
+	}
+}
+
+/// Returns the current heap usage.
+pub fn heap_stats() -> HeapStats {
+	HeapStats {
+		size: HEAP_SIZE,
+		used: HEAP_USED.load(Ordering::Relaxed),
+		allocations: HEAP_ALLOCATIONS.load(Ordering::Relaxed)
+	}
+}
+
 /// A generic starting off kernel allocator. This is just to allocate the global allocator.
 #[allow(deprecated)]
 pub static LOCAL_HEAP_ALLOCATOR: Locked<LinkedListAllocator> =
@@ -83,32 +119,75 @@ pub static LOCAL_HEAP_ALLOCATOR: Locked<LinkedListAllocator> =
 
 struct GlobalAllocator;
 
+// the strategies only `try_lock` their state, so an interrupt handler that
+// allocates while task code is mid-allocation would find the heap locked
 unsafe impl GlobalAlloc for GlobalAllocator {
 	unsafe fn alloc(&self, layout: alloc::Layout) -> *mut u8 {
-		unsafe {
+		interrupts::without_interrupts(|| unsafe {
 			if let Some(ref strategy) = *ALLOCATOR_INFO.strategy.read() {
-				return strategy.alloc(layout)
+				let ptr = strategy.alloc(layout);
+				if !ptr.is_null() {
+					HEAP_USED.fetch_add(layout.size(), Ordering::Relaxed);
+					HEAP_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+				}
+				ptr
 			} else {
 				null_mut()
 			}
-		}
+		})
 	}
 
 	unsafe fn dealloc(&self, ptr: *mut u8, layout: alloc::Layout) {
-		unsafe {
+		interrupts::without_interrupts(|| unsafe {
 			if let Some(ref strategy) = *ALLOCATOR_INFO.strategy.read() {
 				strategy.dealloc(ptr, layout);
+				HEAP_USED.fetch_sub(layout.size(), Ordering::Relaxed);
+				HEAP_ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
 			}
-		}
+		})
 	}
 }
 
 #[global_allocator]
 static ALLOCATOR: GlobalAllocator = GlobalAllocator;
 
+/// Writes the out-of-memory report for `layout`. Formats straight into `out`,
+/// since the heap can't be used anymore.
+fn write_alloc_error(out: &mut dyn Write, layout: alloc::Layout) -> fmt::Result {
+	let stats = heap_stats();
+	writeln!(out, "OUT OF MEMORY: heap allocation failed")?;
+	writeln!(out, "  requested: {} bytes, align {}", layout.size(), layout.align())?;
+	writeln!(
+		out,
+		"  heap: {} of {} bytes used, {} free, {} live allocations",
+		stats.used,
+		stats.size,
+		stats.free(),
+		stats.allocations
+	)
+}
+
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::Layout) -> ! {
-	panic!("Allocation error: {:?}", layout)
+	x86_64::instructions::interrupts::disable();
+
+	// the failed allocation may have been made with the console locked, so
+	// only report there if it's free; serial is forced since nothing else
+	// runs from here on
+	if let Some(mut writer) = crate::vga_buffer::WRITER.try_lock() {
+		let _ = write_alloc_error(&mut *writer, layout);
+	}
+	if crate::serial::SERIAL1.try_lock().is_none() {
+		unsafe { crate::serial::SERIAL1.force_unlock() };
+	}
+	let _ = write_alloc_error(&mut *crate::serial::SERIAL1.lock(), layout);
+
+	#[cfg(feature = "test")]
+	crate::qemu_exit(crate::utils::ktest::QEMU_EXIT_FAILURE);
+	if crate::config::reboot_on_fatal_fault() {
+		crate::arch::x86_64::reset::reboot();
+	}
+	crate::hlt_loop()
 }
 
 /// Initialises the kernels heap memory.
@@ -211,7 +290,10 @@ fn align_up(addr: usize, align: usize) -> usize {
 
 #[cfg(feature = "test")]
 pub mod tests {
-	use crate::{allocator::align_up, utils::ktest::TestError};
+	use crate::{
+		allocator::{align_up, write_alloc_error},
+		utils::ktest::TestError
+	};
 
 	pub fn test_align_up_already_aligned() -> Result<(), TestError> {
 		let a = 0x1000usize;
@@ -228,4 +310,15 @@ pub mod tests {
 		Ok(())
 	}
 	crate::create_test!(test_align_up_non_aligned);
+
+	pub fn test_alloc_error_report() -> Result<(), TestError> {
+		let layout = core::alloc::Layout::from_size_align(4096, 64).map_err(|_| TestError::Error)?;
+		let mut report = alloc::string::String::new();
+		write_alloc_error(&mut report, layout).map_err(|_| TestError::Error)?;
+		assert!(report.starts_with("OUT OF MEMORY"));
+		assert!(report.contains("requested: 4096 bytes, align 64"));
+		assert!(report.contains("live allocations"));
+		Ok(())
+	}
+	crate::create_test!(test_alloc_error_report);
 }
@@ -4,9 +4,10 @@
 //! APIC timer and register definitions.
 //!
 
+use alloc::vec::Vec;
 use core::{
 	ptr::{read_volatile, write_volatile},
-	sync::atomic::AtomicU64
+	sync::atomic::{AtomicU64, Ordering}
 };
 
 use x86_64::instructions::interrupts;
@@ -21,6 +22,56 @@ pub static APIC_BASE: SpinMutex<usize> = SpinMutex::new(0);
 pub static APIC_TICK_COUNT: AtomicU64 = AtomicU64::new(0);
 /// The TPS (Ticks per second) at which the APIC runs at
 pub static APIC_TPS: AtomicU64 = AtomicU64::new(0);
+/// The rate the APIC timer interrupt fires at, in ticks per second
+pub static APIC_TIMER_HZ: AtomicU64 = AtomicU64::new(DEFAULT_TIMER_HZ);
+
+/// The rate the APIC timer interrupt is started at, in ticks per second.
+pub const DEFAULT_TIMER_HZ: u64 = 1024;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// A stretch of ticks counted at one rate: the tick the timer changed to
+/// `hz` on, and the time elapsed up to it.
+struct TimerEpoch {
+	ticks: u64,
+	nanos: u64,
+	hz: u64
+}
+
+impl TimerEpoch {
+	/// Converts `ticks`, which is on or after the start of the epoch.
+	fn to_hrt(&self, ticks: u64) -> u64 {
+		let elapsed = ticks.saturating_sub(self.ticks);
+		self.nanos + (elapsed as u128 * NANOS_PER_SECOND as u128 / self.hz as u128) as u64
+	}
+}
+
+/// The rate the timer runs at from boot until the first change.
+const BOOT_EPOCH: TimerEpoch = TimerEpoch {
+	ticks: 0,
+	nanos: 0,
+	hz: DEFAULT_TIMER_HZ
+};
+
+/// Every rate change so far, oldest first. A tick is converted with the
+/// epoch it was counted in, so time stays continuous across changes and
+/// timestamps taken before one keep their value.
+struct TimerEpochs(Vec<TimerEpoch>);
+
+impl TimerEpochs {
+	fn to_hrt(&self, ticks: u64) -> u64 {
+		let epoch = self.0.iter().rev().find(|epoch| epoch.ticks <= ticks).unwrap_or(&BOOT_EPOCH);
+		epoch.to_hrt(ticks)
+	}
+
+	/// Starts counting at `hz` from tick `ticks` on.
+	fn change_rate(&mut self, ticks: u64, hz: u64) {
+		let nanos = self.to_hrt(ticks);
+		self.0.push(TimerEpoch { ticks, nanos, hz });
+	}
+}
+
+static TIMER_EPOCHS: SpinMutex<TimerEpochs> = SpinMutex::new(TimerEpochs(Vec::new()));
 
 // pic code
 
@@ -50,9 +101,7 @@ const APIC_EOI: usize = 0x0B0;
 const APIC_SVR: usize = 0x0F0;
 #[allow(unused)]
 const APIC_ISR_BASE: usize = 0x100; // ISR 0x100..0x170
-#[allow(unused)]
 const APIC_ICRLO: usize = 0x300;
-#[allow(unused)]
 const APIC_ICRHI: usize = 0x310;
 const APIC_LVT_TIMER: usize = 0x320;
 #[allow(unused)]
@@ -73,6 +122,15 @@ const APIC_DIVIDE_CONF: usize = 0x3E0;
 const SVR_APIC_ENABLE: u32 = 1 << 8;
 const LVT_MASK_BIT: u32 = 1 << 16;
 const LVT_MODE_PERIODIC: u32 = 1 << 17;
+/// ICR delivery mode INIT.
+pub const ICR_INIT: u32 = 0b101 << 8;
+/// ICR delivery mode Start-Up. The vector is the page number of the code to
+/// start at.
+pub const ICR_STARTUP: u32 = 0b110 << 8;
+/// ICR level assert, required for everything but an INIT de-assert.
+pub const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+/// ICR bit set while the last IPI is still being sent.
+const ICR_SEND_PENDING: u32 = 1 << 12;
 
 #[inline(always)]
 unsafe fn apic_reg_ptr(offset: usize) -> *mut u32 {
@@ -121,6 +179,24 @@ pub unsafe fn send_eoi() {
 	}
 }
 
+/// Sends an inter-processor interrupt to the local APIC `dest`. `command` is
+/// the low ICR word: delivery mode, level and vector. Waits until the APIC
+/// has sent it.
+///
+/// # Safety
+/// The local APIC needs to be mapped, `dest` needs to be the APIC id of a
+/// present CPU and `command` a valid ICR command for it, or else undefined
+/// behaviour. A wrong INIT or SIPI resets or restarts that CPU.
+pub unsafe fn send_ipi(dest: u8, command: u32) {
+	unsafe {
+		write_register(APIC_ICRHI, (dest as u32) << 24);
+		write_register(APIC_ICRLO, command);
+		while read_register(APIC_ICRLO) & ICR_SEND_PENDING != 0 {
+			core::hint::spin_loop();
+		}
+	}
+}
+
 /// Set the timer divide configuration.
 unsafe fn set_timer_divide(divide_cfg: u32) {
 	unsafe {
@@ -231,7 +307,83 @@ pub fn calibrate(target_hz: u32) -> Result<(u64, u32), NullexError> {
 	Ok((ticks_per_second, initial_count))
 }
 
+/// Returns the rate the APIC timer interrupt fires at, in ticks per second.
+pub fn timer_hz() -> u64 {
+	APIC_TIMER_HZ.load(Ordering::Relaxed)
+}
+
+/// Converts an APIC tick count to nanoseconds since the timer started, at
+/// the rate the tick was counted at.
+pub fn to_hrt(ticks: u64) -> u64 {
+	interrupts::without_interrupts(|| TIMER_EPOCHS.lock().to_hrt(ticks))
+}
+
+/// Nanoseconds since the APIC timer started.
+pub fn uptime_ns() -> u64 {
+	interrupts::without_interrupts(|| {
+		TIMER_EPOCHS.lock().to_hrt(APIC_TICK_COUNT.load(Ordering::Relaxed))
+	})
+}
+
+/// Re-programs the running APIC timer to interrupt `hz` times per second.
+///
+/// A new epoch starts at the current tick, so `to_hrt` and `uptime_ns` carry
+/// on from where they were and earlier ticks keep converting at the old
+/// rate. Pending timers keep their tick deadlines, so they fire sooner or
+/// later in wall-clock time.
+pub fn set_timer_frequency(hz: u64) -> Result<(), NullexError> {
+	if hz == 0 {
+		return Err(NullexError::ValueBelowZero);
+	}
+	let ticks_per_second = APIC_TPS.load(Ordering::Relaxed);
+	if ticks_per_second == 0 {
+		return Err(NullexError::DeviceNotInitialized);
+	}
+
+	let initial_count = ticks_per_second / hz;
+	if initial_count == 0 || initial_count > u32::MAX as u64 {
+		return Err(NullexError::InvalidInitialCount);
+	}
+
+	// keep the tick handler out until the new rate is in place
+	interrupts::without_interrupts(|| {
+		let ticks = APIC_TICK_COUNT.load(Ordering::Relaxed);
+		TIMER_EPOCHS.lock().change_rate(ticks, hz);
+		APIC_TIMER_HZ.store(hz, Ordering::Relaxed);
+		unsafe { set_timer_initial(initial_count as u32) };
+	});
+	Ok(())
+}
+
 /// Prelude module for APIC.
 pub mod prelude {
 	pub use crate::apic::*;
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::vec::Vec;
+
+	use crate::{apic::*, utils::ktest::TestError};
+
+	pub fn test_timer_epoch_to_hrt() -> Result<(), TestError> {
+		let mut epochs = TimerEpochs(Vec::new());
+		assert_eq!(epochs.to_hrt(1024), NANOS_PER_SECOND);
+		assert_eq!(epochs.to_hrt(512), NANOS_PER_SECOND / 2);
+
+		// switching to 4096 Hz two seconds in keeps counting from two seconds
+		epochs.change_rate(2048, 4096);
+		assert_eq!(epochs.to_hrt(2048), 2 * NANOS_PER_SECOND);
+		assert_eq!(epochs.to_hrt(2048 + 4096), 3 * NANOS_PER_SECOND);
+		// ticks from before the change keep the time they were counted at
+		assert_eq!(epochs.to_hrt(0), 0);
+		assert_eq!(epochs.to_hrt(1024), NANOS_PER_SECOND);
+
+		// and so do ticks from between two changes
+		epochs.change_rate(2048 + 4096, 1024);
+		assert_eq!(epochs.to_hrt(2048 + 2048), 2 * NANOS_PER_SECOND + NANOS_PER_SECOND / 2);
+		assert_eq!(epochs.to_hrt(2048 + 4096 + 1024), 4 * NANOS_PER_SECOND);
+		Ok(())
+	}
+	crate::create_test!(test_timer_epoch_to_hrt);
+}
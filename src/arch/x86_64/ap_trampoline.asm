@@ -0,0 +1,96 @@
+; Start-up code for the application processors. It's copied to
+; AP_TRAMPOLINE_BASE (below 1 MiB, where a SIPI can start a CPU) by smp.rs,
+; so every address in it is computed for that copy, not for where it's
+; linked. The BSP fills in the variables at the end before each SIPI.
+
+global ap_trampoline_start
+global ap_trampoline_end
+global ap_trampoline_cr3
+global ap_trampoline_stack
+global ap_trampoline_entry
+
+; must match AP_TRAMPOLINE_BASE in smp.rs
+AP_TRAMPOLINE_BASE equ 0x8000
+%define copied(label) (AP_TRAMPOLINE_BASE + (label - ap_trampoline_start))
+
+section .rodata
+bits 16
+ap_trampoline_start:
+    cli
+    cld
+    xor ax, ax
+    mov ds, ax
+
+    ; protected mode with the flat segments below
+    lgdt [copied(trampoline_gdt.pointer)]
+    mov eax, cr0
+    or eax, 1
+    mov cr0, eax
+    jmp dword trampoline_gdt.code32:copied(ap_protected_mode)
+
+bits 32
+ap_protected_mode:
+    mov ax, trampoline_gdt.data
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    ; enable PAE and use the BSP's page tables
+    mov eax, cr4
+    or eax, 1 << 5
+    mov cr4, eax
+    mov eax, [copied(ap_trampoline_cr3)]
+    mov cr3, eax
+
+    ; long mode, and no-execute like the BSP has (init_efer)
+    mov ecx, 0xC0000080
+    rdmsr
+    or eax, (1 << 8) | (1 << 11)
+    wrmsr
+
+    mov eax, cr0
+    or eax, 1 << 31
+    mov cr0, eax
+
+    jmp trampoline_gdt.code64:copied(ap_long_mode)
+
+bits 64
+ap_long_mode:
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+
+    mov rsp, [copied(ap_trampoline_stack)]
+    mov rax, [copied(ap_trampoline_entry)]
+    call rax
+
+    ; the entry point never returns
+.hang:
+    cli
+    hlt
+    jmp .hang
+
+align 8
+trampoline_gdt:
+    dq 0 ; zero entry
+.code32: equ $ - trampoline_gdt
+    dq 0x00CF9A000000FFFF ; flat 32-bit code
+.data: equ $ - trampoline_gdt
+    dq 0x00CF92000000FFFF ; flat data
+.code64: equ $ - trampoline_gdt
+    dq (1<<43) | (1<<44) | (1<<47) | (1<<53) ; 64-bit code, like gdt64
+.pointer:
+    dw $ - trampoline_gdt - 1
+    dd copied(trampoline_gdt)
+
+align 8
+ap_trampoline_cr3:
+    dq 0
+ap_trampoline_stack:
+    dq 0
+ap_trampoline_entry:
+    dq 0
+ap_trampoline_end:
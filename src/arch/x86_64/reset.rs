@@ -0,0 +1,92 @@
+//!
+//! reset.rs
+//!
+//! Machine reset for fatal error paths, tried without relying on a triple
+//! fault: the ACPI reset register first, then the keyboard controller. Also
+//! powering off through ACPI S5.
+//!
+
+use x86_64::instructions::interrupts;
+
+use crate::{
+	acpi,
+	common::ports::{inb, outb, outw},
+	hlt_loop,
+	serial_println
+};
+
+/// 8042 keyboard controller status/command port.
+const KBC_COMMAND_PORT: u16 = 0x64;
+/// Status bit set while the controller's input buffer is full.
+const KBC_STATUS_INPUT_FULL: u8 = 1 << 1;
+/// Command that pulses the CPU reset line.
+const KBC_PULSE_RESET: u8 = 0xFE;
+/// How many status reads to wait for the controller, or for a reset to land.
+const RESET_SPIN_LIMIT: usize = 100_000;
+
+/// SLP_EN bit of the PM1 control registers.
+const PM1_SLEEP_ENABLE: u16 = 1 << 13;
+/// Position of the SLP_TYP field in the PM1 control registers.
+const PM1_SLEEP_TYPE_SHIFT: u16 = 10;
+/// PM1a control ports of QEMU's q35 and i440fx chipsets, and of older QEMU
+/// and Bochs. Their S5 sleep type is 0.
+const QEMU_PM1A_CONTROL_PORTS: [u16; 2] = [0x604, 0xb004];
+
+fn spin() {
+	for _ in 0..RESET_SPIN_LIMIT {
+		core::hint::spin_loop();
+	}
+}
+
+/// Resets the machine. Halts forever if neither reset method works.
+pub fn reboot() -> ! {
+	interrupts::disable();
+
+	if let Some((port, value)) = acpi::reset_register() {
+		serial_println!("[RESET] Writing {:#x} to ACPI reset register {:#x}", value, port);
+		unsafe { outb(port, value) };
+		spin();
+	}
+
+	serial_println!("[RESET] Pulsing the keyboard controller reset line");
+	unsafe {
+		for _ in 0..RESET_SPIN_LIMIT {
+			if inb(KBC_COMMAND_PORT) & KBC_STATUS_INPUT_FULL == 0 {
+				break;
+			}
+		}
+		outb(KBC_COMMAND_PORT, KBC_PULSE_RESET);
+	}
+	spin();
+
+	serial_println!("[RESET] Reset failed, halting");
+	hlt_loop();
+}
+
+/// Powers the machine off by entering ACPI S5, falling back to QEMU's
+/// PM1a control ports. Halts forever if neither works.
+pub fn shutdown() -> ! {
+	interrupts::disable();
+
+	if let Some(off) = acpi::soft_off() {
+		serial_println!("[POWER] Entering S5 through PM1a control port {:#x}", off.pm1a_control);
+		let control =
+			|sleep_type: u8| ((sleep_type as u16) << PM1_SLEEP_TYPE_SHIFT) | PM1_SLEEP_ENABLE;
+		unsafe {
+			outw(off.pm1a_control, control(off.sleep_type_a));
+			if let Some(port) = off.pm1b_control {
+				outw(port, control(off.sleep_type_b));
+			}
+		}
+		spin();
+	}
+
+	serial_println!("[POWER] Trying QEMU's power off ports");
+	for port in QEMU_PM1A_CONTROL_PORTS {
+		unsafe { outw(port, PM1_SLEEP_ENABLE) };
+		spin();
+	}
+
+	serial_println!("[POWER] Power off failed, halting");
+	hlt_loop();
+}
@@ -30,6 +30,10 @@ const TRANSITION_STACK_SIZE: usize = 4096 * 4;
 
 pub static mut KERNEL_CR3: u64 = 0;
 
+/// The user process `enter_user_process` is running, for syscalls that
+/// change its image. Null while no user process runs.
+pub static mut CURRENT_USER_PROCESS: *mut Process = core::ptr::null_mut();
+
 #[repr(align(16))]
 struct TransitionStack([u8; TRANSITION_STACK_SIZE]);
 
@@ -90,7 +94,23 @@ unsafe fn push_u64(
     unsafe { push_bytes(stack_frames, sp, &bytes) };
 }
 
-
+/// Maps the user stack into `address_space` and lays out `args` and `envs`
+/// on it like the System V ABI does. Returns the initial `rsp`, which is
+/// 16-byte aligned and points at `argc`:
+///
+/// ```text
+/// rsp                         argc
+/// rsp + 8                     argv[0] .. argv[argc - 1]
+/// rsp + 8 * (argc + 1)        NULL
+/// rsp + 8 * (argc + 2)        envp[0] .. envp[envc - 1]
+/// rsp + 8 * (argc + envc + 2) NULL
+///                             padding up to 16 bytes, if any
+///                             argument and environment strings, NUL-terminated
+/// USER_STACK_TOP
+/// ```
+///
+/// The program also gets argc, argv and envp in `rdi`, `rsi` and `rdx`; see
+/// `UserContext::set_entry_args`.
 pub unsafe fn setup_user_stack(
     address_space: &mut AddressSpace,
     args: &[&str],
@@ -155,22 +175,27 @@ pub unsafe fn setup_user_stack(
         arg_ptrs.push(addr);
     }
 
-    // Align stack
+    // Align stack, leaving room for a padding word if the pointers and argc
+    // add up to an odd number of words
     sp &= !0xF;
+    if (args.len() + envs.len() + 3) % 2 == 1 {
+        unsafe { push_u64(&stack_frames.as_slice(), &mut sp, 0) };
+    }
 
     // envp NULL
     unsafe { push_u64(&stack_frames.as_slice(), &mut sp, 0) };
 
-    // envp pointers
-    for &ptr in env_ptrs.iter().rev() {
+    // envp pointers, last first; the strings were pushed last first too, so
+    // the pointers are already in that order
+    for &ptr in env_ptrs.iter() {
         unsafe { push_u64(&stack_frames.as_slice(), &mut sp, ptr) };
     }
 
     // argv NULL
     unsafe { push_u64(&stack_frames.as_slice(), &mut sp, 0) };
 
-    // argv pointers
-    for &ptr in arg_ptrs.iter().rev() {
+    // argv pointers, last first
+    for &ptr in arg_ptrs.iter() {
         unsafe { push_u64(&stack_frames.as_slice(), &mut sp, ptr) };
     }
 
@@ -185,8 +210,18 @@ pub unsafe fn setup_user_stack(
     sp
 }
 
+/// Runs `process` in user mode until it makes the `halt` syscall, which
+/// returns here. The kernel's stack and return point are saved for that in
+/// `KERNEL_RETURN_*`, and `process` is `CURRENT_USER_PROCESS` meanwhile.
+///
+/// # Safety
+/// `process` needs a loaded address space that maps the kernel like the
+/// current one does, and a context whose `rip` and `rsp` point into it, or
+/// else undefined behaviour. Only one user process can be entered at a time,
+/// since they share the transition stack and the saved return point.
+pub unsafe fn enter_user_process(process: &mut Process) {
+    unsafe { CURRENT_USER_PROCESS = process as *mut Process };
 
-pub unsafe fn enter_user_process(process: &Process) {
     let address_space = process
         .address_space
         .as_ref()
@@ -235,6 +270,55 @@ pub unsafe fn enter_user_process(process: &Process) {
             rflags = in(reg) process.context.rflags,
             cs = in(reg) crate::gdt::user_code_selector() as u64,
             rip = in(reg) process.context.rip,
+            in("rdi") process.context.rdi,
+            in("rsi") process.context.rsi,
+            in("rdx") process.context.rdx,
         );
     }
-}
\ No newline at end of file
+
+    unsafe { CURRENT_USER_PROCESS = core::ptr::null_mut() };
+}
+
+/// Jumps to the saved user context of `process` in its address space.
+///
+/// Unlike `enter_user_process` this doesn't save a new kernel return point,
+/// so the `halt` syscall still returns to whoever entered the process first.
+/// Used to resume a process whose image was replaced inside a syscall.
+///
+/// # Safety
+/// Same as `enter_user_process`, which has to have entered the user process
+/// being replaced, so its saved return point is still valid. Nothing on the
+/// current stack is dropped or returned to.
+pub unsafe fn switch_to_process(process: &Process) -> ! {
+    let address_space = process
+        .address_space
+        .as_ref()
+        .expect("attempted to switch_to_process on a kernel process");
+
+    let trampoline_sp = unsafe { transition_stack_top() };
+
+    unsafe {
+        core::arch::asm!(
+            "cli",
+            "mov rsp, {tramp_sp}",
+            "push {ss}",
+            "push {user_rsp}",
+            "push {rflags}",
+            "push {cs}",
+            "push {rip}",
+            "mov cr3, {cr3}",
+            "iretq",
+            tramp_sp = in(reg) trampoline_sp,
+            cr3 = in(reg) address_space.page_table.start_address().as_u64(),
+            user_rsp = in(reg) process.context.rsp,
+            ss = in(reg) crate::gdt::user_data_selector() as u64,
+            rflags = in(reg) process.context.rflags,
+            cs = in(reg) crate::gdt::user_code_selector() as u64,
+            rip = in(reg) process.context.rip,
+            in("rdi") process.context.rdi,
+            in("rsi") process.context.rsi,
+            in("rdx") process.context.rdx,
+            options(noreturn)
+        );
+    }
+}
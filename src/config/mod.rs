@@ -4,5 +4,22 @@
 //! Kernel configuration file handling module declaration. 
 //! 
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 #[allow(unused, private_interfaces)]
 pub mod ini_parser;
+
+/// Whether fatal faults (double faults and panics) reset the machine instead
+/// of halting. On by default with the `reboot-on-fault` feature, so automated
+/// runs under QEMU's `-no-reboot` end instead of hanging.
+static REBOOT_ON_FATAL_FAULT: AtomicBool = AtomicBool::new(cfg!(feature = "reboot-on-fault"));
+
+/// Returns whether fatal faults reset the machine.
+pub fn reboot_on_fatal_fault() -> bool {
+	REBOOT_ON_FATAL_FAULT.load(Ordering::Relaxed)
+}
+
+/// Sets whether fatal faults reset the machine instead of halting.
+pub fn set_reboot_on_fatal_fault(enabled: bool) {
+	REBOOT_ON_FATAL_FAULT.store(enabled, Ordering::Relaxed);
+}
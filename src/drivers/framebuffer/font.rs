@@ -0,0 +1,296 @@
+//!
+//! font.rs
+//!
+//! 8x16 bitmap font for the framebuffer console, covering printable ASCII
+//! and the code page 437 characters the VGA console writes: box drawing,
+//! shades, blocks, arrows and a few symbols. The ASCII glyphs are the public
+//! domain font8x8 set with every row doubled.
+//!
+
+/// Width of a glyph in pixels.
+pub const GLYPH_WIDTH: usize = 8;
+/// Height of a glyph in pixels.
+pub const GLYPH_HEIGHT: usize = 16;
+
+/// Drawn for bytes outside printable ASCII, like the 0xfe block the VGA
+/// console falls back to.
+const UNKNOWN: [u8; GLYPH_HEIGHT] = expand([0x00, 0x00, 0x3c, 0x3c, 0x3c, 0x3c, 0x00, 0x00]);
+
+/// Rows of the glyphs for 0x20 (space) to 0x7e (tilde). Bit 7 of each row is
+/// the leftmost pixel.
+static GLYPHS: [[u8; GLYPH_HEIGHT]; 95] = [
+	expand([0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), // space
+	expand([0x18, 0x3c, 0x3c, 0x18, 0x18, 0x00, 0x18, 0x00]), // !
+	expand([0x6c, 0x6c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), // "
+	expand([0x6c, 0x6c, 0xfe, 0x6c, 0xfe, 0x6c, 0x6c, 0x00]), // #
+	expand([0x30, 0x7c, 0xc0, 0x78, 0x0c, 0xf8, 0x30, 0x00]), // $
+	expand([0x00, 0xc6, 0xcc, 0x18, 0x30, 0x66, 0xc6, 0x00]), // %
+	expand([0x38, 0x6c, 0x38, 0x76, 0xdc, 0xcc, 0x76, 0x00]), // &
+	expand([0x60, 0x60, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00]), // '
+	expand([0x18, 0x30, 0x60, 0x60, 0x60, 0x30, 0x18, 0x00]), // (
+	expand([0x60, 0x30, 0x18, 0x18, 0x18, 0x30, 0x60, 0x00]), // )
+	expand([0x00, 0x66, 0x3c, 0xff, 0x3c, 0x66, 0x00, 0x00]), // *
+	expand([0x00, 0x30, 0x30, 0xfc, 0x30, 0x30, 0x00, 0x00]), // +
+	expand([0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x30, 0x60]), // ,
+	expand([0x00, 0x00, 0x00, 0xfc, 0x00, 0x00, 0x00, 0x00]), // -
+	expand([0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x30, 0x00]), // .
+	expand([0x06, 0x0c, 0x18, 0x30, 0x60, 0xc0, 0x80, 0x00]), // /
+	expand([0x7c, 0xc6, 0xce, 0xde, 0xf6, 0xe6, 0x7c, 0x00]), // 0
+	expand([0x30, 0x70, 0x30, 0x30, 0x30, 0x30, 0xfc, 0x00]), // 1
+	expand([0x78, 0xcc, 0x0c, 0x38, 0x60, 0xcc, 0xfc, 0x00]), // 2
+	expand([0x78, 0xcc, 0x0c, 0x38, 0x0c, 0xcc, 0x78, 0x00]), // 3
+	expand([0x1c, 0x3c, 0x6c, 0xcc, 0xfe, 0x0c, 0x1e, 0x00]), // 4
+	expand([0xfc, 0xc0, 0xf8, 0x0c, 0x0c, 0xcc, 0x78, 0x00]), // 5
+	expand([0x38, 0x60, 0xc0, 0xf8, 0xcc, 0xcc, 0x78, 0x00]), // 6
+	expand([0xfc, 0xcc, 0x0c, 0x18, 0x30, 0x30, 0x30, 0x00]), // 7
+	expand([0x78, 0xcc, 0xcc, 0x78, 0xcc, 0xcc, 0x78, 0x00]), // 8
+	expand([0x78, 0xcc, 0xcc, 0x7c, 0x0c, 0x18, 0x70, 0x00]), // 9
+	expand([0x00, 0x30, 0x30, 0x00, 0x00, 0x30, 0x30, 0x00]), // :
+	expand([0x00, 0x30, 0x30, 0x00, 0x00, 0x30, 0x30, 0x60]), // ;
+	expand([0x18, 0x30, 0x60, 0xc0, 0x60, 0x30, 0x18, 0x00]), // <
+	expand([0x00, 0x00, 0xfc, 0x00, 0x00, 0xfc, 0x00, 0x00]), // =
+	expand([0x60, 0x30, 0x18, 0x0c, 0x18, 0x30, 0x60, 0x00]), // >
+	expand([0x78, 0xcc, 0x0c, 0x18, 0x30, 0x00, 0x30, 0x00]), // ?
+	expand([0x7c, 0xc6, 0xde, 0xde, 0xde, 0xc0, 0x78, 0x00]), // @
+	expand([0x30, 0x78, 0xcc, 0xcc, 0xfc, 0xcc, 0xcc, 0x00]), // A
+	expand([0xfc, 0x66, 0x66, 0x7c, 0x66, 0x66, 0xfc, 0x00]), // B
+	expand([0x3c, 0x66, 0xc0, 0xc0, 0xc0, 0x66, 0x3c, 0x00]), // C
+	expand([0xf8, 0x6c, 0x66, 0x66, 0x66, 0x6c, 0xf8, 0x00]), // D
+	expand([0xfe, 0x62, 0x68, 0x78, 0x68, 0x62, 0xfe, 0x00]), // E
+	expand([0xfe, 0x62, 0x68, 0x78, 0x68, 0x60, 0xf0, 0x00]), // F
+	expand([0x3c, 0x66, 0xc0, 0xc0, 0xce, 0x66, 0x3e, 0x00]), // G
+	expand([0xcc, 0xcc, 0xcc, 0xfc, 0xcc, 0xcc, 0xcc, 0x00]), // H
+	expand([0x78, 0x30, 0x30, 0x30, 0x30, 0x30, 0x78, 0x00]), // I
+	expand([0x1e, 0x0c, 0x0c, 0x0c, 0xcc, 0xcc, 0x78, 0x00]), // J
+	expand([0xe6, 0x66, 0x6c, 0x78, 0x6c, 0x66, 0xe6, 0x00]), // K
+	expand([0xf0, 0x60, 0x60, 0x60, 0x62, 0x66, 0xfe, 0x00]), // L
+	expand([0xc6, 0xee, 0xfe, 0xfe, 0xd6, 0xc6, 0xc6, 0x00]), // M
+	expand([0xc6, 0xe6, 0xf6, 0xde, 0xce, 0xc6, 0xc6, 0x00]), // N
+	expand([0x38, 0x6c, 0xc6, 0xc6, 0xc6, 0x6c, 0x38, 0x00]), // O
+	expand([0xfc, 0x66, 0x66, 0x7c, 0x60, 0x60, 0xf0, 0x00]), // P
+	expand([0x78, 0xcc, 0xcc, 0xcc, 0xdc, 0x78, 0x1c, 0x00]), // Q
+	expand([0xfc, 0x66, 0x66, 0x7c, 0x6c, 0x66, 0xe6, 0x00]), // R
+	expand([0x78, 0xcc, 0xe0, 0x70, 0x1c, 0xcc, 0x78, 0x00]), // S
+	expand([0xfc, 0xb4, 0x30, 0x30, 0x30, 0x30, 0x78, 0x00]), // T
+	expand([0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xfc, 0x00]), // U
+	expand([0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0x78, 0x30, 0x00]), // V
+	expand([0xc6, 0xc6, 0xc6, 0xd6, 0xfe, 0xee, 0xc6, 0x00]), // W
+	expand([0xc6, 0xc6, 0x6c, 0x38, 0x38, 0x6c, 0xc6, 0x00]), // X
+	expand([0xcc, 0xcc, 0xcc, 0x78, 0x30, 0x30, 0x78, 0x00]), // Y
+	expand([0xfe, 0xc6, 0x8c, 0x18, 0x32, 0x66, 0xfe, 0x00]), // Z
+	expand([0x78, 0x60, 0x60, 0x60, 0x60, 0x60, 0x78, 0x00]), // [
+	expand([0xc0, 0x60, 0x30, 0x18, 0x0c, 0x06, 0x02, 0x00]), // \
+	expand([0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0x78, 0x00]), // ]
+	expand([0x10, 0x38, 0x6c, 0xc6, 0x00, 0x00, 0x00, 0x00]), // ^
+	expand([0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff]), // _
+	expand([0x30, 0x30, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00]), // `
+	expand([0x00, 0x00, 0x78, 0x0c, 0x7c, 0xcc, 0x76, 0x00]), // a
+	expand([0xe0, 0x60, 0x60, 0x7c, 0x66, 0x66, 0xdc, 0x00]), // b
+	expand([0x00, 0x00, 0x78, 0xcc, 0xc0, 0xcc, 0x78, 0x00]), // c
+	expand([0x1c, 0x0c, 0x0c, 0x7c, 0xcc, 0xcc, 0x76, 0x00]), // d
+	expand([0x00, 0x00, 0x78, 0xcc, 0xfc, 0xc0, 0x78, 0x00]), // e
+	expand([0x38, 0x6c, 0x60, 0xf0, 0x60, 0x60, 0xf0, 0x00]), // f
+	expand([0x00, 0x00, 0x76, 0xcc, 0xcc, 0x7c, 0x0c, 0xf8]), // g
+	expand([0xe0, 0x60, 0x6c, 0x76, 0x66, 0x66, 0xe6, 0x00]), // h
+	expand([0x30, 0x00, 0x70, 0x30, 0x30, 0x30, 0x78, 0x00]), // i
+	expand([0x0c, 0x00, 0x0c, 0x0c, 0x0c, 0xcc, 0xcc, 0x78]), // j
+	expand([0xe0, 0x60, 0x66, 0x6c, 0x78, 0x6c, 0xe6, 0x00]), // k
+	expand([0x70, 0x30, 0x30, 0x30, 0x30, 0x30, 0x78, 0x00]), // l
+	expand([0x00, 0x00, 0xcc, 0xfe, 0xfe, 0xd6, 0xc6, 0x00]), // m
+	expand([0x00, 0x00, 0xf8, 0xcc, 0xcc, 0xcc, 0xcc, 0x00]), // n
+	expand([0x00, 0x00, 0x78, 0xcc, 0xcc, 0xcc, 0x78, 0x00]), // o
+	expand([0x00, 0x00, 0xdc, 0x66, 0x66, 0x7c, 0x60, 0xf0]), // p
+	expand([0x00, 0x00, 0x76, 0xcc, 0xcc, 0x7c, 0x0c, 0x1e]), // q
+	expand([0x00, 0x00, 0xdc, 0x76, 0x66, 0x60, 0xf0, 0x00]), // r
+	expand([0x00, 0x00, 0x7c, 0xc0, 0x78, 0x0c, 0xf8, 0x00]), // s
+	expand([0x10, 0x30, 0x7c, 0x30, 0x30, 0x34, 0x18, 0x00]), // t
+	expand([0x00, 0x00, 0xcc, 0xcc, 0xcc, 0xcc, 0x76, 0x00]), // u
+	expand([0x00, 0x00, 0xcc, 0xcc, 0xcc, 0x78, 0x30, 0x00]), // v
+	expand([0x00, 0x00, 0xc6, 0xd6, 0xfe, 0xfe, 0x6c, 0x00]), // w
+	expand([0x00, 0x00, 0xc6, 0x6c, 0x38, 0x6c, 0xc6, 0x00]), // x
+	expand([0x00, 0x00, 0xcc, 0xcc, 0xcc, 0x7c, 0x0c, 0xf8]), // y
+	expand([0x00, 0x00, 0xfc, 0x98, 0x30, 0x64, 0xfc, 0x00]), // z
+	expand([0x1c, 0x30, 0x30, 0xe0, 0x30, 0x30, 0x1c, 0x00]), // {
+	expand([0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00]), // |
+	expand([0xe0, 0x30, 0x30, 0x1c, 0x30, 0x30, 0xe0, 0x00]), // }
+	expand([0x76, 0xdc, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]) // ~
+];
+
+/// Doubles each row of an 8x8 glyph.
+const fn expand(rows: [u8; 8]) -> [u8; GLYPH_HEIGHT] {
+	let mut glyph = [0u8; GLYPH_HEIGHT];
+	let mut i = 0;
+	while i < GLYPH_HEIGHT {
+		glyph[i] = rows[i / 2];
+		i += 1;
+	}
+	glyph
+}
+
+/// Row the horizontal strokes of box drawing glyphs are drawn on.
+const BOX_ROW: usize = GLYPH_HEIGHT / 2;
+/// Pixels of the vertical stroke, and of the horizontal stroke left and
+/// right of it, in a box drawing glyph row.
+const BOX_VERTICAL: u8 = 0x10;
+const BOX_LEFT: u8 = 0xf0;
+const BOX_RIGHT: u8 = 0x1f;
+
+/// Builds a single-line box drawing glyph with strokes from the middle of
+/// the cell to the edges asked for.
+const fn box_glyph(up: bool, down: bool, left: bool, right: bool) -> [u8; GLYPH_HEIGHT] {
+	let mut glyph = [0u8; GLYPH_HEIGHT];
+	let mut row = 0;
+	while row < GLYPH_HEIGHT {
+		if (up && row <= BOX_ROW) || (down && row >= BOX_ROW) {
+			glyph[row] |= BOX_VERTICAL;
+		}
+		row += 1;
+	}
+	if left {
+		glyph[BOX_ROW] |= BOX_LEFT;
+	}
+	if right {
+		glyph[BOX_ROW] |= BOX_RIGHT;
+	}
+	glyph
+}
+
+/// Columns of the two vertical strokes of double-line box drawing glyphs,
+/// either side of the single-line stroke.
+const DOUBLE_LEFT_COL: usize = 2;
+const DOUBLE_RIGHT_COL: usize = 4;
+/// Rows of the two horizontal strokes, either side of `BOX_ROW`.
+const DOUBLE_TOP_ROW: usize = BOX_ROW - 1;
+const DOUBLE_BOTTOM_ROW: usize = BOX_ROW + 1;
+
+/// Pixels of columns `from` to `to` in a glyph row.
+const fn columns(from: usize, to: usize) -> u8 {
+	(0xff >> from) & (0xff << (GLYPH_WIDTH - 1 - to))
+}
+
+/// Builds a double-line box drawing glyph with strokes to the edges asked
+/// for. Where two arms meet, the inner strokes stop at each other and the
+/// outer strokes turn the corner.
+const fn double_box_glyph(up: bool, down: bool, left: bool, right: bool) -> [u8; GLYPH_HEIGHT] {
+	let (l, r) = (DOUBLE_LEFT_COL, DOUBLE_RIGHT_COL);
+	let (t, b) = (DOUBLE_TOP_ROW, DOUBLE_BOTTOM_ROW);
+
+	let mut glyph = [0u8; GLYPH_HEIGHT];
+	let mut row = 0;
+	while row < GLYPH_HEIGHT {
+		let left_up = up && row <= if left { t } else { b };
+		let left_down = down && row >= if left { b } else { t };
+		let right_up = up && row <= if right { t } else { b };
+		let right_down = down && row >= if right { b } else { t };
+		if left_up || left_down {
+			glyph[row] |= columns(l, l);
+		}
+		if right_up || right_down {
+			glyph[row] |= columns(r, r);
+		}
+		row += 1;
+	}
+
+	if left {
+		glyph[t] |= columns(0, if up { l } else { r });
+		glyph[b] |= columns(0, if down { l } else { r });
+	}
+	if right {
+		glyph[t] |= columns(if up { r } else { l }, GLYPH_WIDTH - 1);
+		glyph[b] |= columns(if down { r } else { l }, GLYPH_WIDTH - 1);
+	}
+	glyph
+}
+
+/// Builds a shade glyph from the patterns of its even and odd rows.
+const fn shade(even: u8, odd: u8) -> [u8; GLYPH_HEIGHT] {
+	let mut glyph = [0u8; GLYPH_HEIGHT];
+	let mut row = 0;
+	while row < GLYPH_HEIGHT {
+		glyph[row] = if row % 2 == 0 { even } else { odd };
+		row += 1;
+	}
+	glyph
+}
+
+/// Builds a block glyph filling the top and/or bottom half of the cell.
+const fn block(top: bool, bottom: bool) -> [u8; GLYPH_HEIGHT] {
+	let mut glyph = [0u8; GLYPH_HEIGHT];
+	let mut row = 0;
+	while row < GLYPH_HEIGHT {
+		if (top && row < GLYPH_HEIGHT / 2) || (bottom && row >= GLYPH_HEIGHT / 2) {
+			glyph[row] = 0xff;
+		}
+		row += 1;
+	}
+	glyph
+}
+
+/// Code page 437 box drawing bytes and their glyphs.
+static BOX_GLYPHS: [(u8, [u8; GLYPH_HEIGHT]); 22] = [
+	(0xc4, box_glyph(false, false, true, true)), // ─
+	(0xb3, box_glyph(true, true, false, false)), // │
+	(0xda, box_glyph(false, true, false, true)), // ┌
+	(0xbf, box_glyph(false, true, true, false)), // ┐
+	(0xc0, box_glyph(true, false, false, true)), // └
+	(0xd9, box_glyph(true, false, true, false)), // ┘
+	(0xc3, box_glyph(true, true, false, true)), // ├
+	(0xb4, box_glyph(true, true, true, false)), // ┤
+	(0xc2, box_glyph(false, true, true, true)), // ┬
+	(0xc1, box_glyph(true, false, true, true)), // ┴
+	(0xc5, box_glyph(true, true, true, true)), // ┼
+	(0xcd, double_box_glyph(false, false, true, true)), // ═
+	(0xba, double_box_glyph(true, true, false, false)), // ║
+	(0xc9, double_box_glyph(false, true, false, true)), // ╔
+	(0xbb, double_box_glyph(false, true, true, false)), // ╗
+	(0xc8, double_box_glyph(true, false, false, true)), // ╚
+	(0xbc, double_box_glyph(true, false, true, false)), // ╝
+	(0xcc, double_box_glyph(true, true, false, true)), // ╠
+	(0xb9, double_box_glyph(true, true, true, false)), // ╣
+	(0xcb, double_box_glyph(false, true, true, true)), // ╦
+	(0xca, double_box_glyph(true, false, true, true)), // ╩
+	(0xce, double_box_glyph(true, true, true, true)) // ╬
+];
+
+/// Code page 437 shades, blocks, arrows and symbols and their glyphs.
+static SYMBOL_GLYPHS: [(u8, [u8; GLYPH_HEIGHT]); 22] = [
+	(0xb0, shade(0x22, 0x88)), // ░
+	(0xb1, shade(0x55, 0xaa)), // ▒
+	(0xb2, shade(0xdd, 0x77)), // ▓
+	(0xdb, block(true, true)), // █
+	(0xdf, block(true, false)), // ▀
+	(0xdc, block(false, true)), // ▄
+	(0xfe, expand([0x00, 0x00, 0x3c, 0x3c, 0x3c, 0x3c, 0x00, 0x00])), // ■
+	(0x18, expand([0x18, 0x3c, 0x7e, 0x18, 0x18, 0x18, 0x18, 0x00])), // ↑
+	(0x19, expand([0x18, 0x18, 0x18, 0x18, 0x7e, 0x3c, 0x18, 0x00])), // ↓
+	(0x1a, expand([0x00, 0x08, 0x0c, 0xfe, 0x0c, 0x08, 0x00, 0x00])), // →
+	(0x1b, expand([0x00, 0x20, 0x60, 0xfe, 0x60, 0x20, 0x00, 0x00])), // ←
+	(0x12, expand([0x18, 0x3c, 0x7e, 0x18, 0x18, 0x7e, 0x3c, 0x18])), // ↕
+	(0x1d, expand([0x00, 0x24, 0x66, 0xff, 0x66, 0x24, 0x00, 0x00])), // ↔
+	(0x1e, expand([0x00, 0x18, 0x18, 0x3c, 0x3c, 0x7e, 0x7e, 0x00])), // ▲
+	(0x1f, expand([0x00, 0x7e, 0x7e, 0x3c, 0x3c, 0x18, 0x18, 0x00])), // ▼
+	(0x10, expand([0x40, 0x70, 0x7c, 0x7f, 0x7c, 0x70, 0x40, 0x00])), // ►
+	(0x11, expand([0x02, 0x0e, 0x3e, 0xfe, 0x3e, 0x0e, 0x02, 0x00])), // ◄
+	(0x07, expand([0x00, 0x00, 0x18, 0x3c, 0x3c, 0x18, 0x00, 0x00])), // •
+	(0xfa, expand([0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00])), // ·
+	(0xf8, expand([0x38, 0x6c, 0x6c, 0x38, 0x00, 0x00, 0x00, 0x00])), // °
+	(0xf1, expand([0x18, 0x18, 0x7e, 0x18, 0x18, 0x00, 0x7e, 0x00])), // ±
+	(0xfb, expand([0x0f, 0x0c, 0x0c, 0x0c, 0xec, 0x6c, 0x3c, 0x1c])) // √
+];
+
+/// Returns the glyph for `byte`, or `None` if the font doesn't have one.
+pub(crate) fn known_glyph(byte: u8) -> Option<&'static [u8; GLYPH_HEIGHT]> {
+	match byte {
+		0x20..=0x7e => Some(&GLYPHS[(byte - 0x20) as usize]),
+		_ => BOX_GLYPHS
+			.iter()
+			.chain(SYMBOL_GLYPHS.iter())
+			.find(|(code, _)| *code == byte)
+			.map(|(_, glyph)| glyph)
+	}
+}
+
+/// Returns the glyph for `byte`.
+pub fn glyph(byte: u8) -> &'static [u8; GLYPH_HEIGHT] {
+	known_glyph(byte).unwrap_or(&UNKNOWN)
+}
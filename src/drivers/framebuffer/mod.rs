@@ -0,0 +1,211 @@
+//!
+//! drivers/framebuffer/mod.rs
+//!
+//! Text console on the linear framebuffer set up by the boot loader, for
+//! boots without the legacy VGA text mode. It draws the same 80x25 grid of
+//! cells as the VGA buffer, in the top-left corner of the screen.
+//!
+
+pub mod font;
+
+use x86_64::{PhysAddr, instructions::interrupts};
+
+use crate::{
+	drivers::framebuffer::font::{GLYPH_HEIGHT, GLYPH_WIDTH, glyph},
+	error::NullexError,
+	memory::map_mmio,
+	serial_println,
+	utils::multiboot2::{FramebufferInfo, PixelFormat},
+	vga_buffer::{BUFFER_HEIGHT, BUFFER_WIDTH, WRITER}
+};
+
+/// RGB values of the 16 VGA text colours, indexed by `vga_buffer::Color`.
+pub const VGA_PALETTE: [[u8; 3]; 16] = [
+	[0x00, 0x00, 0x00],
+	[0x00, 0x00, 0xaa],
+	[0x00, 0xaa, 0x00],
+	[0x00, 0xaa, 0xaa],
+	[0xaa, 0x00, 0x00],
+	[0xaa, 0x00, 0xaa],
+	[0xaa, 0x55, 0x00],
+	[0xaa, 0xaa, 0xaa],
+	[0x55, 0x55, 0x55],
+	[0x55, 0x55, 0xff],
+	[0x55, 0xff, 0x55],
+	[0x55, 0xff, 0xff],
+	[0xff, 0x55, 0x55],
+	[0xff, 0x55, 0xff],
+	[0xff, 0xff, 0x55],
+	[0xff, 0xff, 0xff]
+];
+
+/// Height of the cursor bar drawn under the cursor cell, in pixels.
+const CURSOR_HEIGHT: usize = 2;
+
+/// Returns the pixel value for `rgb` in `format`.
+pub fn encode_colour(format: &PixelFormat, rgb: [u8; 3]) -> u32 {
+	match format {
+		PixelFormat::Rgb { red, green, blue } => {
+			let channel = |value: u8, (position, size): (u8, u8)| {
+				let size = size.min(8);
+				((value as u32) >> (8 - size)) << position
+			};
+			channel(rgb[0], *red) | channel(rgb[1], *green) | channel(rgb[2], *blue)
+		}
+		PixelFormat::Indexed { palette, len } => {
+			let distance = |entry: &[u8; 3]| {
+				entry
+					.iter()
+					.zip(rgb.iter())
+					.map(|(a, b)| (*a as i32 - *b as i32).pow(2) as u32)
+					.sum::<u32>()
+			};
+			palette[..*len]
+				.iter()
+				.enumerate()
+				.min_by_key(|(_, entry)| distance(entry))
+				.map_or(0, |(index, _)| index as u32)
+		}
+	}
+}
+
+/// A character grid drawn into a linear framebuffer.
+pub struct FramebufferConsole {
+	base: *mut u8,
+	pitch: usize,
+	bytes_per_pixel: usize,
+	/// Pixel values of the 16 VGA colours in the framebuffer's format.
+	colours: [u32; 16],
+	/// The cell the cursor bar is drawn in, if any.
+	cursor: Option<(usize, usize)>
+}
+
+// The framebuffer is only reached through `WRITER`'s lock.
+unsafe impl Send for FramebufferConsole {}
+
+impl FramebufferConsole {
+	/// Maps the framebuffer described by `info`. Fails if it's too small for
+	/// the text grid or its pixels aren't 8, 16, 24 or 32 bits.
+	pub fn new(info: &FramebufferInfo) -> Result<FramebufferConsole, NullexError> {
+		if !matches!(info.bpp, 8 | 16 | 24 | 32)
+			|| (info.width as usize) < BUFFER_WIDTH * GLYPH_WIDTH
+			|| (info.height as usize) < BUFFER_HEIGHT * GLYPH_HEIGHT
+		{
+			return Err(NullexError::UnsupportedFramebuffer);
+		}
+
+		let size = info.pitch as usize * info.height as usize;
+		let base = map_mmio(PhysAddr::new(info.addr), size, true)?;
+
+		let mut colours = [0u32; 16];
+		for (colour, rgb) in colours.iter_mut().zip(VGA_PALETTE.iter()) {
+			*colour = encode_colour(&info.format, *rgb);
+		}
+
+		Ok(FramebufferConsole {
+			base: base.as_mut_ptr(),
+			pitch: info.pitch as usize,
+			bytes_per_pixel: info.bpp as usize / 8,
+			colours,
+			cursor: None
+		})
+	}
+
+	fn put_pixel(&mut self, x: usize, y: usize, value: u32) {
+		let offset = y * self.pitch + x * self.bytes_per_pixel;
+		let bytes = value.to_le_bytes();
+		for (i, byte) in bytes.iter().take(self.bytes_per_pixel).enumerate() {
+			unsafe { core::ptr::write_volatile(self.base.add(offset + i), *byte) };
+		}
+	}
+
+	/// Draws `byte` in the cell at `row`, `col` with VGA colours `fg` on `bg`.
+	pub fn draw_char(&mut self, row: usize, col: usize, byte: u8, fg: u8, bg: u8) {
+		if self.cursor == Some((row, col)) {
+			self.cursor = None;
+		}
+
+		let fg = self.colours[(fg & 0xf) as usize];
+		let bg = self.colours[(bg & 0xf) as usize];
+		let (x0, y0) = (col * GLYPH_WIDTH, row * GLYPH_HEIGHT);
+		for (y, bits) in glyph(byte).iter().enumerate() {
+			for x in 0..GLYPH_WIDTH {
+				let lit = bits & (0x80 >> x) != 0;
+				self.put_pixel(x0 + x, y0 + y, if lit { fg } else { bg });
+			}
+		}
+	}
+
+	/// Draws the cursor bar at the bottom of the cell at `row`, `col` in VGA
+	/// colour `fg`. Returns the cell the bar was previously drawn in, which
+	/// the caller redraws to erase it.
+	pub fn move_cursor(&mut self, row: usize, col: usize, fg: u8) -> Option<(usize, usize)> {
+		let previous = self.cursor.take();
+		if row >= BUFFER_HEIGHT || col >= BUFFER_WIDTH {
+			return previous;
+		}
+
+		let fg = self.colours[(fg & 0xf) as usize];
+		let (x0, y0) = (col * GLYPH_WIDTH, row * GLYPH_HEIGHT);
+		for y in GLYPH_HEIGHT - CURSOR_HEIGHT..GLYPH_HEIGHT {
+			for x in 0..GLYPH_WIDTH {
+				self.put_pixel(x0 + x, y0 + y, fg);
+			}
+		}
+		self.cursor = Some((row, col));
+		previous.filter(|cell| *cell != (row, col))
+	}
+}
+
+/// Moves the console from the VGA text buffer to the framebuffer described
+/// by `info`. Needs the kernel heap and page mapper.
+pub fn init(info: &FramebufferInfo) -> Result<(), NullexError> {
+	let console = FramebufferConsole::new(info)?;
+	interrupts::without_interrupts(|| WRITER.lock().attach_framebuffer(console));
+	serial_println!(
+		"[FB] Console on {}x{} framebuffer at {:#x}",
+		info.width,
+		info.height,
+		info.addr
+	);
+	Ok(())
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{
+		drivers::framebuffer::*,
+		utils::{ktest::TestError, multiboot2::PixelFormat}
+	};
+
+	pub fn test_encode_colour() -> Result<(), TestError> {
+		// x8r8g8b8
+		let rgb32 = PixelFormat::Rgb {
+			red: (16, 8),
+			green: (8, 8),
+			blue: (0, 8)
+		};
+		assert_eq!(encode_colour(&rgb32, [0xaa, 0x55, 0xff]), 0x00aa_55ff);
+
+		// r5g6b5 keeps the top bits of each channel
+		let rgb16 = PixelFormat::Rgb {
+			red: (11, 5),
+			green: (5, 6),
+			blue: (0, 5)
+		};
+		assert_eq!(encode_colour(&rgb16, [0xff, 0xff, 0xff]), 0xffff);
+		assert_eq!(encode_colour(&rgb16, [0xaa, 0x00, 0x00]), 0x15 << 11);
+
+		let mut palette = [[0u8; 3]; 256];
+		palette[1] = [0xff, 0xff, 0xff];
+		palette[2] = [0xa0, 0x00, 0x00];
+		palette[3] = [0x00, 0xff, 0x00];
+		let indexed = PixelFormat::Indexed { palette, len: 3 };
+		assert_eq!(encode_colour(&indexed, VGA_PALETTE[4]), 2);
+		assert_eq!(encode_colour(&indexed, VGA_PALETTE[15]), 1);
+		// entries past `len` are never picked
+		assert_eq!(encode_colour(&indexed, [0x00, 0xff, 0x00]), 0);
+		Ok(())
+	}
+	crate::create_test!(test_encode_colour);
+}
@@ -1 +1,123 @@
+pub mod uk105;
 pub mod us104;
+
+use crate::{
+	drivers::keyboard::{
+		layout::{KeyboardLayout, PhysicalKeyboard},
+		layouts::{uk105::Uk105Key, us104::Us104Key},
+		scancode::KeyCode
+	},
+	io::keyboard::decode::{DecodedKey, HandleControl, Modifiers},
+	utils::mutex::SpinMutex
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Keyboard layouts that can be switched between at runtime.
+pub enum Keymap {
+	/// US 104-key (ANSI).
+	Us,
+	/// UK 105-key (ISO).
+	Uk
+}
+
+impl Keymap {
+	/// Every selectable keymap.
+	pub const ALL: [Keymap; 2] = [Keymap::Us, Keymap::Uk];
+
+	/// Name of the keymap, as used by the `keymap` command.
+	pub fn name(self) -> &'static str {
+		match self {
+			Keymap::Us => "us",
+			Keymap::Uk => "uk"
+		}
+	}
+
+	/// Looks up a keymap by name, ignoring case.
+	pub fn from_name(name: &str) -> Option<Keymap> {
+		Self::ALL
+			.into_iter()
+			.find(|keymap| keymap.name().eq_ignore_ascii_case(name))
+	}
+}
+
+/// The keymap used to decode key presses.
+static KEYMAP: SpinMutex<Keymap> = SpinMutex::new(Keymap::Us);
+
+/// Returns the active keymap.
+pub fn keymap() -> Keymap {
+	*KEYMAP.lock()
+}
+
+/// Switches the active keymap. Takes effect on the next key decoded.
+pub fn set_keymap(keymap: Keymap) {
+	*KEYMAP.lock() = keymap;
+}
+
+/// A layout that forwards to whichever `Keymap` is active when a key is
+/// decoded.
+///
+/// Modifier state is kept by the `Keyboard` decoding the keys, not by the
+/// layout, so switching keymaps while shift or caps lock is held keeps them.
+pub struct ActiveLayout;
+
+impl KeyboardLayout for ActiveLayout {
+	fn map_keycode(
+		&self,
+		keycode: KeyCode,
+		modifiers: &Modifiers,
+		handle_ctrl: HandleControl
+	) -> DecodedKey {
+		match keymap() {
+			Keymap::Us => Us104Key.map_keycode(keycode, modifiers, handle_ctrl),
+			Keymap::Uk => Uk105Key.map_keycode(keycode, modifiers, handle_ctrl)
+		}
+	}
+
+	fn get_physical(&self) -> PhysicalKeyboard {
+		match keymap() {
+			Keymap::Us => Us104Key.get_physical(),
+			Keymap::Uk => Uk105Key.get_physical()
+		}
+	}
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{
+		drivers::keyboard::{layouts::*, ps2::Keyboard, scancode::ScancodeSet1},
+		io::keyboard::decode::{DecodedKey, HandleControl},
+		utils::ktest::TestError
+	};
+
+	pub fn test_keymap_switch_keeps_modifiers() -> Result<(), TestError> {
+		const LEFT_SHIFT: u8 = 0x2A;
+		const KEY_2: u8 = 0x03;
+		const KEY_2_RELEASE: u8 = 0x83;
+
+		let previous = keymap();
+		let mut keyboard = Keyboard::new(ScancodeSet1::new(), ActiveLayout, HandleControl::Ignore);
+		let mut press = |scancode: u8| {
+			keyboard
+				.add_byte(scancode)
+				.ok()
+				.flatten()
+				.and_then(|event| keyboard.process_keyevent(event))
+		};
+
+		set_keymap(Keymap::Us);
+		press(LEFT_SHIFT);
+		assert!(matches!(press(KEY_2), Some(DecodedKey::Unicode('@'))));
+		press(KEY_2_RELEASE);
+
+		// shift is still held after the switch
+		set_keymap(Keymap::Uk);
+		let shifted = press(KEY_2);
+		set_keymap(previous);
+		assert!(matches!(shifted, Some(DecodedKey::Unicode('"'))));
+
+		assert_eq!(Keymap::from_name("UK"), Some(Keymap::Uk));
+		assert_eq!(Keymap::from_name("dvorak"), None);
+		Ok(())
+	}
+	crate::create_test!(test_keymap_switch_keeps_modifiers);
+}
@@ -0,0 +1,39 @@
+// code from https://github.com/rust-embedded-community/pc-keyboard
+// license in THIRD_PARTY_LICENSE
+
+use crate::{
+	drivers::keyboard::{
+		layout::{KeyboardLayout, PhysicalKeyboard},
+		layouts::us104::Us104Key,
+		scancode::KeyCode
+	},
+	io::keyboard::decode::{DecodedKey, HandleControl, Modifiers, QUO, SLS}
+};
+
+pub struct Uk105Key;
+
+impl KeyboardLayout for Uk105Key {
+	#[rustfmt::skip]
+	fn map_keycode(
+        &self,
+        keycode: KeyCode,
+        modifiers: &Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        match keycode {
+            KeyCode::Oem8            => modifiers.handle_symbol3('`', '¬', '¦'),
+            KeyCode::Key2            => modifiers.handle_symbol2('2', '"'),
+            KeyCode::Key3            => modifiers.handle_symbol2('3', '£'),
+            KeyCode::Key4            => modifiers.handle_symbol3('4', '$', '€'),
+            KeyCode::Oem3            => modifiers.handle_symbol2(QUO, '@'),
+            KeyCode::Oem7            => modifiers.handle_symbol2('#', '~'),
+            KeyCode::Oem5            => modifiers.handle_symbol2(SLS, '|'),
+            // everything else matches the US layout
+            k                        => Us104Key.map_keycode(k, modifiers, handle_ctrl),
+        }
+    }
+
+	fn get_physical(&self) -> PhysicalKeyboard {
+		PhysicalKeyboard::Iso
+	}
+}
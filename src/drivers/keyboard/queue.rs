@@ -6,42 +6,148 @@
 //! Scancode queue logic and definitions for the kernel.
 //! 
 
-use core::task::Poll;
+use core::{
+	sync::atomic::{AtomicBool, AtomicU64, Ordering},
+	task::Poll
+};
 
 use crossbeam_queue::ArrayQueue;
 use futures::{Stream, task::AtomicWaker};
 
-use crate::{println, utils::oncecell::spin::OnceCell};
+use crate::{
+	println,
+	serial_println,
+	utils::{multiboot2::KernelCmdline, oncecell::spin::OnceCell}
+};
 
-static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+/// Scancodes `ScancodeStream::new` buffers before spilling into the overflow
+/// ring.
+pub const DEFAULT_SCANCODE_QUEUE_CAPACITY: usize = 100;
+
+/// Kernel command line argument setting the scancode queue capacity
+/// (`kbdqueue=256`).
+pub const CAPACITY_ARG: &str = "kbdqueue";
+
+/// Scancodes held back in the overflow ring once the queue is full, before
+/// input is dropped.
+const OVERFLOW_CAPACITY: usize = 512;
+
+/// The queue the keyboard interrupt fills, plus the overflow ring it spills
+/// into while the consumer is behind.
+///
+/// Once anything has spilled, later scancodes go to the overflow ring too
+/// until the consumer has drained it, so input stays in order.
+struct ScancodeBuffer {
+	queue: ArrayQueue<u8>,
+	overflow: ArrayQueue<u8>
+}
+
+impl ScancodeBuffer {
+	fn new(capacity: usize, overflow: usize) -> Self {
+		Self {
+			queue: ArrayQueue::new(capacity),
+			overflow: ArrayQueue::new(overflow)
+		}
+	}
+
+	/// Queues `scancode`, spilling into the overflow ring if the queue is full
+	/// or already spilled. Returns whether it was kept.
+	fn push(&self, scancode: u8) -> bool {
+		if self.overflow.is_empty() && self.queue.push(scancode).is_ok() {
+			return true;
+		}
+		self.overflow.push(scancode).is_ok()
+	}
+
+	/// Pops the oldest scancode. Everything in the queue was pushed before
+	/// anything still in the overflow ring, so it is drained first.
+	fn pop(&self) -> Option<u8> {
+		self.queue.pop().or_else(|| self.overflow.pop())
+	}
+}
+
+static SCANCODE_BUFFER: OnceCell<ScancodeBuffer> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
+/// Scancodes dropped because both rings were full or not set up yet.
+static DROPPED_SCANCODES: AtomicU64 = AtomicU64::new(0);
+/// Whether input was dropped since the last scancode got through, so an
+/// overflow is only logged once.
+static OVERFLOWING: AtomicBool = AtomicBool::new(false);
 
 pub(crate) fn add_scancode(scancode: u8) {
-	if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-		if queue.push(scancode).is_err() {
-			println!(
-				"WARNING: scancode queue full; dropping keyboard input {}",
-				scancode
-			);
-		} else {
-			WAKER.wake();
-		}
-	} else {
+	let Ok(buffer) = SCANCODE_BUFFER.try_get() else {
+		DROPPED_SCANCODES.fetch_add(1, Ordering::Relaxed);
 		println!("WARNING: scancode queue uninitialized");
+		return;
+	};
+
+	if push_and_wake(buffer, scancode) {
+		OVERFLOWING.store(false, Ordering::Relaxed);
+	} else {
+		DROPPED_SCANCODES.fetch_add(1, Ordering::Relaxed);
+		// logging every drop would only add to the load causing them
+		if !OVERFLOWING.swap(true, Ordering::Relaxed) {
+			serial_println!("WARNING: scancode queue full; dropping keyboard input");
+		}
 	}
 }
 
+/// Queues `scancode` and wakes the consumer, which is woken even if the
+/// scancode was dropped so it drains the backlog. Returns whether it was
+/// kept.
+fn push_and_wake(buffer: &ScancodeBuffer, scancode: u8) -> bool {
+	let pushed = buffer.push(scancode);
+	WAKER.wake();
+	pushed
+}
+
+/// Counts a scancode dropped on its way to another queue, such as a full
+/// foreground process queue, so `dropped_scancodes` covers every drop.
+pub(crate) fn count_dropped_scancode() {
+	DROPPED_SCANCODES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns how many scancodes have been dropped since boot.
+pub fn dropped_scancodes() -> u64 {
+	DROPPED_SCANCODES.load(Ordering::Relaxed)
+}
+
+/// Returns the scancode queue capacity asked for by the `kbdqueue=` kernel
+/// command line argument, or `DEFAULT_SCANCODE_QUEUE_CAPACITY`.
+pub fn capacity_from_cmdline(cmdline: &KernelCmdline) -> usize {
+	cmdline
+		.get(CAPACITY_ARG)
+		.and_then(|value| value.parse().ok())
+		.filter(|capacity| *capacity > 0)
+		.unwrap_or(DEFAULT_SCANCODE_QUEUE_CAPACITY)
+}
+
+/// Pops a scancode off the queue without going through `ScancodeStream`.
+///
+/// Only meant for commands that take over the keyboard while the shell task
+/// is blocked running them.
+pub(crate) fn pop_scancode() -> Option<u8> {
+	SCANCODE_BUFFER.try_get().ok()?.pop()
+}
+
 /// A stream of all scancodes coming in from interrupts.
 pub struct ScancodeStream {
 	_private: ()
 }
 
 impl ScancodeStream {
-	/// Creates a new `ScancodeStream` with a capacity of 100.
+	/// Creates a new `ScancodeStream` with a capacity of
+	/// `DEFAULT_SCANCODE_QUEUE_CAPACITY`.
 	pub fn new() -> ScancodeStream {
-		SCANCODE_QUEUE
-			.try_init_once(|| ArrayQueue::new(100))
-			.expect("ScancodeStream::new should only be called once");
+		Self::with_capacity(DEFAULT_SCANCODE_QUEUE_CAPACITY)
+	}
+
+	/// Creates a new `ScancodeStream` buffering up to `capacity` scancodes
+	/// before spilling into the overflow ring.
+	pub fn with_capacity(capacity: usize) -> ScancodeStream {
+		SCANCODE_BUFFER
+			.try_init_once(|| ScancodeBuffer::new(capacity, OVERFLOW_CAPACITY))
+			.expect("ScancodeStream should only be created once");
 
 		Self {
 			_private: ()
@@ -62,17 +168,17 @@ impl Stream for ScancodeStream {
 		self: core::pin::Pin<&mut Self>,
 		cx: &mut core::task::Context<'_>
 	) -> core::task::Poll<Option<Self::Item>> {
-		let queue = SCANCODE_QUEUE
+		let buffer = SCANCODE_BUFFER
 			.try_get()
-			.expect("SCANCODE_QUEUE not initialized");
+			.expect("SCANCODE_BUFFER not initialized");
 
-		if let Some(scancode) = queue.pop() {
+		if let Some(scancode) = buffer.pop() {
 			return Poll::Ready(Some(scancode));
 		}
 
 		WAKER.register(cx.waker());
 
-		match queue.pop() {
+		match buffer.pop() {
 			Some(c) => {
 				WAKER.take();
 				Poll::Ready(Some(c))
@@ -81,3 +187,40 @@ impl Stream for ScancodeStream {
 		}
 	}
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{
+		drivers::keyboard::queue::*,
+		utils::{ktest::TestError, multiboot2::KernelCmdline}
+	};
+
+	pub fn test_scancode_overflow_spills_in_order() -> Result<(), TestError> {
+		let buffer = ScancodeBuffer::new(2, 2);
+		assert!(push_and_wake(&buffer, 0x1E));
+		assert!(push_and_wake(&buffer, 0x30));
+		assert!(push_and_wake(&buffer, 0x2E));
+		assert_eq!(buffer.pop(), Some(0x1E));
+		// the queue has room again, but 0x2E is still waiting in the overflow
+		assert!(push_and_wake(&buffer, 0x20));
+		assert!(!push_and_wake(&buffer, 0x12));
+
+		assert_eq!(buffer.pop(), Some(0x30));
+		assert_eq!(buffer.pop(), Some(0x2E));
+		assert_eq!(buffer.pop(), Some(0x20));
+		assert_eq!(buffer.pop(), None);
+		Ok(())
+	}
+	crate::create_test!(test_scancode_overflow_spills_in_order);
+
+	pub fn test_capacity_from_cmdline() -> Result<(), TestError> {
+		let cmdline = KernelCmdline::new(b"quiet kbdqueue=256");
+		assert_eq!(capacity_from_cmdline(&cmdline), 256);
+		let cmdline = KernelCmdline::new(b"kbdqueue=0");
+		assert_eq!(capacity_from_cmdline(&cmdline), DEFAULT_SCANCODE_QUEUE_CAPACITY);
+		let cmdline = KernelCmdline::empty();
+		assert_eq!(capacity_from_cmdline(&cmdline), DEFAULT_SCANCODE_QUEUE_CAPACITY);
+		Ok(())
+	}
+	crate::create_test!(test_capacity_from_cmdline);
+}
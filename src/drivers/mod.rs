@@ -4,6 +4,8 @@
 //! Driver module declaration.
 //! 
 
+pub mod framebuffer;
 pub mod keyboard;
+pub mod mouse;
 #[allow(unused)]
 pub mod virtio;
@@ -0,0 +1,7 @@
+//!
+//! drivers/mouse/mod.rs
+//! 
+//! Mouse module declaration.
+//! 
+
+pub mod ps2;
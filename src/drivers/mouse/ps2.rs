@@ -0,0 +1,323 @@
+//!
+//! drivers/mouse/ps2.rs
+//!
+//! PS/2 mouse on the auxiliary port of the 8042 controller. Movement arrives
+//! on IRQ 12 as 3-byte packets, which are decoded into `MouseState`s and
+//! queued for `MouseStream`.
+//!
+
+use core::task::Poll;
+
+use crossbeam_queue::ArrayQueue;
+use futures::{Stream, task::AtomicWaker};
+use x86_64::instructions::interrupts;
+
+use crate::{
+	common::ports::{inb, outb},
+	error::NullexError,
+	ioapic::IOAPIC,
+	serial_println,
+	utils::{mutex::SpinMutex, oncecell::spin::OnceCell}
+};
+
+/// Data port of the 8042 controller, shared with the keyboard.
+const DATA_PORT: u16 = 0x60;
+/// Status register when read, command register when written.
+const COMMAND_PORT: u16 = 0x64;
+
+/// Status bit: a byte is waiting in the data port.
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// Status bit: the controller hasn't taken the last byte written yet.
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// Status bit: the waiting byte came from the auxiliary device.
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_ENABLE_AUX: u8 = 0xa8;
+/// Sends the next data byte to the mouse instead of the keyboard.
+const CMD_WRITE_AUX: u8 = 0xd4;
+
+/// Config byte bit: raise IRQ 12 for auxiliary data.
+const CONFIG_AUX_IRQ: u8 = 1 << 1;
+/// Config byte bit: auxiliary clock disabled.
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+
+const MOUSE_SET_DEFAULTS: u8 = 0xf6;
+const MOUSE_SET_SAMPLE_RATE: u8 = 0xf3;
+const MOUSE_ENABLE_REPORTING: u8 = 0xf4;
+const MOUSE_ACK: u8 = 0xfa;
+
+/// Packets per second the mouse is asked to report at most.
+const SAMPLE_RATE: u8 = 100;
+/// Status polls before giving up on the controller.
+const CONTROLLER_TIMEOUT: usize = 100_000;
+/// IRQ line of the PS/2 mouse on the IOAPIC.
+pub const MOUSE_IRQ: u8 = 12;
+/// Capacity of the mouse event queue.
+const MOUSE_QUEUE_CAPACITY: usize = 128;
+
+const PACKET_LEFT: u8 = 1 << 0;
+const PACKET_RIGHT: u8 = 1 << 1;
+const PACKET_MIDDLE: u8 = 1 << 2;
+/// First packet byte: always set, used to find packet boundaries.
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+static MOUSE_QUEUE: OnceCell<ArrayQueue<MouseState>> = OnceCell::uninit();
+static MOUSE_WAKER: AtomicWaker = AtomicWaker::new();
+/// Packet being put together by the interrupt handler.
+static DECODER: SpinMutex<PacketDecoder> = SpinMutex::new(PacketDecoder::new());
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// Movement and buttons reported by one mouse packet.
+pub struct MouseState {
+	/// Movement to the right since the last packet.
+	pub dx: i16,
+	/// Movement up since the last packet.
+	pub dy: i16,
+	/// Whether the left button is held.
+	pub left: bool,
+	/// Whether the right button is held.
+	pub right: bool,
+	/// Whether the middle button is held.
+	pub middle: bool
+}
+
+/// Puts the bytes coming from the mouse back together into packets.
+#[derive(Debug, Default)]
+pub struct PacketDecoder {
+	bytes: [u8; 3],
+	len: usize
+}
+
+impl PacketDecoder {
+	/// Creates a decoder waiting for the first byte of a packet.
+	pub const fn new() -> Self {
+		Self {
+			bytes: [0; 3],
+			len: 0
+		}
+	}
+
+	/// Adds the next byte from the mouse. Returns the decoded state once a
+	/// whole packet has arrived.
+	pub fn add_byte(&mut self, byte: u8) -> Option<MouseState> {
+		// a first byte without the always-set bit means we're out of step,
+		// so wait for one that has it
+		if self.len == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+			return None;
+		}
+
+		self.bytes[self.len] = byte;
+		self.len += 1;
+		if self.len < self.bytes.len() {
+			return None;
+		}
+		self.len = 0;
+		Some(Self::decode(self.bytes))
+	}
+
+	fn decode([flags, x, y]: [u8; 3]) -> MouseState {
+		// the movement is 9-bit two's complement with the sign in `flags`
+		let delta = |value: u8, sign: u8, overflow: u8| -> i16 {
+			if flags & overflow != 0 {
+				return 0;
+			}
+			if flags & sign != 0 {
+				value as i16 - 0x100
+			} else {
+				value as i16
+			}
+		};
+
+		MouseState {
+			dx: delta(x, PACKET_X_SIGN, PACKET_X_OVERFLOW),
+			dy: delta(y, PACKET_Y_SIGN, PACKET_Y_OVERFLOW),
+			left: flags & PACKET_LEFT != 0,
+			right: flags & PACKET_RIGHT != 0,
+			middle: flags & PACKET_MIDDLE != 0
+		}
+	}
+}
+
+/// Waits until the controller will take another byte.
+fn wait_for_input_empty() -> Result<(), NullexError> {
+	for _ in 0..CONTROLLER_TIMEOUT {
+		if unsafe { inb(COMMAND_PORT) } & STATUS_INPUT_FULL == 0 {
+			return Ok(());
+		}
+		core::hint::spin_loop();
+	}
+	Err(NullexError::Timeout)
+}
+
+/// Waits for a byte in the data port and reads it.
+fn read_data() -> Result<u8, NullexError> {
+	for _ in 0..CONTROLLER_TIMEOUT {
+		if unsafe { inb(COMMAND_PORT) } & STATUS_OUTPUT_FULL != 0 {
+			return Ok(unsafe { inb(DATA_PORT) });
+		}
+		core::hint::spin_loop();
+	}
+	Err(NullexError::Timeout)
+}
+
+fn write_command(command: u8) -> Result<(), NullexError> {
+	wait_for_input_empty()?;
+	unsafe { outb(COMMAND_PORT, command) };
+	Ok(())
+}
+
+fn write_data(data: u8) -> Result<(), NullexError> {
+	wait_for_input_empty()?;
+	unsafe { outb(DATA_PORT, data) };
+	Ok(())
+}
+
+/// Sends `byte` to the mouse and waits for it to be acknowledged.
+fn write_mouse(byte: u8) -> Result<(), NullexError> {
+	write_command(CMD_WRITE_AUX)?;
+	write_data(byte)?;
+	match read_data()? {
+		MOUSE_ACK => Ok(()),
+		_ => Err(NullexError::InitFailed("PS/2 mouse did not acknowledge a command"))
+	}
+}
+
+fn init_controller() -> Result<(), NullexError> {
+	write_command(CMD_ENABLE_AUX)?;
+
+	write_command(CMD_READ_CONFIG)?;
+	let config = read_data()?;
+	write_command(CMD_WRITE_CONFIG)?;
+	write_data((config | CONFIG_AUX_IRQ) & !CONFIG_AUX_CLOCK_DISABLED)?;
+
+	write_mouse(MOUSE_SET_DEFAULTS)?;
+	write_mouse(MOUSE_SET_SAMPLE_RATE)?;
+	write_mouse(SAMPLE_RATE)?;
+	write_mouse(MOUSE_ENABLE_REPORTING)
+}
+
+/// Enables the mouse on the 8042's auxiliary port and unmasks IRQ 12.
+/// Packets then arrive through the mouse interrupt handler and are read by
+/// `MouseStream`.
+pub fn init() -> Result<(), NullexError> {
+	let _ = MOUSE_QUEUE.try_init_once(|| ArrayQueue::new(MOUSE_QUEUE_CAPACITY));
+
+	// the keyboard handler would eat the replies if it ran in between
+	interrupts::without_interrupts(|| -> Result<(), NullexError> {
+		init_controller()?;
+		unsafe { IOAPIC.lock().enable_irq(MOUSE_IRQ) };
+		Ok(())
+	})?;
+
+	serial_println!("[MOUSE] PS/2 mouse enabled on IRQ {}", MOUSE_IRQ);
+	Ok(())
+}
+
+/// Reads the byte the mouse sent. Called by the mouse interrupt handler.
+pub(crate) fn handle_interrupt() {
+	let status = unsafe { inb(COMMAND_PORT) };
+	if status & (STATUS_OUTPUT_FULL | STATUS_AUX_DATA) != STATUS_OUTPUT_FULL | STATUS_AUX_DATA {
+		return;
+	}
+	let byte = unsafe { inb(DATA_PORT) };
+
+	let Some(state) = DECODER.lock().add_byte(byte) else {
+		return;
+	};
+	if let Ok(queue) = MOUSE_QUEUE.try_get() {
+		// a full queue drops the newest movement, which is harmless
+		if queue.push(state).is_ok() {
+			MOUSE_WAKER.wake();
+		}
+	}
+}
+
+/// A stream of the `MouseState`s decoded from mouse packets. `init` must
+/// have run first.
+pub struct MouseStream {
+	_private: ()
+}
+
+impl MouseStream {
+	/// Creates a new `MouseStream`.
+	pub fn new() -> MouseStream {
+		Self {
+			_private: ()
+		}
+	}
+}
+
+impl Default for MouseStream {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Stream for MouseStream {
+	type Item = MouseState;
+
+	fn poll_next(
+		self: core::pin::Pin<&mut Self>,
+		cx: &mut core::task::Context<'_>
+	) -> core::task::Poll<Option<Self::Item>> {
+		let queue = MOUSE_QUEUE
+			.try_get()
+			.expect("MOUSE_QUEUE not initialized");
+
+		if let Some(state) = queue.pop() {
+			return Poll::Ready(Some(state));
+		}
+
+		MOUSE_WAKER.register(cx.waker());
+
+		match queue.pop() {
+			Some(state) => {
+				MOUSE_WAKER.take();
+				Poll::Ready(Some(state))
+			}
+			None => Poll::Pending
+		}
+	}
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{drivers::mouse::ps2::*, utils::ktest::TestError};
+
+	pub fn test_packet_decoder() -> Result<(), TestError> {
+		let mut decoder = PacketDecoder::new();
+
+		// bytes before a valid first byte are skipped
+		assert_eq!(decoder.add_byte(0x00), None);
+
+		// left button, moved right 5 and down 3
+		assert_eq!(decoder.add_byte(0x29), None);
+		assert_eq!(decoder.add_byte(5), None);
+		let state = decoder.add_byte(0xfd).ok_or(TestError::Error)?;
+		assert_eq!(
+			state,
+			MouseState {
+				dx: 5,
+				dy: -3,
+				left: true,
+				right: false,
+				middle: false
+			}
+		);
+
+		// overflowed movement is dropped, buttons are kept
+		decoder.add_byte(0x5e);
+		decoder.add_byte(0xff);
+		let state = decoder.add_byte(0x10).ok_or(TestError::Error)?;
+		assert_eq!((state.dx, state.dy), (0, 0x10));
+		assert!(state.right && state.middle && !state.left);
+		Ok(())
+	}
+	crate::create_test!(test_packet_decoder);
+}
@@ -0,0 +1,506 @@
+//!
+//! blk.rs
+//!
+//! VirtIO Block Driver Specification based module for the kernel.
+//!
+
+use core::ptr::{read_volatile, write_bytes, write_volatile};
+
+use crate::{
+	drivers::virtio::{
+		VirtIODeviceStatus,
+		VirtQueue,
+		VirtioDevice,
+		transport::{VIRTIO_F_VERSION_1, VirtioTransport}
+	},
+	error::NullexError,
+	fs::block_cache::{BlockDevice, SECTOR_SIZE},
+	io::pci::{DriverInfo, PciDevice, VIRTIO_PCI_VENDOR_ID, pci_enable_device, register_driver},
+	lazy_static,
+	memory::{DmaBuffer, dma_alloc},
+	serial_println,
+	tsc,
+	utils::mutex::SpinMutex
+};
+
+lazy_static! {
+	/// Static reference to the VirtIO block instance.
+	pub static ref VIRTIO_BLK_INSTANCE: SpinMutex<Option<VirtioBlk>> = SpinMutex::new(None);
+}
+
+/// PCI device IDs of virtio block devices: transitional, then modern.
+const VIRTIO_BLK_PCI_DEVICE_IDS: [u16; 2] = [0x1001, 0x1042];
+
+/// Device is read-only.
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+
+const BLK_DRIVER_SUPPORTED_FEATURES: u64 = VIRTIO_BLK_F_RO;
+
+// request types
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+
+// request status values
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+/// Most sectors moved by a single request. Longer transfers are split.
+const MAX_REQUEST_SECTORS: usize = 8;
+/// How long a request may take before it's given up on.
+const REQUEST_TIMEOUT_MS: u64 = 5000;
+
+#[repr(C)]
+/// The header in front of every block request.
+struct VirtioBlkReqHeader {
+	req_type: u32,
+	reserved: u32,
+	sector: u64
+}
+
+// sanity
+const _: () = assert!(core::mem::size_of::<VirtioBlkReqHeader>() == 16);
+
+/// Structure representing the Virtio Block device.
+pub struct VirtioBlk {
+	/// The transport the device is driven through.
+	pub transport: VirtioTransport,
+	/// Size of the disk in 512-byte sectors.
+	pub capacity: u64,
+	/// All features that are currently active on the device.
+	pub negotiated_features: u64,
+	/// The request queue for the device.
+	pub request_queue: Option<VirtQueue>,
+	status: Option<u16>,
+	/// Holds the request header, followed by the status byte.
+	request: Option<DmaBuffer>,
+	/// Bounce buffer the sector data moves through.
+	data: Option<DmaBuffer>,
+	/// How long a request may take before the device is given up on.
+	timeout_ms: u64,
+	/// Set once a request timed out. The device may still complete it into
+	/// `request` and `data`, so nothing is submitted after that.
+	failed: bool
+}
+
+impl VirtioBlk {
+	/// Creates a new `VirtioBlk` device driven through `transport`.
+	pub fn new(transport: VirtioTransport) -> VirtioBlk {
+		Self {
+			transport,
+			capacity: 0,
+			negotiated_features: 0,
+			request_queue: None,
+			status: None,
+			request: None,
+			data: None,
+			timeout_ms: REQUEST_TIMEOUT_MS,
+			failed: false
+		}
+	}
+
+	/// Features this driver can use. The modern transport also requires
+	/// accepting `VIRTIO_F_VERSION_1`.
+	fn driver_features(&self) -> u64 {
+		if self.transport.is_modern() {
+			BLK_DRIVER_SUPPORTED_FEATURES | VIRTIO_F_VERSION_1
+		} else {
+			BLK_DRIVER_SUPPORTED_FEATURES
+		}
+	}
+
+	/// Returns whether the device refuses writes.
+	pub fn is_read_only(&self) -> bool {
+		self.negotiated_features & VIRTIO_BLK_F_RO != 0
+	}
+
+	/// Reads `buf.len() / 512` sectors starting at `lba` into `buf`.
+	pub fn read(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), NullexError> {
+		if self.failed {
+			return Err(NullexError::VirtioBlkFailed);
+		}
+		self.check_range(lba, buf.len())?;
+		for (i, chunk) in buf.chunks_mut(MAX_REQUEST_SECTORS * SECTOR_SIZE).enumerate() {
+			let start = lba + (i * MAX_REQUEST_SECTORS) as u64;
+			let data = self.submit(VIRTIO_BLK_T_IN, start, chunk.len())?;
+			unsafe {
+				let src = data.virt.as_ptr::<u8>();
+				core::ptr::copy_nonoverlapping(src, chunk.as_mut_ptr(), chunk.len());
+			}
+		}
+		Ok(())
+	}
+
+	/// Writes `buf`, a whole number of sectors, starting at `lba`.
+	pub fn write(&mut self, lba: u64, buf: &[u8]) -> Result<(), NullexError> {
+		if self.failed {
+			return Err(NullexError::VirtioBlkFailed);
+		}
+		if self.is_read_only() {
+			return Err(NullexError::VirtioBlkReadOnly);
+		}
+		self.check_range(lba, buf.len())?;
+		for (i, chunk) in buf.chunks(MAX_REQUEST_SECTORS * SECTOR_SIZE).enumerate() {
+			let start = lba + (i * MAX_REQUEST_SECTORS) as u64;
+			let data = self.data.as_ref().ok_or(NullexError::VirtQueueUnavailable)?;
+			unsafe {
+				let dst = data.virt.as_mut_ptr::<u8>();
+				core::ptr::copy_nonoverlapping(chunk.as_ptr(), dst, chunk.len());
+			}
+			self.submit(VIRTIO_BLK_T_OUT, start, chunk.len())?;
+		}
+		Ok(())
+	}
+
+	/// Checks a transfer of `len` bytes at `lba` fits the disk, returning its
+	/// length in sectors.
+	fn check_range(&self, lba: u64, len: usize) -> Result<u64, NullexError> {
+		if len == 0 || !len.is_multiple_of(SECTOR_SIZE) {
+			return Err(NullexError::InvalidArgument);
+		}
+		let sectors = (len / SECTOR_SIZE) as u64;
+		match lba.checked_add(sectors) {
+			Some(end) if end <= self.capacity => Ok(sectors),
+			_ => Err(NullexError::InvalidArgument)
+		}
+	}
+
+	/// Submits one request moving `len` bytes through the data buffer and
+	/// waits for the device to complete it.
+	fn submit(
+		&mut self,
+		req_type: u32,
+		sector: u64,
+		len: usize
+	) -> Result<&DmaBuffer, NullexError> {
+		let (Some(queue), Some(request), Some(data)) =
+			(self.request_queue.as_mut(), self.request.as_ref(), self.data.as_ref())
+		else {
+			return Err(NullexError::VirtQueueUnavailable);
+		};
+
+		let header_len = core::mem::size_of::<VirtioBlkReqHeader>();
+		let status_ptr = unsafe { request.virt.as_mut_ptr::<u8>().add(header_len) };
+		unsafe {
+			write_volatile(
+				request.virt.as_mut_ptr::<VirtioBlkReqHeader>(),
+				VirtioBlkReqHeader { req_type, reserved: 0, sector }
+			);
+			// anything but OK until the device says otherwise
+			write_volatile(status_ptr, 0xFF);
+		}
+
+		let head = queue.add_chain(&[
+			(request.phys, header_len as u32, false),
+			(data.phys, len as u32, req_type == VIRTIO_BLK_T_IN),
+			(request.phys + header_len as u64, 1, true)
+		])?;
+		queue.push_avail(head);
+		queue.kick();
+
+		// timed with the TSC, since the instance lock keeps interrupts
+		// disabled and the tick count still
+		let deadline = tsc::now_ns() + self.timeout_ms * 1_000_000;
+		loop {
+			match queue.pop_used() {
+				Some((id, _)) if id == head => break,
+				// only one request is ever in flight, so this isn't ours
+				Some((id, _)) => {
+					serial_println!("[VIRTIO-BLK] Ignoring completion of unknown request {}", id);
+				}
+				None if tsc::now_ns() >= deadline => {
+					serial_println!("[VIRTIO-BLK] Request for sector {} timed out", sector);
+					// its chain and buffers stay with the device, which may
+					// still complete it, so the device isn't used again. One
+					// that was never started has nothing to be told
+					self.failed = true;
+					if self.status.is_some() {
+						let failed = VirtIODeviceStatus::FAILED.bits();
+						self.status = Some(failed as u16);
+						self.transport.set_status(failed);
+					}
+					return Err(NullexError::VirtioBlkTimeout);
+				}
+				None => core::hint::spin_loop()
+			}
+		}
+		queue.free_chain(head);
+
+		match unsafe { read_volatile(status_ptr) } {
+			VIRTIO_BLK_S_OK => Ok(data),
+			VIRTIO_BLK_S_UNSUPP => Err(NullexError::VirtioBlkUnsupported),
+			// VIRTIO_BLK_S_IOERR, or a status the device never wrote
+			_ => Err(NullexError::VirtioBlkIoError)
+		}
+	}
+}
+
+impl VirtioDevice for VirtioBlk {
+	fn alloc_virtqueue(&mut self, qidx: u16) -> Result<VirtQueue, NullexError> {
+		VirtQueue::new(&self.transport, qidx)
+	}
+
+	fn device_features(&mut self) -> u64 {
+		if self.negotiated_features == 0 {
+			self.transport.device_features()
+		} else {
+			self.negotiated_features
+		}
+	}
+
+	fn set_driver_features(&mut self, features: u64) {
+		self.negotiated_features = features;
+		self.transport.set_driver_features(features);
+	}
+
+	fn driver_status(&mut self) -> u16 {
+		if let Some(cur_status) = self.status {
+			cur_status
+		} else {
+			let status = self.transport.status();
+			self.set_driver_status(status);
+			status as u16
+		}
+	}
+
+	fn set_driver_status(&mut self, status: u8) {
+		let new_status: u16 = match self.status {
+			Some(current) => {
+				if status == VirtIODeviceStatus::FAILED.bits() {
+					status as u16
+				} else {
+					current | (status as u16)
+				}
+			}
+			None => status as u16
+		};
+		self.status = Some(new_status);
+		self.transport.set_status(new_status as u8);
+	}
+
+	fn has_status(&mut self, status: u8) -> bool {
+		(self.driver_status() & (status as u16)) != 0
+	}
+
+	fn supported_features(&mut self) -> u64 {
+		self.negotiated_features
+	}
+
+	fn init(&mut self) -> Result<(), NullexError> {
+		let mut queue = self.alloc_virtqueue(0)?;
+		// requests are polled, so the device never needs to interrupt
+		queue.suppress_interrupts();
+		self.request_queue = Some(queue);
+
+		let request_len = core::mem::size_of::<VirtioBlkReqHeader>() + 1;
+		let (virt, phys) = dma_alloc(request_len)?;
+		self.request = Some(DmaBuffer { phys, virt, len: request_len });
+
+		let data_len = MAX_REQUEST_SECTORS * SECTOR_SIZE;
+		let (virt, phys) = dma_alloc(data_len)?;
+		unsafe { write_bytes(virt.as_mut_ptr::<u8>(), 0, data_len) };
+		self.data = Some(DmaBuffer { phys, virt, len: data_len });
+
+		// capacity is the first field of the config space, little endian
+		let capacity = (0..8).fold(0u64, |acc, i| {
+			acc | (self.transport.read_config(i) as u64) << (i * 8)
+		});
+		self.capacity = capacity;
+
+		serial_println!(
+			"[VIRTIO-BLK] Device initialized ({} sectors, read-only: {})",
+			capacity,
+			self.is_read_only()
+		);
+		Ok(())
+	}
+}
+
+/// A `BlockDevice` backed by the probed virtio block device, so it can sit
+/// behind a `BlockCache`.
+pub struct VirtioBlkDisk;
+
+impl BlockDevice for VirtioBlkDisk {
+	fn read_sector(&mut self, lba: u64, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), NullexError> {
+		virtio_blk_read(lba, buf)
+	}
+
+	fn write_sector(&mut self, lba: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), NullexError> {
+		virtio_blk_write(lba, buf)
+	}
+}
+
+/// Returns whether a virtio block device was probed.
+pub fn virtio_blk_present() -> bool {
+	VIRTIO_BLK_INSTANCE.lock().is_some()
+}
+
+/// Returns the size of the virtio disk in sectors.
+pub fn virtio_blk_capacity() -> Option<u64> {
+	VIRTIO_BLK_INSTANCE.lock().as_ref().map(|blk| blk.capacity)
+}
+
+/// Reads sectors starting at `lba` from the virtio disk into `buf`, whose
+/// length must be a multiple of 512.
+pub fn virtio_blk_read(lba: u64, buf: &mut [u8]) -> Result<(), NullexError> {
+	VIRTIO_BLK_INSTANCE
+		.lock()
+		.as_mut()
+		.ok_or(NullexError::MissingVirtIOInstance)?
+		.read(lba, buf)
+}
+
+/// Writes `buf`, whose length must be a multiple of 512, to the virtio disk
+/// starting at `lba`.
+pub fn virtio_blk_write(lba: u64, buf: &[u8]) -> Result<(), NullexError> {
+	VIRTIO_BLK_INSTANCE
+		.lock()
+		.as_mut()
+		.ok_or(NullexError::MissingVirtIOInstance)?
+		.write(lba, buf)
+}
+
+/// Initialize the Virtio Block driver.
+pub fn virtio_blk_driver_init() {
+	serial_println!("[VIRTIO-BLK] Registering driver");
+	for device in VIRTIO_BLK_PCI_DEVICE_IDS {
+		register_driver(DriverInfo {
+			vendor: Some(VIRTIO_PCI_VENDOR_ID),
+			device: Some(device),
+			class: None,
+			subclass: None,
+			probe: Some(virtio_blk_probe)
+		});
+	}
+}
+
+/// Probe the virtio block device.
+pub fn virtio_blk_probe(dev: &mut PciDevice) -> Result<usize, NullexError> {
+	serial_println!("[VIRTIO-BLK] Probing device {:?}", dev.bdf);
+
+	// a modern-only device has no I/O BAR for this to enable
+	if let Err(e) = pci_enable_device(dev) {
+		serial_println!("[VIRTIO-BLK] Failed to enable legacy BAR: {:?}", e);
+	}
+	let transport = VirtioTransport::detect(dev.bdf, dev.io_base)?;
+	let mut virtio_blk = VirtioBlk::new(transport);
+
+	virtio_blk.set_driver_status(0);
+	virtio_blk.set_driver_status(
+		VirtIODeviceStatus::ACKNOWLEDGE
+			.union(VirtIODeviceStatus::DRIVER)
+			.bits()
+	);
+
+	let features = virtio_blk.device_features() & virtio_blk.driver_features();
+	virtio_blk.set_driver_features(features);
+	virtio_blk.set_driver_status(VirtIODeviceStatus::FEATURES_OK.bits());
+
+	if !virtio_blk.has_status(VirtIODeviceStatus::FEATURES_OK.bits()) {
+		virtio_blk.set_driver_status(VirtIODeviceStatus::FAILED.bits());
+		return Err(NullexError::DeviceRejectedFeatures);
+	}
+
+	if let Err(e) = virtio_blk.init() {
+		virtio_blk.set_driver_status(VirtIODeviceStatus::FAILED.bits());
+		return Err(e);
+	}
+	virtio_blk.set_driver_status(VirtIODeviceStatus::DRIVER_OK.bits());
+
+	if virtio_blk.transport.status() & VirtIODeviceStatus::DRIVER_OK.bits() == 0 {
+		return Err(NullexError::DriverNotOk);
+	}
+
+	*VIRTIO_BLK_INSTANCE.lock() = Some(virtio_blk);
+	serial_println!("[VIRTIO-BLK] Probe complete");
+	Ok(0)
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::vec;
+
+	use x86_64::instructions::interrupts;
+
+	use crate::{
+		drivers::virtio::{
+			VirtqueueAvailable,
+			VirtqueueDescriptor,
+			VirtqueueUsed,
+			blk::*,
+			transport::VirtioTransport
+		},
+		error::NullexError,
+		memory::dma_free,
+		utils::ktest::TestError
+	};
+
+	pub fn test_virtio_blk_request_validation() -> Result<(), TestError> {
+		// nothing reaches the device before init, so no transport is needed
+		let mut blk = VirtioBlk::new(VirtioTransport::Legacy(0));
+		blk.capacity = 16;
+
+		assert_eq!(blk.check_range(0, 512), Ok(1));
+		assert_eq!(blk.check_range(8, 8 * 512), Ok(8));
+		assert_eq!(blk.check_range(15, 1024), Err(NullexError::InvalidArgument));
+		assert_eq!(blk.check_range(0, 100), Err(NullexError::InvalidArgument));
+		assert_eq!(blk.check_range(u64::MAX, 512), Err(NullexError::InvalidArgument));
+
+		let mut buf = [0u8; 512];
+		assert_eq!(blk.read(0, &mut buf), Err(NullexError::VirtQueueUnavailable));
+
+		blk.negotiated_features = VIRTIO_BLK_F_RO;
+		assert_eq!(blk.write(0, &buf), Err(NullexError::VirtioBlkReadOnly));
+		Ok(())
+	}
+	crate::create_test!(test_virtio_blk_request_validation);
+
+	pub fn test_virtio_blk_timeout_fails_device() -> Result<(), TestError> {
+		// a queue no device ever completes
+		let mut descs = vec![VirtqueueDescriptor { addr: 0, len: 0, flags: 0, next: 0 }; 4];
+		let mut avail = [0u16; 2 + 4];
+		let mut used = VirtqueueUsed::default();
+		let mut queue = VirtQueue::empty();
+		queue.size = 4;
+		queue.desc = descs.as_mut_ptr();
+		queue.avail = avail.as_mut_ptr() as *mut VirtqueueAvailable;
+		queue.used = &mut used;
+		queue.init_free_list();
+
+		let mut blk = VirtioBlk::new(VirtioTransport::Legacy(0));
+		blk.capacity = 16;
+		blk.timeout_ms = 1;
+		blk.request_queue = Some(queue);
+		// the allocator's locks would leave interrupts disabled, and the
+		// timeout needs the clock moving
+		let request_len = core::mem::size_of::<VirtioBlkReqHeader>() + 1;
+		let (virt, phys) = interrupts::without_interrupts(|| dma_alloc(request_len))
+			.map_err(|_| TestError::Error)?;
+		blk.request = Some(DmaBuffer { phys, virt, len: request_len });
+		let data_len = MAX_REQUEST_SECTORS * SECTOR_SIZE;
+		let (virt, phys) = interrupts::without_interrupts(|| dma_alloc(data_len))
+			.map_err(|_| TestError::Error)?;
+		blk.data = Some(DmaBuffer { phys, virt, len: data_len });
+
+		let mut buf = [0xAAu8; 512];
+		assert_eq!(blk.read(0, &mut buf), Err(NullexError::VirtioBlkTimeout));
+
+		// the timed out request still owns the buffers, so the next one must
+		// not be submitted, nor touch the caller's data
+		assert_eq!(blk.read(1, &mut buf), Err(NullexError::VirtioBlkFailed));
+		assert_eq!(blk.write(1, &buf), Err(NullexError::VirtioBlkFailed));
+		assert!(buf.iter().all(|&b| b == 0xAA));
+
+		// the queue points into this stack frame
+		blk.request_queue = None;
+		// only the first request reached the queue
+		assert_eq!(avail[1], 1);
+		for buffer in [blk.request.take(), blk.data.take()].into_iter().flatten() {
+			interrupts::without_interrupts(|| unsafe {
+				dma_free(buffer.virt, buffer.phys, buffer.len)
+			})
+			.map_err(|_| TestError::Error)?;
+		}
+		Ok(())
+	}
+	crate::create_test!(test_virtio_blk_timeout_fails_device);
+}
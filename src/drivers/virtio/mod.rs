@@ -6,15 +6,23 @@
 
 #[allow(unused)]
 pub mod net;
+pub mod blk;
+pub mod transport;
 
 use core::{
-	ptr::null_mut,
+	ptr::{null_mut, write_bytes},
 	sync::atomic::{Ordering, fence}
 };
 
 use x86_64::{PhysAddr, VirtAddr, align_up};
 
-use crate::{bitflags, common::ports::outw, ensure, error::NullexError};
+use crate::{
+	bitflags,
+	drivers::virtio::transport::{QueueNotify, VirtioTransport},
+	ensure,
+	error::NullexError,
+	memory::dma_alloc
+};
 
 const VIRTIO_IO_DEVICE_FEATURES: usize = 0x00;
 const VIRTIO_IO_DRIVER_FEATURES: usize = 0x04;
@@ -30,6 +38,7 @@ const VIRTIO_IO_DEVICE_CFG: usize = 0x14; // start of config space
 
 const VIRTQ_DESC_F_NEXT: u16 = 1;
 const VIRTQ_DESC_F_WRITE: u16 = 2;
+const VIRTQ_AVAIL_F_NO_INTERRUPT: u16 = 1;
 
 bitflags! {
 	/// A simple low-level indication of the completed steps in the device
@@ -166,8 +175,8 @@ pub struct VirtQueue {
 
 	/// Index identifying this VirtQueue for the device
 	pub queue_index: u16,
-	/// I/O base address for device communication
-	pub io_base: u16
+	/// Where the device is notified of new buffers
+	pub notify: QueueNotify
 }
 
 unsafe impl Send for VirtQueue {}
@@ -187,8 +196,49 @@ impl VirtQueue {
 			phys_addr: PhysAddr::zero(),
 			virt_addr: VirtAddr::zero(),
 			queue_index: 0,
-			io_base: 0
+			notify: QueueNotify::None
+		}
+	}
+
+	/// Allocates queue `qidx` of the device behind `transport` and hands it
+	/// to the device.
+	pub fn new(transport: &VirtioTransport, qidx: u16) -> Result<VirtQueue, NullexError> {
+		let size = transport.queue_size(qidx);
+		if size == 0 {
+			return Err(NullexError::VirtQueueUnavailable);
 		}
+
+		let layout_size = virtqueue_size(size as usize)?;
+		let (virt_addr, phys_addr) = dma_alloc(layout_size)?;
+		unsafe { write_bytes(virt_addr.as_mut_ptr::<u8>(), 0, layout_size) };
+
+		let avail_offset = core::mem::size_of::<VirtqueueDescriptor>() * size as usize;
+		let used_offset = align_up(
+			(avail_offset + core::mem::size_of::<VirtqueueAvailable>() + size as usize * 2)
+				.try_into()
+				.unwrap(),
+			4096
+		);
+		let notify = transport.setup_queue(qidx, phys_addr, avail_offset as u64, used_offset);
+
+		let mut vq = unsafe {
+			VirtQueue {
+				size,
+				desc: virt_addr.as_mut_ptr::<VirtqueueDescriptor>(),
+				avail: (virt_addr.as_mut_ptr::<u8>().add(avail_offset)) as *mut VirtqueueAvailable,
+				used: (virt_addr.as_mut_ptr::<u8>().add(used_offset.try_into().unwrap()))
+					as *mut VirtqueueUsed,
+				free_head: 0,
+				last_used: 0,
+				num_free: size,
+				phys_addr,
+				virt_addr,
+				queue_index: qidx,
+				notify
+			}
+		};
+		vq.init_free_list();
+		Ok(vq)
 	}
 
 	// Initialize the free list after allocation
@@ -229,6 +279,59 @@ impl VirtQueue {
 		Ok(idx)
 	}
 
+	/// Chains one descriptor per `(address, length, device_writes)` buffer and
+	/// returns the index of the first.
+	fn add_chain(&mut self, buffers: &[(PhysAddr, u32, bool)]) -> Result<u16, NullexError> {
+		ensure!(!buffers.is_empty(), NullexError::InvalidArgument);
+		ensure!(self.num_free as usize >= buffers.len(), NullexError::VirtQueueFull);
+
+		let head = self.free_head;
+		let mut idx = head;
+		for (i, &(phys_addr, len, device_writes)) in buffers.iter().enumerate() {
+			let desc = unsafe { &mut *self.desc.add(idx as usize) };
+			// free descriptors are linked through `next` already
+			let next = desc.next;
+			let last = i + 1 == buffers.len();
+
+			desc.addr = phys_addr.as_u64();
+			desc.len = len;
+			desc.flags = if device_writes { VIRTQ_DESC_F_WRITE } else { 0 };
+			if last {
+				desc.next = 0;
+				self.free_head = next;
+			} else {
+				desc.flags |= VIRTQ_DESC_F_NEXT;
+				idx = next;
+			}
+		}
+
+		self.num_free -= buffers.len() as u16;
+		Ok(head)
+	}
+
+	/// Returns every descriptor of the chain starting at `head` to the free
+	/// list.
+	fn free_chain(&mut self, head: u16) {
+		let mut idx = head;
+		loop {
+			let (flags, next) = unsafe {
+				let desc = &*self.desc.add(idx as usize);
+				(desc.flags, desc.next)
+			};
+			self.free_descriptor(idx);
+			if flags & VIRTQ_DESC_F_NEXT == 0 {
+				break;
+			}
+			idx = next;
+		}
+	}
+
+	/// Asks the device not to interrupt when it uses buffers, for queues that
+	/// are polled.
+	fn suppress_interrupts(&mut self) {
+		unsafe { (*self.avail).flags = VIRTQ_AVAIL_F_NO_INTERRUPT };
+	}
+
 	fn free_descriptor(&mut self, desc_idx: u16) {
 		unsafe {
 			let desc = &mut *self.desc.add(desc_idx as usize);
@@ -251,12 +354,17 @@ impl VirtQueue {
 	}
 
 	fn kick(&self) {
-		unsafe {
-			outw(
-				self.io_base + VIRTIO_IO_QUEUE_NOTIFY as u16,
-				self.queue_index
-			);
+		self.notify.notify(self.queue_index);
+	}
+
+	/// Returns whether the device has used buffers the driver hasn't popped
+	/// yet. Cheap enough to call on every timer tick.
+	fn has_used(&self) -> bool {
+		if self.used.is_null() {
+			return false;
 		}
+		let idx = unsafe { core::ptr::read_volatile(&raw const (*self.used).idx) };
+		idx != self.last_used
 	}
 
 	fn pop_used(&mut self) -> Option<(u16, u32)> {
@@ -318,3 +426,57 @@ pub trait VirtioDevice {
 	/// Initialise the VirtIO device.
 	fn init(&mut self) -> Result<(), NullexError>;
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::vec;
+
+	use crate::{drivers::virtio::*, utils::ktest::TestError};
+
+	pub fn test_virtqueue_descriptor_chains() -> Result<(), TestError> {
+		let mut descs = vec![VirtqueueDescriptor { addr: 0, len: 0, flags: 0, next: 0 }; 4];
+		let mut queue = VirtQueue::empty();
+		queue.size = 4;
+		queue.desc = descs.as_mut_ptr();
+		queue.init_free_list();
+
+		let head = queue
+			.add_chain(&[
+				(PhysAddr::new(0x1000), 16, false),
+				(PhysAddr::new(0x2000), 512, true),
+				(PhysAddr::new(0x3000), 1, true)
+			])
+			.map_err(|_| TestError::Error)?;
+		assert_eq!(queue.num_free, 1);
+		assert!(queue.add_chain(&[(PhysAddr::new(0), 1, false); 2]).is_err());
+
+		let first = descs[head as usize];
+		assert_eq!((first.addr, first.flags), (0x1000, VIRTQ_DESC_F_NEXT));
+		let second = descs[first.next as usize];
+		assert_eq!(second.flags, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+		let third = descs[second.next as usize];
+		assert_eq!((third.len, third.flags), (1, VIRTQ_DESC_F_WRITE));
+
+		queue.free_chain(head);
+		assert_eq!(queue.num_free, 4);
+		Ok(())
+	}
+	crate::create_test!(test_virtqueue_descriptor_chains);
+
+	pub fn test_virtqueue_has_used() -> Result<(), TestError> {
+		// a queue that was never set up has nothing to poll
+		assert!(!VirtQueue::empty().has_used());
+
+		let mut used = VirtqueueUsed::default();
+		let mut queue = VirtQueue::empty();
+		queue.used = &mut used;
+		assert!(!queue.has_used());
+
+		used.idx = 1;
+		assert!(queue.has_used());
+		queue.last_used = 1;
+		assert!(!queue.has_used());
+		Ok(())
+	}
+	crate::create_test!(test_virtqueue_has_used);
+}
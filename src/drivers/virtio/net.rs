@@ -6,35 +6,33 @@
 
 use alloc::vec::Vec;
 use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
-use core::{intrinsics::copy_nonoverlapping, ptr::write_bytes};
+use core::{
+	intrinsics::copy_nonoverlapping,
+	ptr::write_bytes,
+	sync::atomic::{AtomicBool, AtomicU64, Ordering}
+};
 
-use x86_64::{align_up, structures::idt::InterruptStackFrame};
+use x86_64::{instructions::interrupts, structures::idt::InterruptStackFrame};
 
 use crate::{
-	apic::send_eoi, common::ports::{inb, inw, outl, outw}, drivers::virtio::{
-		VIRTIO_IO_DEVICE_CFG,
-		VIRTIO_IO_DEVICE_FEATURES,
-		VIRTIO_IO_DEVICE_STATUS,
-		VIRTIO_IO_DRIVER_FEATURES,
-		VIRTIO_IO_ISR,
-		VIRTIO_IO_QUEUE_ADDR,
-		VIRTIO_IO_QUEUE_SELECT,
-		VIRTIO_IO_QUEUE_SIZE,
+	apic::send_eoi, drivers::virtio::{
 		VirtIODeviceStatus,
 		VirtQueue,
 		VirtioDevice,
 		VirtqueueAvailable,
 		VirtqueueDescriptor,
 		VirtqueueUsed,
-		virtqueue_size
-	}, error::NullexError, gsi::GSI_TABLE, io::{
-		io_read,
-		io_write,
-		pci::{DriverInfo, PciDevice, VIRTIO_PCI_VENDOR_ID, pci_enable_device, register_driver}
-	}, lazy_static, memory::{DmaBuffer, dma_alloc}, net::receive_packet, serial_println, utils::{
+		transport::{VIRTIO_F_VERSION_1, VirtioTransport}
+	}, error::NullexError, gsi::GSI_TABLE, io::pci::{
+		DriverInfo,
+		PciDevice,
+		VIRTIO_PCI_VENDOR_ID,
+		pci_enable_device,
+		register_driver
+	}, lazy_static, memory::{DmaBuffer, dma_alloc, try_dma_free}, net::receive_packet,
+	serial_println, task::timer::ms_to_ticks, utils::{
 		endian::{Le16, Le32},
-		mutex::SpinMutex,
-		types::{BYTE, QWORD}
+		mutex::SpinMutex
 	}
 };
 
@@ -55,10 +53,43 @@ lazy_static! {
 	pub static ref TX_INFLIGHT: SpinMutex<Vec<Option<DmaBuffer>>> = SpinMutex::new(Vec::new());
 }
 
+/// Whether `VIRTIO_NET_F_MRG_RXBUF` was negotiated. Kept outside of
+/// `VIRTIO_NET_INSTANCE` because the RX/TX paths run while that lock may
+/// already be held (e.g. by smoltcp).
+static MRG_RXBUF_ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Whether `VIRTIO_F_VERSION_1` was negotiated, which always puts
+/// `num_buffers` in the header.
+static VERSION_1_ACTIVE: AtomicBool = AtomicBool::new(false);
+/// The MAC address outgoing frames are sent from, packed by
+/// `store_mac_address`, or `NO_MAC` before the device is up. Kept outside of
+/// `VIRTIO_NET_INSTANCE` for the same reason as `MRG_RXBUF_ACTIVE`.
+static MAC_ADDRESS: AtomicU64 = AtomicU64::new(NO_MAC);
+const NO_MAC: u64 = u64::MAX;
+/// Set while a smoltcp interface takes received frames through
+/// `Device::receive`. `rx_poll` leaves the RX queue alone meanwhile.
+static SMOLTCP_RX: AtomicBool = AtomicBool::new(false);
+
+/// How often the APIC timer checks the queues for completions whose interrupt
+/// was lost, in milliseconds.
+const FALLBACK_POLL_MS: u64 = 10;
+
+/// Set while the RX used ring is drained, so the interrupt handler, the timer
+/// and callers of `rx_poll` never drain it at the same time.
+static RX_POLLING: AtomicBool = AtomicBool::new(false);
+/// Set while the TX used ring is drained.
+static TX_POLLING: AtomicBool = AtomicBool::new(false);
+/// How many timer polls found completions no interrupt had reported.
+static FALLBACK_POLLS: AtomicU64 = AtomicU64::new(0);
+/// TX buffers the device is done with, waiting to be freed. Completions are
+/// drained in interrupt context, where the frame allocator may already be
+/// locked; buffers that can't be freed then are freed by a later poll or the
+/// next `transmit_packet`.
+static TX_RECLAIM: SpinMutex<Vec<DmaBuffer>> = SpinMutex::new(Vec::new());
+
 /// Structure to store device-specific data for interrupt handler
 pub struct VirtioNetDevice {
-	/// Base IO address
-	pub io_base: u16,
+	/// The transport the device is driven through
+	pub transport: VirtioTransport,
 	/// Global System Interrupt number
 	pub gsi: u8,
 	/// Interrupt Vector
@@ -67,11 +98,16 @@ pub struct VirtioNetDevice {
 
 // https://docs.oasis-open.org/virtio/virtio/v1.3/csd01/virtio-v1.3-csd01.html#x1-2340001
 const VIRTIO_DEVICE_ID: u8 = 1;
+/// PCI device IDs of virtio network devices: transitional, then modern.
+const VIRTIO_NET_PCI_DEVICE_IDS: [u16; 2] = [0x1000, 0x1041];
 const VIRTIO_NET_IDT_VECTOR: u8 = 34;
 
-const NET_DRIVER_SUPPORTED_FEATURES: u64 = VIRTIO_NET_F_MAC | VIRTIO_NET_F_STATUS;
+const NET_DRIVER_SUPPORTED_FEATURES: u64 =
+	VIRTIO_NET_F_MAC | VIRTIO_NET_F_STATUS | VIRTIO_NET_F_MRG_RXBUF;
 
 const VIRTIO_NET_RX_BUFFERS: u64 = 256;
+/// Largest Ethernet frame we expect (1500 payload + 14 ethernet header).
+const VIRTIO_NET_MAX_FRAME: usize = 1514;
 
 // other virtqueues like (2n+1) arent implemented
 
@@ -136,8 +172,7 @@ const VIRTIO_NET_F_MQ: u64 = 1 << 22;
 /// Set MAC address through control channel.
 /// Requires `VIRTIO_NET_F_CTRL_VQ`.
 const VIRTIO_NET_F_CTRL_MAC_ADDR: u64 = 1 << 23;
-// 24-32 not in use
-const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+// 24-31 not in use, 32 is VIRTIO_F_VERSION_1
 // 33-50 not in use
 /// Device supports inner header hash for encapsulated packets.
 /// Requires `VIRTIO_NET_F_CTRL_VQ` along with
@@ -249,10 +284,33 @@ pub struct VirtioNetHeader {
 // sanity
 const _: () = assert!(core::mem::size_of::<VirtioNetHeader>() == 10);
 
+#[repr(C)]
+#[derive(Default)]
+/// Structure representing a VirtioNet Header when `VIRTIO_NET_F_MRG_RXBUF`
+/// is negotiated.
+pub struct VirtioNetHeaderMrgRxbuf {
+	hdr: VirtioNetHeader,
+	/// Number of descriptors this packet is spread across (RX only).
+	num_buffers: Le16
+}
+
+const _: () = assert!(core::mem::size_of::<VirtioNetHeaderMrgRxbuf>() == 12);
+
+/// Returns the size of the virtio-net header in front of every RX and TX
+/// buffer, which grows by `num_buffers` when merged RX buffers or virtio 1.0
+/// are active.
+fn net_header_len() -> usize {
+	if MRG_RXBUF_ACTIVE.load(Ordering::Acquire) || VERSION_1_ACTIVE.load(Ordering::Acquire) {
+		core::mem::size_of::<VirtioNetHeaderMrgRxbuf>()
+	} else {
+		core::mem::size_of::<VirtioNetHeader>()
+	}
+}
+
 /// Structure representing the Virtio Network device.
 pub struct VirtioNet {
-	/// The base IO address of the device.
-	pub io_base: usize,
+	/// The transport the device is driven through.
+	pub transport: VirtioTransport,
 	/// The header for the device.
 	pub header: VirtioNetHeader,
 	/// The configuration for the device.
@@ -270,7 +328,7 @@ pub struct VirtioNet {
 impl VirtioNet {
 	/// Creates a new `VirtioNet` device. 
 	pub fn new( 
-		io_base: usize,
+		transport: VirtioTransport,
 		header: VirtioNetHeader,
 		config: VirtioNetConfig,
 		nf: u64,
@@ -279,7 +337,7 @@ impl VirtioNet {
 		ctrl: Option<VirtQueue>
 	) -> VirtioNet {
 		Self {
-			io_base,
+			transport,
 			header,
 			config,
 			negotiated_features: nf,
@@ -288,64 +346,26 @@ impl VirtioNet {
 			ctrl_queue: ctrl
 		}
 	}
+
+	/// Features this driver can use. The modern transport also requires
+	/// accepting `VIRTIO_F_VERSION_1`.
+	fn driver_features(&self) -> u64 {
+		if self.transport.is_modern() {
+			NET_DRIVER_SUPPORTED_FEATURES | VIRTIO_F_VERSION_1
+		} else {
+			NET_DRIVER_SUPPORTED_FEATURES
+		}
+	}
 }
 
 impl VirtioDevice for VirtioNet {
 	fn alloc_virtqueue(&mut self, qidx: u16) -> Result<VirtQueue, NullexError> {
-		unsafe {
-			outw(
-				(self.io_base + VIRTIO_IO_QUEUE_SELECT).try_into().unwrap(),
-				qidx
-			);
-			let size = inw((self.io_base + VIRTIO_IO_QUEUE_SIZE).try_into().unwrap());
-			if size == 0 {
-				return Err(NullexError::VirtQueueUnavailable);
-			}
-
-			let layout_size = virtqueue_size(size as usize)?;
-			let (virt_addr, phys_addr) = dma_alloc(layout_size)?;
-			write_bytes(virt_addr.as_mut_ptr::<u8>(), 0, layout_size);
-
-			outl(
-				(self.io_base + VIRTIO_IO_QUEUE_ADDR).try_into().unwrap(),
-				(phys_addr.as_u64() >> 12) as u32
-			);
-
-			let mut vq = VirtQueue {
-				size,
-				desc: virt_addr.as_mut_ptr::<VirtqueueDescriptor>(),
-				avail: (virt_addr
-					.as_mut_ptr::<u8>()
-					.add(core::mem::size_of::<VirtqueueDescriptor>() * size as usize))
-					as *mut VirtqueueAvailable,
-				used: (virt_addr.as_mut_ptr::<u8>().add(
-					align_up(
-						(core::mem::size_of::<VirtqueueDescriptor>() * size as usize
-							+ core::mem::size_of::<VirtqueueAvailable>()
-							+ size as usize * 2)
-							.try_into()
-							.unwrap(),
-						4096
-					)
-					.try_into()
-					.unwrap()
-				)) as *mut VirtqueueUsed,
-				free_head: 0,
-				last_used: 0,
-				num_free: size,
-				phys_addr,
-				virt_addr,
-				queue_index: qidx,
-				io_base: self.io_base as u16
-			};
-			vq.init_free_list();
-			Ok(vq)
-		}
+		VirtQueue::new(&self.transport, qidx)
 	}
 
 	fn device_features(&mut self) -> u64 {
 		if self.negotiated_features == 0 {
-			io_read::<QWORD>(self.io_base, VIRTIO_IO_DEVICE_FEATURES).unwrap()
+			self.transport.device_features()
 		} else {
 			self.negotiated_features
 		}
@@ -353,14 +373,14 @@ impl VirtioDevice for VirtioNet {
 
 	fn set_driver_features(&mut self, features: u64) {
 		self.negotiated_features = features;
-		io_write::<QWORD>(self.io_base, VIRTIO_IO_DRIVER_FEATURES, features).unwrap();
+		self.transport.set_driver_features(features);
 	}
 
 	fn driver_status(&mut self) -> u16 {
 		if let Some(cur_status) = self.config.status {
 			cur_status
 		} else {
-			let status = io_read::<BYTE>(self.io_base, VIRTIO_IO_DEVICE_STATUS).unwrap();
+			let status = self.transport.status();
 			self.set_driver_status(status);
 			status as u16
 		}
@@ -378,7 +398,7 @@ impl VirtioDevice for VirtioNet {
 			None => status as u16
 		};
 		self.config.status = Some(new_status);
-		io_write::<BYTE>(self.io_base, VIRTIO_IO_DEVICE_STATUS, new_status as u8).unwrap();
+		self.transport.set_status(new_status as u8);
 	}
 
 	fn has_status(&mut self, status: u8) -> bool {
@@ -391,13 +411,19 @@ impl VirtioDevice for VirtioNet {
 
 	fn init(&mut self) -> Result<(), NullexError> {
 		let supported = self.supported_features();
-		let want = supported & NET_DRIVER_SUPPORTED_FEATURES;
+		let want = supported & self.driver_features();
 		self.set_driver_features(want);
+		MRG_RXBUF_ACTIVE.store(want & VIRTIO_NET_F_MRG_RXBUF != 0, Ordering::Release);
+		VERSION_1_ACTIVE.store(want & VIRTIO_F_VERSION_1 != 0, Ordering::Release);
 
 		let mut rx_vq = self.alloc_virtqueue(0)?;
 		let rx_queue_size = rx_vq.size as usize;
 
-		serial_println!("[VIRTIO-NET] RX queue size: {}", rx_queue_size);
+		serial_println!(
+			"[VIRTIO-NET] RX queue size: {}, merged RX buffers: {}",
+			rx_queue_size,
+			MRG_RXBUF_ACTIVE.load(Ordering::Acquire)
+		);
 
 		{
 			let mut rx_buffers = RX_BUFFERS.lock();
@@ -406,7 +432,7 @@ impl VirtioDevice for VirtioNet {
 		}
 
 		for _ in 0..rx_queue_size {
-			let buf_size = 1500 + core::mem::size_of::<VirtioNetHeader>();
+			let buf_size = VIRTIO_NET_MAX_FRAME + net_header_len();
 			let (virt_addr, phys_addr) = dma_alloc(buf_size).expect("DMA alloc failed");
 			unsafe { write_bytes(virt_addr.as_mut_ptr::<u8>(), 0, buf_size) }
 
@@ -476,13 +502,28 @@ impl Device for VirtioNet {
 	}
 
 	fn receive(&mut self, timestamp: smoltcp::time::Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+		if MRG_RXBUF_ACTIVE.load(Ordering::Acquire) {
+			loop {
+				let popped = {
+					let mut rx_queue = RX_QUEUE.lock();
+					rx_pop_merged(&mut rx_queue)
+				};
+				let (data, descs) = popped?;
+				rx_recycle(&descs);
+				if let Some(data) = data {
+					crate::net::capture::record(crate::net::capture::Direction::Rx, &data);
+					return Some((VirtioRxPacket(data), VirtioTxPacket));
+				}
+			}
+		}
+
 		let packet = {
 			let mut rx_queue = RX_QUEUE.lock();
 			rx_queue.pop_used()
 		};
 
 		if let Some((desc_id, len)) = packet {
-			let hdr_len = size_of::<VirtioNetHeader>();
+			let hdr_len = net_header_len();
 			let pkt_len = (len as usize).saturating_sub(hdr_len);
 
 			let data = {
@@ -495,6 +536,7 @@ impl Device for VirtioNet {
 				}
 				data
 			};
+			crate::net::capture::record(crate::net::capture::Direction::Rx, &data);
 
 			{
 				let mut rx_queue = RX_QUEUE.lock();
@@ -518,7 +560,7 @@ impl Device for VirtioNet {
 }
 
 fn handle_rx_packet(desc_id: u16, len: u32) {
-	let hdr_len = core::mem::size_of::<VirtioNetHeader>();
+	let hdr_len = net_header_len();
 
 	let pkt_ptr = {
 		let rx_buffers = RX_BUFFERS.lock();
@@ -558,6 +600,91 @@ fn handle_rx_packet(desc_id: u16, len: u32) {
 	}
 }
 
+/// Pops the next complete packet off the RX used ring when merged RX buffers
+/// are active, gathering its data across `num_buffers` descriptors.
+///
+/// Returns `None` if the used ring is empty. Otherwise returns the packet data
+/// (without the virtio-net header), or `None` in its place if the packet was
+/// malformed and dropped, along with every descriptor consumed so the caller
+/// can hand them back to the device.
+fn rx_pop_merged(rx_queue: &mut VirtQueue) -> Option<(Option<Vec<u8>>, Vec<u16>)> {
+	let hdr_len = core::mem::size_of::<VirtioNetHeaderMrgRxbuf>();
+	let (first_id, first_len) = rx_queue.pop_used()?;
+
+	let rx_buffers = RX_BUFFERS.lock();
+	let mut descs = vec![first_id];
+
+	let first = match rx_buffers.get(first_id as usize).and_then(|o| o.as_ref()) {
+		Some(buf) => buf,
+		None => {
+			serial_println!("[VIRTIO-NET] ERROR: No buffer at desc_id {}", first_id);
+			return Some((None, descs));
+		}
+	};
+
+	if (first_len as usize) < hdr_len || first_len as usize > first.len {
+		serial_println!("[VIRTIO-NET] Dropping RX packet with bad length {}", first_len);
+		return Some((None, descs));
+	}
+
+	let num_buffers = unsafe {
+		let offset = core::mem::offset_of!(VirtioNetHeaderMrgRxbuf, num_buffers);
+		core::ptr::read_unaligned(first.virt.as_ptr::<u8>().add(offset) as *const Le16)
+	};
+
+	if num_buffers == 0 || num_buffers > rx_queue.size {
+		serial_println!("[VIRTIO-NET] Dropping RX packet with num_buffers={}", num_buffers);
+		return Some((None, descs));
+	}
+
+	let mut data = Vec::with_capacity(first_len as usize - hdr_len);
+	unsafe {
+		let src = first.virt.as_ptr::<u8>().add(hdr_len);
+		data.extend_from_slice(core::slice::from_raw_parts(src, first_len as usize - hdr_len));
+	}
+
+	let mut valid = true;
+	for _ in 1..num_buffers {
+		// the device publishes every buffer of a packet before bumping the used
+		// index, so a short ring means the device misbehaved.
+		let (desc_id, len) = match rx_queue.pop_used() {
+			Some(used) => used,
+			None => {
+				serial_println!(
+					"[VIRTIO-NET] RX packet truncated, expected {} buffers",
+					num_buffers
+				);
+				return Some((None, descs));
+			}
+		};
+		descs.push(desc_id);
+
+		match rx_buffers.get(desc_id as usize).and_then(|o| o.as_ref()) {
+			Some(buf) if len as usize <= buf.len => unsafe {
+				let src = buf.virt.as_ptr::<u8>();
+				data.extend_from_slice(core::slice::from_raw_parts(src, len as usize));
+			},
+			_ => valid = false
+		}
+	}
+
+	if !valid {
+		serial_println!("[VIRTIO-NET] Dropping merged RX packet with bad descriptor");
+		return Some((None, descs));
+	}
+
+	Some((Some(data), descs))
+}
+
+/// Hands RX descriptors back to the device.
+fn rx_recycle(descs: &[u16]) {
+	let mut rx_queue = RX_QUEUE.lock();
+	for desc_id in descs {
+		rx_queue.push_avail(*desc_id);
+	}
+	rx_queue.kick();
+}
+
 fn _rx_replenish_one(desc_id: u16, _old_buf: DmaBuffer) {
 	serial_println!("[VIRTIO-NET] Replenish: requeue desc_id={}", desc_id);
 	let mut rx_queue = RX_QUEUE.lock();
@@ -567,6 +694,7 @@ fn _rx_replenish_one(desc_id: u16, _old_buf: DmaBuffer) {
 
 /// Transmit a packet to the transport queue (TX)
 pub fn transmit_packet(packet: &[u8]) -> Result<(), NullexError> {
+	crate::net::capture::record(crate::net::capture::Direction::Tx, packet);
 	serial_println!("[VIRTIO-NET] TX packet ({} bytes)", packet.len());
 	serial_println!("[VIRTIO-NET] Packet contents (Ethernet header):");
 	serial_println!(
@@ -589,16 +717,17 @@ pub fn transmit_packet(packet: &[u8]) -> Result<(), NullexError> {
 	);
 	serial_println!("  EtherType: 0x{:02X}{:02X}", packet[12], packet[13]);
 
-	const HEADER_SIZE: usize = core::mem::size_of::<VirtioNetHeader>();
-	let total_size = HEADER_SIZE + packet.len();
+	reclaim_tx_buffers();
+
+	let header_size = net_header_len();
+	let total_size = header_size + packet.len();
 	let (virt_addr, phys_addr) = dma_alloc(total_size)?;
 
 	unsafe {
-		let header = VirtioNetHeader::default();
-		let header_ptr = virt_addr.as_mut_ptr::<VirtioNetHeader>();
-		core::ptr::write(header_ptr, header);
+		// an all-zero header is a valid "no offloads" header in both layouts
+		write_bytes(virt_addr.as_mut_ptr::<u8>(), 0, header_size);
 
-		let packet_ptr = virt_addr.as_mut_ptr::<u8>().add(HEADER_SIZE);
+		let packet_ptr = virt_addr.as_mut_ptr::<u8>().add(header_size);
 		core::ptr::copy_nonoverlapping(packet.as_ptr(), packet_ptr, packet.len());
 	}
 
@@ -629,13 +758,65 @@ pub fn transmit_packet(packet: &[u8]) -> Result<(), NullexError> {
 	Ok(())
 }
 
+/// Frees the TX buffers of completed transmits, leaving them queued if the
+/// frame allocator is busy.
+fn reclaim_tx_buffers() {
+	interrupts::without_interrupts(|| {
+		let mut reclaim = TX_RECLAIM.lock();
+		while let Some(buffer) = reclaim.last() {
+			match unsafe { try_dma_free(buffer.virt, buffer.phys, buffer.len) } {
+				Ok(true) => {}
+				Ok(false) => return,
+				Err(e) => serial_println!("[VIRTIO-NET] Failed to free TX buffer: {}", e)
+			}
+			reclaim.pop();
+		}
+	});
+}
+
+/// Override the MAC address used by the device.
+///
+/// The stored configuration MAC (used as the source of all outgoing frames) is
+/// always updated. The device itself only learns the new address on the legacy
+/// transport when `VIRTIO_NET_F_CTRL_MAC_ADDR` isn't negotiated, where the
+/// config space MAC is writable. Setting the address through the control queue
+/// isn't implemented yet, so in that case only outgoing frames change.
+pub fn set_mac_address(mac: [u8; 6]) -> Result<(), NullexError> {
+	let mut instance = VIRTIO_NET_INSTANCE.lock();
+	let (virtio_net, _) = instance
+		.as_mut()
+		.ok_or(NullexError::MissingVirtIOInstance)?;
+
+	virtio_net.config.mac = mac;
+	store_mac_address(mac);
+
+	if virtio_net.negotiated_features & VIRTIO_NET_F_CTRL_MAC_ADDR == 0 {
+		for (i, byte) in mac.iter().enumerate() {
+			virtio_net.transport.write_config(i, *byte);
+		}
+	} else {
+		serial_println!("[VIRTIO-NET] CTRL_MAC_ADDR negotiated, control queue not implemented");
+	}
+
+	serial_println!(
+		"[VIRTIO-NET] MAC set to {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+		mac[0],
+		mac[1],
+		mac[2],
+		mac[3],
+		mac[4],
+		mac[5]
+	);
+	Ok(())
+}
+
 fn virtio_net_finalize() -> Result<(), NullexError> {
 	serial_println!("[VIRTIO-NET] Finalizing device (setting DRIVER_OK)");
 
 	let mut instance = VIRTIO_NET_INSTANCE.lock();
-	if let Some((ref mut virtio_net, io_base)) = *instance {
+	if let Some((ref mut virtio_net, _)) = *instance {
 		virtio_net.set_driver_status(VirtIODeviceStatus::DRIVER_OK.bits());
-		serial_println!("[VIRTIO-NET] Device finalized at io_base={:#x}", io_base);
+		serial_println!("[VIRTIO-NET] Device finalized ({:?})", virtio_net.transport);
 		Ok(())
 	} else {
 		Err(NullexError::MissingVirtIOInstance)
@@ -645,24 +826,30 @@ fn virtio_net_finalize() -> Result<(), NullexError> {
 /// Initialize the Virtio Net driver.
 pub fn virtio_net_driver_init() {
 	serial_println!("[VIRTIO-NET] Registering driver");
-	register_driver(DriverInfo {
-		vendor: Some(VIRTIO_PCI_VENDOR_ID),
-		device: None,
-		class: None,
-		subclass: None,
-		probe: Some(virtio_net_probe)
-	});
+	for device in VIRTIO_NET_PCI_DEVICE_IDS {
+		register_driver(DriverInfo {
+			vendor: Some(VIRTIO_PCI_VENDOR_ID),
+			device: Some(device),
+			class: None,
+			subclass: None,
+			probe: Some(virtio_net_probe)
+		});
+	}
 }
 
 /// Probe the virtio net device.
 pub fn virtio_net_probe(dev: &mut PciDevice) -> Result<usize, NullexError> {
 	serial_println!("[VIRTIO-NET] Probing device {:?}", dev.bdf);
 
-	pci_enable_device(dev)?;
-	let io_base = dev.io_base.ok_or("no io base")?;
+	// a modern-only device has no I/O BAR for this to enable
+	if let Err(e) = pci_enable_device(dev) {
+		serial_println!("[VIRTIO-NET] Failed to enable legacy BAR: {:?}", e);
+	}
+	let transport = VirtioTransport::detect(dev.bdf, dev.io_base)?;
+	let io_base = dev.io_base.unwrap_or(0);
 
 	let mut virtio_net = VirtioNet::new(
-		io_base,
+		transport,
 		VirtioNetHeader::default(),
 		VirtioNetConfig::default(),
 		0,
@@ -679,7 +866,7 @@ pub fn virtio_net_probe(dev: &mut PciDevice) -> Result<usize, NullexError> {
 	);
 
 	let dev_features = virtio_net.device_features();
-	let driv_ok_features = dev_features & NET_DRIVER_SUPPORTED_FEATURES;
+	let driv_ok_features = dev_features & virtio_net.driver_features();
 
 	virtio_net.set_driver_features(driv_ok_features);
 	virtio_net.set_driver_status(VirtIODeviceStatus::FEATURES_OK.bits());
@@ -692,7 +879,7 @@ pub fn virtio_net_probe(dev: &mut PciDevice) -> Result<usize, NullexError> {
 	let mac = {
 		let mut value = [0u8; 6];
 		for i in 0..6 {
-			value[i] = unsafe { inb((io_base + VIRTIO_IO_DEVICE_CFG + i) as u16) };
+			value[i] = transport.read_config(i);
 		}
 		value
 	};
@@ -714,7 +901,7 @@ pub fn virtio_net_probe(dev: &mut PciDevice) -> Result<usize, NullexError> {
 	serial_println!("[VIRTIO-NET] DRIVER_OK status set");
 
 	// Verify DRIVER_OK is actually set
-	let status = unsafe { inb((io_base + VIRTIO_IO_DEVICE_STATUS) as u16) };
+	let status = transport.status();
 	serial_println!("[VIRTIO-NET] Device status register: {:#x}", status);
 	if (status & VirtIODeviceStatus::DRIVER_OK.bits()) == 0 {
 		return Err(NullexError::DriverNotOk);
@@ -727,12 +914,13 @@ pub fn virtio_net_probe(dev: &mut PciDevice) -> Result<usize, NullexError> {
 	}
 
 	*VIRTIO_NET_INSTANCE.lock() = Some((virtio_net, io_base));
+	store_mac_address(mac);
 
 	let gsi = dev.interrupt_line()? as usize;
 	serial_println!("[VIRTIO-NET] Device uses GSI {}", gsi);
 
 	*VIRTIO_NET_DEVICE.lock() = Some(VirtioNetDevice {
-		io_base: io_base as u16,
+		transport,
 		gsi: gsi as u8,
 		vector: VIRTIO_NET_IDT_VECTOR
 	});
@@ -782,12 +970,13 @@ pub fn virtio_net_probe(dev: &mut PciDevice) -> Result<usize, NullexError> {
 
 /// VirtioNet Interrupt Handler.
 pub extern "x86-interrupt" fn virtio_net_interrupt_handler(_stack_frame: InterruptStackFrame) {
+	crate::interrupts::count_irq(VIRTIO_NET_IDT_VECTOR);
 	serial_println!("[VIRTIO-NET] Interrupt!");
 
-	let io_base = {
+	let transport = {
 		let dev = VIRTIO_NET_DEVICE.lock();
 		match dev.as_ref() {
-			Some(d) => d.io_base as usize,
+			Some(d) => d.transport,
 			None => {
 				unsafe {
 					send_eoi();
@@ -797,7 +986,7 @@ pub extern "x86-interrupt" fn virtio_net_interrupt_handler(_stack_frame: Interru
 		}
 	};
 
-	let isr = unsafe { inb(io_base as u16 + VIRTIO_IO_ISR as u16) };
+	let isr = transport.read_isr();
 	serial_println!("[VIRTIO-NET] ISR={:#x}", isr);
 
 	if (isr & 0x1) != 0 {
@@ -811,7 +1000,58 @@ pub extern "x86-interrupt" fn virtio_net_interrupt_handler(_stack_frame: Interru
 	}
 }
 
+/// Checks for RX and TX completions from the APIC timer, every
+/// `FALLBACK_POLL_MS` milliseconds. Catches up on bursts whose interrupt was lost.
+///
+/// The interrupted code may hold any of the driver's locks, so nothing is
+/// polled unless all of them are free.
+pub fn timer_poll(now: u64) {
+	// converted on every tick, since `timerhz` can change the rate
+	if !now.is_multiple_of(ms_to_ticks(FALLBACK_POLL_MS)) {
+		return;
+	}
+
+	let (rx_pending, tx_pending) = {
+		let (Some(rx_queue), Some(tx_queue), Some(_rx_buffers), Some(_tx_inflight)) = (
+			RX_QUEUE.try_lock(),
+			TX_QUEUE.try_lock(),
+			RX_BUFFERS.try_lock(),
+			TX_INFLIGHT.try_lock()
+		) else {
+			return;
+		};
+		let rx_pending = rx_queue.has_used() && !SMOLTCP_RX.load(Ordering::Acquire);
+		(rx_pending, tx_queue.has_used())
+	};
+
+	if rx_pending || tx_pending {
+		FALLBACK_POLLS.fetch_add(1, Ordering::Relaxed);
+	}
+	if rx_pending {
+		rx_poll();
+	}
+	if tx_pending {
+		tx_poll();
+	}
+}
+
+/// Returns how many times the timer fallback found completions that no
+/// interrupt had reported.
+pub fn fallback_poll_count() -> u64 {
+	FALLBACK_POLLS.load(Ordering::Relaxed)
+}
+
 fn tx_poll() {
+	// whoever is already draining the ring will pick these up too
+	if TX_POLLING.swap(true, Ordering::Acquire) {
+		return;
+	}
+	tx_drain();
+	reclaim_tx_buffers();
+	TX_POLLING.store(false, Ordering::Release);
+}
+
+fn tx_drain() {
 	//serial_println!("[VIRTIO-NET] Polling TX queue");
 
 	let completions = {
@@ -829,19 +1069,85 @@ fn tx_poll() {
 			completions.len()
 		);
 		let mut tx_inflight = TX_INFLIGHT.lock();
+		let mut done = Vec::new();
 		for desc_id in completions.iter() {
 			serial_println!("[VIRTIO-NET] TX completed desc_id={}", desc_id);
-			if (*desc_id as usize) < tx_inflight.len() {
-				tx_inflight[*desc_id as usize] = None;
+			if let Some(buffer) = tx_inflight.get_mut(*desc_id as usize).and_then(Option::take) {
+				done.push(buffer);
 			}
 		}
+		TX_RECLAIM.lock().extend(done);
+	}
+}
+
+/// Returns the MAC address outgoing frames are sent from, once the device is
+/// up. Doesn't lock `VIRTIO_NET_INSTANCE`, so it can be used from the RX path.
+pub fn mac_address() -> Option<[u8; 6]> {
+	let packed = MAC_ADDRESS.load(Ordering::Relaxed);
+	let bytes = packed.to_be_bytes();
+	(packed != NO_MAC).then(|| [bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]])
+}
+
+fn store_mac_address(mac: [u8; 6]) {
+	let mut bytes = [0u8; 8];
+	bytes[2..].copy_from_slice(&mac);
+	MAC_ADDRESS.store(u64::from_be_bytes(bytes), Ordering::Relaxed);
+}
+
+/// Gives received frames to smoltcp instead of the kernel's own stack until
+/// dropped, so the interrupt handler and the timer fallback don't take the
+/// frames a smoltcp interface is waiting for.
+pub struct SmoltcpRx(());
+
+impl SmoltcpRx {
+	/// Claims the RX queue for smoltcp.
+	pub fn claim() -> SmoltcpRx {
+		SMOLTCP_RX.store(true, Ordering::Release);
+		SmoltcpRx(())
+	}
+}
+
+impl Drop for SmoltcpRx {
+	fn drop(&mut self) {
+		SMOLTCP_RX.store(false, Ordering::Release);
 	}
 }
 
 /// Poll the receive queue. (RX)
+///
+/// Does nothing while a `SmoltcpRx` claim is held.
 pub fn rx_poll() {
+	if SMOLTCP_RX.load(Ordering::Acquire) || RX_POLLING.swap(true, Ordering::Acquire) {
+		return;
+	}
+	rx_drain();
+	RX_POLLING.store(false, Ordering::Release);
+}
+
+fn rx_drain() {
 	//serial_println!("[VIRTIO-NET] Polling RX queue");
 
+	if MRG_RXBUF_ACTIVE.load(Ordering::Acquire) {
+		loop {
+			let popped = {
+				let mut rx_queue = RX_QUEUE.lock();
+				rx_pop_merged(&mut rx_queue)
+			};
+			let Some((data, descs)) = popped else { break };
+
+			if let Some(data) = data {
+				serial_println!(
+					"[VIRTIO-NET] RX packet ({} bytes) across {} buffers",
+					data.len(),
+					descs.len()
+				);
+				crate::net::receive_packet(data.as_ptr(), data.len());
+			}
+			rx_recycle(&descs);
+		}
+		return;
+	}
+
 	let packets = {
 		let mut rx_queue = RX_QUEUE.lock();
 		let mut packets = Vec::new();
@@ -0,0 +1,463 @@
+//!
+//! drivers/virtio/transport.rs
+//!
+//! Virtio PCI transports. Devices are driven through the legacy I/O port
+//! interface, or through the modern (virtio 1.0) interface whose registers
+//! live in memory BARs described by vendor PCI capabilities.
+//!
+
+use core::ptr::{read_volatile, write_volatile};
+
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::{
+	common::ports::{inb, inw, outb, outl, outw},
+	drivers::virtio::{
+		VIRTIO_IO_DEVICE_CFG,
+		VIRTIO_IO_DEVICE_FEATURES,
+		VIRTIO_IO_DEVICE_STATUS,
+		VIRTIO_IO_DRIVER_FEATURES,
+		VIRTIO_IO_ISR,
+		VIRTIO_IO_QUEUE_ADDR,
+		VIRTIO_IO_QUEUE_NOTIFY,
+		VIRTIO_IO_QUEUE_SELECT,
+		VIRTIO_IO_QUEUE_SIZE
+	},
+	error::NullexError,
+	io::{
+		io_read,
+		io_write,
+		pci::{
+			Bdf,
+			PCI_CAP_ID_VENDOR,
+			pci_capabilities,
+			pci_config_read,
+			pci_enable_memory,
+			pci_memory_bar
+		}
+	},
+	memory,
+	serial_println,
+	utils::types::{BYTE, DWORD, QWORD}
+};
+
+/// The device complies with virtio 1.0 or later, and so supports the modern
+/// transport.
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// Common configuration.
+pub const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+/// Queue notifications.
+pub const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+/// Interrupt status.
+pub const VIRTIO_PCI_CAP_ISR_CFG: u8 = 3;
+/// Device specific configuration.
+pub const VIRTIO_PCI_CAP_DEVICE_CFG: u8 = 4;
+
+// common configuration layout
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0C;
+const COMMON_DEVICE_STATUS: usize = 0x14;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_ENABLE: usize = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFF: usize = 0x1E;
+const COMMON_QUEUE_DESC: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+/// Number of BARs a PCI function can have.
+const PCI_BAR_COUNT: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A `virtio_pci_cap` structure, locating one register block in a BAR.
+pub struct VirtioPciCap {
+	/// Which register block this is (`VIRTIO_PCI_CAP_*`).
+	pub cfg_type: u8,
+	/// The BAR the block lives in.
+	pub bar: u8,
+	/// Offset of the block within the BAR.
+	pub offset: u32,
+	/// Length of the block in bytes.
+	pub length: u32,
+	/// Multiplier for queue notify offsets. Only set on the notify capability.
+	pub notify_off_multiplier: u32
+}
+
+impl VirtioPciCap {
+	/// Decodes a capability from the dwords at its config space offset. The
+	/// fifth dword is only read for the notify capability.
+	pub fn from_raw(raw: [u32; 5]) -> VirtioPciCap {
+		let cfg_type = (raw[0] >> 24) as u8;
+		VirtioPciCap {
+			cfg_type,
+			bar: raw[1] as u8,
+			offset: raw[2],
+			length: raw[3],
+			notify_off_multiplier: if cfg_type == VIRTIO_PCI_CAP_NOTIFY_CFG { raw[4] } else { 0 }
+		}
+	}
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// The register blocks of a modern virtio device.
+pub struct ModernCaps {
+	/// Common configuration.
+	pub common: Option<VirtioPciCap>,
+	/// Queue notifications.
+	pub notify: Option<VirtioPciCap>,
+	/// Interrupt status.
+	pub isr: Option<VirtioPciCap>,
+	/// Device specific configuration, absent on devices without any.
+	pub device: Option<VirtioPciCap>
+}
+
+impl ModernCaps {
+	/// Records `cap`. The first capability of each type wins, as the spec asks
+	/// drivers to prefer earlier ones.
+	pub fn add(&mut self, cap: VirtioPciCap) {
+		let slot = match cap.cfg_type {
+			VIRTIO_PCI_CAP_COMMON_CFG => &mut self.common,
+			VIRTIO_PCI_CAP_NOTIFY_CFG => &mut self.notify,
+			VIRTIO_PCI_CAP_ISR_CFG => &mut self.isr,
+			VIRTIO_PCI_CAP_DEVICE_CFG => &mut self.device,
+			_ => return
+		};
+		if slot.is_none() && (cap.bar as usize) < PCI_BAR_COUNT {
+			*slot = Some(cap);
+		}
+	}
+
+	/// Returns whether every block the modern transport needs was found.
+	pub fn is_complete(&self) -> bool {
+		self.common.is_some() && self.notify.is_some() && self.isr.is_some()
+	}
+}
+
+/// Walks `bdf`'s capability list for virtio vendor capabilities.
+pub fn find_modern_caps(bdf: Bdf) -> ModernCaps {
+	let mut caps = ModernCaps::default();
+	for (offset, id) in pci_capabilities(bdf) {
+		if id != PCI_CAP_ID_VENDOR {
+			continue;
+		}
+
+		let mut raw = [0u32; 5];
+		for (i, dword) in raw.iter_mut().enumerate() {
+			let Some(dword_offset) = offset.checked_add(i as u8 * 4) else {
+				break;
+			};
+			*dword = pci_config_read::<DWORD>(bdf, dword_offset).unwrap_or(0);
+		}
+		caps.add(VirtioPciCap::from_raw(raw));
+	}
+	caps
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The mapped register blocks of a device using the modern transport.
+pub struct ModernTransport {
+	common: VirtAddr,
+	notify: VirtAddr,
+	notify_off_multiplier: u32,
+	isr: VirtAddr,
+	device: Option<VirtAddr>
+}
+
+impl ModernTransport {
+	/// Maps the BARs holding `caps`' register blocks.
+	pub fn map(bdf: Bdf, caps: &ModernCaps) -> Result<ModernTransport, NullexError> {
+		let (common, notify, isr) = match (caps.common, caps.notify, caps.isr) {
+			(Some(common), Some(notify), Some(isr)) => (common, notify, isr),
+			_ => return Err(NullexError::Io("Incomplete virtio capabilities"))
+		};
+
+		// the blocks usually share a BAR, so map each one only once
+		let mut bars: [Option<VirtAddr>; PCI_BAR_COUNT] = [None; PCI_BAR_COUNT];
+		let mut locate = |cap: VirtioPciCap| -> Result<VirtAddr, NullexError> {
+			let base = match bars[cap.bar as usize] {
+				Some(base) => base,
+				None => {
+					let bar = pci_memory_bar(bdf, cap.bar)?;
+					if bar.base == 0 || bar.size == 0 {
+						return Err(NullexError::Io("Virtio BAR not assigned"));
+					}
+					let base = memory::map_mmio(PhysAddr::new(bar.base), bar.size as usize, false)?;
+					bars[cap.bar as usize] = Some(base);
+					base
+				}
+			};
+			Ok(base + cap.offset as u64)
+		};
+
+		let transport = ModernTransport {
+			common: locate(common)?,
+			notify: locate(notify)?,
+			notify_off_multiplier: notify.notify_off_multiplier,
+			isr: locate(isr)?,
+			device: caps.device.map(&mut locate).transpose()?
+		};
+		pci_enable_memory(bdf)?;
+		Ok(transport)
+	}
+
+	fn read<T: Copy>(base: VirtAddr, offset: usize) -> T {
+		unsafe { read_volatile((base + offset as u64).as_ptr::<T>()) }
+	}
+
+	fn write<T: Copy>(base: VirtAddr, offset: usize, value: T) {
+		unsafe { write_volatile((base + offset as u64).as_mut_ptr::<T>(), value) }
+	}
+
+	fn device_features(&self) -> u64 {
+		Self::write::<u32>(self.common, COMMON_DEVICE_FEATURE_SELECT, 0);
+		let lo = Self::read::<u32>(self.common, COMMON_DEVICE_FEATURE);
+		Self::write::<u32>(self.common, COMMON_DEVICE_FEATURE_SELECT, 1);
+		let hi = Self::read::<u32>(self.common, COMMON_DEVICE_FEATURE);
+		((hi as u64) << 32) | lo as u64
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where a queue's available buffer notifications are written.
+pub enum QueueNotify {
+	/// The queue isn't set up.
+	None,
+	/// The legacy notify port, written with the queue index.
+	Port(u16),
+	/// The queue's modern notify address, written with the queue index.
+	Mmio(VirtAddr)
+}
+
+impl QueueNotify {
+	/// Tells the device queue `queue_index` has new buffers.
+	pub fn notify(&self, queue_index: u16) {
+		match *self {
+			QueueNotify::None => {}
+			QueueNotify::Port(port) => unsafe { outw(port, queue_index) },
+			QueueNotify::Mmio(addr) => unsafe {
+				write_volatile(addr.as_mut_ptr::<u16>(), queue_index)
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How a virtio PCI device is driven.
+pub enum VirtioTransport {
+	/// The legacy I/O port interface at the given base.
+	Legacy(usize),
+	/// The modern interface in memory BARs.
+	Modern(ModernTransport)
+}
+
+impl VirtioTransport {
+	/// Picks the transport for `bdf`: the modern one when the device exposes
+	/// its capabilities and offers `VIRTIO_F_VERSION_1`, otherwise the legacy
+	/// one at `io_base`. Logs the choice.
+	pub fn detect(bdf: Bdf, io_base: Option<usize>) -> Result<VirtioTransport, NullexError> {
+		let caps = find_modern_caps(bdf);
+		let modern = if caps.is_complete() {
+			match ModernTransport::map(bdf, &caps) {
+				Ok(modern) => Some(modern),
+				Err(e) => {
+					serial_println!("[VIRTIO] {}: failed to map modern registers: {:?}", bdf, e);
+					None
+				}
+			}
+		} else {
+			None
+		};
+
+		let version_1 = modern.is_some_and(|m| m.device_features() & VIRTIO_F_VERSION_1 != 0);
+		match (modern, io_base) {
+			(Some(modern), _) if version_1 => {
+				serial_println!("[VIRTIO] {}: using modern PCI transport (virtio 1.0)", bdf);
+				Ok(VirtioTransport::Modern(modern))
+			}
+			(_, Some(io_base)) => {
+				serial_println!(
+					"[VIRTIO] {}: using legacy PCI transport at io_base={:#x} (modern caps: {}, VERSION_1: {})",
+					bdf,
+					io_base,
+					caps.is_complete(),
+					version_1
+				);
+				Ok(VirtioTransport::Legacy(io_base))
+			}
+			_ => Err(NullexError::Io("No usable virtio transport"))
+		}
+	}
+
+	/// Returns whether this is the modern transport.
+	pub fn is_modern(&self) -> bool {
+		matches!(self, VirtioTransport::Modern(_))
+	}
+
+	/// Reads the features the device offers.
+	pub fn device_features(&self) -> u64 {
+		match self {
+			VirtioTransport::Legacy(io_base) => {
+				io_read::<QWORD>(*io_base, VIRTIO_IO_DEVICE_FEATURES).unwrap()
+			}
+			VirtioTransport::Modern(m) => m.device_features()
+		}
+	}
+
+	/// Writes the features the driver accepts.
+	pub fn set_driver_features(&self, features: u64) {
+		match self {
+			VirtioTransport::Legacy(io_base) => {
+				io_write::<QWORD>(*io_base, VIRTIO_IO_DRIVER_FEATURES, features).unwrap()
+			}
+			VirtioTransport::Modern(m) => {
+				ModernTransport::write::<u32>(m.common, COMMON_DRIVER_FEATURE_SELECT, 0);
+				ModernTransport::write::<u32>(m.common, COMMON_DRIVER_FEATURE, features as u32);
+				ModernTransport::write::<u32>(m.common, COMMON_DRIVER_FEATURE_SELECT, 1);
+				let hi = (features >> 32) as u32;
+				ModernTransport::write::<u32>(m.common, COMMON_DRIVER_FEATURE, hi);
+			}
+		}
+	}
+
+	/// Reads the device status register.
+	pub fn status(&self) -> u8 {
+		match self {
+			VirtioTransport::Legacy(io_base) => {
+				io_read::<BYTE>(*io_base, VIRTIO_IO_DEVICE_STATUS).unwrap()
+			}
+			VirtioTransport::Modern(m) => {
+				ModernTransport::read::<u8>(m.common, COMMON_DEVICE_STATUS)
+			}
+		}
+	}
+
+	/// Writes the device status register.
+	pub fn set_status(&self, status: u8) {
+		match self {
+			VirtioTransport::Legacy(io_base) => {
+				io_write::<BYTE>(*io_base, VIRTIO_IO_DEVICE_STATUS, status).unwrap()
+			}
+			VirtioTransport::Modern(m) => {
+				ModernTransport::write::<u8>(m.common, COMMON_DEVICE_STATUS, status)
+			}
+		}
+	}
+
+	/// Reads (and so acknowledges) the interrupt status.
+	pub fn read_isr(&self) -> u8 {
+		match self {
+			VirtioTransport::Legacy(io_base) => unsafe { inb((io_base + VIRTIO_IO_ISR) as u16) },
+			VirtioTransport::Modern(m) => ModernTransport::read::<u8>(m.isr, 0)
+		}
+	}
+
+	/// Reads a byte of the device specific configuration.
+	pub fn read_config(&self, offset: usize) -> u8 {
+		match self {
+			VirtioTransport::Legacy(io_base) => unsafe {
+				inb((io_base + VIRTIO_IO_DEVICE_CFG + offset) as u16)
+			},
+			VirtioTransport::Modern(m) => match m.device {
+				Some(device) => ModernTransport::read::<u8>(device, offset),
+				None => 0
+			}
+		}
+	}
+
+	/// Writes a byte of the device specific configuration.
+	pub fn write_config(&self, offset: usize, value: u8) {
+		match self {
+			VirtioTransport::Legacy(io_base) => unsafe {
+				outb((io_base + VIRTIO_IO_DEVICE_CFG + offset) as u16, value)
+			},
+			VirtioTransport::Modern(m) => {
+				if let Some(device) = m.device {
+					ModernTransport::write::<u8>(device, offset, value);
+				}
+			}
+		}
+	}
+
+	/// Selects queue `index` and returns its size, 0 if it doesn't exist.
+	pub fn queue_size(&self, index: u16) -> u16 {
+		match self {
+			VirtioTransport::Legacy(io_base) => unsafe {
+				outw((io_base + VIRTIO_IO_QUEUE_SELECT) as u16, index);
+				inw((io_base + VIRTIO_IO_QUEUE_SIZE) as u16)
+			},
+			VirtioTransport::Modern(m) => {
+				ModernTransport::write::<u16>(m.common, COMMON_QUEUE_SELECT, index);
+				ModernTransport::read::<u16>(m.common, COMMON_QUEUE_SIZE)
+			}
+		}
+	}
+
+	/// Hands queue `index` to the device. `phys` is the start of the legacy
+	/// layout: descriptors, then the available ring, then the used ring at
+	/// `used_offset`.
+	pub fn setup_queue(
+		&self,
+		index: u16,
+		phys: PhysAddr,
+		avail_offset: u64,
+		used_offset: u64
+	) -> QueueNotify {
+		match self {
+			VirtioTransport::Legacy(io_base) => unsafe {
+				outw((io_base + VIRTIO_IO_QUEUE_SELECT) as u16, index);
+				outl((io_base + VIRTIO_IO_QUEUE_ADDR) as u16, (phys.as_u64() >> 12) as u32);
+				QueueNotify::Port((io_base + VIRTIO_IO_QUEUE_NOTIFY) as u16)
+			},
+			VirtioTransport::Modern(m) => {
+				let phys = phys.as_u64();
+				ModernTransport::write::<u16>(m.common, COMMON_QUEUE_SELECT, index);
+				ModernTransport::write::<u64>(m.common, COMMON_QUEUE_DESC, phys);
+				ModernTransport::write::<u64>(m.common, COMMON_QUEUE_DRIVER, phys + avail_offset);
+				ModernTransport::write::<u64>(m.common, COMMON_QUEUE_DEVICE, phys + used_offset);
+				let notify_off = ModernTransport::read::<u16>(m.common, COMMON_QUEUE_NOTIFY_OFF);
+				ModernTransport::write::<u16>(m.common, COMMON_QUEUE_ENABLE, 1);
+
+				let offset = notify_off as u64 * m.notify_off_multiplier as u64;
+				QueueNotify::Mmio(m.notify + offset)
+			}
+		}
+	}
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{drivers::virtio::transport::*, utils::ktest::TestError};
+
+	pub fn test_virtio_modern_caps_parsing() -> Result<(), TestError> {
+		// cap_vndr=0x09, cap_next=0x50, cap_len=20, cfg_type=notify; bar 4
+		let notify = VirtioPciCap::from_raw([0x0214_5009, 0x0000_0004, 0x3000, 0x1000, 4]);
+		assert_eq!(notify.cfg_type, VIRTIO_PCI_CAP_NOTIFY_CFG);
+		assert_eq!(notify.bar, 4);
+		assert_eq!((notify.offset, notify.length), (0x3000, 0x1000));
+		assert_eq!(notify.notify_off_multiplier, 4);
+
+		// the multiplier dword belongs to the next capability for other types
+		let common = VirtioPciCap::from_raw([0x0110_6009, 0x0000_0004, 0, 0x1000, 0xFFFF]);
+		assert_eq!(common.notify_off_multiplier, 0);
+		let isr = VirtioPciCap { cfg_type: VIRTIO_PCI_CAP_ISR_CFG, offset: 0x1000, ..common };
+
+		let mut caps = ModernCaps::default();
+		caps.add(common);
+		caps.add(notify);
+		assert!(!caps.is_complete());
+
+		caps.add(isr);
+		assert!(caps.is_complete());
+
+		// later duplicates and out of range BARs are ignored
+		caps.add(VirtioPciCap { bar: 2, ..common });
+		caps.add(VirtioPciCap { cfg_type: VIRTIO_PCI_CAP_DEVICE_CFG, bar: 6, ..common });
+		assert_eq!(caps.common, Some(common));
+		assert_eq!(caps.device, None);
+		Ok(())
+	}
+	crate::create_test!(test_virtio_modern_caps_parsing);
+}
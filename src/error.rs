@@ -6,7 +6,11 @@
 
 use alloc::string::String;
 use thiserror::Error;
-use x86_64::{VirtAddr, structures::paging::{PhysFrame, Size4KiB, mapper::MapToError}};
+use x86_64::{
+    PhysAddr,
+    VirtAddr,
+    structures::paging::{PhysFrame, Size4KiB, mapper::{MapToError, UnmapError}}
+};
 use crate::alloc::string::ToString;
 
 #[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
@@ -42,9 +46,18 @@ pub enum NullexError {
     /// An attempt was made to map a virtual page that is already assigned to a frame.
     #[error("page already mapped: frame={0:?}")]
     PageAlreadyMapped(PhysFrame),
+    /// An attempt was made to unmap a virtual page that isn't mapped.
+    #[error("page not mapped")]
+    PageNotMapped,
+    /// A page table entry points at a physical address that isn't a valid frame.
+    #[error("invalid frame address: {0:?}")]
+    InvalidFrameAddress(PhysAddr),
     /// Failed to allocate a contiguous memory block for Direct Memory Access.
     #[error("dma allocation failed")]
     DmaAllocFailed,
+    /// A block passed to `dma_free` isn't mapped the way `dma_alloc` mapped it.
+    #[error("dma free of a block dma_alloc didn't map")]
+    DmaFreeMismatch,
     /// The kernel's `FrameAllocator` is not initialized.
     #[error("frame allocator not initialized")]
     FrameAllocatorNotInitialized,
@@ -109,14 +122,51 @@ pub enum NullexError {
     /// Data could not be read from the ATA disk.
     #[error("ata read failed")]
     AtaReadError,
+    /// Data could not be written to the ATA disk.
+    #[error("ata write failed")]
+    AtaWriteError,
     /// The ATA drive reported an internal hardware or controller fault.
     #[error("ata drive error")]
     AtaDriveError,
+    /// The virtio block device didn't complete a request in time.
+    #[error("virtio-blk timeout")]
+    VirtioBlkTimeout,
+    /// The virtio block device was given up on after a request timed out.
+    #[error("virtio-blk device failed")]
+    VirtioBlkFailed,
+    /// The virtio block device reported an I/O error for a request.
+    #[error("virtio-blk i/o error")]
+    VirtioBlkIoError,
+    /// The virtio block device doesn't support a request type.
+    #[error("virtio-blk request unsupported")]
+    VirtioBlkUnsupported,
+    /// The virtio block device is read-only.
+    #[error("virtio-blk device is read-only")]
+    VirtioBlkReadOnly,
+    /// The boot framebuffer has a layout the console can't draw to.
+    #[error("unsupported framebuffer")]
+    UnsupportedFramebuffer,
+    /// A color name isn't one of the 16 VGA text mode colors.
+    #[error("unknown color, expected one of: black, blue, green, cyan, red, magenta, brown, \
+        lightgray, darkgray, lightblue, lightgreen, lightcyan, lightred, pink, yellow, white")]
+    UnknownColor,
 
     // -- FS Errors -- //
     /// The kernel cannot find the file specified.
     #[error("file not found")]
     FileNotFound,
+    /// The reserved disk region holds no filesystem image.
+    #[error("no filesystem image on disk")]
+    NoFsImage,
+    /// A filesystem image is truncated or fails its checksum.
+    #[error("corrupt filesystem image")]
+    FsImageCorrupt,
+    /// A filesystem image was written by an incompatible format version.
+    #[error("unsupported filesystem image version {0}")]
+    FsImageVersion(u16),
+    /// The filesystem doesn't fit in the reserved disk region.
+    #[error("filesystem image too large")]
+    FsImageTooLarge,
 
     // --- VirtIO / Network Errors --- //
     /// The handshake or setup process for a VirtIO device failed.
@@ -158,6 +208,12 @@ pub enum NullexError {
     /// A required MAC address was missing for a network operation.
     #[error("missing mac address")]
     MissingMacAddress,
+    /// No DHCP server answered before the timeout.
+    #[error("dhcp timed out")]
+    DhcpTimeout,
+    /// The DHCP server refused our request for the offered address.
+    #[error("dhcp request refused")]
+    DhcpNak,
 
     // -- Network Errors -- //
     #[error("tcp connection failed")]
@@ -168,6 +224,9 @@ pub enum NullexError {
     TcpFailedToReceive,
     #[error("invalid http response")]
     HttpInvalidResponse,
+    /// A network resource limit (see `net::limits`) has been reached.
+    #[error("resource limit reached: {0}")]
+    ResourceLimit(&'static str),
 
     // --- Serial Output Errors --- //
     /// An unspecified error occurred during serial port communication.
@@ -212,11 +271,32 @@ pub enum NullexError {
     /// The process queue is full and cannot accept new processes.
     #[error("process queue full")]
     ProcessQueueFull,
+    /// A process-only operation was called outside of a running process.
+    #[error("no process is currently running")]
+    NoCurrentProcess,
+    /// The running process has been asked to stop.
+    #[error("process cancelled")]
+    Cancelled,
+    /// The process isn't a child of the caller, so it can't be waited on.
+    #[error("not a child of the calling process")]
+    NotChild,
 
     // --- Process Errors (ELF) --- //
     /// ELF magic number is incorrect
     #[error("ELF magic number doesnt match")]
     ElfMagicIncorrect,
+    /// The ELF file isn't a 64-bit little-endian one.
+    #[error("ELF file is not 64-bit little-endian")]
+    ElfUnsupportedClass,
+    /// The ELF file is built for another machine than x86_64.
+    #[error("ELF file is not for x86_64 (machine {0:#x})")]
+    ElfWrongMachine(u16),
+    /// The ELF file is a relocatable object, shared object or core dump.
+    #[error("ELF file is not an executable (type {0})")]
+    ElfNotExecutable(u16),
+    /// A header or segment points outside of the file or of user memory.
+    #[error("malformed ELF file: {0}")]
+    ElfMalformed(&'static str),
 
     // non-panicking errors.
     /// A non-panicking failure occurred during a component's initialization phase.
@@ -240,6 +320,16 @@ impl From<MapToError<Size4KiB>> for NullexError {
     }
 }
 
+impl From<UnmapError> for NullexError {
+    fn from(value: UnmapError) -> Self {
+        match value {
+            UnmapError::ParentEntryHugePage => NullexError::ParentEntryHugePage,
+            UnmapError::PageNotMapped => NullexError::PageNotMapped,
+            UnmapError::InvalidFrameAddress(addr) => NullexError::InvalidFrameAddress(addr),
+        }
+    }
+}
+
 impl NullexError {
     // do we need this? im not sure if the #[error] does that already.
     /// Represents the Errors as `str`'s
@@ -12,6 +12,27 @@ use x86_64::instructions::{interrupts, port::Port};
 
 use crate::error::NullexError;
 
+/// Size of a sector in bytes.
+pub const ATA_SECTOR_SIZE: usize = 512;
+/// Number of addressable sectors with 48-bit LBA.
+const LBA48_MAX_SECTORS: u64 = 1 << 48;
+
+/// READ SECTORS EXT (48-bit LBA, PIO)
+const ATA_CMD_READ_SECTORS_EXT: u8 = 0x24;
+/// WRITE SECTORS EXT (48-bit LBA, PIO)
+const ATA_CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+/// FLUSH CACHE EXT
+const ATA_CMD_CACHE_FLUSH_EXT: u8 = 0xEA;
+
+/// Status register: busy
+const ATA_SR_BSY: u8 = 0x80;
+/// Status register: drive fault
+const ATA_SR_DF: u8 = 0x20;
+/// Status register: data request ready
+const ATA_SR_DRQ: u8 = 0x08;
+/// Status register: error
+const ATA_SR_ERR: u8 = 0x01;
+
 pub struct AtaDisk {
 	data_port: Port<u16>,
 	pub sector_count_port: Port<u8>,
@@ -20,7 +41,8 @@ pub struct AtaDisk {
 	pub lba_high_port: Port<u8>,
 	pub device_port: Port<u8>,
 	pub command_port: Port<u8>,
-	pub status_port: Port<u8>
+	pub status_port: Port<u8>,
+	pub alt_status_port: Port<u8>
 }
 
 impl AtaDisk {
@@ -35,7 +57,8 @@ impl AtaDisk {
 			lba_high_port: Port::new(0x1F5),
 			device_port: Port::new(0x1F6),
 			command_port: Port::new(0x1F7),
-			status_port: Port::new(0x1F7)
+			status_port: Port::new(0x1F7),
+			alt_status_port: Port::new(0x3F6)
 		}
 	}
 
@@ -58,20 +81,133 @@ impl AtaDisk {
 		Err(NullexError::AtaTimeout)
 	}
 
+	/// Waits until the drive has data ready to transfer (DRQ set, BSY clear).
+	pub fn wait_drq(&mut self) -> Result<(), NullexError> {
+		let mut timeout = 100_000;
+		unsafe {
+			while timeout > 0 {
+				let status = self.status_port.read();
+				if status & ATA_SR_BSY == 0 {
+					if status & (ATA_SR_ERR | ATA_SR_DF) != 0 {
+						return Err(NullexError::AtaDriveError);
+					}
+					if status & ATA_SR_DRQ != 0 {
+						return Ok(());
+					}
+				}
+				timeout -= 1;
+			}
+		}
+		Err(NullexError::AtaTimeout)
+	}
+
+	/// Gives the drive the 400ns it needs to update its status after a
+	/// command, by reading the alternate status register four times.
+	fn delay_400ns(&mut self) {
+		for _ in 0..4 {
+			unsafe {
+				self.alt_status_port.read();
+			}
+		}
+	}
+
+	/// Selects the drive, programs a 48-bit LBA and sector count, and issues
+	/// `command`. The drive must not be busy.
+	fn issue_lba48(&mut self, lba: u64, count: u16, command: u8) {
+		unsafe {
+			// select `slave` drive (second disk in QEMU) in LBA mode
+			self.device_port.write(0x50);
+			self.delay_400ns();
+
+			// high bytes first, then low bytes (48-bit LBA register order)
+			self.sector_count_port.write((count >> 8) as u8);
+			self.lba_low_port.write((lba >> 24) as u8);
+			self.lba_mid_port.write((lba >> 32) as u8);
+			self.lba_high_port.write((lba >> 40) as u8);
+			self.sector_count_port.write(count as u8);
+			self.lba_low_port.write(lba as u8);
+			self.lba_mid_port.write((lba >> 8) as u8);
+			self.lba_high_port.write((lba >> 16) as u8);
+
+			self.command_port.write(command);
+		}
+	}
+
+	/// Reads a single sector. Kept for compatibility, delegates to
+	/// `read_sectors`.
 	pub fn read_sector(&mut self, lba: u32, buf: &mut [u8; 512]) -> Result<(), NullexError> {
+		self.read_sectors(lba as u64, 1, buf)
+	}
+
+	/// Reads `count` sectors starting at `lba` into `buf` using 48-bit LBA
+	/// addressing.
+	///
+	/// `buf` must be exactly `count * 512` bytes long.
+	pub fn read_sectors(
+		&mut self,
+		lba: u64,
+		count: u16,
+		buf: &mut [u8]
+	) -> Result<(), NullexError> {
+		if count == 0 || lba + count as u64 > LBA48_MAX_SECTORS {
+			return Err(NullexError::InvalidArgument);
+		}
+		if buf.len() != count as usize * ATA_SECTOR_SIZE {
+			return Err(NullexError::BufferTooSmall);
+		}
+
 		interrupts::without_interrupts(|| {
-			unsafe {
-				// select `slave` drive (second disk in QEMU)
-				self.device_port.write(0xF0 | ((lba >> 24) as u8 & 0x0F));
+			self.wait_ready()?;
+
+			self.issue_lba48(lba, count, ATA_CMD_READ_SECTORS_EXT);
+
+			for sector in buf.chunks_exact_mut(ATA_SECTOR_SIZE) {
+				self.delay_400ns();
+				self.wait_drq()?;
 
-				// full sector read
 				for i in 0..256 {
-					let word = self.data_port.read();
-					buf[i * 2] = word as u8;
-					buf[i * 2 + 1] = (word >> 8) as u8;
+					let word = unsafe { self.data_port.read() };
+					sector[i * 2] = word as u8;
+					sector[i * 2 + 1] = (word >> 8) as u8;
 				}
-				Ok(())
 			}
+			Ok(())
+		})
+	}
+
+	/// Writes a single sector at `lba` and flushes the drive's write cache,
+	/// so the data is on the platter once this returns `Ok`.
+	pub fn write_disk_sector(&mut self, lba: u64, buf: &[u8; 512]) -> Result<(), NullexError> {
+		if lba >= LBA48_MAX_SECTORS {
+			return Err(NullexError::InvalidArgument);
+		}
+
+		interrupts::without_interrupts(|| {
+			self.wait_ready()?;
+			self.issue_lba48(lba, 1, ATA_CMD_WRITE_SECTORS_EXT);
+
+			// the drive raises DRQ once it is ready to take the sector
+			self.delay_400ns();
+			self.wait_drq().map_err(|e| match e {
+				NullexError::AtaDriveError => NullexError::AtaWriteError,
+				e => e
+			})?;
+
+			for i in 0..256 {
+				let word = u16::from_le_bytes([buf[i * 2], buf[i * 2 + 1]]);
+				unsafe { self.data_port.write(word) };
+			}
+
+			// wait for the drive to take the data before flushing
+			self.delay_400ns();
+			self.wait_ready().map_err(|e| match e {
+				NullexError::AtaDriveError => NullexError::AtaWriteError,
+				e => e
+			})?;
+
+			unsafe { self.command_port.write(ATA_CMD_CACHE_FLUSH_EXT) };
+			self.delay_400ns();
+			self.wait_ready()
 		})
 	}
 }
@@ -0,0 +1,282 @@
+//!
+//! block_cache.rs
+//!
+//! LRU sector cache sitting in front of the kernel's block devices.
+//!
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{
+	drivers::virtio::blk::{VirtioBlkDisk, virtio_blk_present},
+	error::NullexError,
+	fs::ata::AtaDisk,
+	lazy_static,
+	utils::mutex::SpinMutex
+};
+
+/// Size of a single disk sector in bytes.
+pub const SECTOR_SIZE: usize = 512;
+/// Number of sectors kept by the global ATA cache.
+pub const DEFAULT_CACHE_ENTRIES: usize = 64;
+
+lazy_static! {
+	/// Static reference to the cache in front of the ATA disk. Every sector
+	/// read or write of the disk should go through this.
+	pub static ref ATA_CACHE: SpinMutex<BlockCache<AtaDisk>> =
+		SpinMutex::new(BlockCache::new(unsafe { AtaDisk::new() }, DEFAULT_CACHE_ENTRIES));
+	/// Static reference to the cache in front of the virtio disk.
+	pub static ref VIRTIO_BLK_CACHE: SpinMutex<BlockCache<VirtioBlkDisk>> =
+		SpinMutex::new(BlockCache::new(VirtioBlkDisk, DEFAULT_CACHE_ENTRIES));
+}
+
+/// A device that can be read and written a sector at a time.
+pub trait BlockDevice {
+	/// Reads the sector at `lba` into `buf`.
+	fn read_sector(&mut self, lba: u64, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), NullexError>;
+
+	/// Writes `buf` to the sector at `lba`.
+	fn write_sector(&mut self, lba: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), NullexError>;
+}
+
+/// A cached sector.
+struct CacheEntry {
+	lba: u64,
+	/// Value of the cache's use counter when this entry was last touched.
+	last_used: u64,
+	data: Box<[u8; SECTOR_SIZE]>
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// Hit and miss counters of a `BlockCache`.
+pub struct CacheStats {
+	/// Reads served from the cache.
+	pub hits: u64,
+	/// Reads that had to go to the device.
+	pub misses: u64
+}
+
+/// A write-through, least recently used sector cache keyed by LBA.
+pub struct BlockCache<D: BlockDevice> {
+	device: D,
+	entries: Vec<CacheEntry>,
+	capacity: usize,
+	/// Monotonic counter used to order entries by recency.
+	clock: u64,
+	stats: CacheStats
+}
+
+impl<D: BlockDevice> BlockCache<D> {
+	/// Creates a cache holding up to `capacity` sectors of `device`.
+	pub fn new(device: D, capacity: usize) -> BlockCache<D> {
+		BlockCache {
+			device,
+			entries: Vec::with_capacity(capacity),
+			capacity,
+			clock: 0,
+			stats: CacheStats::default()
+		}
+	}
+
+	/// Returns the hit and miss counters.
+	pub fn stats(&self) -> CacheStats {
+		self.stats
+	}
+
+	/// Returns how many sectors are currently cached.
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Returns whether no sectors are cached.
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// Returns the device behind the cache.
+	pub fn device(&mut self) -> &mut D {
+		&mut self.device
+	}
+
+	/// Reads the sector at `lba`, from the cache if possible.
+	pub fn read_sector(&mut self, lba: u64, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), NullexError> {
+		self.clock += 1;
+
+		if let Some(entry) = self.entries.iter_mut().find(|e| e.lba == lba) {
+			entry.last_used = self.clock;
+			buf.copy_from_slice(&entry.data[..]);
+			self.stats.hits += 1;
+			return Ok(());
+		}
+
+		self.stats.misses += 1;
+		self.device.read_sector(lba, buf)?;
+		self.insert(lba, buf);
+		Ok(())
+	}
+
+	/// Writes `buf` to the sector at `lba`. The device is written first; the
+	/// cached copy is only updated once the write succeeded, and dropped if
+	/// it failed.
+	pub fn write_sector(&mut self, lba: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), NullexError> {
+		self.clock += 1;
+
+		if let Err(e) = self.device.write_sector(lba, buf) {
+			self.invalidate(lba);
+			return Err(e);
+		}
+
+		match self.entries.iter_mut().find(|e| e.lba == lba) {
+			Some(entry) => {
+				entry.last_used = self.clock;
+				entry.data.copy_from_slice(buf);
+			}
+			None => self.insert(lba, buf)
+		}
+		Ok(())
+	}
+
+	/// Drops the cached copy of the sector at `lba`, if there is one.
+	pub fn invalidate(&mut self, lba: u64) {
+		self.entries.retain(|e| e.lba != lba);
+	}
+
+	/// Drops every cached sector.
+	pub fn invalidate_all(&mut self) {
+		self.entries.clear();
+	}
+
+	/// Caches a copy of `buf`, evicting the least recently used sector if the
+	/// cache is full.
+	fn insert(&mut self, lba: u64, buf: &[u8; SECTOR_SIZE]) {
+		if self.capacity == 0 {
+			return;
+		}
+
+		if self.entries.len() >= self.capacity
+			&& let Some(lru) = self
+				.entries
+				.iter()
+				.enumerate()
+				.min_by_key(|(_, e)| e.last_used)
+				.map(|(i, _)| i)
+		{
+			self.entries.swap_remove(lru);
+		}
+
+		self.entries.push(CacheEntry {
+			lba,
+			last_used: self.clock,
+			data: Box::new(*buf)
+		});
+	}
+}
+
+impl BlockDevice for AtaDisk {
+	fn read_sector(&mut self, lba: u64, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), NullexError> {
+		self.read_sectors(lba, 1, buf)
+	}
+
+	fn write_sector(&mut self, lba: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), NullexError> {
+		self.write_disk_sector(lba, buf)
+	}
+}
+
+/// Reads the sector at `lba` of the kernel's disk through its cache. That is
+/// the virtio disk if one was probed, otherwise the ATA disk.
+pub fn read_disk_sector(lba: u64, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), NullexError> {
+	if virtio_blk_present() {
+		VIRTIO_BLK_CACHE.lock().read_sector(lba, buf)
+	} else {
+		ATA_CACHE.lock().read_sector(lba, buf)
+	}
+}
+
+/// Writes the sector at `lba` of the kernel's disk through its cache. That is
+/// the virtio disk if one was probed, otherwise the ATA disk.
+pub fn write_disk_sector(lba: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), NullexError> {
+	if virtio_blk_present() {
+		VIRTIO_BLK_CACHE.lock().write_sector(lba, buf)
+	} else {
+		ATA_CACHE.lock().write_sector(lba, buf)
+	}
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::vec::Vec;
+
+	use crate::{error::NullexError, fs::block_cache::*, utils::ktest::TestError};
+
+	/// An in-memory disk counting how often it is actually read.
+	struct MemDisk {
+		sectors: Vec<[u8; SECTOR_SIZE]>,
+		reads: usize
+	}
+
+	impl BlockDevice for MemDisk {
+		fn read_sector(&mut self, lba: u64, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), NullexError> {
+			let sector = self.sectors.get(lba as usize).ok_or(NullexError::InvalidArgument)?;
+			buf.copy_from_slice(sector);
+			self.reads += 1;
+			Ok(())
+		}
+
+		fn write_sector(&mut self, lba: u64, buf: &[u8; SECTOR_SIZE]) -> Result<(), NullexError> {
+			let sector = self.sectors.get_mut(lba as usize).ok_or(NullexError::InvalidArgument)?;
+			sector.copy_from_slice(buf);
+			Ok(())
+		}
+	}
+
+	fn mem_disk(sectors: usize) -> MemDisk {
+		let mut disk = MemDisk {
+			sectors: Vec::new(),
+			reads: 0
+		};
+		for i in 0..sectors {
+			disk.sectors.push([i as u8; SECTOR_SIZE]);
+		}
+		disk
+	}
+
+	pub fn test_block_cache_repeated_reads_hit() -> Result<(), TestError> {
+		let mut cache = BlockCache::new(mem_disk(4), 2);
+		let mut buf = [0u8; SECTOR_SIZE];
+
+		// the ext2 superblock lives at byte 1024, i.e. sector 2
+		for _ in 0..3 {
+			cache.read_sector(2, &mut buf).map_err(|_| TestError::Error)?;
+			assert_eq!(buf, [2; SECTOR_SIZE]);
+		}
+		assert_eq!(cache.device().reads, 1);
+		assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 1 });
+		Ok(())
+	}
+	crate::create_test!(test_block_cache_repeated_reads_hit);
+
+	pub fn test_block_cache_lru_eviction_and_write_through() -> Result<(), TestError> {
+		let mut cache = BlockCache::new(mem_disk(4), 2);
+		let mut buf = [0u8; SECTOR_SIZE];
+
+		cache.read_sector(0, &mut buf).map_err(|_| TestError::Error)?;
+		cache.read_sector(1, &mut buf).map_err(|_| TestError::Error)?;
+		// touch 0 so 1 becomes the least recently used
+		cache.read_sector(0, &mut buf).map_err(|_| TestError::Error)?;
+		cache.read_sector(3, &mut buf).map_err(|_| TestError::Error)?;
+		assert_eq!(cache.len(), 2);
+		assert_eq!(cache.device().reads, 3);
+
+		cache.read_sector(1, &mut buf).map_err(|_| TestError::Error)?;
+		assert_eq!(cache.device().reads, 4);
+
+		cache.write_sector(1, &[0xAA; SECTOR_SIZE]).map_err(|_| TestError::Error)?;
+		assert_eq!(cache.device().sectors[1], [0xAA; SECTOR_SIZE]);
+		cache.read_sector(1, &mut buf).map_err(|_| TestError::Error)?;
+		assert_eq!(buf, [0xAA; SECTOR_SIZE]);
+		assert_eq!(cache.device().reads, 4);
+
+		assert!(cache.write_sector(9, &[0; SECTOR_SIZE]).is_err());
+		Ok(())
+	}
+	crate::create_test!(test_block_cache_lru_eviction_and_write_through);
+}
@@ -6,6 +6,9 @@
 
 #[allow(missing_docs)]
 pub mod ata;
+pub mod block_cache;
+pub mod persist;
+pub mod procfs;
 pub mod ramfs;
 
 use alloc::{
@@ -13,7 +16,13 @@ use alloc::{
 	vec::Vec
 };
 
-use crate::{drivers::keyboard::scancode::CWD, fs::ramfs::FileSystem, utils::mutex::SpinMutex};
+use crate::{
+	arch::x86_64::user::CURRENT_USER_PROCESS,
+	drivers::keyboard::scancode::CWD,
+	fs::ramfs::{FileSystem, FsError, Stat},
+	task::executor,
+	utils::{logger::sinks::FILE_SINK, mutex::SpinMutex}
+};
 
 // TODO: maybe lazy_static!
 /// Current `FileSystem` in use.
@@ -22,25 +31,103 @@ pub static FS: SpinMutex<Option<FileSystem>> = SpinMutex::new(None);
 /// Initialises the kernel's `FileSystem`
 pub fn init_fs(fs: FileSystem) {
 	*FS.lock() = Some(fs);
+	// write out anything logged before the filesystem existed
+	FILE_SINK.flush();
 }
 
 /// Use the current `FileSystem` to perform an action.
 pub fn with_fs<R>(f: impl FnOnce(&mut FileSystem) -> R) -> R {
 	let mut fs_lock = FS.lock();
 	let fs_ref = fs_lock.as_mut().expect("Filesystem must be initialized");
+	f(fs_ref)
+}
+
+/// Reads a whole file. Paths under `/proc` are generated by `procfs`, every
+/// other path is read from the current `FileSystem`.
+pub fn read_file(path: &str) -> Result<Vec<u8>, FsError> {
+	if procfs::is_proc_path(path) {
+		procfs::read(path)
+	} else {
+		with_fs(|fs| fs.read_file(path).map(|content| content.to_vec()))
+	}
+}
+
+/// Lists a directory, including the virtual `/proc` tree.
+pub fn list_dir(path: &str) -> Result<Vec<String>, FsError> {
+	if procfs::is_proc_path(path) {
+		procfs::list_dir(path)
+	} else {
+		with_fs(|fs| fs.list_dir(path))
+	}
+}
+
+/// Returns the type, permissions, size and timestamps of a path, including
+/// the virtual `/proc` tree.
+pub fn stat(path: &str) -> Result<Stat, FsError> {
+	if procfs::is_proc_path(path) {
+		procfs::stat(path)
+	} else {
+		with_fs(|fs| fs.stat(path))
+	}
+}
+
+/// If a path is a directory, including the virtual `/proc` tree.
+pub fn is_dir(path: &str) -> bool {
+	if procfs::is_proc_path(path) {
+		procfs::is_dir(path)
+	} else {
+		with_fs(|fs| fs.is_dir(path))
+	}
+}
+
+/// Returns the working directory of the caller: the running user program's,
+/// else the running process's, else the shell's `CWD` for processes without
+/// their own.
+pub fn current_dir() -> String {
+	with_current_cwd(|cwd| cwd.clone()).unwrap_or_else(|| CWD.lock().clone())
+}
+
+/// Changes the caller's working directory (see `current_dir`) to the
+/// directory at `path`, relative to the current one.
+pub fn set_current_dir(path: &str) -> Result<(), FsError> {
+	let path = resolve_path(path);
+	if !is_dir(&path) {
+		return Err(match read_file(&path) {
+			Ok(_) => FsError::NotADirectory,
+			Err(_) => FsError::EntryNotFound
+		});
+	}
 
-	// release VGA lock before FS operations
-	unsafe { crate::vga_buffer::WRITER.force_unlock() };
-	let result = f(fs_ref);
-	crate::vga_buffer::WRITER.lock();
+	let mut path = Some(path);
+	if with_current_cwd(|cwd| *cwd = path.take().unwrap_or_default()).is_none() {
+		*CWD.lock() = path.unwrap_or_default();
+	}
+	Ok(())
+}
 
-	result
+/// Runs `f` on the working directory of the running user program or else
+/// the running process, if it has its own.
+fn with_current_cwd<R>(f: impl FnOnce(&mut String) -> R) -> Option<R> {
+	// both processes are locked by whoever runs them, so they're reached
+	// through the raw pointers like the syscalls do
+	let processes = unsafe { [CURRENT_USER_PROCESS, executor::CURRENT_PROCESS_GUARD] };
+	let cwd = processes
+		.into_iter()
+		.find_map(|process| unsafe { process.as_mut() })
+		.and_then(|process| process.cwd.as_mut())?;
+	Some(f(cwd))
 }
 
 /// Helper function to resolve a file path relative to the current working
 /// directory.
 pub fn resolve_path(path: &str) -> String {
-	let mut cwd = CWD.lock().clone();
+	resolve_path_from(&current_dir(), path)
+}
+
+/// Resolves `path` relative to the working directory `cwd`, which another
+/// process's `Process.cwd` can be passed as.
+pub fn resolve_path_from(cwd: &str, path: &str) -> String {
+	let mut cwd = cwd.to_string();
 	let mut result = if path.starts_with('/') {
 		String::new()
 	} else {
@@ -72,3 +159,52 @@ fn normalize_path(path: &str) -> String {
 		format!("/{}/", stack.join("/"))
 	}
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{
+		fs::*,
+		task::{Process, ProcessId, ProcessState},
+		utils::ktest::TestError
+	};
+
+	fn process_in(cwd: &str) -> Result<Process, TestError> {
+		let state = ProcessState::for_test(ProcessId::new(0), None);
+		let mut process = Process::new(state).map_err(|_| TestError::Error)?;
+		process.cwd = Some(cwd.to_string());
+		Ok(process)
+	}
+
+	/// Runs `f` as if `process` were the one being polled.
+	fn run_as<R>(process: &mut Process, f: impl FnOnce() -> R) -> R {
+		let previous = unsafe { executor::CURRENT_PROCESS_GUARD };
+		unsafe { executor::CURRENT_PROCESS_GUARD = process as *mut Process };
+		let result = f();
+		unsafe { executor::CURRENT_PROCESS_GUARD = previous };
+		result
+	}
+
+	pub fn test_processes_keep_separate_cwds() -> Result<(), TestError> {
+		let shell_cwd = CWD.lock().clone();
+		let mut first = process_in("/")?;
+		let mut second = process_in("/")?;
+
+		run_as(&mut first, || set_current_dir("/proc")).map_err(|_| TestError::Error)?;
+		assert_eq!(run_as(&mut first, current_dir), "/proc/");
+		assert_eq!(run_as(&mut second, current_dir), "/");
+		assert_eq!(run_as(&mut first, || resolve_path("uptime")), "/proc/uptime/");
+		assert_eq!(run_as(&mut second, || resolve_path("uptime")), "/uptime/");
+
+		// relative to the process's own directory, and only if it exists
+		run_as(&mut second, || set_current_dir("proc")).map_err(|_| TestError::Error)?;
+		assert!(run_as(&mut first, || set_current_dir("no-such-dir")).is_err());
+		assert_eq!(run_as(&mut first, current_dir), "/proc/");
+		assert_eq!(second.cwd.as_deref(), Some("/proc/"));
+
+		// the shell's directory is untouched
+		assert_eq!(*CWD.lock(), shell_cwd);
+		assert_eq!(resolve_path_from("/proc/", "../apps"), "/apps/");
+		Ok(())
+	}
+	crate::create_test!(test_processes_keep_separate_cwds);
+}
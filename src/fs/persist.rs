@@ -0,0 +1,110 @@
+//!
+//! persist.rs
+//!
+//! Saving the RAMFS to, and loading it from, a reserved region of the disk
+//! (the virtio disk when there is one, otherwise the ATA disk).
+//!
+
+use alloc::{string::ToString, vec::Vec};
+
+use crate::{
+	drivers::keyboard::scancode::CWD,
+	error::NullexError,
+	fs::{
+		self,
+		block_cache::{SECTOR_SIZE, read_disk_sector, write_disk_sector},
+		ramfs::{FileSystem, FsError, IMAGE_HEADER_LEN, image_body_len}
+	},
+	serial_println
+};
+
+/// First sector of the region the RAMFS image is stored in (1MiB into the
+/// disk, clear of any boot sector or partition table).
+pub const PERSIST_START_LBA: u64 = 2048;
+/// Size of the reserved region in sectors (4MiB).
+pub const PERSIST_MAX_SECTORS: u64 = 8192;
+
+fn map_image_error(e: FsError) -> NullexError {
+	match e {
+		FsError::UnsupportedVersion(v) => NullexError::FsImageVersion(v),
+		_ => NullexError::FsImageCorrupt
+	}
+}
+
+/// Writes the current `FileSystem` to the reserved disk region.
+///
+/// The first sector, which holds the image header, is written last, so the
+/// header never describes a body that hasn't been written yet. Returns the
+/// image size in bytes.
+pub fn sync() -> Result<usize, NullexError> {
+	let image = fs::with_fs(|fs| fs.serialize());
+	let sectors = image.len().div_ceil(SECTOR_SIZE) as u64;
+	if sectors > PERSIST_MAX_SECTORS {
+		return Err(NullexError::FsImageTooLarge);
+	}
+
+	let mut chunks: Vec<[u8; SECTOR_SIZE]> = image
+		.chunks(SECTOR_SIZE)
+		.map(|chunk| {
+			let mut sector = [0u8; SECTOR_SIZE];
+			sector[..chunk.len()].copy_from_slice(chunk);
+			sector
+		})
+		.collect();
+
+	let header = chunks.remove(0);
+	for (i, sector) in chunks.iter().enumerate() {
+		write_disk_sector(PERSIST_START_LBA + 1 + i as u64, sector)?;
+	}
+	write_disk_sector(PERSIST_START_LBA, &header)?;
+
+	serial_println!("[FS] Synced {} bytes ({} sectors) to disk", image.len(), sectors);
+	Ok(image.len())
+}
+
+/// Reads the image in the reserved disk region and replaces the current
+/// `FileSystem` with it.
+///
+/// The current `FileSystem` is left untouched if the region holds no image,
+/// a corrupt one, or one written by a different format version.
+pub fn mount() -> Result<(), NullexError> {
+	let mut sector = [0u8; SECTOR_SIZE];
+	read_disk_sector(PERSIST_START_LBA, &mut sector)?;
+
+	let body_len = match image_body_len(&sector) {
+		Ok(len) => len,
+		Err(FsError::CorruptImage) => return Err(NullexError::NoFsImage),
+		Err(e) => return Err(map_image_error(e))
+	};
+
+	let total = IMAGE_HEADER_LEN + body_len;
+	let sectors = total.div_ceil(SECTOR_SIZE) as u64;
+	if sectors > PERSIST_MAX_SECTORS {
+		return Err(NullexError::FsImageCorrupt);
+	}
+
+	let mut image = Vec::with_capacity(sectors as usize * SECTOR_SIZE);
+	image.extend_from_slice(&sector);
+	for lba in PERSIST_START_LBA + 1..PERSIST_START_LBA + sectors {
+		read_disk_sector(lba, &mut sector)?;
+		image.extend_from_slice(&sector);
+	}
+
+	let new_fs = FileSystem::deserialize(&image).map_err(map_image_error)?;
+	fs::init_fs(new_fs);
+	// the old working directory may not exist in the mounted tree
+	*CWD.lock() = "/".to_string();
+
+	serial_println!("[FS] Mounted {} byte image from disk", total);
+	Ok(())
+}
+
+/// Loads a previously synced `FileSystem` at boot, keeping the fresh one if
+/// there is nothing usable on disk.
+pub fn load_on_boot() {
+	match mount() {
+		Ok(()) => serial_println!("[FS] Restored RAMFS from disk"),
+		Err(NullexError::NoFsImage) => serial_println!("[FS] No RAMFS image on disk"),
+		Err(e) => serial_println!("[FS] Not restoring RAMFS: {}", e)
+	}
+}
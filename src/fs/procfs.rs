@@ -0,0 +1,247 @@
+//!
+//! procfs.rs
+//!
+//! Virtual `/proc` filesystem. Nothing under `/proc` is stored in the RAMFS;
+//! every read generates its content from live kernel state.
+//!
+
+use alloc::{
+	string::{String, ToString},
+	vec,
+	vec::Vec
+};
+use core::{fmt::Write, sync::atomic::Ordering};
+
+use crate::{
+	allocator::heap_stats,
+	apic::uptime_ns,
+	fs::ramfs::{FsError, Metadata, Permission, Stat},
+	memory::{frames_free, frames_total, frames_used},
+	task::{
+		ProcessId,
+		executor::{CURRENT_PROCESS, EXECUTOR}
+	},
+	utils::multiboot2::kernel_cmdline
+};
+
+/// Directory the virtual filesystem is mounted on.
+pub const PROC_ROOT: &str = "/proc";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A node of the `/proc` tree.
+enum ProcNode {
+	/// `/proc` itself.
+	Root,
+	/// `/proc/uptime`
+	Uptime,
+	/// `/proc/meminfo`
+	MemInfo,
+	/// `/proc/cmdline`
+	Cmdline,
+	/// `/proc/<pid>`
+	ProcessDir(ProcessId),
+	/// `/proc/<pid>/status`
+	ProcessStatus(ProcessId)
+}
+
+impl ProcNode {
+	fn is_dir(self) -> bool {
+		matches!(self, ProcNode::Root | ProcNode::ProcessDir(_))
+	}
+}
+
+/// Returns whether `path` (an absolute, normalized path) lies under `/proc`.
+pub fn is_proc_path(path: &str) -> bool {
+	path.split('/').find(|c| !c.is_empty()) == Some("proc")
+}
+
+/// Reads the file at `path`, generating its content.
+pub fn read(path: &str) -> Result<Vec<u8>, FsError> {
+	let content = match lookup(path)? {
+		ProcNode::Uptime => uptime(),
+		ProcNode::MemInfo => meminfo(),
+		ProcNode::Cmdline => format!("{}\n", kernel_cmdline().as_str()),
+		ProcNode::ProcessStatus(pid) => status(pid)?,
+		ProcNode::Root | ProcNode::ProcessDir(_) => return Err(FsError::NotAFile)
+	};
+	Ok(content.into_bytes())
+}
+
+/// Lists the directory at `path`.
+pub fn list_dir(path: &str) -> Result<Vec<String>, FsError> {
+	match lookup(path)? {
+		ProcNode::Root => {
+			let mut entries = vec![
+				"uptime".to_string(),
+				"meminfo".to_string(),
+				"cmdline".to_string()
+			];
+			let executor = EXECUTOR.lock();
+			entries.extend(executor.processes.keys().map(|pid| pid.get().to_string()));
+			Ok(entries)
+		}
+		ProcNode::ProcessDir(_) => Ok(vec!["status".to_string()]),
+		_ => Err(FsError::NotADirectory)
+	}
+}
+
+/// Returns the type, size and permissions of `path`. Generated entries are
+/// read-only and, like their content, count as created right now.
+pub fn stat(path: &str) -> Result<Stat, FsError> {
+	let is_dir = lookup(path)?.is_dir();
+	let size = if is_dir {
+		list_dir(path)?.len()
+	} else {
+		read(path)?.len()
+	};
+	Ok(Stat {
+		is_dir,
+		permission: Permission::read(),
+		metadata: Metadata::new(size)
+	})
+}
+
+/// Returns whether `path` is a directory of the `/proc` tree.
+pub fn is_dir(path: &str) -> bool {
+	lookup(path).is_ok_and(ProcNode::is_dir)
+}
+
+/// Returns whether `path` exists in the `/proc` tree.
+pub fn exists(path: &str) -> bool {
+	lookup(path).is_ok()
+}
+
+fn lookup(path: &str) -> Result<ProcNode, FsError> {
+	let mut components = path.split('/').filter(|c| !c.is_empty());
+	if components.next() != Some("proc") {
+		return Err(FsError::InvalidPath);
+	}
+
+	let node = match (components.next(), components.next()) {
+		(None, _) => ProcNode::Root,
+		(Some("uptime"), None) => ProcNode::Uptime,
+		(Some("meminfo"), None) => ProcNode::MemInfo,
+		(Some("cmdline"), None) => ProcNode::Cmdline,
+		(Some(pid), rest) => {
+			let pid = pid.parse().map(ProcessId::new).map_err(|_| FsError::EntryNotFound)?;
+			if !EXECUTOR.lock().processes.contains_key(&pid) {
+				return Err(FsError::EntryNotFound);
+			}
+			match rest {
+				None => ProcNode::ProcessDir(pid),
+				Some("status") => ProcNode::ProcessStatus(pid),
+				Some(_) => return Err(FsError::EntryNotFound)
+			}
+		}
+	};
+
+	if components.next().is_some() {
+		return Err(FsError::EntryNotFound);
+	}
+	Ok(node)
+}
+
+/// Seconds since boot with two decimals, like Linux's `/proc/uptime`.
+fn uptime() -> String {
+	let nanos = uptime_ns();
+	let seconds = nanos / 1_000_000_000;
+	let hundredths = nanos % 1_000_000_000 / 10_000_000;
+	format!("{}.{:02}\n", seconds, hundredths)
+}
+
+fn meminfo() -> String {
+	let stats = heap_stats();
+	let mut out = String::new();
+	let _ = writeln!(out, "HeapTotal:  {:>8} kB", stats.size / 1024);
+	let _ = writeln!(out, "HeapUsed:   {:>8} kB", stats.used / 1024);
+	let _ = writeln!(out, "HeapFree:   {:>8} kB", stats.free() / 1024);
+	let _ = writeln!(out, "HeapAllocs: {:>8}", stats.allocations);
+	let _ = writeln!(out, "MemTotal:   {:>8} kB", frames_total() * 4);
+	let _ = writeln!(out, "MemUsed:    {:>8} kB", frames_used() * 4);
+	let _ = writeln!(out, "MemFree:    {:>8} kB", frames_free() * 4);
+	out
+}
+
+fn status(pid: ProcessId) -> Result<String, FsError> {
+	let process = EXECUTOR.lock().processes.get(&pid).cloned().ok_or(FsError::EntryNotFound)?;
+
+	let mut out = String::new();
+	let _ = writeln!(out, "Pid:       {}", pid.get());
+
+	// the running process stays locked while it is polled, so it can only
+	// be described through its shared state
+	match process.try_lock() {
+		Some(process) => {
+			let state = if process.state.queued.load(Ordering::Acquire) {
+				"queued"
+			} else {
+				"sleeping"
+			};
+			let _ = writeln!(out, "State:     {}", state);
+			let _ = writeln!(out, "Child:     {}", process.state.is_child);
+			let _ = writeln!(out, "User:      {}", process.address_space.is_some());
+			let _ = writeln!(out, "OpenFiles: {}", process.open_files.len());
+		}
+		None => {
+			let current = CURRENT_PROCESS.lock().clone();
+			let state = match current {
+				Some(ref state) if state.id == pid => "running",
+				_ => "busy"
+			};
+			let _ = writeln!(out, "State:     {}", state);
+			if let Some(state) = current.filter(|s| s.id == pid) {
+				let _ = writeln!(out, "Child:     {}", state.is_child);
+			}
+		}
+	}
+	Ok(out)
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::string::String;
+
+	use crate::{
+		fs::{procfs::*, ramfs::FsError},
+		utils::ktest::TestError
+	};
+
+	pub fn test_procfs_paths() -> Result<(), TestError> {
+		assert!(is_proc_path("/proc/"));
+		assert!(is_proc_path("/proc/uptime/"));
+		assert!(!is_proc_path("/process"));
+		assert!(!is_proc_path("/logs/proc"));
+
+		assert!(is_dir("/proc/"));
+		assert!(!is_dir("/proc/uptime"));
+		assert!(exists("/proc/meminfo/"));
+		assert!(!exists("/proc/nothing"));
+		assert!(matches!(read("/proc/"), Err(FsError::NotAFile)));
+		assert!(matches!(read("/proc/99999/status"), Err(FsError::EntryNotFound)));
+
+		let entries = list_dir("/proc").map_err(|_| TestError::Error)?;
+		assert!(entries.iter().any(|e| e == "uptime"));
+		assert!(entries.iter().any(|e| e == "meminfo"));
+		Ok(())
+	}
+	crate::create_test!(test_procfs_paths);
+
+	pub fn test_procfs_generated_content() -> Result<(), TestError> {
+		let uptime = read("/proc/uptime").map_err(|_| TestError::Error)?;
+		let uptime = String::from_utf8(uptime).map_err(|_| TestError::Error)?;
+		let (seconds, hundredths) = uptime.trim_end().split_once('.').ok_or(TestError::Error)?;
+		assert!(seconds.parse::<u64>().is_ok());
+		assert_eq!(hundredths.len(), 2);
+
+		let meminfo = read("/proc/meminfo").map_err(|_| TestError::Error)?;
+		let meminfo = String::from_utf8(meminfo).map_err(|_| TestError::Error)?;
+		assert!(meminfo.starts_with("HeapTotal:"));
+		assert!(meminfo.contains("HeapUsed:"));
+		assert!(meminfo.contains("MemFree:"));
+
+		let cmdline = read("/proc/cmdline").map_err(|_| TestError::Error)?;
+		assert_eq!(cmdline.last(), Some(&b'\n'));
+		Ok(())
+	}
+	crate::create_test!(test_procfs_generated_content);
+}
@@ -9,11 +9,18 @@ use alloc::{
 	string::{String, ToString},
 	vec::Vec
 };
-use core::{fmt, str};
+use core::{
+	fmt, str,
+	sync::atomic::Ordering
+};
 
 use hashbrown::HashMap;
 
-use crate::{fs::init_fs, utils::elf::HELLO_ELF};
+use crate::{apic::APIC_TICK_COUNT, fs::init_fs, utils::elf::HELLO_ELF};
+
+/// How many symbolic links a single lookup may follow before it is treated
+/// as a loop.
+pub const MAX_SYMLINK_DEPTH: usize = 8;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 /// Permission Levels for file access.
@@ -46,45 +53,140 @@ impl Permission {
 	}
 }
 
-#[derive(Debug)]
+impl fmt::Display for Permission {
+	/// Formats the permission as `ls` does, e.g. `rw-`.
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let bit = |set: bool, c: char| if set { c } else { '-' };
+		write!(
+			f,
+			"{}{}{}",
+			bit(self.read, 'r'),
+			bit(self.write, 'w'),
+			bit(self.execute, 'x')
+		)
+	}
+}
+
+/// Current time for file timestamps, in APIC timer ticks since boot.
+fn now() -> u64 {
+	APIC_TICK_COUNT.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Size and timestamps of a file or directory. Timestamps are APIC timer
+/// ticks since boot.
+pub struct Metadata {
+	/// Content length in bytes for a file, number of entries for a directory.
+	pub size: usize,
+	/// When the entry was created.
+	pub created: u64,
+	/// When a file was last written, or an entry was last created in or
+	/// removed from a directory.
+	pub modified: u64
+}
+
+impl Metadata {
+	/// Metadata of an entry `size` big, created now.
+	pub fn new(size: usize) -> Self {
+		let now = now();
+		Self {
+			size,
+			created: now,
+			modified: now
+		}
+	}
+
+	/// Records a modification that left the entry `size` big.
+	fn touch(&mut self, size: usize) {
+		self.size = size;
+		self.modified = now();
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// What `FileSystem::stat` reports about an entry.
+pub struct Stat {
+	/// Whether the entry is a directory rather than a file.
+	pub is_dir: bool,
+	/// Permission level of the entry.
+	pub permission: Permission,
+	/// Size and timestamps of the entry.
+	pub metadata: Metadata
+}
+
+#[derive(Debug, Clone)]
 /// Structure representing a file in the file system.
 pub struct File {
 	/// Content in bytes.
 	pub content: Vec<u8>,
 	/// Permission level for the file.
-	pub permission: Permission
+	pub permission: Permission,
+	/// Size and timestamps, kept up to date by `FileSystem::write_file`.
+	pub metadata: Metadata
 }
 
 impl File {
 	fn new(permission: Permission) -> Self {
 		Self {
 			content: Vec::new(),
-			permission
+			permission,
+			metadata: Metadata::new(0)
 		}
 	}
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// Structure representing a directory (multiple files + directories)
 pub struct Directory {
 	entries: HashMap<String, Entry>,
 	/// Directory permissions
-	pub permission: Permission
+	pub permission: Permission,
+	/// Size and timestamps of the directory.
+	pub metadata: Metadata
 }
 
 impl Directory {
 	fn new(permission: Permission) -> Self {
 		Self {
 			entries: HashMap::new(),
-			permission
+			permission,
+			metadata: Metadata::new(0)
+		}
+	}
+
+	/// Records that an entry was added or removed.
+	fn touch(&mut self) {
+		self.metadata.touch(self.entries.len());
+	}
+
+	/// The entries sorted by name, case-insensitively, so listings don't
+	/// depend on the hash map's order. Names differing only in case are
+	/// ordered by their exact bytes.
+	fn sorted_entries(&self) -> impl Iterator<Item = (&String, &Entry)> {
+		let mut entries: Vec<_> = self.entries.iter().collect();
+		entries.sort_by(|(a, _), (b, _)| {
+			a.to_lowercase()
+				.cmp(&b.to_lowercase())
+				.then_with(|| a.cmp(b))
+		});
+		entries.into_iter()
+	}
+
+	fn stat(&self) -> Stat {
+		Stat {
+			is_dir: true,
+			permission: self.permission,
+			metadata: self.metadata
 		}
 	}
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Entry {
 	File(File),
-	Directory(Box<Directory>)
+	Directory(Box<Directory>),
+	/// A symbolic link to the stored (absolute or relative) path.
+	Symlink(String)
 }
 
 #[derive(Debug)]
@@ -103,7 +205,15 @@ pub enum FsError {
 	/// Path is invalid.
 	InvalidPath,
 	/// The directory is currently not empty.
-	DirectoryNotEmpty
+	DirectoryNotEmpty,
+	/// Too many symbolic links were followed, most likely a loop.
+	SymlinkLoop,
+	/// A directory can't be copied into itself.
+	CopyIntoSelf,
+	/// A serialized image is truncated or malformed.
+	CorruptImage,
+	/// A serialized image was written by an incompatible format version.
+	UnsupportedVersion(u16)
 }
 
 impl fmt::Display for FsError {
@@ -115,7 +225,11 @@ impl fmt::Display for FsError {
 			Self::PermissionDenied => write!(f, "Permission denied"),
 			Self::AlreadyExists => write!(f, "Entry already exists"),
 			Self::InvalidPath => write!(f, "Invalid path"),
-			Self::DirectoryNotEmpty => write!(f, "Directory not empty")
+			Self::DirectoryNotEmpty => write!(f, "Directory not empty"),
+			Self::SymlinkLoop => write!(f, "Too many levels of symbolic links"),
+			Self::CopyIntoSelf => write!(f, "Cannot copy a directory into itself"),
+			Self::CorruptImage => write!(f, "Corrupt filesystem image"),
+			Self::UnsupportedVersion(v) => write!(f, "Unsupported filesystem image version {}", v)
 		}
 	}
 }
@@ -146,6 +260,7 @@ impl FileSystem {
 		}
 
 		dir.entries.insert(file_name, Entry::File(File::new(perm)));
+		dir.touch();
 		Ok(())
 	}
 
@@ -160,9 +275,46 @@ impl FileSystem {
 
 		dir.entries
 			.insert(dir_name, Entry::Directory(Box::new(Directory::new(perm))));
+		dir.touch();
+		Ok(())
+	}
+
+	/// Creates a symbolic link at `path` pointing to `target`. The target
+	/// doesn't need to exist.
+	pub fn create_symlink(&mut self, path: &str, target: &str) -> Result<(), FsError> {
+		if target.is_empty() || target.len() > u16::MAX as usize {
+			return Err(FsError::InvalidPath);
+		}
+
+		let (dir_components, link_name) = Self::split_path(path)?;
+		let dir = self.get_dir_mut_from_components(&dir_components.as_slice())?;
+
+		if dir.entries.contains_key(&link_name) {
+			return Err(FsError::AlreadyExists);
+		}
+
+		dir.entries.insert(link_name, Entry::Symlink(target.to_string()));
+		dir.touch();
 		Ok(())
 	}
 
+	/// Returns the target of the symbolic link at `path`.
+	pub fn read_link(&self, path: &str) -> Result<&str, FsError> {
+		let (dir_components, name) = Self::split_path(path)?;
+		let dir = self.get_dir_from_components(&dir_components.as_slice())?;
+
+		match dir.entries.get(&name) {
+			Some(Entry::Symlink(target)) => Ok(target),
+			Some(_) => Err(FsError::InvalidPath),
+			None => Err(FsError::EntryNotFound)
+		}
+	}
+
+	/// If a path is a symbolic link (the link itself, not what it points to).
+	pub fn is_symlink(&self, path: &str) -> bool {
+		self.read_link(path).is_ok()
+	}
+
 	/// Writes to a file that already exists
 	pub fn write_file(
 		&mut self,
@@ -181,9 +333,30 @@ impl FileSystem {
 		} else {
 			file.content.extend_from_slice(content);
 		}
+		file.metadata.touch(file.content.len());
 		Ok(())
 	}
 
+	/// Returns the type, permissions, size and timestamps of the entry at
+	/// `path`, following symbolic links.
+	pub fn stat(&self, path: &str) -> Result<Stat, FsError> {
+		let components = self.follow_symlinks(&self.resolve_path(path)?, true)?;
+		let Some((name, dir_components)) = components.split_last() else {
+			return Ok(self.root.stat());
+		};
+
+		match self.get_dir_from_components(dir_components)?.entries.get(name) {
+			Some(Entry::File(file)) => Ok(Stat {
+				is_dir: false,
+				permission: file.permission,
+				metadata: file.metadata
+			}),
+			Some(Entry::Directory(dir)) => Ok(dir.stat()),
+			// links were all followed, so one here is dangling
+			Some(Entry::Symlink(_)) | None => Err(FsError::EntryNotFound)
+		}
+	}
+
 	/// Read the current file.
 	// todo: add read permission checks, forgot to add this before.
 	pub fn read_file(&self, path: &str) -> Result<&[u8], FsError> {
@@ -235,9 +408,66 @@ impl FileSystem {
 		self.get_dir_from_components(&components.as_slice())
 	}
 
+	/// Walks `components` from the root, replacing every symbolic link met on
+	/// the way with its target, and returns the link-free path. The last
+	/// component is only followed when `follow_last` is set.
+	///
+	/// Missing entries are only allowed as the last component, so callers can
+	/// resolve the path of something they are about to create.
+	fn follow_symlinks(
+		&self,
+		components: &[String],
+		follow_last: bool
+	) -> Result<Vec<String>, FsError> {
+		let mut pending: Vec<String> = components.iter().rev().cloned().collect();
+		let mut resolved: Vec<String> = Vec::new();
+		let mut current = &self.root;
+		let mut links_followed = 0;
+
+		while let Some(name) = pending.pop() {
+			let is_last = pending.is_empty();
+			match current.entries.get(&name) {
+				Some(Entry::Symlink(target)) if !is_last || follow_last => {
+					links_followed += 1;
+					if links_followed > MAX_SYMLINK_DEPTH {
+						return Err(FsError::SymlinkLoop);
+					}
+
+					// relative targets are relative to the link's directory
+					let mut path = if target.starts_with('/') {
+						Vec::new()
+					} else {
+						core::mem::take(&mut resolved)
+					};
+					for component in target.split('/').filter(|s| !s.is_empty() && *s != ".") {
+						if component == ".." {
+							path.pop();
+						} else {
+							path.push(component.to_string());
+						}
+					}
+
+					// restart the walk from the root with the substituted path
+					pending.extend(path.into_iter().rev());
+					resolved.clear();
+					current = &self.root;
+				}
+				Some(Entry::Directory(dir)) => {
+					resolved.push(name);
+					current = &**dir;
+				}
+				Some(_) | None if is_last => resolved.push(name),
+				Some(_) => return Err(FsError::NotADirectory),
+				None => return Err(FsError::EntryNotFound)
+			}
+		}
+		Ok(resolved)
+	}
+
 	fn get_dir_from_components(&self, components: &[String]) -> Result<&Directory, FsError> {
+		let components = self.follow_symlinks(components, true)?;
 		let mut current = &self.root;
-		for component in components {
+		for component in components.iter() {
 			current = match current.entries.get(component) {
 				Some(Entry::Directory(dir)) => &**dir,
 				Some(_) => return Err(FsError::NotADirectory),
@@ -251,8 +481,9 @@ impl FileSystem {
 		&mut self,
 		components: &[String]
 	) -> Result<&mut Directory, FsError> {
+		let components = self.follow_symlinks(components, true)?;
 		let mut current = &mut self.root;
-		for component in components {
+		for component in components.iter() {
 			current = match current.entries.get_mut(component) {
 				Some(Entry::Directory(dir)) => &mut **dir,
 				Some(_) => return Err(FsError::NotADirectory),
@@ -264,7 +495,7 @@ impl FileSystem {
 
 	/// Get a specific file from a file path.
 	pub fn get_file(&self, path: &str) -> Result<&File, FsError> {
-		let (dir_components, file_name) = Self::split_path(path)?;
+		let (dir_components, file_name) = self.split_followed(path)?;
 		let dir = self.get_dir_from_components(&dir_components.as_slice())?;
 
 		match dir.entries.get(&file_name) {
@@ -274,8 +505,25 @@ impl FileSystem {
 		}
 	}
 
+	/// Returns the permissions a file is effectively accessed with: its own,
+	/// with write taken away if the directory holding it is read-only (like
+	/// `/proc`).
+	pub fn effective_permission(&self, path: &str) -> Result<Permission, FsError> {
+		let (dir_components, file_name) = self.split_followed(path)?;
+		let dir = self.get_dir_from_components(&dir_components.as_slice())?;
+
+		match dir.entries.get(&file_name) {
+			Some(Entry::File(file)) => Ok(Permission {
+				write: file.permission.write && dir.permission.write,
+				..file.permission
+			}),
+			Some(_) => Err(FsError::NotAFile),
+			None => Err(FsError::EntryNotFound)
+		}
+	}
+
 	fn get_file_mut(&mut self, path: &str) -> Result<&mut File, FsError> {
-		let (dir_components, file_name) = Self::split_path(path)?;
+		let (dir_components, file_name) = self.split_followed(path)?;
 		let dir = self.get_dir_mut_from_components(&dir_components.as_slice())?;
 
 		match dir.entries.get_mut(&file_name) {
@@ -285,47 +533,37 @@ impl FileSystem {
 		}
 	}
 
-	/// List all contents of a specified path.
+	/// Like `split_path`, but with every symbolic link in the path, including
+	/// the last component, already followed.
+	fn split_followed(&self, path: &str) -> Result<(Vec<String>, String), FsError> {
+		let mut components = self.follow_symlinks(&Self::path_components(path)?, true)?;
+		let name = components.pop().ok_or(FsError::InvalidPath)?;
+		Ok((components, name))
+	}
+
+	/// List all contents of a specified path, sorted by name.
 	pub fn list_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
 		let dir = self.get_dir(path)?;
-		Ok(dir.entries.keys().cloned().collect())
+		Ok(dir.sorted_entries().map(|(name, _)| name.clone()).collect())
 	}
 
-	/// List all contents of a specified path and their type.
+	/// List the type of every entry of a specified path, in the same order as
+	/// `list_dir`.
 	pub fn list_dir_entry_types(&self, path: &str) -> Result<Vec<String>, FsError> {
 		let dir = self.get_dir(path)?;
 		Ok(dir
-			.entries
-			.values()
-			.map(|entry| match entry {
+			.sorted_entries()
+			.map(|(_, entry)| match entry {
 				Entry::File(_) => "File".to_string(),
-				Entry::Directory(_) => "Directory".to_string()
+				Entry::Directory(_) => "Directory".to_string(),
+				Entry::Symlink(_) => "Symlink".to_string()
 			})
 			.collect())
 	}
 
-	/// If a path is a directory.
+	/// If a path is a directory, following symbolic links.
 	pub fn is_dir(&self, path: &str) -> bool {
-		let components = match self.resolve_path(path) {
-			Ok(c) => c,
-			Err(_) => return false
-		};
-
-		// special case for root directory
-		if components.is_empty() {
-			return true;
-		}
-
-		match self.get_dir_from_components(&components[..components.len() - 1]) {
-			Ok(parent_dir) => {
-				if let Some(entry) = parent_dir.entries.get(&components[components.len() - 1]) {
-					matches!(entry, Entry::Directory(_))
-				} else {
-					false
-				}
-			}
-			Err(_) => false
-		}
+		self.get_dir(path).is_ok()
 	}
 
 	/// Remove the item at the specified path.
@@ -357,9 +595,13 @@ impl FileSystem {
 					Self::recursive_remove(&mut dir_box);
 				}
 				// with recursive deletion (or if empty), dropping dir_box completes removal.
+				parent_dir.touch();
+				Ok(())
+			}
+			Entry::File(_) | Entry::Symlink(_) => {
+				parent_dir.touch();
 				Ok(())
 			}
-			Entry::File(_) => Ok(())
 		}
 	}
 
@@ -378,6 +620,51 @@ impl FileSystem {
 		dir.entries.clear();
 	}
 
+	/// Copies the file or directory at `from` to `to`, directories with
+	/// everything in them. Permissions are copied along.
+	///
+	/// If `to` is an existing directory the copy is placed inside it under
+	/// the source's name. A copied file replaces an existing file it may
+	/// write to, but a directory is never copied onto a file, over another
+	/// directory or into itself.
+	pub fn copy(&mut self, from: &str, to: &str) -> Result<(), FsError> {
+		let (source_dir, source_name) = self.split_followed(from)?;
+		let entry = self
+			.get_dir_from_components(source_dir.as_slice())?
+			.entries
+			.get(&source_name)
+			.cloned()
+			.ok_or(FsError::EntryNotFound)?;
+
+		let mut target = self.follow_symlinks(&Self::path_components(to)?, true)?;
+		if self.get_dir_from_components(target.as_slice()).is_ok() {
+			target.push(source_name.clone());
+		}
+
+		if let Entry::Directory(_) = entry {
+			let mut source = source_dir;
+			source.push(source_name);
+			if target.starts_with(&source) {
+				return Err(FsError::CopyIntoSelf);
+			}
+		}
+
+		let target_name = target.pop().ok_or(FsError::InvalidPath)?;
+		let target_dir = self.get_dir_mut_from_components(target.as_slice())?;
+		match (target_dir.entries.get(&target_name), &entry) {
+			(Some(Entry::File(file)), Entry::File(_)) if !file.permission.write => {
+				return Err(FsError::PermissionDenied);
+			}
+			(None, _) | (Some(Entry::File(_)), Entry::File(_)) => {}
+			(Some(Entry::File(_)), _) => return Err(FsError::NotADirectory),
+			(Some(_), _) => return Err(FsError::AlreadyExists)
+		}
+
+		target_dir.entries.insert(target_name, entry);
+		target_dir.touch();
+		Ok(())
+	}
+
 	/// If the specified path exists.
 	pub fn exists(&self, path: &str) -> bool {
 		let components = match self.resolve_path(path) {
@@ -399,6 +686,225 @@ impl FileSystem {
 	}
 }
 
+// ----- ON-DISK IMAGE ----- //
+//
+// `FileSystem::serialize` produces the following image, all integers little
+// endian:
+//
+//   header (16 bytes)
+//     [0..4]    magic, `IMAGE_MAGIC`
+//     [4..6]    format version, `IMAGE_VERSION`
+//     [6..8]    reserved, zero
+//     [8..12]   body length in bytes
+//     [12..16]  FNV-1a hash of the body
+//   body
+//     the root directory record
+//
+// A record is a file, a directory or a symbolic link:
+//
+//   file:      tag (0) u8, permission u8, content length u32, content
+//   directory: tag (1) u8, permission u8, entry count u32, entries
+//   symlink:   tag (2) u8, permission u8 (zero), target length u16, target
+//
+// and every directory entry is a name length u16, the UTF-8 name, then the
+// entry's record. Permissions are packed as read = 1, write = 2, execute = 4.
+// Timestamps aren't stored, since ticks restart at every boot; a loaded
+// entry counts as created when the image was read.
+//
+// Version 2 added symlink records; version 1 images are still readable since
+// they are a subset. Readers reject images from newer versions rather than
+// guessing at the layout.
+
+/// Magic at the start of a serialized `FileSystem` image.
+pub const IMAGE_MAGIC: [u8; 4] = *b"NXFS";
+/// Version of the image format written by `FileSystem::serialize`.
+pub const IMAGE_VERSION: u16 = 2;
+/// Oldest image format version that can still be read.
+const IMAGE_MIN_VERSION: u16 = 1;
+/// Size of the image header in bytes.
+pub const IMAGE_HEADER_LEN: usize = 16;
+
+const RECORD_FILE: u8 = 0;
+const RECORD_DIRECTORY: u8 = 1;
+const RECORD_SYMLINK: u8 = 2;
+
+impl Permission {
+	fn to_bits(self) -> u8 {
+		self.read as u8 | (self.write as u8) << 1 | (self.execute as u8) << 2
+	}
+
+	fn from_bits(bits: u8) -> Self {
+		Self {
+			read: bits & 1 != 0,
+			write: bits & 2 != 0,
+			execute: bits & 4 != 0
+		}
+	}
+}
+
+/// FNV-1a hash, used to catch torn or corrupted images.
+fn image_hash(data: &[u8]) -> u32 {
+	data.iter()
+		.fold(0x811C_9DC5u32, |hash, &b| (hash ^ b as u32).wrapping_mul(0x0100_0193))
+}
+
+/// Reads the body length out of an image header, validating the magic and
+/// version. Lets a caller find out how much more of an image to load after
+/// reading just the first sector.
+pub fn image_body_len(header: &[u8]) -> Result<usize, FsError> {
+	if header.len() < IMAGE_HEADER_LEN || header[0..4] != IMAGE_MAGIC {
+		return Err(FsError::CorruptImage);
+	}
+
+	let version = u16::from_le_bytes([header[4], header[5]]);
+	if !(IMAGE_MIN_VERSION..=IMAGE_VERSION).contains(&version) {
+		return Err(FsError::UnsupportedVersion(version));
+	}
+
+	Ok(u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize)
+}
+
+/// Cursor over a serialized image.
+struct ImageReader<'a> {
+	data: &'a [u8],
+	pos: usize
+}
+
+impl<'a> ImageReader<'a> {
+	fn take(&mut self, len: usize) -> Result<&'a [u8], FsError> {
+		let end = self.pos.checked_add(len).ok_or(FsError::CorruptImage)?;
+		let bytes = self.data.get(self.pos..end).ok_or(FsError::CorruptImage)?;
+		self.pos = end;
+		Ok(bytes)
+	}
+
+	fn u8(&mut self) -> Result<u8, FsError> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn u16(&mut self) -> Result<u16, FsError> {
+		let b = self.take(2)?;
+		Ok(u16::from_le_bytes([b[0], b[1]]))
+	}
+
+	fn u32(&mut self) -> Result<u32, FsError> {
+		let b = self.take(4)?;
+		Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+	}
+
+	fn entry(&mut self) -> Result<Entry, FsError> {
+		let tag = self.u8()?;
+		let permission = Permission::from_bits(self.u8()?);
+
+		match tag {
+			RECORD_FILE => {
+				let len = self.u32()? as usize;
+				Ok(Entry::File(File {
+					content: self.take(len)?.to_vec(),
+					permission,
+					metadata: Metadata::new(len)
+				}))
+			}
+			RECORD_DIRECTORY => {
+				let count = self.u32()?;
+				let mut dir = Directory::new(permission);
+				for _ in 0..count {
+					let name_len = self.u16()? as usize;
+					let name = str::from_utf8(self.take(name_len)?)
+						.map_err(|_| FsError::CorruptImage)?;
+					if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+						return Err(FsError::CorruptImage);
+					}
+					let entry = self.entry()?;
+					dir.entries.insert(name.to_string(), entry);
+				}
+				dir.metadata.size = dir.entries.len();
+				Ok(Entry::Directory(Box::new(dir)))
+			}
+			RECORD_SYMLINK => {
+				let len = self.u16()? as usize;
+				let target = str::from_utf8(self.take(len)?).map_err(|_| FsError::CorruptImage)?;
+				Ok(Entry::Symlink(target.to_string()))
+			}
+			_ => Err(FsError::CorruptImage)
+		}
+	}
+}
+
+fn write_entry(out: &mut Vec<u8>, entry: &Entry) {
+	match entry {
+		Entry::File(file) => {
+			out.push(RECORD_FILE);
+			out.push(file.permission.to_bits());
+			out.extend_from_slice(&(file.content.len() as u32).to_le_bytes());
+			out.extend_from_slice(&file.content);
+		}
+		Entry::Directory(dir) => write_dir(out, dir),
+		Entry::Symlink(target) => {
+			out.push(RECORD_SYMLINK);
+			out.push(0);
+			out.extend_from_slice(&(target.len() as u16).to_le_bytes());
+			out.extend_from_slice(target.as_bytes());
+		}
+	}
+}
+
+fn write_dir(out: &mut Vec<u8>, dir: &Directory) {
+	out.push(RECORD_DIRECTORY);
+	out.push(dir.permission.to_bits());
+	out.extend_from_slice(&(dir.entries.len() as u32).to_le_bytes());
+	for (name, entry) in dir.entries.iter() {
+		out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+		out.extend_from_slice(name.as_bytes());
+		write_entry(out, entry);
+	}
+}
+
+impl FileSystem {
+	/// Serializes the whole directory tree and file contents into a compact
+	/// image. See the format description above `IMAGE_MAGIC`.
+	pub fn serialize(&self) -> Vec<u8> {
+		let mut body = Vec::new();
+		write_dir(&mut body, &self.root);
+
+		let mut image = Vec::with_capacity(IMAGE_HEADER_LEN + body.len());
+		image.extend_from_slice(&IMAGE_MAGIC);
+		image.extend_from_slice(&IMAGE_VERSION.to_le_bytes());
+		image.extend_from_slice(&0u16.to_le_bytes());
+		image.extend_from_slice(&(body.len() as u32).to_le_bytes());
+		image.extend_from_slice(&image_hash(&body).to_le_bytes());
+		image.extend_from_slice(&body);
+		image
+	}
+
+	/// Rebuilds a `FileSystem` from an image produced by `serialize`.
+	///
+	/// Anything past the end of the image (e.g. sector padding) is ignored.
+	pub fn deserialize(image: &[u8]) -> Result<FileSystem, FsError> {
+		let body_len = image_body_len(image)?;
+		let body = image
+			.get(IMAGE_HEADER_LEN..IMAGE_HEADER_LEN + body_len)
+			.ok_or(FsError::CorruptImage)?;
+
+		let hash = u32::from_le_bytes([image[12], image[13], image[14], image[15]]);
+		if image_hash(body) != hash {
+			return Err(FsError::CorruptImage);
+		}
+
+		let mut reader = ImageReader {
+			data: body,
+			pos: 0
+		};
+		match reader.entry()? {
+			Entry::Directory(root) if reader.pos == body.len() => Ok(FileSystem {
+				root: *root,
+				current_path: Vec::new()
+			}),
+			_ => Err(FsError::CorruptImage)
+		}
+	}
+}
+
 impl Default for FileSystem {
 	fn default() -> Self {
 		Self::new()
@@ -415,4 +921,171 @@ pub fn setup_system_files(mut fs: FileSystem) {
 	fs.write_file("/apps/hello.elf", HELLO_ELF, true).unwrap();
 
 	init_fs(fs);
-}
\ No newline at end of file
+}
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{fs::ramfs::*, utils::ktest::TestError};
+
+	pub fn test_ramfs_image_round_trip() -> Result<(), TestError> {
+		let mut fs = FileSystem::new();
+		fs.create_dir("/docs", Permission::all()).map_err(|_| TestError::Error)?;
+		fs.create_dir("/docs/empty", Permission::read()).map_err(|_| TestError::Error)?;
+		fs.create_file("/docs/notes", Permission::read()).map_err(|_| TestError::Error)?;
+		let notes = fs.get_file_mut("/docs/notes").map_err(|_| TestError::Error)?;
+		notes.content = b"persist me".to_vec();
+
+		let mut image = fs.serialize();
+		// sector padding after the image must be ignored
+		image.resize(image.len() + 100, 0);
+		let restored = FileSystem::deserialize(&image).map_err(|_| TestError::Error)?;
+
+		assert!(restored.is_dir("/docs/empty"));
+		let notes = restored.get_file("/docs/notes").map_err(|_| TestError::Error)?;
+		assert_eq!(notes.content.as_slice(), b"persist me");
+		assert_eq!(notes.permission, Permission::read());
+		Ok(())
+	}
+	crate::create_test!(test_ramfs_image_round_trip);
+
+	pub fn test_ramfs_copy() -> Result<(), TestError> {
+		let mut fs = FileSystem::new();
+		fs.create_dir("/logs", Permission::all()).map_err(|_| TestError::Error)?;
+		fs.create_dir("/logs/old", Permission::all()).map_err(|_| TestError::Error)?;
+		fs.create_file("/logs/a.txt", Permission::read()).map_err(|_| TestError::Error)?;
+		fs.get_file_mut("/logs/a.txt").map_err(|_| TestError::Error)?.content = b"a".to_vec();
+
+		// file to a new name keeps content and permission
+		fs.copy("/logs/a.txt", "/logs/a.bak").map_err(|_| TestError::Error)?;
+		let copy = fs.get_file("/logs/a.bak").map_err(|_| TestError::Error)?;
+		assert_eq!(copy.content.as_slice(), b"a");
+		assert_eq!(copy.permission, Permission::read());
+
+		// into an existing directory, then the directory recursively
+		fs.copy("/logs/a.txt", "/logs/old").map_err(|_| TestError::Error)?;
+		fs.copy("/logs", "/backup").map_err(|_| TestError::Error)?;
+		assert!(fs.get_file("/backup/old/a.txt").is_ok());
+		// the copy is independent of the source
+		fs.get_file_mut("/backup/a.txt").map_err(|_| TestError::Error)?.content.clear();
+		assert_eq!(fs.read_file("/logs/a.txt").map_err(|_| TestError::Error)?, b"a");
+
+		assert!(matches!(fs.copy("/logs", "/logs/old"), Err(FsError::CopyIntoSelf)));
+		assert!(matches!(fs.copy("/logs/old", "/logs/a.bak"), Err(FsError::NotADirectory)));
+		assert!(matches!(fs.copy("/missing", "/x"), Err(FsError::EntryNotFound)));
+		// the read-only copy isn't overwritten
+		fs.create_file("/logs/b.txt", Permission::all()).map_err(|_| TestError::Error)?;
+		assert!(matches!(fs.copy("/logs/b.txt", "/logs/a.bak"), Err(FsError::PermissionDenied)));
+		assert_eq!(fs.read_file("/logs/a.bak").map_err(|_| TestError::Error)?, b"a");
+		Ok(())
+	}
+	crate::create_test!(test_ramfs_copy);
+
+	pub fn test_ramfs_image_rejects_bad_version_and_corruption() -> Result<(), TestError> {
+		let mut fs = FileSystem::new();
+		fs.create_file("/a", Permission::all()).map_err(|_| TestError::Error)?;
+		let image = fs.serialize();
+
+		let mut newer = image.clone();
+		newer[4..6].copy_from_slice(&(IMAGE_VERSION + 1).to_le_bytes());
+		assert!(matches!(
+			FileSystem::deserialize(&newer),
+			Err(FsError::UnsupportedVersion(v)) if v == IMAGE_VERSION + 1
+		));
+
+		let mut corrupt = image.clone();
+		let last = corrupt.len() - 1;
+		corrupt[last] ^= 0xFF;
+		assert!(matches!(FileSystem::deserialize(&corrupt), Err(FsError::CorruptImage)));
+
+		assert!(matches!(
+			FileSystem::deserialize(&image[..image.len() - 1]),
+			Err(FsError::CorruptImage)
+		));
+		Ok(())
+	}
+	crate::create_test!(test_ramfs_image_rejects_bad_version_and_corruption);
+
+	pub fn test_ramfs_symlinks_followed() -> Result<(), TestError> {
+		let mut fs = FileSystem::new();
+		fs.create_dir("/logs", Permission::all()).map_err(|_| TestError::Error)?;
+		fs.create_file("/logs/syslog", Permission::all()).map_err(|_| TestError::Error)?;
+		fs.write_file("/logs/syslog", b"boot", true).map_err(|_| TestError::Error)?;
+
+		fs.create_symlink("/l", "/logs").map_err(|_| TestError::Error)?;
+		fs.create_symlink("/logs/latest", "syslog").map_err(|_| TestError::Error)?;
+
+		assert!(fs.is_dir("/l"));
+		assert!(fs.is_symlink("/l"));
+		assert!(fs.list_dir("/l").map_err(|_| TestError::Error)?.contains(&"syslog".to_string()));
+		assert_eq!(fs.read_file("/l/latest").map_err(|_| TestError::Error)?, b"boot");
+
+		// removing the link leaves the target alone
+		fs.remove("/l", false, false).map_err(|_| TestError::Error)?;
+		assert!(!fs.exists("/l"));
+		assert!(fs.is_dir("/logs"));
+		Ok(())
+	}
+	crate::create_test!(test_ramfs_symlinks_followed);
+
+	pub fn test_ramfs_symlink_loop_rejected() -> Result<(), TestError> {
+		let mut fs = FileSystem::new();
+		fs.create_symlink("/a", "/b").map_err(|_| TestError::Error)?;
+		fs.create_symlink("/b", "/a").map_err(|_| TestError::Error)?;
+
+		assert!(matches!(fs.list_dir("/a"), Err(FsError::SymlinkLoop)));
+		assert!(matches!(fs.read_file("/b"), Err(FsError::SymlinkLoop)));
+
+		let restored = FileSystem::deserialize(&fs.serialize()).map_err(|_| TestError::Error)?;
+		assert_eq!(restored.read_link("/a").map_err(|_| TestError::Error)?, "/b");
+		Ok(())
+	}
+	crate::create_test!(test_ramfs_symlink_loop_rejected);
+
+	pub fn test_ramfs_stat_tracks_size_and_entries() -> Result<(), TestError> {
+		let mut fs = FileSystem::new();
+		fs.create_dir("/logs", Permission::all()).map_err(|_| TestError::Error)?;
+		fs.create_file("/logs/syslog", Permission::all()).map_err(|_| TestError::Error)?;
+
+		let created = fs.stat("/logs/syslog").map_err(|_| TestError::Error)?;
+		assert!(!created.is_dir);
+		assert_eq!(created.metadata.size, 0);
+		assert_eq!(created.permission.to_string(), "rwx");
+		assert_eq!(Permission::read().to_string(), "r--");
+
+		fs.write_file("/logs/syslog", b"boot", true).map_err(|_| TestError::Error)?;
+		fs.write_file("/logs/syslog", b" ok", false).map_err(|_| TestError::Error)?;
+		fs.create_symlink("/latest", "/logs/syslog").map_err(|_| TestError::Error)?;
+		let written = fs.stat("/latest").map_err(|_| TestError::Error)?;
+		assert_eq!(written.metadata.size, 7);
+		assert_eq!(written.metadata.created, created.metadata.created);
+		assert!(written.metadata.modified >= created.metadata.modified);
+
+		// directories count their entries
+		fs.create_file("/logs/old", Permission::all()).map_err(|_| TestError::Error)?;
+		assert_eq!(fs.stat("/logs").map_err(|_| TestError::Error)?.metadata.size, 2);
+		fs.remove("/logs/old", false, false).map_err(|_| TestError::Error)?;
+		let logs = fs.stat("/logs").map_err(|_| TestError::Error)?;
+		assert!(logs.is_dir);
+		assert_eq!(logs.metadata.size, 1);
+		assert_eq!(fs.stat("/").map_err(|_| TestError::Error)?.metadata.size, 2);
+
+		assert!(matches!(fs.stat("/missing"), Err(FsError::EntryNotFound)));
+		Ok(())
+	}
+	crate::create_test!(test_ramfs_stat_tracks_size_and_entries);
+
+	pub fn test_ramfs_list_dir_sorted() -> Result<(), TestError> {
+		let mut fs = FileSystem::new();
+		for name in ["/b", "/C", "/a", "/B", "/apps"] {
+			fs.create_file(name, Permission::all()).map_err(|_| TestError::Error)?;
+		}
+		fs.create_dir("/dir", Permission::all()).map_err(|_| TestError::Error)?;
+
+		assert_eq!(fs.list_dir("/").map_err(|_| TestError::Error)?, [
+			"a", "apps", "B", "b", "C", "dir"
+		]);
+		let types = fs.list_dir_entry_types("/").map_err(|_| TestError::Error)?;
+		assert_eq!(types.last().map(String::as_str), Some("Directory"));
+		Ok(())
+	}
+	crate::create_test!(test_ramfs_list_dir_sorted);
+}
@@ -10,12 +10,12 @@ use x86_64::{
         tss::TaskStateSegment
     }
 };
-use crate::lazy_static;
+use crate::{lazy_static, memory::allocate_kernel_stack};
 
 pub(crate) const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-const KERNEL_STACK_SIZE: usize = 4096 * 5;
-static mut KERNEL_STACK: [u8; KERNEL_STACK_SIZE] = [0; KERNEL_STACK_SIZE];
+/// Pages of the double fault stack, which has a guard page below it.
+const DOUBLE_FAULT_STACK_PAGES: usize = 5;
 
 /// Size of the stack when an interrupt is fired.
 pub const INTERRUPT_STACK_SIZE: usize = 4096 * 8;
@@ -35,11 +35,11 @@ lazy_static! {
     pub static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
 
-        // IST slot 0: dedicated double-fault stack
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            let stack_start = VirtAddr::from_ptr(core::ptr::addr_of!(KERNEL_STACK));
-            stack_start + KERNEL_STACK_SIZE
-        };
+        // IST slot 0: dedicated double-fault stack, kept for the kernel's lifetime
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            allocate_kernel_stack(DOUBLE_FAULT_STACK_PAGES)
+                .expect("FATAL: could not allocate the double fault stack")
+                .top();
 
         // rsp0: kernel stack for ring 3 -> ring 0 transitions (interrupts, syscalls)
         tss.privilege_stack_table[0] = VirtAddr::new(interrupt_stack_top());
@@ -4,27 +4,29 @@
 //! Interrupt handling module for the kernel.
 //!
 
+use alloc::vec::Vec;
 use core::{
 	mem::MaybeUninit,
-	sync::atomic::{AtomicBool, Ordering}
+	sync::atomic::{AtomicBool, AtomicU64, Ordering}
 };
 
 use ::x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
 use crate::{
-	apic::{APIC_TICK_COUNT, PIC_EOI, PIC1_CMD, PIC2_CMD, send_eoi}, common::ports::{inb, outb}, drivers::keyboard::queue::add_scancode, error::NullexError, gdt, hlt_loop, lazy_static, println, rtc::{
+	apic::{APIC_TICK_COUNT, PIC_EOI, PIC1_CMD, PIC2_CMD, send_eoi}, common::ports::{inb, outb}, drivers::{mouse, virtio::net}, error::NullexError, gdt, hlt_loop, ioapic::IOAPIC, lazy_static, println, rtc::{
 		CMOS_DATA,
 		CMOS_INDEX,
 		NMI_BIT,
 		REG_C,
 		RTC_TICKS,
 		send_rtc_eoi
-	}, serial::add_byte, serial_println, syscall::syscall, task::executor::CURRENT_PROCESS, utils::{bits::BitMap, mutex::SpinMutex}
+	}, serial::add_byte, serial_println, syscall::syscall, task::{keyboard::foreground::route_scancode, timer, watchdog}, utils::{bits::BitMap, mutex::SpinMutex}
 };
 
 pub(crate) const APIC_TIMER_VECTOR: u8 = 32;
 const KEYBOARD_VECTOR: u8 = 33;
 const SERIAL_VECTOR: u8 = 36;
+const MOUSE_VECTOR: u8 = 44;
 const RTC_VECTOR: u8 = 0x70; // irq 8 - 15 is mapped from 0x70 to 0x77;
 const SYSCALL_VECTOR: u8 = 0x80;
 
@@ -32,6 +34,9 @@ const SYSCALL_VECTOR: u8 = 0x80;
 static mut IDT_STORAGE: MaybeUninit<InterruptDescriptorTable> = MaybeUninit::uninit();
 static IDT_INITED: AtomicBool = AtomicBool::new(false);
 
+/// Interrupts taken per IDT vector, counted by the device handlers.
+static IRQ_COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
 lazy_static! {
 	/// Static reference to all used vectors for ISO's (Interrupt Source Override)
 	pub static ref VECTOR_TABLE: SpinMutex<BitMap> = {
@@ -63,6 +68,7 @@ pub unsafe fn init_idt() {
 		local_idt[APIC_TIMER_VECTOR as usize].set_handler_fn(apic_timer_handler);
 		local_idt[KEYBOARD_VECTOR as usize].set_handler_fn(keyboard_interrupt_handler);
 		local_idt[SERIAL_VECTOR as usize].set_handler_fn(serial_input_interrupt_handler);
+		local_idt[MOUSE_VECTOR as usize].set_handler_fn(mouse_interrupt_handler);
 		local_idt[RTC_VECTOR as usize].set_handler_fn(rtc_timer_handler);
 
 		// syscall handler
@@ -99,9 +105,77 @@ pub unsafe fn add_idt_entry(
 	});
 }}
 
+/// Counts an interrupt on `vector`. Called at the top of device handlers.
+#[inline(always)]
+pub fn count_irq(vector: u8) {
+	IRQ_COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns how many interrupts were counted on `vector`.
+pub fn irq_count(vector: u8) -> u64 {
+	IRQ_COUNTS[vector as usize].load(Ordering::Relaxed)
+}
+
+/// Returns whether the IDT has a handler for `vector`.
+pub fn has_handler(vector: u8) -> bool {
+	if !IDT_INITED.load(Ordering::SeqCst) {
+		return false;
+	}
+	let idt = unsafe { &*(core::ptr::addr_of!(IDT_STORAGE) as *const InterruptDescriptorTable) };
+	!idt[vector as usize].handler_addr().is_null()
+}
+
+/// Interrupt statistics of one vector, as shown by `irqstat`.
+#[derive(Debug, Clone, Copy)]
+pub struct IrqStat {
+	/// The IDT vector.
+	pub vector: u8,
+	/// The IOAPIC input routed to the vector, if any. The APIC timer has none.
+	pub gsi: Option<u8>,
+	/// Whether the IDT has a handler for the vector.
+	pub has_handler: bool,
+	/// Interrupts counted on the vector.
+	pub count: u64
+}
+
+/// Returns the statistics of every device vector that has a handler or
+/// has been counted.
+pub fn irq_stats() -> Vec<IrqStat> {
+	// which unmasked IOAPIC input feeds each vector
+	let mut gsis = [None; 256];
+	::x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut ioapic = IOAPIC.lock();
+		let max_entry = unsafe { ioapic.max_table_entry() };
+		for gsi in 0..=max_entry {
+			let entry = unsafe { ioapic.table_entry(gsi) };
+			if !entry.mask() {
+				gsis[entry.vector() as usize] = Some(gsi);
+			}
+		}
+	});
+
+	(APIC_TIMER_VECTOR..=u8::MAX)
+		.map(|vector| IrqStat {
+			vector,
+			gsi: gsis[vector as usize],
+			has_handler: has_handler(vector),
+			count: irq_count(vector)
+		})
+		.filter(|stat| stat.has_handler || stat.count > 0)
+		.collect()
+}
+
 /// Breakpoint exception handler.
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-	println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+	crate::try_println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+}
+
+/// Reports a kernel stack overflow if `fault_addr` is in a stack guard page.
+fn report_stack_overflow(fault_addr: u64) {
+    if crate::memory::is_kernel_stack_guard(fault_addr) {
+        serial_println!("KERNEL STACK OVERFLOW (guard page hit at {:#x})", fault_addr);
+        println!("KERNEL STACK OVERFLOW (guard page hit at {:#x})", fault_addr);
+    }
 }
 
 extern "x86-interrupt" fn page_fault_handler(
@@ -111,6 +185,7 @@ extern "x86-interrupt" fn page_fault_handler(
     use ::x86_64::registers::control::Cr2;
 
     let addr = Cr2::read();
+    report_stack_overflow(Cr2::read_raw());
     serial_println!("EXCEPTION: PAGE FAULT");
     serial_println!("Accessed Address: {:?}", addr);
     serial_println!("Error Code: {:?}", error_code);
@@ -136,6 +211,9 @@ extern "x86-interrupt" fn general_protection_fault_handler(
     println!("Error Code: {}", error_code);
     println!("StackFrame: {:#?}", stack_frame);
 
+    if crate::config::reboot_on_fatal_fault() {
+        crate::arch::x86_64::reset::reboot();
+    }
     panic!("System halted");
 }
 
@@ -151,28 +229,25 @@ extern "x86-interrupt" fn double_fault_handler(
     println!("Error Code: {}", error_code);
     println!("StackFrame: {:#?}", stack_frame);
 
+    // a page fault on an overflowed stack can't push its frame, so stack
+    // overflows usually end up here
+    report_stack_overflow(::x86_64::registers::control::Cr2::read_raw());
+
+    if crate::config::reboot_on_fatal_fault() {
+        crate::arch::x86_64::reset::reboot();
+    }
     panic!("System halted");
 }
 
 /// Keyboard interrupt handler.
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+	count_irq(KEYBOARD_VECTOR);
 	use ::x86_64::instructions::port::Port;
 
 	let mut port = Port::new(0x60);
 	let scancode: u8 = unsafe { port.read() };
 
-	{
-		let mut lock = CURRENT_PROCESS.lock();
-		if let Some(proc) = lock.as_mut() {
-			if let Ok(queue) = proc.scancode_queue.try_get()
-				&& queue.push(scancode).is_ok()
-			{
-				proc.waker.wake();
-			}
-		} else {
-			add_scancode(scancode);
-		}
-	}
+	route_scancode(scancode);
 
 	unsafe {
 		send_eoi();
@@ -180,6 +255,7 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
 }
 
 extern "x86-interrupt" fn serial_input_interrupt_handler(_stack_frame: InterruptStackFrame) {
+	count_irq(SERIAL_VECTOR);
 	use ::x86_64::instructions::port::Port;
 
 	loop {
@@ -199,6 +275,16 @@ extern "x86-interrupt" fn serial_input_interrupt_handler(_stack_frame: Interrupt
 	}
 }
 
+/// PS/2 mouse interrupt handler.
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+	count_irq(MOUSE_VECTOR);
+	mouse::ps2::handle_interrupt();
+
+	unsafe {
+		send_eoi();
+	}
+}
+
 /// Spurious interrupt handler (vector 0xFF).
 extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
 	serial_println!("[WARNING] Spurious interrupt received (vector 0xFF)");
@@ -210,13 +296,18 @@ extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStac
 ///
 /// This handler is invoked when the APIC timer fires.
 extern "x86-interrupt" fn apic_timer_handler(_stack_frame: InterruptStackFrame) {
-	APIC_TICK_COUNT.fetch_add(1, Ordering::Relaxed);
+	count_irq(APIC_TIMER_VECTOR);
+	let now = APIC_TICK_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+	timer::wake_expired(now);
+	watchdog::check(now);
+	net::timer_poll(now);
 	unsafe {
 		send_eoi();
 	}
 }
 
 extern "x86-interrupt" fn rtc_timer_handler(_stack_frame: InterruptStackFrame) {
+	count_irq(RTC_VECTOR);
 	// ack
 	unsafe {
 		outb(CMOS_INDEX, REG_C | NMI_BIT);
@@ -318,3 +409,30 @@ pub fn allocate_and_register_vector(
 
 	Err(NullexError::VectorTableFull) // table full
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{interrupts::*, utils::ktest::TestError};
+
+	pub fn test_irq_counts() -> Result<(), TestError> {
+		// counted by hand, since the test runner leaves interrupts disabled;
+		// nothing is routed to this vector
+		let vector = 0xf0;
+		let before = irq_count(vector);
+		count_irq(vector);
+		count_irq(vector);
+		assert_eq!(irq_count(vector), before + 2);
+
+		let stats = irq_stats();
+		// vectors without a handler still show up once they've been counted
+		assert!(stats.iter().any(|stat| stat.vector == vector && stat.count == before + 2));
+		let timer = stats
+			.iter()
+			.find(|stat| stat.vector == APIC_TIMER_VECTOR)
+			.ok_or(TestError::Error)?;
+		assert!(timer.has_handler && timer.gsi.is_none());
+		assert!(!has_handler(0xfe) || stats.iter().any(|stat| stat.vector == 0xfe));
+		Ok(())
+	}
+	crate::create_test!(test_irq_counts);
+}
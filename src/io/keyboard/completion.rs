@@ -11,10 +11,11 @@ use alloc::{
 
 use crate::{
 	drivers::keyboard::scancode::CWD,
-	fs,
+	fs::{self, resolve_path},
 	print,
+	print_colours,
 	println,
-	task::keyboard::commands::{CMD_HISTORY, CMD_HISTORY_INDEX},
+	task::keyboard::commands::{self, CMD_HISTORY, CMD_HISTORY_INDEX},
 	vga_buffer::console_backspace
 };
 
@@ -37,176 +38,213 @@ fn command_supports_completion(command: &str) -> CompletionType {
 	}
 }
 
-/// Complete the command with the use of the `TAB` key
-pub fn tab_completion(line: &mut String) {
-	let parts: Vec<&str> = line.split(' ').collect();
-	let part = parts[parts.len() - 1].to_string();
-
-	let completion_type = command_supports_completion(parts[0]);
-	if completion_type == CompletionType::None {
-		line.push_str("    ");
-		print!("    ");
-		return;
-	}
+/// Something `TAB` can complete to.
+struct Candidate {
+	name: String,
+	is_dir: bool
+}
 
-	fs::with_fs(|fs| {
-		let files = fs.list_dir(&CWD.lock());
-		let file_types = fs
-			.list_dir_entry_types(&CWD.lock())
+/// Complete the command with the use of the `TAB` key.
+///
+/// The first word completes against the registered commands, later words
+/// against the entries of the directory they point into (the current one by
+/// default). A unique match is filled in; otherwise the common prefix of the
+/// matches is filled in, or the matches are listed if there is none.
+pub fn tab_completion(line: &mut String) {
+	let (first_word, word) = match line.rsplit_once(' ') {
+		Some((before, word)) => (before.split_whitespace().next(), word.to_string()),
+		None => (None, line.clone())
+	};
+
+	let (dir, prefix) = split_path_word(&word);
+	let mut candidates = match first_word {
+		None => commands::command_names()
 			.into_iter()
-			.flatten()
-			.collect::<Vec<String>>();
-
-		if let Ok(files) = files {
-			let mut matches = files
-				.iter()
-				.filter(|f| f.starts_with(&part))
-				.collect::<Vec<_>>();
-
-			if matches.len() == 1 {
-				match completion_type {
-					CompletionType::File => {
-						if let Some(pos) = files.iter().position(|r| r == matches[0].as_str()) {
-							if file_types[pos] == "File" {
-								let match_str = matches.pop().unwrap();
-
-								// remove the part of the line that is being
-								// completed.
-								for _ in 0..part.len() {
-									line.pop();
-									console_backspace();
-								}
-
-								line.push_str(match_str);
-								print!("{}", match_str);
-							}
-						}
-					}
-					CompletionType::Directory => {
-						if let Some(pos) = files.iter().position(|r| r == matches[0].as_str()) {
-							if file_types[pos] == "Directory" {
-								let match_str = matches.pop().unwrap();
-
-								for _ in 0..part.len() {
-									line.pop();
-									console_backspace();
-								}
-
-								line.push_str(match_str);
-								print!("{}", match_str);
-							}
-						}
-					}
-					CompletionType::Both => {
-						if let Some(pos) = files.iter().position(|r| r == matches[0].as_str()) {
-							if file_types[pos] == "Directory" || file_types[pos] == "File" {
-								let match_str = matches.pop().unwrap();
-
-								for _ in 0..part.len() {
-									line.pop();
-									console_backspace();
-								}
-
-								line.push_str(match_str);
-								print!("{}", match_str);
-							}
-						}
-					}
-					_ => return
-				}
+			.filter(|name| name.starts_with(prefix))
+			.map(|name| Candidate { name, is_dir: false })
+			.collect(),
+		Some(command) => {
+			let completion_type = command_supports_completion(command);
+			if completion_type == CompletionType::None {
+				line.push_str("    ");
+				print!("    ");
+				return;
 			}
-			if matches.len() > 1 {
-				println!();
-
-				match completion_type {
-					CompletionType::File => {
-						for m in matches {
-							if let Some(pos) = files.iter().position(|r| r == m.as_str()) {
-								if file_types[pos] == "File" {
-									println!("{}", m);
-								}
-							}
-						}
-					}
-					CompletionType::Directory => {
-						for m in matches {
-							if let Some(pos) = files.iter().position(|r| r == m.as_str()) {
-								if file_types[pos] == "Directory" {
-									println!("{}", m);
-								}
-							}
-						}
-					}
-					CompletionType::Both => {
-						for m in matches {
-							println!("{}", m);
-						}
-					}
-					_ => return
-				}
-				print!("test@nullex: {} $ {}", *CWD.lock(), line);
+			path_candidates(dir, prefix, completion_type)
+		}
+	};
+	candidates.sort_by(|a, b| a.name.cmp(&b.name));
+
+	match candidates.as_slice() {
+		[] => {}
+		[only] => {
+			// directories keep going, anything else ends the word
+			let suffix = if only.is_dir { "/" } else { " " };
+			insert(line, &only.name[prefix.len()..]);
+			insert(line, suffix);
+		}
+		_ => {
+			let names: Vec<&str> = candidates.iter().map(|c| c.name.as_str()).collect();
+			let common = common_prefix(&names);
+			if common.len() > prefix.len() {
+				insert(line, &common[prefix.len()..]);
+				return;
 			}
+
+			println!();
+			for candidate in candidates.iter() {
+				let suffix = if candidate.is_dir { "/" } else { "" };
+				print!("{}{}  ", candidate.name, suffix);
+			}
+			println!();
+			print_colours!(
+				("test", Color::Green),
+				(&format!("@nullex: {} $ ", *CWD.lock()), Color::White)
+			);
+			print!("{}", line);
 		}
-	});
+	}
 }
 
-/// Uparrow completion. Goes through the command history.
-pub fn uparrow_completion(line: &mut String) {
-	// lock the history and history index.
-	let history = CMD_HISTORY.lock();
-	let mut index = CMD_HISTORY_INDEX.lock();
+/// Appends `text` to the line and the screen.
+fn insert(line: &mut String, text: &str) {
+	line.push_str(text);
+	print!("{}", text);
+}
 
-	if history.is_empty() {
-		return;
+/// Splits a word being completed into the directory it points into and the
+/// prefix of the entry name, e.g. `apps/he` into `apps/` and `he`.
+fn split_path_word(word: &str) -> (&str, &str) {
+	match word.rfind('/') {
+		Some(slash) => word.split_at(slash + 1),
+		None => ("", word)
 	}
+}
 
-	// if we're not at the oldest command move one step backward.
-	if *index > 0 {
-		*index -= 1;
+/// Entries of `dir` (relative to the current directory) starting with
+/// `prefix`, filtered by what the command accepts. Directories are always
+/// offered, so a path can be completed through them.
+fn path_candidates(dir: &str, prefix: &str, completion_type: CompletionType) -> Vec<Candidate> {
+	let dir = resolve_path(if dir.is_empty() { "." } else { dir });
+	let Ok(entries) = fs::list_dir(&dir) else {
+		return Vec::new();
+	};
+
+	entries
+		.into_iter()
+		.filter(|name| name.starts_with(prefix))
+		.map(|name| {
+			let is_dir = fs::is_dir(&format!("{}{}", dir, name));
+			Candidate { name, is_dir }
+		})
+		.filter(|c| c.is_dir || completion_type != CompletionType::Directory)
+		.collect()
+}
+
+/// Longest prefix shared by all of `names`.
+fn common_prefix<'a>(names: &[&'a str]) -> &'a str {
+	let Some(first) = names.first() else {
+		return "";
+	};
+
+	let mut len = first.len();
+	for name in &names[1..] {
+		len = first
+			.char_indices()
+			.zip(name.chars())
+			.take_while(|((_, a), b)| a == b)
+			.last()
+			.map_or(0, |((i, a), _)| i + a.len_utf8())
+			.min(len);
 	}
+	&first[..len]
+}
 
-	// clear the current input from the screen.
-	for _ in 0..line.len() {
-		line.pop();
+/// Works out the history index after pressing up (`up`) or down.
+///
+/// Index `len` is the empty line past the newest entry. Returns `None` when
+/// there is no history to walk.
+fn next_history_index(index: usize, len: usize, up: bool) -> Option<usize> {
+	if len == 0 {
+		return None;
+	}
+
+	let index = index.min(len);
+	Some(if up { index.saturating_sub(1) } else { (index + 1).min(len) })
+}
+
+/// Replaces the input line on screen and in `line` with `new`.
+fn replace_line(line: &mut String, new: &str) {
+	// erase one cell per character, not per byte
+	for _ in 0..line.chars().count() {
 		console_backspace();
 	}
+	line.clear();
 
-	// get the command from history and print it.
-	let cmd = &history[*index];
-	print!("{}", cmd);
-	line.push_str(cmd);
+	print!("{}", new);
+	line.push_str(new);
 }
 
-/// Downarrow completion. Goes through the command history
-pub fn downarrow_completion(line: &mut String) {
+/// Moves through the command history and shows the selected entry. Moving
+/// down past the newest entry clears the line.
+fn walk_history(line: &mut String, up: bool) {
+	// lock the history and history index.
 	let history = CMD_HISTORY.lock();
 	let mut index = CMD_HISTORY_INDEX.lock();
 
-	if history.is_empty() {
+	let Some(next) = next_history_index(*index, history.len(), up) else {
 		return;
-	}
+	};
+	*index = next;
 
-	// if already at the newest command clear the line and do nothing.
-	if *index >= history.len() - 1 {
-		// clear the current input from the screen.
-		for _ in 0..line.len() {
-			line.pop();
-			console_backspace();
-		}
-		return;
+	match history.get(next) {
+		Some(cmd) => replace_line(line, cmd),
+		None => replace_line(line, "")
 	}
+}
 
-	// otherwise move one step forward.
-	*index += 1;
+/// Uparrow completion. Goes through the command history.
+pub fn uparrow_completion(line: &mut String) {
+	walk_history(line, true);
+}
 
-	// clear the current input from the screen.
-	for _ in 0..line.len() {
-		line.pop();
-		console_backspace();
-	}
+/// Downarrow completion. Goes through the command history
+pub fn downarrow_completion(line: &mut String) {
+	walk_history(line, false);
+}
 
-	// get the command from history and display it.
-	let cmd = &history[*index];
-	print!("{}", cmd);
-	line.push_str(cmd);
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{io::keyboard::completion::*, utils::ktest::TestError};
+
+	pub fn test_completion_helpers() -> Result<(), TestError> {
+		assert_eq!(split_path_word("he"), ("", "he"));
+		assert_eq!(split_path_word("apps/he"), ("apps/", "he"));
+		assert_eq!(split_path_word("/proc/"), ("/proc/", ""));
+
+		assert_eq!(common_prefix(&["netstat", "netpoll"]), "net");
+		assert_eq!(common_prefix(&["cat", "cd"]), "c");
+		assert_eq!(common_prefix(&["ls", "mkdir"]), "");
+		assert_eq!(common_prefix(&["sync"]), "sync");
+		assert_eq!(common_prefix(&[]), "");
+		Ok(())
+	}
+	crate::create_test!(test_completion_helpers);
+
+	pub fn test_history_navigation_edges() -> Result<(), TestError> {
+		// nothing to walk
+		assert_eq!(next_history_index(0, 0, true), None);
+		assert_eq!(next_history_index(0, 0, false), None);
+
+		// after running a command the index sits past the newest entry, and
+		// up recalls the last command
+		assert_eq!(next_history_index(3, 3, true), Some(2));
+		assert_eq!(next_history_index(0, 3, true), Some(0));
+
+		// down past the newest entry lands on the empty line and stays there
+		assert_eq!(next_history_index(2, 3, false), Some(3));
+		assert_eq!(next_history_index(3, 3, false), Some(3));
+		Ok(())
+	}
+	crate::create_test!(test_history_navigation_edges);
 }
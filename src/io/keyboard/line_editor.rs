@@ -12,21 +12,22 @@ use crate::{
 	drivers::keyboard::{
 		layouts,
 		ps2::Keyboard,
-		queue::ScancodeStream,
+		queue::{ScancodeStream, capacity_from_cmdline},
 		scancode::{CWD, KeyCode, ScancodeSet1}
 	}, io::keyboard::{
 		completion::{downarrow_completion, tab_completion, uparrow_completion},
 		decode::{DecodedKey, HandleControl}
-	}, print, print_colours, task::yield_now, vga_buffer::{WRITER, console_backspace}
+	}, print, print_colours, task::yield_now, utils::multiboot2::kernel_cmdline,
+	vga_buffer::{WRITER, console_backspace}
 };
 
 /// The async function that reads scancodes and processes keypresses.
 pub async fn print_keypresses() -> i32 {
-	let mut scancodes = ScancodeStream::new();
+	let mut scancodes = ScancodeStream::with_capacity(capacity_from_cmdline(&kernel_cmdline()));
 
 	let mut keyboard = Keyboard::new(
 		ScancodeSet1::new(),
-		layouts::us104::Us104Key,
+		layouts::ActiveLayout,
 		HandleControl::Ignore
 	);
 
@@ -71,11 +72,20 @@ pub async fn print_keypresses() -> i32 {
 						continue;
 					// escape: clear screen
 					} else if c as u8 == 27 {
-						WRITER.lock().clear_everything();
+						// redraw off screen so the prompt and cursor don't flicker
+						{
+							let mut writer = WRITER.lock();
+							writer.set_cursor_visible(false);
+							writer.set_double_buffered(true);
+							writer.clear_everything();
+						}
 						print_colours!(
 							("test", Color::Green),
 							(&format!("@nullex: {} $ ", *CWD.lock()), Color::White)
 						);
+						let mut writer = WRITER.lock();
+						writer.set_double_buffered(false);
+						writer.set_cursor_visible(true);
 						continue;
 
 					// tab: handle tab completion
@@ -5,9 +5,12 @@
 //! 
 
 use alloc::vec::Vec;
+use core::fmt;
+
+use x86_64::PhysAddr;
 
 use crate::{
-	allocator::io_alloc::IO_ALLOC, common::ports::{inl, outb, outl, outq, outw}, error::NullexError, lazy_static, serial_println, utils::{
+	allocator::io_alloc::IO_ALLOC, common::ports::{inl, outb, outl, outq, outw}, ensure, error::NullexError, lazy_static, memory, serial_println, utils::{
 		mutex::SpinMutex,
 		types::{DWORD, WORD}
 	}
@@ -19,7 +22,13 @@ pub const VIRTIO_PCI_VENDOR_ID: u16 = 0x1af4;
 pub const INTEL_VENDOR_ID: u16 = 0x8086;
 
 const PCI_COMMAND_IO: u16 = 0x0001;
+const PCI_COMMAND_MEMORY: u16 = 0x0002;
 const PCI_BUS_MASTER: u16 = 0x0004;
+const PCI_STATUS: u8 = 0x06;
+const PCI_STATUS_CAP_LIST: u16 = 0x0010;
+const PCI_CAPABILITY_LIST: u8 = 0x34;
+/// Capability ID of vendor specific capabilities, used by virtio.
+pub const PCI_CAP_ID_VENDOR: u8 = 0x09;
 
 const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
 const PCI_CONFIG_DATA: u16 = 0xCFC;
@@ -53,6 +62,13 @@ impl Bdf {
 	}
 }
 
+impl fmt::Display for Bdf {
+	/// Formats as `bus:device.function`, like `lspci`.
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.func)
+	}
+}
+
 /// Callback type for finalizing device initialization after IOAPIC setup
 pub type DeviceFinalizeCallback = fn() -> Result<(), NullexError>;
 
@@ -63,8 +79,9 @@ pub struct PciDevice {
 	pub bdf: Bdf,
 	info: DriverInfo,
 	bound_driver: Option<usize>,
-	// to be added.
+	/// Virtual address the memory BAR is mapped at.
 	mmio_base: Option<usize>,
+	mmio_size: Option<usize>,
 	/// The Base IO address for the device.
 	pub io_base: Option<usize>,
 	io_size: Option<usize>,
@@ -87,6 +104,7 @@ impl PciDevice {
 			info,
 			bound_driver,
 			mmio_base,
+			mmio_size: None,
 			io_base,
 			io_size,
 			finalize_callback: None
@@ -103,6 +121,67 @@ impl PciDevice {
 	pub fn set_finalize_callback(&mut self, callback: DeviceFinalizeCallback) {
 		self.finalize_callback = Some(callback);
 	}
+
+	/// The device's vendor ID.
+	pub fn vendor_id(&self) -> u16 {
+		self.info.vendor.unwrap_or(0xFFFF)
+	}
+
+	/// The device's device ID.
+	pub fn device_id(&self) -> u16 {
+		self.info.device.unwrap_or(0xFFFF)
+	}
+
+	/// The device's class code.
+	pub fn class(&self) -> u8 {
+		self.info.class.unwrap_or(0xFF)
+	}
+
+	/// The device's subclass code.
+	pub fn subclass(&self) -> u8 {
+		self.info.subclass.unwrap_or(0xFF)
+	}
+
+	/// Virtual address of the device's memory-mapped BAR, once enabled.
+	pub fn mmio_base(&self) -> Option<usize> {
+		self.mmio_base
+	}
+
+	/// Size in bytes of the device's memory-mapped BAR, once enabled.
+	pub fn mmio_size(&self) -> Option<usize> {
+		self.mmio_size
+	}
+
+	/// The instance index of the driver bound to this device, if any.
+	pub fn bound_driver(&self) -> Option<usize> {
+		self.bound_driver
+	}
+}
+
+/// Returns a readable name for a PCI class and subclass, as `lspci` shows.
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+	match (class, subclass) {
+		(0x01, 0x01) => "IDE interface",
+		(0x01, 0x06) => "SATA controller",
+		(0x01, 0x08) => "Non-Volatile memory controller",
+		(0x01, _) => "Mass storage controller",
+		(0x02, 0x00) => "Ethernet controller",
+		(0x02, _) => "Network controller",
+		(0x03, 0x00) => "VGA compatible controller",
+		(0x03, _) => "Display controller",
+		(0x04, _) => "Multimedia controller",
+		(0x05, _) => "Memory controller",
+		(0x06, 0x00) => "Host bridge",
+		(0x06, 0x01) => "ISA bridge",
+		(0x06, 0x04) => "PCI bridge",
+		(0x06, _) => "Bridge",
+		(0x07, _) => "Communication controller",
+		(0x08, _) => "System peripheral",
+		(0x0C, 0x03) => "USB controller",
+		(0x0C, 0x05) => "SMBus",
+		(0x0C, _) => "Serial bus controller",
+		_ => "Unclassified device"
+	}
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -444,7 +523,136 @@ pub fn pci_enable_device(dev: &mut PciDevice) -> Result<(), NullexError> {
 		);
 		return Ok(())
 	} else {
-		todo!("implement mmio pci device enabling");
+		let bar = size_memory_bar(dev.bdf, bar_offset, orig, mask)?;
+		if bar.size == 0 {
+			return Err(NullexError::Io("MMIO Bar Size == 0"));
+		}
+		// there's no allocator for physical address space, so rely on the
+		// firmware having placed the BAR
+		if bar.base == 0 {
+			return Err(NullexError::Io("MMIO Bar not assigned"));
+		}
+
+		let virt = memory::map_mmio(PhysAddr::new(bar.base), bar.size as usize, bar.prefetchable)?;
+		dev.mmio_base = Some(virt.as_u64() as usize);
+		dev.mmio_size = Some(bar.size as usize);
+
+		let mut cmd = pci_config_read::<WORD>(dev.bdf, 0x04)
+			.map_err(|_| NullexError::Io("Failed to read command register"))?;
+		cmd |= PCI_COMMAND_MEMORY;
+		cmd |= PCI_BUS_MASTER;
+		pci_config_write::<WORD>(dev.bdf, 0x04, cmd)?;
+
+		serial_println!(
+			"[PCI] Device: {:?} enabled (MMIO phys={:#x}, virt={:#x}, size={:#x}, 64-bit={}, prefetchable={})",
+			dev.bdf,
+			bar.base,
+			virt.as_u64(),
+			bar.size,
+			bar.is_64bit,
+			bar.prefetchable
+		);
+		Ok(())
+	}
+}
+
+/// Reads and sizes the memory BAR at slot `index` (0-5) without touching the
+/// command register. Fails if the slot holds an I/O BAR.
+pub fn pci_memory_bar(bdf: Bdf, index: u8) -> Result<MemoryBar, NullexError> {
+	ensure!(index < 6, NullexError::Io("BAR index out of range"));
+	let bar_offset = 0x10 + index * 4;
+	let orig = pci_config_read::<DWORD>(bdf, bar_offset)
+		.map_err(|_| NullexError::Io("Failed to read original BAR value"))?;
+	ensure!(orig & 1 == 0, NullexError::Io("BAR is not a memory BAR"));
+
+	pci_config_write::<DWORD>(bdf, bar_offset, 0xFFFF_FFFF)?;
+	let mask = pci_config_read::<DWORD>(bdf, bar_offset)
+		.map_err(|_| NullexError::Io("Failed to read BAR size mask"))?;
+	pci_config_write::<DWORD>(bdf, bar_offset, orig)?;
+
+	size_memory_bar(bdf, bar_offset, orig, mask)
+}
+
+/// Decodes a memory BAR from its lower half, sizing the upper half in the
+/// next slot first if it is a 64-bit BAR.
+fn size_memory_bar(
+	bdf: Bdf,
+	bar_offset: u8,
+	orig: u32,
+	mask: u32
+) -> Result<MemoryBar, NullexError> {
+	let (orig_hi, mask_hi) = if mask & 0x6 == 0x4 {
+		let hi_offset = bar_offset + 4;
+		let orig_hi = pci_config_read::<DWORD>(bdf, hi_offset)
+			.map_err(|_| NullexError::Io("Failed to read upper BAR value"))?;
+		pci_config_write::<DWORD>(bdf, hi_offset, 0xFFFF_FFFF)?;
+		let mask_hi = pci_config_read::<DWORD>(bdf, hi_offset)
+			.map_err(|_| NullexError::Io("Failed to read upper BAR size mask"))?;
+		pci_config_write::<DWORD>(bdf, hi_offset, orig_hi)?;
+		(orig_hi, mask_hi)
+	} else {
+		(0, 0xFFFF_FFFF)
+	};
+
+	Ok(MemoryBar::decode(orig, mask, orig_hi, mask_hi))
+}
+
+/// Walks the device's capability list, returning the config space offset and
+/// ID of every capability in order. Empty if the device has no list.
+pub fn pci_capabilities(bdf: Bdf) -> Vec<(u8, u8)> {
+	let mut caps = Vec::new();
+	let status = pci_config_read::<WORD>(bdf, PCI_STATUS).unwrap_or(0);
+	if status & PCI_STATUS_CAP_LIST == 0 {
+		return caps;
+	}
+
+	let mut offset = pci_config_read::<u8>(bdf, PCI_CAPABILITY_LIST).unwrap_or(0) & !0x3;
+	// a list can't hold more entries than fit in config space, which also
+	// stops a looping list
+	while offset >= 0x40 && caps.len() < 48 {
+		let id = pci_config_read::<u8>(bdf, offset).unwrap_or(0);
+		caps.push((offset, id));
+		offset = pci_config_read::<u8>(bdf, offset + 1).unwrap_or(0) & !0x3;
+	}
+	caps
+}
+
+/// Enables memory space decoding and bus mastering, for devices whose
+/// memory BARs are mapped by their driver.
+pub fn pci_enable_memory(bdf: Bdf) -> Result<(), NullexError> {
+	let mut cmd = pci_config_read::<WORD>(bdf, 0x04)
+		.map_err(|_| NullexError::Io("Failed to read command register"))?;
+	cmd |= PCI_COMMAND_MEMORY;
+	cmd |= PCI_BUS_MASTER;
+	pci_config_write::<WORD>(bdf, 0x04, cmd)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A decoded memory BAR.
+pub struct MemoryBar {
+	/// Physical base address.
+	pub base: u64,
+	/// Size in bytes.
+	pub size: u64,
+	/// Whether the BAR spans two slots.
+	pub is_64bit: bool,
+	/// Whether reads have no side effects, so the region may be cached.
+	pub prefetchable: bool
+}
+
+impl MemoryBar {
+	/// Decodes a memory BAR from its original value and the size mask read
+	/// back after writing all ones. For a 32-bit BAR pass 0 and `0xFFFF_FFFF`
+	/// as the upper halves.
+	pub fn decode(orig_lo: u32, mask_lo: u32, orig_hi: u32, mask_hi: u32) -> MemoryBar {
+		let base = ((orig_hi as u64) << 32) | (orig_lo & !0xF) as u64;
+		let mask = ((mask_hi as u64) << 32) | (mask_lo & !0xF) as u64;
+		MemoryBar {
+			base,
+			size: (!mask).wrapping_add(1),
+			is_64bit: mask_lo & 0x6 == 0x4,
+			prefetchable: mask_lo & 0x8 != 0
+		}
 	}
 }
 
@@ -460,3 +668,38 @@ pub fn pci_find_index_from_gsi(gsi: usize) -> Option<usize> {
 	}
 	None
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::string::ToString;
+
+	use crate::{io::pci::*, utils::ktest::TestError};
+
+	pub fn test_pci_device_formatting() -> Result<(), TestError> {
+		assert_eq!(Bdf::new(0, 3, 0).to_string(), "00:03.0");
+		assert_eq!(Bdf::new(0x1a, 0x1f, 7).to_string(), "1a:1f.7");
+
+		assert_eq!(class_name(0x02, 0x00), "Ethernet controller");
+		assert_eq!(class_name(0x06, 0x00), "Host bridge");
+		assert_eq!(class_name(0x02, 0x80), "Network controller");
+		assert_eq!(class_name(0xFF, 0x00), "Unclassified device");
+		Ok(())
+	}
+	crate::create_test!(test_pci_device_formatting);
+
+	pub fn test_memory_bar_decoding() -> Result<(), TestError> {
+		// 32-bit, non-prefetchable, 4 KiB at 0xFEBD_1000
+		let bar = MemoryBar::decode(0xFEBD_1000, 0xFFFF_F000, 0, 0xFFFF_FFFF);
+		assert_eq!(bar.base, 0xFEBD_1000);
+		assert_eq!(bar.size, 0x1000);
+		assert!(!bar.is_64bit && !bar.prefetchable);
+
+		// 64-bit, prefetchable, 16 KiB above 4 GiB
+		let bar = MemoryBar::decode(0x0000_000C, 0xFFFF_C00C, 0x0000_0008, 0xFFFF_FFFF);
+		assert_eq!(bar.base, 0x8_0000_0000);
+		assert_eq!(bar.size, 0x4000);
+		assert!(bar.is_64bit && bar.prefetchable);
+		Ok(())
+	}
+	crate::create_test!(test_memory_bar_decoding);
+}
@@ -41,8 +41,10 @@ pub mod pit;
 pub mod rtc;
 #[allow(deprecated)]
 pub mod serial;
+pub mod smp;
 pub mod syscall;
 pub mod task;
+pub mod tsc;
 pub mod utils;
 pub mod vga_buffer;
 
@@ -72,13 +74,14 @@ use crate::{
 	},
 	ioapic::{IOAPIC, dump_gsi},
 	memory::{BootInfoFrameAllocator, init_global_alloc},
+	serial::{init_serial_input, serial_console},
 	task::{
-		Process, ProcessId, executor::{self, CURRENT_PROCESS, EXECUTOR}, keyboard
+		Priority, Process, ProcessId, executor::{self, CURRENT_PROCESS, EXECUTOR}, keyboard, watchdog
 	},
-	utils::{boot::init_efer, multiboot2::parse_multiboot2, mutex::SpinMutex, process::spawn_process}
+	utils::{boot::init_efer, multiboot2::parse_multiboot2, mutex::SpinMutex, process::{set_priority, spawn_process}}
 };
 
-use crate::drivers::virtio::net::virtio_net_driver_init;
+use crate::drivers::virtio::{blk::virtio_blk_driver_init, net::virtio_net_driver_init};
 
 lazy_static! {
 	/// Static reference to the physical memory offset for the kernel.
@@ -119,6 +122,13 @@ pub unsafe extern "C" fn kernel_main(mbi_addr: usize) -> ! {
 		panic!("Global Allocator Initialization failed: {}", e);
 	}
 
+	// Graphical boots have no VGA text buffer, so draw the console ourselves
+	if let Some(fb) = boot_info.framebuffer.as_ref()
+		&& let Err(e) = drivers::framebuffer::init(fb)
+	{
+		serial_println!("[FB] Staying on the VGA text buffer: {}", e);
+	}
+
 	// Initialize GDT and IDT
 	crate::init();
 
@@ -141,7 +151,7 @@ pub unsafe extern "C" fn kernel_main(mbi_addr: usize) -> ! {
 	}
 
 	rtc::init_rtc();
-	match apic::calibrate(1024) {
+	match apic::calibrate(apic::DEFAULT_TIMER_HZ as u32) {
 		Ok((ticks_per_sec, initial_count)) => {
 			serial_println!("APIC ticks/sec = {}", ticks_per_sec);
 			APIC_TPS.store(ticks_per_sec, Ordering::SeqCst);
@@ -174,9 +184,13 @@ pub unsafe extern "C" fn kernel_main(mbi_addr: usize) -> ! {
 
 	serial_println!("[PCI] Registering platform drivers before PCI discovery...");
 	virtio_net_driver_init();
+	virtio_blk_driver_init();
 
 	discover_pci_devices();
 
+	// the disk holding the image may be a virtio one, so wait for probing
+	crate::fs::persist::load_on_boot();
+
 	// Link ISOs and program IOAPIC
 	unsafe {
 		link_isos();
@@ -187,18 +201,28 @@ pub unsafe extern "C" fn kernel_main(mbi_addr: usize) -> ! {
 		panic!("Failed to finalize PCI devices: {}", e);
 	}
 
+	if let Err(e) = drivers::mouse::ps2::init() {
+		serial_println!("[MOUSE] No PS/2 mouse: {}", e);
+	}
+
 	serial_println!("[INIT] Enabling CPU interrupts...");
 	enable();
 	serial_println!("[INIT] Interrupts enabled successfully!");
 
+	// calibration counts APIC timer ticks, so interrupts have to be on;
+	// starting the other CPUs then times its waits with the TSC
+	tsc::calibrate();
+	smp::start_aps();
+
 	dump_gsi(11);
 
 	// network init
 	crate::net::init();
+	let gateway = crate::net::gateway_ip();
 	serial_println!("[NET] Resolving gateway MAC...");
-	let _ = crate::net::send_arp_request(crate::net::GATEWAY_IP);
+	let _ = crate::net::send_arp_request(gateway);
 
-	match crate::net::arp::wait_for_arp(crate::net::GATEWAY_IP, 2000) {
+	match crate::net::arp::wait_for_arp(gateway, 2000) {
 		Ok(mac) => {
 			serial_println!(
 				"[NET] Gateway MAC: {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
@@ -224,7 +248,7 @@ pub unsafe extern "C" fn kernel_main(mbi_addr: usize) -> ! {
 	if let Some(mac) = crate::net::arp::ARP_CACHE
 		.lock()
 		.iter()
-		.find(|(ip, _)| *ip == crate::net::GATEWAY_IP)
+		.find(|(ip, _)| *ip == gateway)
 		.map(|(_, mac)| *mac)
 	{
 		serial_println!(
@@ -240,6 +264,9 @@ pub unsafe extern "C" fn kernel_main(mbi_addr: usize) -> ! {
 		serial_println!("[NET] WARNING: Gateway MAC not resolved!");
 	}
 
+	#[cfg(feature = "test")]
+	crate::utils::ktest::run_all_tests(crate::utils::ktest::test_filter(&boot_info.cmdline));
+
 	WRITER.lock().clear_everything();
 
 	// Spawn processes
@@ -259,7 +286,7 @@ pub unsafe extern "C" fn kernel_main(mbi_addr: usize) -> ! {
 		}
 	};
 
-	let _keyboard_pid = match spawn_process(
+	let keyboard_pid = match spawn_process(
 		|_state| Box::pin(print_keypresses()) as Pin<Box<dyn Future<Output = i32>>>,
 		false
 	) {
@@ -269,10 +296,27 @@ pub unsafe extern "C" fn kernel_main(mbi_addr: usize) -> ! {
 			ProcessId::new(0)
 		}
 	};
+	// keep input responsive however busy the other processes are
+	if let Err(e) = set_priority(keyboard_pid, Priority::High) {
+		serial_println!("[ERROR] Failed to raise keyboard process priority: {}", e);
+	}
+
+	init_serial_input();
+	let _serial_pid = match spawn_process(
+		|_state| Box::pin(serial_console()) as Pin<Box<dyn Future<Output = i32>>>,
+		false
+	) {
+		Ok(pid) => pid,
+		Err(e) => {
+			serial_println!("[ERROR] Failed to spawn serial console process: {}", e);
+			ProcessId::new(0)
+		}
+	};
 
 	// Main executor loop
 	let process_queue = EXECUTOR.lock().process_queue.clone();
 	loop {
+		watchdog::feed();
 		if let Some(pid) = process_queue.pop() {
 			if let Some(process_arc) = EXECUTOR.lock().processes.get(&pid) {
 				process_arc
@@ -309,15 +353,31 @@ pub unsafe extern "C" fn kernel_main(mbi_addr: usize) -> ! {
 						.clone()
 				};
 				let mut context = Context::from_waker(&waker);
-				let result = process.future.as_mut().poll(&mut context);
+				// lets `request_cancel` wake the process from any context
+				process.state.waker.register(&waker);
+				// a cancelled process that didn't wind down in time is ended here
+				let result = if process.state.cancel_expired() {
+					Poll::Ready(executor::CANCELLED_EXIT_CODE)
+				} else {
+					// `lock()` left interrupts disabled. Turn them back on so the
+					// watchdog in the timer interrupt sees a process that spins;
+					// no interrupt handler locks a process, so holding this one
+					// is fine
+					enable();
+					process.future.as_mut().poll(&mut context)
+				};
+				// keep polling a cancelled process until it ends or its grace
+				// period runs out, even if it is waiting on something
+				if result.is_pending() && process.state.is_cancelled() {
+					waker.wake_by_ref();
+				}
 				unsafe {
 					executor::CURRENT_PROCESS_GUARD = core::ptr::null_mut();
 				}
+				drop(process);
+				memory::log_low_frames_warning();
 				if let Poll::Ready(exit_code) = result {
-					let mut executor = EXECUTOR.lock();
-					executor.processes.remove(&pid);
-					executor.waker_cache.remove(&pid);
-					serial_println!("Process {} exited with code: {}", pid.get(), exit_code);
+					EXECUTOR.lock().end_process(pid, exit_code);
 				}
 				*CURRENT_PROCESS.lock() = None;
 			}
@@ -344,6 +404,13 @@ fn qemu_exit(code: u32) -> ! {
 /// This function is called on panic.
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-	println!("{}", info);
+	let regs = crate::utils::panic::Registers::capture();
+	crate::utils::panic::report(info, &regs);
+	// a panicking test fails the run instead of hanging it
+	#[cfg(feature = "test")]
+	qemu_exit(crate::utils::ktest::QEMU_EXIT_FAILURE);
+	if crate::config::reboot_on_fatal_fault() {
+		crate::arch::x86_64::reset::reboot();
+	}
 	crate::hlt_loop();
 }
\ No newline at end of file
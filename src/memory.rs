@@ -5,12 +5,14 @@
 //!
 
 use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use x86_64::{
 	PhysAddr,
 	VirtAddr,
 	structures::paging::{
 		FrameAllocator,
+		FrameDeallocator,
 		Mapper,
 		OffsetPageTable,
 		Page,
@@ -23,7 +25,8 @@ use x86_64::{
 };
 
 use crate::{
-	PHYS_MEM_OFFSET, allocator::{self, ALLOCATOR_INFO}, arch::x86_64::bootinfo::{MemoryMap, MemoryRegionType}, error::NullexError, kassert, lazy_static, println, serial_println, task::AddressSpace, utils::{
+	PHYS_MEM_OFFSET, allocator::{self, ALLOCATOR_INFO}, arch::x86_64::bootinfo::{MemoryMap, MemoryRegionType}, error::NullexError, kassert, lazy_static, println, serial_println, smp::AP_TRAMPOLINE_BASE, task::AddressSpace, utils::{
+		logger::{levels::LogLevel, sinks::SYSLOG_SINK, traits::logger_sink::LoggerSink},
 		multiboot2::{__link_phys_base, _end, compute_phys_map_offset},
 		mutex::SpinMutex
 	}
@@ -35,7 +38,48 @@ lazy_static! {
 		SpinMutex::new(unsafe { compute_phys_map_offset() });
 }
 
+/// Free frame count below which a low-memory warning is logged (1 MiB).
+pub const LOW_FRAMES_THRESHOLD: usize = 256;
+
+/// Usable frames in the boot memory map, outside the kernel image.
+static FRAMES_TOTAL: AtomicUsize = AtomicUsize::new(0);
+/// Frames handed out by the frame allocator.
+static FRAMES_USED: AtomicUsize = AtomicUsize::new(0);
+/// Set the first time free frames drop below `LOW_FRAMES_THRESHOLD`, so the
+/// warning is only logged once even if freed frames bring the count back up.
+static LOW_FRAMES_WARNED: AtomicBool = AtomicBool::new(false);
+/// Set when the low-memory warning is due but hasn't been logged yet.
+static LOW_FRAMES_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Returns the number of frames the frame allocator has handed out.
+pub fn frames_used() -> usize {
+	FRAMES_USED.load(Ordering::Relaxed)
+}
+
+/// Returns the number of usable frames the frame allocator manages.
+pub fn frames_total() -> usize {
+	FRAMES_TOTAL.load(Ordering::Relaxed)
+}
+
+/// Returns the number of frames the frame allocator can still hand out.
+pub fn frames_free() -> usize {
+	frames_total().saturating_sub(frames_used())
+}
+
 static mut NEXT_DMA_VIRT: u64 = 0x5555_0000_0000;
+static mut NEXT_MMIO_VIRT: u64 = 0x5556_0000_0000;
+
+/// Start of the region kernel stacks are mapped in. It's above the PML4
+/// entries process address spaces share with the kernel, so every address
+/// space sees the stacks.
+const KERNEL_STACK_REGION: u64 = 0xFFFF_FE00_0000_0000;
+/// Pages reserved for each kernel stack, including its guard page.
+const KERNEL_STACK_SLOT_PAGES: usize = 64;
+/// Number of kernel stack slots.
+const KERNEL_STACK_SLOTS: usize = 512;
+/// Which kernel stack slots are in use.
+static KERNEL_STACK_SLOTS_USED: SpinMutex<[bool; KERNEL_STACK_SLOTS]> =
+	SpinMutex::new([false; KERNEL_STACK_SLOTS]);
 
 #[derive(Clone, Copy)]
 /// Structure representing a buffer of DMA (Direct Memory Access) information
@@ -134,20 +178,27 @@ pub fn map_ioapic(
 }
 
 /// A FrameAllocator that returns usable frames from the bootloader's memory
-/// map.
-#[derive(Clone, Copy)]
+/// map. Frames given back with `deallocate_frame` are kept on a free list
+/// and handed out again before any new frame.
+#[derive(Clone)]
 pub struct BootInfoFrameAllocator {
 	memory_map: &'static MemoryMap,
-	next: usize
+	next: usize,
+	/// Returned frames, reused last in first out.
+	free: Vec<PhysFrame>
 }
 
 impl BootInfoFrameAllocator {
 	/// Create a FrameAllocator from the passed memory map.
 	pub fn init(memory_map: &'static MemoryMap) -> Self {
-		BootInfoFrameAllocator { 
+		let allocator = BootInfoFrameAllocator { 
 			memory_map,
-			next: 0
-		}
+			next: 0,
+			free: Vec::new()
+		};
+		FRAMES_TOTAL.store(allocator.usable_frames().count(), Ordering::Relaxed);
+		FRAMES_USED.store(0, Ordering::Relaxed);
+		allocator
 	}
 
 	/// Returns an iterator over the usable frames specified in the memory map.
@@ -165,18 +216,81 @@ impl BootInfoFrameAllocator {
 
 		frame_addresses
 			.filter(move |addr| (addr < &kernel_start) || (addr >= &kernel_end))
+			// kept free for the code that starts the other CPUs
+			.filter(|addr| *addr != AP_TRAMPOLINE_BASE)
 			.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
 	}
-}
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
-	fn allocate_frame(&mut self) -> Option<PhysFrame> {
+	/// Allocates a frame that has never been handed out, skipping the free
+	/// list. Consecutive calls return ascending frames within a memory region,
+	/// which physically contiguous allocations rely on.
+	pub fn allocate_fresh_frame(&mut self) -> Option<PhysFrame> {
 		let frame = self.usable_frames().nth(self.next);
 		self.next += 1;
+		if frame.is_some() {
+			frame_allocated();
+		}
 		frame
 	}
 }
 
+/// Updates the frame counters after a frame was handed out.
+fn frame_allocated() {
+	FRAMES_USED.fetch_add(1, Ordering::Relaxed);
+	warn_if_low_on_frames();
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+	fn allocate_frame(&mut self) -> Option<PhysFrame> {
+		match self.free.pop() {
+			Some(frame) => {
+				frame_allocated();
+				Some(frame)
+			}
+			None => self.allocate_fresh_frame()
+		}
+	}
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+	/// Puts `frame` on the free list.
+	///
+	/// # Safety
+	/// `frame` must have come from this allocator and must no longer be mapped
+	/// or in use by a device.
+	unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+		self.free.push(frame);
+		FRAMES_USED.fetch_sub(1, Ordering::Relaxed);
+	}
+}
+
+/// Flags a warning the first time free frames drop below
+/// `LOW_FRAMES_THRESHOLD`, before `map_to` starts failing. The frame
+/// allocator is locked here and logging takes other locks, so the warning is
+/// left to `log_low_frames_warning`.
+fn warn_if_low_on_frames() {
+	if frames_free() < LOW_FRAMES_THRESHOLD && !LOW_FRAMES_WARNED.swap(true, Ordering::Relaxed) {
+		LOW_FRAMES_PENDING.store(true, Ordering::Relaxed);
+	}
+}
+
+/// Logs the low-memory warning flagged by the frame allocator, if any.
+/// Called by the executor between polls, where no allocator lock is held.
+pub fn log_low_frames_warning() {
+	if !LOW_FRAMES_PENDING.swap(false, Ordering::Relaxed) {
+		return;
+	}
+
+	let free = frames_free();
+	let message = format!(
+		"Low on physical memory: {} of {} frames free\n",
+		free,
+		frames_total()
+	);
+	serial_println!("[Memory] {}", message.trim_end());
+	SYSLOG_SINK.log(&message, LogLevel::Warn);
+}
+
 /// Translates the given virtual address to the mapped physical address, or
 /// `None` if the address is not mapped.
 /// # Safety
@@ -226,10 +340,19 @@ pub fn dma_alloc(size: usize) -> Result<(VirtAddr, PhysAddr), NullexError> {
 
 	let mut frames = Vec::new();
 	for _ in 0..page_count {
-		if let Some(frame) = frame_slot.allocate_frame() {
-			frames.push(frame);
+		// reused frames come back in any order, so only a single page can
+		// come from the free list
+		let frame = if page_count == 1 {
+			frame_slot.allocate_frame()
 		} else {
-			return Err(NullexError::FrameAllocationFailed);
+			frame_slot.allocate_fresh_frame()
+		};
+		match frame {
+			Some(frame) => frames.push(frame),
+			None => {
+				release_frames(frame_slot, &frames);
+				return Err(NullexError::FrameAllocationFailed);
+			}
 		}
 	}
 
@@ -239,6 +362,7 @@ pub fn dma_alloc(size: usize) -> Result<(VirtAddr, PhysAddr), NullexError> {
 				"[DMA] Allocation failed: Frames not contiguous at index {}",
 				i
 			);
+			release_frames(frame_slot, &frames);
 			return Err(NullexError::DmaAllocFailed);
 		}
 	}
@@ -266,6 +390,234 @@ pub fn dma_alloc(size: usize) -> Result<(VirtAddr, PhysAddr), NullexError> {
 	Ok((virt_addr, first_phys))
 }
 
+/// Gives frames that were never mapped back to the frame allocator.
+fn release_frames(frame_allocator: &mut BootInfoFrameAllocator, frames: &[PhysFrame]) {
+	for frame in frames {
+		unsafe { frame_allocator.deallocate_frame(*frame) };
+	}
+}
+
+/// Unmaps a block returned by `dma_alloc` and gives its frames back to the
+/// frame allocator.
+///
+/// # Safety
+/// `virt`, `phys` and `size` must be exactly what `dma_alloc` was called with
+/// and returned, and the device must be done with the block.
+pub unsafe fn dma_free(virt: VirtAddr, phys: PhysAddr, size: usize) -> Result<(), NullexError> {
+	let mut mapper_binding = ALLOCATOR_INFO.mapper.lock();
+	let mapper_slot = mapper_binding.as_mut().ok_or(NullexError::MapperNotInitialized)?;
+	let mut frame_binding = ALLOCATOR_INFO.frame_allocator.lock();
+	let frame_slot = frame_binding.as_mut().ok_or(NullexError::FrameAllocatorNotInitialized)?;
+
+	unsafe { unmap_dma(mapper_slot, frame_slot, virt, phys, size) }
+}
+
+/// Like `dma_free`, but returns `Ok(false)` without freeing anything if the
+/// mapper or frame allocator is locked. Safe to call from interrupt context.
+///
+/// # Safety
+/// Same as `dma_free`.
+pub unsafe fn try_dma_free(
+	virt: VirtAddr,
+	phys: PhysAddr,
+	size: usize
+) -> Result<bool, NullexError> {
+	let (Some(mut mapper_binding), Some(mut frame_binding)) =
+		(ALLOCATOR_INFO.mapper.try_lock(), ALLOCATOR_INFO.frame_allocator.try_lock())
+	else {
+		return Ok(false);
+	};
+	let mapper_slot = mapper_binding.as_mut().ok_or(NullexError::MapperNotInitialized)?;
+	let frame_slot = frame_binding.as_mut().ok_or(NullexError::FrameAllocatorNotInitialized)?;
+
+	unsafe { unmap_dma(mapper_slot, frame_slot, virt, phys, size) }.map(|_| true)
+}
+
+/// Checks that the pages of a DMA block map the frames `dma_alloc` handed out
+/// for it, then unmaps them and frees the frames.
+unsafe fn unmap_dma(
+	mapper: &mut OffsetPageTable<'static>,
+	frame_allocator: &mut BootInfoFrameAllocator,
+	virt: VirtAddr,
+	phys: PhysAddr,
+	size: usize
+) -> Result<(), NullexError> {
+	let page_count = size.div_ceil(4096) as u64;
+	let page_at = |i: u64| Page::<Size4KiB>::containing_address(virt + i * 4096);
+
+	// dma_alloc maps physically contiguous frames to contiguous pages, so
+	// anything else wasn't allocated by it and must not be freed
+	for i in 0..page_count {
+		let frame = mapper.translate_page(page_at(i)).map_err(|_| NullexError::PageNotMapped)?;
+		if frame.start_address() != phys + i * 4096 {
+			return Err(NullexError::DmaFreeMismatch);
+		}
+	}
+
+	for i in 0..page_count {
+		let (frame, flush) = mapper.unmap(page_at(i))?;
+		flush.flush();
+		unsafe { frame_allocator.deallocate_frame(frame) };
+	}
+	Ok(())
+}
+
+/// A kernel stack with an unmapped guard page right below it, so running off
+/// the bottom page faults instead of overwriting whatever is mapped there.
+pub struct KernelStack {
+	slot: usize,
+	pages: usize
+}
+
+impl KernelStack {
+	fn slot_base(&self) -> VirtAddr {
+		VirtAddr::new(KERNEL_STACK_REGION + (self.slot * KERNEL_STACK_SLOT_PAGES * 4096) as u64)
+	}
+
+	/// The guard page below the stack.
+	pub fn guard_page(&self) -> Page<Size4KiB> {
+		Page::containing_address(self.slot_base())
+	}
+
+	/// The lowest usable address of the stack.
+	pub fn bottom(&self) -> VirtAddr {
+		self.slot_base() + 4096u64
+	}
+
+	/// The address just above the stack, which is loaded into RSP.
+	pub fn top(&self) -> VirtAddr {
+		self.bottom() + (self.pages * 4096) as u64
+	}
+}
+
+/// Maps a kernel stack of `pages` pages with fresh frames, leaving the page
+/// below it unmapped as a guard.
+pub fn allocate_kernel_stack(pages: usize) -> Result<KernelStack, NullexError> {
+	if pages == 0 || pages >= KERNEL_STACK_SLOT_PAGES {
+		return Err(NullexError::InvalidArgument);
+	}
+
+	let slot = {
+		let mut used = KERNEL_STACK_SLOTS_USED.lock();
+		let slot = used.iter().position(|used| !used).ok_or(NullexError::OutOfMemory)?;
+		used[slot] = true;
+		slot
+	};
+	let stack = KernelStack { slot, pages };
+
+	let mut mapper_binding = ALLOCATOR_INFO.mapper.lock();
+	let mapper_slot = mapper_binding.as_mut().ok_or(NullexError::MapperNotInitialized)?;
+	let mut frame_binding = ALLOCATOR_INFO.frame_allocator.lock();
+	let frame_slot = frame_binding.as_mut().ok_or(NullexError::FrameAllocatorNotInitialized)?;
+
+	let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+	for i in 0..pages as u64 {
+		let page = Page::containing_address(stack.bottom() + i * 4096);
+		let mapped = frame_slot
+			.allocate_frame()
+			.ok_or(NullexError::FrameAllocationFailed)
+			.and_then(|frame| unsafe {
+				mapper_slot.map_to(page, frame, flags, *frame_slot).map_err(NullexError::from)
+			});
+
+		match mapped {
+			Ok(flush) => flush.flush(),
+			Err(e) => {
+				// undo the pages mapped so far
+				let partial = KernelStack { slot, pages: i as usize };
+				unsafe { unmap_kernel_stack(mapper_slot, frame_slot, &partial) };
+				KERNEL_STACK_SLOTS_USED.lock()[slot] = false;
+				return Err(e);
+			}
+		}
+	}
+
+	Ok(stack)
+}
+
+/// Unmaps a stack from `allocate_kernel_stack` and frees its frames.
+///
+/// # Safety
+/// Nothing may run on the stack or hold references into it.
+pub unsafe fn deallocate_kernel_stack(stack: KernelStack) -> Result<(), NullexError> {
+	{
+		let mut mapper_binding = ALLOCATOR_INFO.mapper.lock();
+		let mapper_slot = mapper_binding.as_mut().ok_or(NullexError::MapperNotInitialized)?;
+		let mut frame_binding = ALLOCATOR_INFO.frame_allocator.lock();
+		let frame_slot =
+			frame_binding.as_mut().ok_or(NullexError::FrameAllocatorNotInitialized)?;
+		unsafe { unmap_kernel_stack(mapper_slot, frame_slot, &stack) };
+	}
+	KERNEL_STACK_SLOTS_USED.lock()[stack.slot] = false;
+	Ok(())
+}
+
+unsafe fn unmap_kernel_stack(
+	mapper: &mut OffsetPageTable<'static>,
+	frame_allocator: &mut BootInfoFrameAllocator,
+	stack: &KernelStack
+) {
+	for i in 0..stack.pages as u64 {
+		let page = Page::<Size4KiB>::containing_address(stack.bottom() + i * 4096);
+		if let Ok((frame, flush)) = mapper.unmap(page) {
+			flush.flush();
+			unsafe { frame_allocator.deallocate_frame(frame) };
+		}
+	}
+}
+
+/// Returns whether `addr` is in the guard page of a kernel stack, meaning
+/// that stack overflowed.
+pub fn is_kernel_stack_guard(addr: u64) -> bool {
+	let region_size = (KERNEL_STACK_SLOTS * KERNEL_STACK_SLOT_PAGES * 4096) as u64;
+	let Some(offset) = addr.checked_sub(KERNEL_STACK_REGION).filter(|o| *o < region_size) else {
+		return false;
+	};
+	let slot_size = (KERNEL_STACK_SLOT_PAGES * 4096) as u64;
+	offset % slot_size < 4096
+}
+
+/// Maps `size` bytes of device memory at `phys` (e.g. a PCI BAR) into the
+/// kernel's address space and returns the virtual address of `phys`.
+///
+/// Mappings are uncached unless `prefetchable` is set, in which case they are
+/// write-through so reads may be cached but writes always reach the device.
+pub fn map_mmio(phys: PhysAddr, size: usize, prefetchable: bool) -> Result<VirtAddr, NullexError> {
+	let mut mapper_binding = ALLOCATOR_INFO.mapper.lock();
+	let mapper_slot = mapper_binding.as_mut().ok_or(NullexError::MapperNotInitialized)?;
+	let mut frame_binding = ALLOCATOR_INFO.frame_allocator.lock();
+	let frame_slot = frame_binding.as_mut().ok_or(NullexError::FrameAllocatorNotInitialized)?;
+
+	let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+	let page_offset = phys.as_u64() - first_frame.start_address().as_u64();
+	let page_count = (page_offset as usize + size).div_ceil(4096);
+
+	let virt_base = VirtAddr::new(unsafe { NEXT_MMIO_VIRT });
+	unsafe {
+		NEXT_MMIO_VIRT += (page_count as u64) * 4096;
+	}
+
+	let caching = if prefetchable {
+		PageTableFlags::WRITE_THROUGH
+	} else {
+		PageTableFlags::NO_CACHE
+	};
+	let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | caching;
+
+	for i in 0..page_count as u64 {
+		let frame = PhysFrame::containing_address(first_frame.start_address() + i * 4096);
+		let page = Page::containing_address(virt_base + i * 4096);
+
+		unsafe {
+			mapper_slot
+				.map_to(page, frame, flags, *frame_slot)?
+				.flush();
+		}
+	}
+
+	Ok(virt_base + page_offset)
+}
+
 /// Maps a range of memory within a `Process`'s `AddressSpace`.
 pub fn map_range(addr_space: &mut AddressSpace, pages: PageRange, flags: PageTableFlags) -> Result<(), NullexError> {
 	let mut frame_binding = ALLOCATOR_INFO.frame_allocator.lock();
@@ -285,3 +637,82 @@ pub fn map_range(addr_space: &mut AddressSpace, pages: PageRange, flags: PageTab
 	Ok(())
 }
 
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{memory::*, utils::ktest::TestError};
+
+	pub fn test_frame_counters() -> Result<(), TestError> {
+		assert!(frames_total() > 0);
+		assert!(frames_used() <= frames_total());
+		assert_eq!(frames_free(), frames_total() - frames_used());
+
+		let before = frames_used();
+		let frame = ALLOCATOR_INFO
+			.frame_allocator
+			.lock()
+			.as_mut()
+			.and_then(|fa| fa.allocate_frame());
+		assert!(frame.is_some());
+		assert_eq!(frames_used(), before + 1);
+		Ok(())
+	}
+	crate::create_test!(test_frame_counters);
+
+	pub fn test_freed_frames_are_reused() -> Result<(), TestError> {
+		let mut binding = ALLOCATOR_INFO.frame_allocator.lock();
+		let frame_allocator = binding.as_mut().ok_or(TestError::Error)?;
+		let used = frames_used();
+
+		let frame = frame_allocator.allocate_frame().ok_or(TestError::Error)?;
+		unsafe { frame_allocator.deallocate_frame(frame) };
+		assert_eq!(frames_used(), used);
+
+		// the freed frame is the next one handed out
+		let reused = frame_allocator.allocate_frame().ok_or(TestError::Error)?;
+		unsafe { frame_allocator.deallocate_frame(reused) };
+		assert_eq!(reused, frame);
+		Ok(())
+	}
+	crate::create_test!(test_freed_frames_are_reused);
+
+	pub fn test_dma_free_returns_frames() -> Result<(), TestError> {
+		let (virt, phys) = dma_alloc(4096).map_err(|_| TestError::Error)?;
+		// mapping may have taken frames for page tables too, which stay
+		let used = frames_used();
+		// freeing with the wrong physical address is refused
+		assert!(unsafe { dma_free(virt, phys + 4096u64, 4096) }.is_err());
+		unsafe { dma_free(virt, phys, 4096) }.map_err(|_| TestError::Error)?;
+		assert_eq!(frames_used(), used - 1);
+		assert!(unsafe { virt_to_phys(virt) }.is_none());
+
+		// the freed frame is the next one handed out
+		let (virt, reused) = dma_alloc(4096).map_err(|_| TestError::Error)?;
+		assert_eq!(reused, phys);
+		unsafe { dma_free(virt, reused, 4096) }.map_err(|_| TestError::Error)?;
+		Ok(())
+	}
+	crate::create_test!(test_dma_free_returns_frames);
+
+	pub fn test_kernel_stack_guard_page() -> Result<(), TestError> {
+		let stack = allocate_kernel_stack(2).map_err(|_| TestError::Error)?;
+		assert_eq!(stack.top() - stack.bottom(), 2 * 4096);
+
+		// both ends are writable, the page below isn't mapped
+		unsafe {
+			stack.bottom().as_mut_ptr::<u64>().write_volatile(1);
+			(stack.top() - 8u64).as_mut_ptr::<u64>().write_volatile(2);
+			assert!(virt_to_phys(stack.guard_page().start_address()).is_none());
+		}
+		assert!(is_kernel_stack_guard(stack.guard_page().start_address().as_u64() + 8));
+		assert!(!is_kernel_stack_guard(stack.bottom().as_u64()));
+		assert!(!is_kernel_stack_guard(0x1000));
+
+		let bottom = stack.bottom();
+		unsafe { deallocate_kernel_stack(stack) }.map_err(|_| TestError::Error)?;
+		assert!(unsafe { virt_to_phys(bottom) }.is_none());
+		assert!(allocate_kernel_stack(KERNEL_STACK_SLOT_PAGES).is_err());
+		Ok(())
+	}
+	crate::create_test!(test_kernel_stack_guard_page);
+}
@@ -65,29 +65,23 @@ pub fn process_arp(pkt: *const u8, len: usize, _src_mac: [u8; 6]) {
 
 		match operation {
 			ARP_OP_REQUEST => {
-				{
-					let mut cache = ARP_CACHE.lock();
-					cache.retain(|(ip, _)| ip != &sender_ip);
-					cache.push((sender_ip, sender_mac));
-					serial_println!(
-						"[ARP] Cached sender: {}.{}.{}.{}",
-						sender_ip[0],
-						sender_ip[1],
-						sender_ip[2],
-						sender_ip[3]
-					);
-				}
+				insert(sender_ip, sender_mac);
+				serial_println!(
+					"[ARP] Cached sender: {}.{}.{}.{}",
+					sender_ip[0],
+					sender_ip[1],
+					sender_ip[2],
+					sender_ip[3]
+				);
 
 				// Check if request is for us
-				if target_ip == super::OUR_IP {
+				if target_ip == super::our_ip() {
 					serial_println!("[ARP] Request for our IP, sending reply");
 					send_arp_reply(&sender_mac, &sender_ip);
 				}
 			}
 			ARP_OP_REPLY => {
-				let mut cache = ARP_CACHE.lock();
-				cache.retain(|(ip, _)| ip != &sender_ip);
-				cache.push((sender_ip, sender_mac));
+				insert(sender_ip, sender_mac);
 				serial_println!(
 					"[ARP] Cached reply from {}.{}.{}.{}",
 					sender_ip[0],
@@ -126,7 +120,7 @@ fn send_arp_reply(target_mac: &[u8; 6], target_ip: &[u8; 4]) {
 	packet[19] = 4; // Proto len
 	packet[20..22].copy_from_slice(&ARP_OP_REPLY.to_be_bytes());
 	packet[22..28].copy_from_slice(&our_mac);
-	packet[28..32].copy_from_slice(&super::OUR_IP);
+	packet[28..32].copy_from_slice(&super::our_ip());
 	packet[32..38].copy_from_slice(target_mac);
 	packet[38..42].copy_from_slice(target_ip);
 
@@ -155,7 +149,7 @@ pub fn send_arp_request(target_ip: [u8; 4]) -> Result<(), NullexError> {
 	packet[19] = 4;
 	packet[20..22].copy_from_slice(&ARP_OP_REQUEST.to_be_bytes());
 	packet[22..28].copy_from_slice(&our_mac);
-	packet[28..32].copy_from_slice(&super::OUR_IP);
+	packet[28..32].copy_from_slice(&super::our_ip());
 	packet[32..38].copy_from_slice(&[0; 6]);
 	packet[38..42].copy_from_slice(&target_ip);
 
@@ -249,6 +243,27 @@ pub fn wait_for_arp(ip: [u8; 4], timeout_ms: u32) -> Result<[u8; 6], NullexError
 	Err(NullexError::ArpFailed)
 }
 
+/// Caches `ip` -> `mac`, replacing any previous entry for `ip`.
+///
+/// The cache is kept in update order, so when it is full the least recently
+/// updated entry is evicted.
+pub fn insert(ip: [u8; 4], mac: [u8; 6]) {
+	let max = super::limits::get().max_arp_entries;
+	let mut cache = ARP_CACHE.lock();
+	cache.retain(|(cached_ip, _)| cached_ip != &ip);
+	while !cache.is_empty() && cache.len() >= max {
+		let (old_ip, _) = cache.remove(0);
+		serial_println!(
+			"[ARP] Cache full, evicting {}.{}.{}.{}",
+			old_ip[0],
+			old_ip[1],
+			old_ip[2],
+			old_ip[3]
+		);
+	}
+	cache.push((ip, mac));
+}
+
 /// Gets the cached IP address from `ARP_CACHE` if it has been cached.
 pub fn get_cached(ip: [u8; 4]) -> Option<[u8; 6]> {
 	let cache = ARP_CACHE.lock();
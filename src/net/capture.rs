@@ -0,0 +1,268 @@
+//!
+//! capture.rs
+//!
+//! Packet capture ring (tcpdump-lite) for the kernel.
+//!
+
+use alloc::{string::String, vec::Vec};
+use core::{
+	fmt::Write,
+	sync::atomic::{AtomicBool, Ordering}
+};
+
+use x86_64::instructions::interrupts;
+
+use crate::{
+	apic::APIC_TICK_COUNT,
+	net::{
+		ethernet::{ETHERTYPE_ARP, ETHERTYPE_IPV4},
+		ipv4::{IP_PROTO_ICMP, IP_PROTO_TCP, IP_PROTO_UDP}
+	},
+	println,
+	utils::mutex::SpinMutex
+};
+
+/// Maximum number of packets kept in the capture ring.
+pub const CAPTURE_RING_SIZE: usize = 64;
+/// Number of bytes captured from the start of every packet.
+pub const CAPTURE_SNAPLEN: usize = 96;
+
+/// Whether capturing is enabled. Checked before anything else so the RX/TX
+/// paths only pay for an atomic load while capture is off.
+static CAPTURE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The captured packets. Every slot is allocated up front, since packets are
+/// recorded from the RX interrupt path, which must not allocate.
+static CAPTURE_RING: SpinMutex<CaptureRing> = SpinMutex::new(CaptureRing::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The direction a captured packet was travelling in.
+pub enum Direction {
+	/// Received from the device.
+	Rx,
+	/// Sent to the device.
+	Tx
+}
+
+#[derive(Clone)]
+/// A single packet in the capture ring.
+pub struct CapturedPacket {
+	/// Direction of the packet.
+	pub direction: Direction,
+	/// APIC timer tick count at the time of capture.
+	pub timestamp: u64,
+	/// Full length of the packet on the wire.
+	pub len: usize,
+	/// The first `CAPTURE_SNAPLEN` bytes of the packet, of which the first
+	/// `captured` are valid.
+	data: [u8; CAPTURE_SNAPLEN],
+	captured: usize
+}
+
+impl CapturedPacket {
+	const EMPTY: CapturedPacket = CapturedPacket {
+		direction: Direction::Rx,
+		timestamp: 0,
+		len: 0,
+		data: [0; CAPTURE_SNAPLEN],
+		captured: 0
+	};
+
+	/// Returns the captured bytes of the packet.
+	pub fn data(&self) -> &[u8] {
+		&self.data[..self.captured]
+	}
+}
+
+/// Fixed-size ring of captured packets, overwriting the oldest once full.
+struct CaptureRing {
+	slots: [CapturedPacket; CAPTURE_RING_SIZE],
+	/// Slot the next packet is written to.
+	next: usize,
+	/// Number of slots holding a packet.
+	len: usize
+}
+
+impl CaptureRing {
+	const fn new() -> CaptureRing {
+		CaptureRing {
+			slots: [CapturedPacket::EMPTY; CAPTURE_RING_SIZE],
+			next: 0,
+			len: 0
+		}
+	}
+
+	/// Copies `pkt` into the next slot, without allocating.
+	fn push(&mut self, direction: Direction, timestamp: u64, pkt: &[u8]) {
+		let slot = &mut self.slots[self.next];
+		let captured = pkt.len().min(CAPTURE_SNAPLEN);
+		slot.direction = direction;
+		slot.timestamp = timestamp;
+		slot.len = pkt.len();
+		slot.data[..captured].copy_from_slice(&pkt[..captured]);
+		slot.captured = captured;
+
+		self.next = (self.next + 1) % CAPTURE_RING_SIZE;
+		self.len = (self.len + 1).min(CAPTURE_RING_SIZE);
+	}
+
+	/// Returns the captured packets, oldest first.
+	fn iter(&self) -> impl Iterator<Item = &CapturedPacket> {
+		let oldest = (self.next + CAPTURE_RING_SIZE - self.len) % CAPTURE_RING_SIZE;
+		(0..self.len).map(move |i| &self.slots[(oldest + i) % CAPTURE_RING_SIZE])
+	}
+
+	fn clear(&mut self) {
+		self.next = 0;
+		self.len = 0;
+	}
+}
+
+/// Starts capturing packets.
+pub fn start() {
+	CAPTURE_ENABLED.store(true, Ordering::Release);
+}
+
+/// Stops capturing packets. Already captured packets are kept.
+pub fn stop() {
+	CAPTURE_ENABLED.store(false, Ordering::Release);
+}
+
+/// Returns whether capture is currently enabled.
+pub fn is_enabled() -> bool {
+	CAPTURE_ENABLED.load(Ordering::Acquire)
+}
+
+/// Drops every captured packet.
+pub fn clear() {
+	interrupts::without_interrupts(|| CAPTURE_RING.lock().clear());
+}
+
+/// Records a packet into the capture ring if capture is enabled.
+///
+/// Called from the RX interrupt path as well, so the ring is only ever
+/// `try_lock`ed here; a packet is skipped rather than deadlocking if the ring is
+/// being read.
+#[inline]
+pub fn record(direction: Direction, pkt: &[u8]) {
+	if !is_enabled() {
+		return;
+	}
+
+	let Some(mut ring) = CAPTURE_RING.try_lock() else {
+		return;
+	};
+
+	ring.push(direction, APIC_TICK_COUNT.load(Ordering::Relaxed), pkt);
+}
+
+/// Returns a copy of the captured packets, oldest first.
+pub fn snapshot() -> Vec<CapturedPacket> {
+	interrupts::without_interrupts(|| CAPTURE_RING.lock().iter().cloned().collect())
+}
+
+/// Prints every captured packet as a decoded header summary followed by a
+/// hex dump of the captured bytes.
+pub fn dump() {
+	let packets = snapshot();
+	if packets.is_empty() {
+		println!("capture: no packets captured");
+		return;
+	}
+
+	for pkt in packets.iter() {
+		let dir = match pkt.direction {
+			Direction::Rx => "RX",
+			Direction::Tx => "TX"
+		};
+		println!(
+			"[{}] {} {} bytes: {}",
+			pkt.timestamp,
+			dir,
+			pkt.len,
+			decode_summary(pkt.data())
+		);
+
+		for (i, chunk) in pkt.data().chunks(16).enumerate() {
+			let mut line = String::new();
+			let _ = write!(line, "  {:04x}:", i * 16);
+			for byte in chunk {
+				let _ = write!(line, " {:02x}", byte);
+			}
+			println!("{}", line);
+		}
+	}
+}
+
+/// Decodes the Ethernet, ARP and IPv4 headers of a captured packet into a
+/// single line summary.
+fn decode_summary(data: &[u8]) -> String {
+	let mut out = String::new();
+	if data.len() < 14 {
+		out.push_str("truncated ethernet header");
+		return out;
+	}
+
+	let ethertype = u16::from_be_bytes([data[12], data[13]]);
+	let _ = write!(out, "{} > {}", fmt_mac(&data[6..12]), fmt_mac(&data[0..6]));
+
+	match ethertype {
+		ETHERTYPE_ARP if data.len() >= 42 => {
+			let op = u16::from_be_bytes([data[20], data[21]]);
+			let _ = write!(
+				out,
+				" ARP op={} {} -> {}",
+				op,
+				fmt_ip(&data[28..32]),
+				fmt_ip(&data[38..42])
+			);
+		}
+		ETHERTYPE_IPV4 if data.len() >= 34 => {
+			let ihl = (data[14] & 0x0F) as usize * 4;
+			let proto = data[23];
+			let _ = write!(out, " IPv4 {} -> {}", fmt_ip(&data[26..30]), fmt_ip(&data[30..34]));
+
+			let l4 = 14 + ihl;
+			if ihl < 20 || l4 > data.len() {
+				let _ = write!(out, " bad header length {}", ihl);
+				return out;
+			}
+			match proto {
+				IP_PROTO_ICMP if data.len() > l4 + 1 => {
+					let _ = write!(out, " ICMP type={} code={}", data[l4], data[l4 + 1]);
+				}
+				IP_PROTO_TCP | IP_PROTO_UDP if data.len() >= l4 + 4 => {
+					let name = if proto == IP_PROTO_TCP { "TCP" } else { "UDP" };
+					let src = u16::from_be_bytes([data[l4], data[l4 + 1]]);
+					let dst = u16::from_be_bytes([data[l4 + 2], data[l4 + 3]]);
+					let _ = write!(out, " {} {} -> {}", name, src, dst);
+				}
+				_ => {
+					let _ = write!(out, " proto={}", proto);
+				}
+			}
+		}
+		_ => {
+			let _ = write!(out, " ethertype=0x{:04X}", ethertype);
+		}
+	}
+
+	out
+}
+
+fn fmt_mac(mac: &[u8]) -> String {
+	let mut s = String::new();
+	for (i, byte) in mac.iter().enumerate() {
+		if i != 0 {
+			s.push(':');
+		}
+		let _ = write!(s, "{:02X}", byte);
+	}
+	s
+}
+
+fn fmt_ip(ip: &[u8]) -> String {
+	let mut s = String::new();
+	let _ = write!(s, "{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3]);
+	s
+}
@@ -0,0 +1,145 @@
+//!
+//! checksum.rs
+//!
+//! The Internet checksum (RFC 1071) and the pseudo-headers TCP, UDP and
+//! ICMPv6 checksums are computed over.
+//!
+
+/// Returns the folded one's complement sum of `data`, read as big endian
+/// 16-bit words. An odd trailing byte is padded with a zero byte.
+///
+/// This is the sum before the final inversion, so partial sums over
+/// even-length pieces can be combined with `combine`.
+pub fn ones_complement_sum(data: &[u8]) -> u16 {
+	let mut sum: u32 = 0;
+
+	let mut chunks = data.chunks_exact(2);
+	for chunk in &mut chunks {
+		sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+	}
+	if let [last] = chunks.remainder() {
+		sum += (*last as u32) << 8;
+	}
+
+	fold(sum)
+}
+
+/// Adds two one's complement sums.
+pub fn combine(a: u16, b: u16) -> u16 {
+	fold(a as u32 + b as u32)
+}
+
+fn fold(mut sum: u32) -> u16 {
+	while (sum >> 16) != 0 {
+		sum = (sum & 0xFFFF) + (sum >> 16);
+	}
+	sum as u16
+}
+
+/// Returns the Internet checksum of `data`. Computing it over data that
+/// already carries a valid checksum gives `0`.
+pub fn checksum(data: &[u8]) -> u16 {
+	!ones_complement_sum(data)
+}
+
+/// Returns the sum of the IPv4 pseudo-header for a TCP or UDP segment of
+/// `len` bytes.
+pub fn pseudo_header_sum(src: &[u8; 4], dst: &[u8; 4], protocol: u8, len: u16) -> u16 {
+	let mut pseudo = [0u8; 12];
+	pseudo[0..4].copy_from_slice(src);
+	pseudo[4..8].copy_from_slice(dst);
+	pseudo[9] = protocol;
+	pseudo[10..12].copy_from_slice(&len.to_be_bytes());
+	ones_complement_sum(&pseudo)
+}
+
+/// Returns the sum of the IPv6 pseudo-header for an upper layer packet of
+/// `len` bytes.
+pub fn pseudo_header_sum_v6(src: &[u8; 16], dst: &[u8; 16], next_header: u8, len: u32) -> u16 {
+	let mut pseudo = [0u8; 40];
+	pseudo[0..16].copy_from_slice(src);
+	pseudo[16..32].copy_from_slice(dst);
+	pseudo[32..36].copy_from_slice(&len.to_be_bytes());
+	pseudo[39] = next_header;
+	ones_complement_sum(&pseudo)
+}
+
+/// Returns the checksum of a TCP or UDP `segment` (header and payload, with
+/// its checksum field zeroed) sent over IPv4 from `src` to `dst`.
+pub fn transport_checksum(src: &[u8; 4], dst: &[u8; 4], protocol: u8, segment: &[u8]) -> u16 {
+	let pseudo = pseudo_header_sum(src, dst, protocol, segment.len() as u16);
+	!combine(pseudo, ones_complement_sum(segment))
+}
+
+/// Returns the value to put in a UDP header's checksum field. A computed
+/// checksum of zero is sent as `0xFFFF`, since zero means "no checksum".
+pub fn udp_checksum(src: &[u8; 4], dst: &[u8; 4], segment: &[u8]) -> u16 {
+	match transport_checksum(src, dst, super::ipv4::IP_PROTO_UDP, segment) {
+		0 => 0xFFFF,
+		checksum => checksum
+	}
+}
+
+/// Checks a received UDP `segment`. Segments sent without a checksum (field
+/// set to zero) are accepted.
+pub fn verify_udp(src: &[u8; 4], dst: &[u8; 4], segment: &[u8]) -> bool {
+	if segment.len() < 8 {
+		return false;
+	}
+	if segment[6..8] == [0, 0] {
+		return true;
+	}
+	transport_checksum(src, dst, super::ipv4::IP_PROTO_UDP, segment) == 0
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{net::checksum::*, utils::ktest::TestError};
+
+	pub fn test_checksum_known_vectors() -> Result<(), TestError> {
+		// RFC 1071 section 3 example
+		let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+		assert_eq!(ones_complement_sum(&data), 0xddf2);
+		assert_eq!(checksum(&data), 0x220d);
+
+		// an odd trailing byte is padded with zero
+		assert_eq!(ones_complement_sum(&[0x12, 0x34, 0x56]), 0x6834);
+		assert_eq!(ones_complement_sum(&[]), 0);
+
+		// IPv4 header from the Wikipedia example, checksum field included
+		let header = [
+			0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8,
+			0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7
+		];
+		assert_eq!(checksum(&header), 0);
+		let mut zeroed = header;
+		zeroed[10..12].copy_from_slice(&[0, 0]);
+		assert_eq!(checksum(&zeroed), 0xb861);
+		Ok(())
+	}
+	crate::create_test!(test_checksum_known_vectors);
+
+	pub fn test_udp_checksum_rules() -> Result<(), TestError> {
+		let src = [10, 0, 2, 15];
+		let dst = [10, 0, 2, 2];
+		// 1234 -> 53, length 13, odd-length payload
+		let mut segment = [
+			0x04, 0xd2, 0x00, 0x35, 0x00, 0x0d, 0x00, 0x00, // udp header
+			b'n', b'u', b'l', b'l', b'x'
+		];
+
+		let sum = udp_checksum(&src, &dst, &segment);
+		assert_eq!(sum, 0x8fda);
+		segment[6..8].copy_from_slice(&sum.to_be_bytes());
+		assert!(verify_udp(&src, &dst, &segment));
+
+		// corrupted payload fails, no checksum at all passes
+		segment[12] ^= 0xFF;
+		assert!(!verify_udp(&src, &dst, &segment));
+		segment[6..8].copy_from_slice(&[0, 0]);
+		assert!(verify_udp(&src, &dst, &segment));
+		assert!(!verify_udp(&src, &dst, &segment[..7]));
+		Ok(())
+	}
+	crate::create_test!(test_udp_checksum_rules);
+}
@@ -0,0 +1,275 @@
+//!
+//! dhcp.rs
+//!
+//! Minimal DHCP client. Runs a single DISCOVER/OFFER/REQUEST/ACK exchange
+//! and applies the lease to the interface; leases are never renewed.
+//!
+
+use alloc::{collections::VecDeque, vec::Vec};
+use core::sync::atomic::Ordering;
+
+use x86_64::instructions::interrupts;
+
+use crate::{
+	apic::APIC_TICK_COUNT,
+	drivers::virtio::net::rx_poll,
+	error::NullexError,
+	net::{self, NetConfig, udp},
+	serial_println,
+	tsc,
+	utils::mutex::SpinMutex
+};
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+/// Asks the server to broadcast its replies, since we can't receive unicast
+/// before we have an address.
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Length of the fixed BOOTP header, up to and including the magic cookie.
+const HEADER_LEN: usize = 240;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETER_LIST: u8 = 55;
+const OPT_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+/// How long to wait for each reply, in milliseconds.
+const REPLY_TIMEOUT_MS: u64 = 3000;
+/// How long to wait between checks for a reply, in milliseconds.
+const REPLY_POLL_INTERVAL_MS: u64 = 10;
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+/// Replies received on the client port, waiting to be parsed.
+static REPLIES: SpinMutex<VecDeque<Vec<u8>>> = SpinMutex::new(VecDeque::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The parts of a server reply the client cares about.
+pub struct DhcpReply {
+	/// DHCP message type (`DHCPOFFER`, `DHCPACK`, ...).
+	pub message_type: u8,
+	/// The address offered to us.
+	pub your_ip: [u8; 4],
+	/// The server identifier, echoed back in the REQUEST.
+	pub server_id: Option<[u8; 4]>,
+	/// Subnet mask.
+	pub subnet_mask: Option<[u8; 4]>,
+	/// The first router listed.
+	pub router: Option<[u8; 4]>,
+	/// The first DNS server listed.
+	pub dns: Option<[u8; 4]>
+}
+
+impl DhcpReply {
+	/// Parses a BOOTP reply, returning `None` if it isn't a DHCP reply to
+	/// transaction `xid`.
+	pub fn parse(data: &[u8], xid: u32) -> Option<DhcpReply> {
+		if data.len() < HEADER_LEN
+			|| data[0] != BOOTREPLY
+			|| data[4..8] != xid.to_be_bytes()
+			|| data[236..240] != MAGIC_COOKIE
+		{
+			return None;
+		}
+
+		let mut reply = DhcpReply {
+			message_type: 0,
+			your_ip: ip_at(data, 16)?,
+			server_id: None,
+			subnet_mask: None,
+			router: None,
+			dns: None
+		};
+
+		let mut i = HEADER_LEN;
+		while i < data.len() {
+			let code = data[i];
+			match code {
+				OPT_END => break,
+				OPT_PAD => {
+					i += 1;
+					continue;
+				}
+				_ => {}
+			}
+
+			let len = *data.get(i + 1)? as usize;
+			let value = data.get(i + 2..i + 2 + len)?;
+			match code {
+				OPT_MESSAGE_TYPE => reply.message_type = *value.first()?,
+				OPT_SERVER_ID => reply.server_id = ip_at(value, 0),
+				OPT_SUBNET_MASK => reply.subnet_mask = ip_at(value, 0),
+				OPT_ROUTER => reply.router = ip_at(value, 0),
+				OPT_DNS => reply.dns = ip_at(value, 0),
+				_ => {}
+			}
+			i += 2 + len;
+		}
+
+		(reply.message_type != 0).then_some(reply)
+	}
+}
+
+fn ip_at(data: &[u8], offset: usize) -> Option<[u8; 4]> {
+	data.get(offset..offset + 4)?.try_into().ok()
+}
+
+/// Builds a DISCOVER, or a REQUEST for `requested` (address, server id).
+fn build_message(xid: u32, mac: [u8; 6], requested: Option<([u8; 4], [u8; 4])>) -> Vec<u8> {
+	let mut msg = alloc::vec![0u8; HEADER_LEN];
+	msg[0] = BOOTREQUEST;
+	msg[1] = HTYPE_ETHERNET;
+	msg[2] = 6; // hardware address length
+	msg[4..8].copy_from_slice(&xid.to_be_bytes());
+	msg[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+	msg[28..34].copy_from_slice(&mac);
+	msg[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+	match requested {
+		None => msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, DHCPDISCOVER]),
+		Some((ip, server)) => {
+			msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, DHCPREQUEST]);
+			msg.extend_from_slice(&[OPT_REQUESTED_IP, 4]);
+			msg.extend_from_slice(&ip);
+			msg.extend_from_slice(&[OPT_SERVER_ID, 4]);
+			msg.extend_from_slice(&server);
+		}
+	}
+	msg.extend_from_slice(&[OPT_PARAMETER_LIST, 3, OPT_SUBNET_MASK, OPT_ROUTER, OPT_DNS]);
+	msg.push(OPT_END);
+	msg
+}
+
+/// UDP handler for the client port.
+fn dhcp_rx_handler(payload: &[u8]) {
+	// the handler may run from the RX path, so never spin on the queue here
+	if let Some(mut replies) = REPLIES.try_lock() {
+		replies.push_back(payload.to_vec());
+	}
+}
+
+fn ticks() -> u64 {
+	APIC_TICK_COUNT.load(Ordering::Relaxed)
+}
+
+/// Waits for a reply to `xid` of one of the `expected` message types.
+fn wait_for_reply(xid: u32, expected: &[u8]) -> Result<DhcpReply, NullexError> {
+	// timed with the TSC, which keeps counting if this runs with interrupts
+	// off, e.g. from a shell command
+	let deadline = tsc::now_ns() + REPLY_TIMEOUT_MS * NANOS_PER_MILLI;
+	loop {
+		rx_poll();
+
+		let received: Vec<Vec<u8>> =
+			interrupts::without_interrupts(|| REPLIES.lock().drain(..).collect());
+		let reply = received
+			.iter()
+			.filter_map(|data| DhcpReply::parse(data, xid))
+			.find(|reply| expected.contains(&reply.message_type));
+		if let Some(reply) = reply {
+			return Ok(reply);
+		}
+
+		let now = tsc::now_ns();
+		if now >= deadline {
+			return Err(NullexError::DhcpTimeout);
+		}
+		let next_poll = now + REPLY_POLL_INTERVAL_MS * NANOS_PER_MILLI;
+		while tsc::now_ns() < next_poll {
+			core::hint::spin_loop();
+		}
+	}
+}
+
+/// Runs a DHCP exchange and applies the lease with `net::set_config`.
+/// Settings the server leaves out keep their current values.
+pub fn configure() -> Result<NetConfig, NullexError> {
+	let mac = net::get_our_mac().ok_or(NullexError::MissingMacAddress)?;
+	let xid = (ticks() as u32) ^ u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]);
+
+	interrupts::without_interrupts(|| REPLIES.lock().clear());
+	udp::register_handler(DHCP_CLIENT_PORT, dhcp_rx_handler)?;
+
+	let result = exchange(xid, mac);
+
+	udp::unregister_handler(DHCP_CLIENT_PORT, dhcp_rx_handler);
+	interrupts::without_interrupts(|| REPLIES.lock().clear());
+
+	let ack = result?;
+	let mut config = net::config();
+	config.ip = ack.your_ip;
+	config.subnet_mask = ack.subnet_mask.unwrap_or(config.subnet_mask);
+	config.gateway = ack.router.unwrap_or(config.gateway);
+	config.dns = ack.dns.unwrap_or(config.dns);
+	net::set_config(config)?;
+	Ok(config)
+}
+
+fn exchange(xid: u32, mac: [u8; 6]) -> Result<DhcpReply, NullexError> {
+	serial_println!("[DHCP] Sending DISCOVER (xid={:#010x})", xid);
+	udp::send_udp_broadcast(DHCP_CLIENT_PORT, DHCP_SERVER_PORT, &build_message(xid, mac, None))?;
+	let offer = wait_for_reply(xid, &[DHCPOFFER])?;
+	let server = offer.server_id.ok_or(NullexError::Udp("dhcp offer without server id"))?;
+
+	serial_println!(
+		"[DHCP] Offered {}, requesting it",
+		net::format_ip(offer.your_ip)
+	);
+	let request = build_message(xid, mac, Some((offer.your_ip, server)));
+	udp::send_udp_broadcast(DHCP_CLIENT_PORT, DHCP_SERVER_PORT, &request)?;
+
+	let ack = wait_for_reply(xid, &[DHCPACK, DHCPNAK])?;
+	if ack.message_type == DHCPNAK {
+		return Err(NullexError::DhcpNak);
+	}
+	Ok(ack)
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{net::dhcp::*, utils::ktest::TestError};
+
+	pub fn test_dhcp_parse_reply() -> Result<(), TestError> {
+		let xid = 0x1234_5678;
+		let mut reply = build_message(xid, [0x52, 0x54, 0, 0x12, 0x34, 0x56], None);
+		reply.truncate(HEADER_LEN);
+		reply[0] = BOOTREPLY;
+		reply[16..20].copy_from_slice(&[10, 0, 2, 15]);
+		reply.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, DHCPOFFER, OPT_PAD]);
+		reply.extend_from_slice(&[OPT_SERVER_ID, 4, 10, 0, 2, 2]);
+		reply.extend_from_slice(&[OPT_SUBNET_MASK, 4, 255, 255, 255, 0]);
+		reply.extend_from_slice(&[OPT_ROUTER, 8, 10, 0, 2, 2, 10, 0, 2, 1]);
+		reply.push(OPT_END);
+
+		let parsed = DhcpReply::parse(&reply, xid).ok_or(TestError::Error)?;
+		assert_eq!(parsed.message_type, DHCPOFFER);
+		assert_eq!(parsed.your_ip, [10, 0, 2, 15]);
+		assert_eq!(parsed.server_id, Some([10, 0, 2, 2]));
+		assert_eq!(parsed.subnet_mask, Some([255, 255, 255, 0]));
+		assert_eq!(parsed.router, Some([10, 0, 2, 2]));
+		assert_eq!(parsed.dns, None);
+
+		// other transactions and truncated options are ignored
+		assert_eq!(DhcpReply::parse(&reply, xid + 1), None);
+		reply.truncate(reply.len() - 6);
+		assert_eq!(DhcpReply::parse(&reply, xid), None);
+		Ok(())
+	}
+	crate::create_test!(test_dhcp_parse_reply);
+}
@@ -8,11 +8,6 @@ use alloc::{collections::BTreeMap, string::String, vec::Vec};
 
 use crate::{error::NullexError, lazy_static, serial_println, utils::mutex::SpinMutex};
 
-// DNS server (QEMU Default)
-// quick note here. 10.0.2.3 is the usermode DNS address
-// however we are not in usermode currently, so we send all requests to
-// to the gateway (10.0.2.2)
-const DNS_SERVER: [u8; 4] = [8, 8, 8, 8];
 const DNS_TIMEOUT_MS: u32 = 5000;
 
 lazy_static! {
@@ -30,7 +25,10 @@ lazy_static! {
 
 /// Initialises DNS Query handling.
 pub fn init() {
-	super::udp::register_handler(53, handle_dns_response);
+	if let Err(e) = super::udp::register_handler(53, handle_dns_response) {
+		serial_println!("[DNS] Failed to register handler: {}", e);
+		return;
+	}
 	serial_println!("[DNS] Initialized");
 }
 
@@ -140,13 +138,14 @@ fn send_dns_query(hostname: &str) -> Result<u16, NullexError> {
     // We must resolve the gateway MAC and inject a static ARP entry
     // that maps 10.0.2.3 -> gateway MAC so your routing layer can
     // build the correct Ethernet frame (IP dst = 10.0.2.3, MAC dst = gateway).
-    let gateway_mac = if let Some(mac) = super::arp::get_cached(super::GATEWAY_IP) {
+    let gateway = super::gateway_ip();
+    let gateway_mac = if let Some(mac) = super::arp::get_cached(gateway) {
         serial_println!("[DNS] Using cached gateway MAC");
         mac
     } else {
         serial_println!("[DNS] Resolving gateway MAC via ARP...");
-        super::arp::send_arp_request(super::GATEWAY_IP)?;
-        match super::arp::wait_for_arp(super::GATEWAY_IP, 5000) {
+        super::arp::send_arp_request(gateway)?;
+        match super::arp::wait_for_arp(gateway, 5000) {
             Ok(mac) => {
                 serial_println!("[DNS] Gateway MAC resolved");
                 mac
@@ -162,7 +161,7 @@ fn send_dns_query(hostname: &str) -> Result<u16, NullexError> {
         }
     };
 
-    match super::udp::send_udp(DNS_SERVER, 12345, 53, &query) {
+    match super::udp::send_udp(super::config().dns, 12345, 53, &query) {
         Ok(()) => {
             serial_println!("[DNS] Query sent for {} (id={})", hostname, transaction_id);
             Ok(transaction_id)
@@ -1,8 +1,10 @@
 //!
 //! ethernet.rs
-//! 
+//!
 //! Ethernet frame handling for the kernel.
-//! 
+//!
+
+use alloc::vec::Vec;
 
 use crate::serial_println;
 
@@ -10,31 +12,138 @@ use crate::serial_println;
 pub const ETHERTYPE_ARP: u16 = 0x0806;
 /// Ethernet Type IPv4 value
 pub const ETHERTYPE_IPV4: u16 = 0x0800;
+/// Ethernet Type IPv6 value
+pub const ETHERTYPE_IPV6: u16 = 0x86DD;
+/// Ethernet Type 802.1Q VLAN tag value
+pub const ETHERTYPE_VLAN: u16 = 0x8100;
+/// Ethernet Type 802.1ad (QinQ) service tag value
+pub const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+/// Length of an untagged Ethernet header.
+pub const ETHERNET_HEADER_LEN: usize = 14;
+/// Length of a single VLAN tag (TPID + TCI).
+const VLAN_TAG_LEN: usize = 4;
+/// Maximum number of stacked VLAN tags we accept (QinQ).
+const MAX_VLAN_TAGS: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A parsed Ethernet header, with any VLAN tags already looked through.
+pub struct EthernetHeader {
+	/// Destination MAC address.
+	pub dst_mac: [u8; 6],
+	/// Source MAC address.
+	pub src_mac: [u8; 6],
+	/// The ethertype of the payload (the innermost one for tagged frames).
+	pub ethertype: u16,
+	/// VLAN ID of the outer tag, if the frame was tagged.
+	pub vlan_id: Option<u16>,
+	/// VLAN ID of the inner (customer) tag of a double-tagged frame.
+	pub inner_vlan_id: Option<u16>,
+	/// Length of the header including tags, the offset of the payload.
+	pub header_len: usize
+}
+
+impl EthernetHeader {
+	/// Parses the Ethernet header at the start of `frame`, walking through up
+	/// to two 802.1Q/802.1ad tags.
+	///
+	/// Returns `None` if the frame is too short or has more tags than we
+	/// support.
+	pub fn parse(frame: &[u8]) -> Option<EthernetHeader> {
+		if frame.len() < ETHERNET_HEADER_LEN {
+			return None;
+		}
+
+		let mut dst_mac = [0u8; 6];
+		let mut src_mac = [0u8; 6];
+		dst_mac.copy_from_slice(&frame[0..6]);
+		src_mac.copy_from_slice(&frame[6..12]);
+
+		let mut tags = [None; MAX_VLAN_TAGS];
+		let mut offset = 12;
+		let mut ethertype = u16::from_be_bytes([frame[offset], frame[offset + 1]]);
+
+		while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+			let depth = tags.iter().filter(|t| t.is_some()).count();
+			if depth == MAX_VLAN_TAGS || frame.len() < offset + VLAN_TAG_LEN + 2 {
+				return None;
+			}
+
+			// the low 12 bits of the TCI are the VLAN ID
+			let tci = u16::from_be_bytes([frame[offset + 2], frame[offset + 3]]);
+			tags[depth] = Some(tci & 0x0FFF);
+
+			offset += VLAN_TAG_LEN;
+			ethertype = u16::from_be_bytes([frame[offset], frame[offset + 1]]);
+		}
+
+		Some(EthernetHeader {
+			dst_mac,
+			src_mac,
+			ethertype,
+			vlan_id: tags[0],
+			inner_vlan_id: tags[1],
+			header_len: offset + 2
+		})
+	}
+
+	/// Returns whether the frame carried at least one VLAN tag.
+	pub fn is_tagged(&self) -> bool {
+		self.vlan_id.is_some()
+	}
+}
+
+/// Copies a tagged frame into an untagged one, so the protocol handlers can
+/// keep assuming their header starts at `ETHERNET_HEADER_LEN`.
+fn strip_vlan_tags(frame: &[u8], header: &EthernetHeader) -> Vec<u8> {
+	let mut untagged = Vec::with_capacity(frame.len() - (header.header_len - ETHERNET_HEADER_LEN));
+	untagged.extend_from_slice(&header.dst_mac);
+	untagged.extend_from_slice(&header.src_mac);
+	untagged.extend_from_slice(&header.ethertype.to_be_bytes());
+	untagged.extend_from_slice(&frame[header.header_len..]);
+	untagged
+}
 
 /// Processes the incoming ethernet frame.
 pub fn process_ethernet_frame(ptr: *const u8, len: usize) {
-	if len < 14 {
+	if len < ETHERNET_HEADER_LEN {
 		serial_println!("[ETH] Too short");
 		return;
 	}
 
-	unsafe {
-		let ethertype = u16::from_be_bytes([*ptr.add(12), *ptr.add(13)]);
-		let src_mac = [
-			*ptr.add(6),
-			*ptr.add(7),
-			*ptr.add(8),
-			*ptr.add(9),
-			*ptr.add(10),
-			*ptr.add(11)
-		];
-
-		serial_println!("[ETH] type={:#06x}", ethertype);
-
-		match ethertype {
-			ETHERTYPE_ARP => super::arp::process_arp(ptr, len, src_mac),
-			ETHERTYPE_IPV4 => super::ipv4::process_ipv4(ptr, len),
-			_ => serial_println!("[ETH] Unknown type")
+	let frame = unsafe { core::slice::from_raw_parts(ptr, len) };
+	let header = match EthernetHeader::parse(frame) {
+		Some(header) => header,
+		None => {
+			serial_println!("[ETH] Dropping malformed or over-tagged frame");
+			return;
+		}
+	};
+
+	if header.is_tagged() {
+		serial_println!(
+			"[ETH] vlan={:?}, inner vlan={:?}, type={:#06x}",
+			header.vlan_id,
+			header.inner_vlan_id,
+			header.ethertype
+		);
+
+		let untagged = strip_vlan_tags(frame, &header);
+		dispatch(&header, untagged.as_ptr(), untagged.len());
+	} else {
+		serial_println!("[ETH] type={:#06x}", header.ethertype);
+		dispatch(&header, ptr, len);
+	}
+}
+
+/// Hands an untagged frame to the handler for its ethertype.
+fn dispatch(header: &EthernetHeader, ptr: *const u8, len: usize) {
+	match header.ethertype {
+		ETHERTYPE_ARP => super::arp::process_arp(ptr, len, header.src_mac),
+		ETHERTYPE_IPV4 => super::ipv4::process_ipv4(ptr, len),
+		ETHERTYPE_IPV6 => {
+			super::ipv6::process_ipv6(unsafe { core::slice::from_raw_parts(ptr, len) })
 		}
+		_ => serial_println!("[ETH] Unknown type")
 	}
 }
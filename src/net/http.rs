@@ -8,8 +8,26 @@ pub struct HttpResponse {
     pub body: Vec<u8>,
 }
 
+/// Where a GET request goes and what it asks for.
+struct HttpRequest<'a> {
+    dst_ip: [u8; 4],
+    dst_port: u16,
+    host: &'a str,
+    path: &'a str,
+    src_port: u16,
+    now: Instant,
+}
+
 pub fn http_get(iface: &mut Interface, device: &mut VirtioNet, sockets: &mut SocketSet<'_>, dst_ip: [u8; 4], dst_port: u16, host: &str, path: &str, src_port: u16, now: Instant) -> Result<HttpResponse, NullexError> {
-    let conn = TcpConnection::new(sockets);
+    let conn = TcpConnection::new(sockets)?;
+    let request = HttpRequest { dst_ip, dst_port, host, path, src_port, now };
+    let result = http_exchange(iface, device, sockets, &conn, request);
+    conn.release(sockets);
+    result
+}
+
+fn http_exchange(iface: &mut Interface, device: &mut VirtioNet, sockets: &mut SocketSet<'_>, conn: &TcpConnection, request: HttpRequest<'_>) -> Result<HttpResponse, NullexError> {
+    let HttpRequest { dst_ip, dst_port, host, path, src_port, now } = request;
     conn.connect(iface, sockets, dst_ip, dst_port, src_port)?;
     serial_println!("[HTTP] Connecting to {}:{}", host, dst_port);
 
@@ -17,6 +35,7 @@ pub fn http_get(iface: &mut Interface, device: &mut VirtioNet, sockets: &mut Soc
     let mut ticks = 0u64;
     loop {
         TcpConnection::poll(iface, device, sockets, timestamp);
+        conn.update_pending(sockets);
 
         let state = sockets.get::<Socket>(conn.handle).state();
         serial_println!("[HTTP] TCP state: {:?}", state);
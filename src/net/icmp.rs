@@ -4,58 +4,51 @@
 //! ICMP packet handling logic for the kernel.
 //! 
 
-use crate::{error::NullexError, serial_println, utils::net::calculate_checksum};
+use crate::{error::NullexError, net::checksum::checksum, serial_println};
 
 const ICMP_ECHO_REPLY: u8 = 0;
 const ICMP_ECHO_REQUEST: u8 = 8;
 
-/// Process incoming ICMP packets.
-pub fn process_icmp(pkt: *const u8, len: usize, ip_header_len: usize, src_ip: &[u8; 4]) {
-	let icmp_offset = 14 + ip_header_len;
-
-	if len < icmp_offset + 8 {
+/// Process incoming ICMP messages. `icmp` is the IPv4 payload.
+pub fn process_icmp(icmp: &[u8], src_ip: &[u8; 4]) {
+	if icmp.len() < 8 {
 		serial_println!("[ICMP] Packet too short");
 		return;
 	}
 
-	unsafe {
-		let icmp_start = pkt.add(icmp_offset);
-
-		let icmp_type = *icmp_start;
-		let id = u16::from_be_bytes([*icmp_start.add(4), *icmp_start.add(5)]);
-		let sequence = u16::from_be_bytes([*icmp_start.add(6), *icmp_start.add(7)]);
-
-		serial_println!("[ICMP] type={}, id={}, seq={}", icmp_type, id, sequence);
-
-		match icmp_type {
-			ICMP_ECHO_REQUEST => {
-				serial_println!("[ICMP] Echo request, sending reply");
-				send_icmp_reply(pkt, len, src_ip, id, sequence);
-			}
-			ICMP_ECHO_REPLY => {
-				serial_println!(
-					"[ICMP] Echo reply from {}.{}.{}.{}: seq={}",
-					src_ip[0],
-					src_ip[1],
-					src_ip[2],
-					src_ip[3],
-					sequence
-				);
-			}
-			_ => {
-				serial_println!("[ICMP] Unknown type: {}", icmp_type);
-			}
+	if checksum(icmp) != 0 {
+		serial_println!("[ICMP] Bad checksum, dropping");
+		return;
+	}
+
+	let icmp_type = icmp[0];
+	let id = u16::from_be_bytes([icmp[4], icmp[5]]);
+	let sequence = u16::from_be_bytes([icmp[6], icmp[7]]);
+
+	serial_println!("[ICMP] type={}, id={}, seq={}", icmp_type, id, sequence);
+
+	match icmp_type {
+		ICMP_ECHO_REQUEST => {
+			serial_println!("[ICMP] Echo request, sending reply");
+			send_icmp_reply(&icmp[8..], src_ip, id, sequence);
+		}
+		ICMP_ECHO_REPLY => {
+			serial_println!(
+				"[ICMP] Echo reply from {}.{}.{}.{}: seq={}",
+				src_ip[0],
+				src_ip[1],
+				src_ip[2],
+				src_ip[3],
+				sequence
+			);
+		}
+		_ => {
+			serial_println!("[ICMP] Unknown type: {}", icmp_type);
 		}
 	}
 }
 
-fn send_icmp_reply(
-	original_pkt: *const u8,
-	original_len: usize,
-	dst_ip: &[u8; 4],
-	id: u16,
-	sequence: u16
-) {
+fn send_icmp_reply(echo_payload: &[u8], dst_ip: &[u8; 4], id: u16, sequence: u16) {
 	let our_mac = match super::get_our_mac() {
 		Some(mac) => mac,
 		None => {
@@ -80,12 +73,7 @@ fn send_icmp_reply(
 		}
 	};
 
-	let ip_header_len = unsafe {
-		let version_ihl = *original_pkt.add(14);
-		((version_ihl & 0x0F) as usize) * 4
-	};
-	let icmp_offset = 14 + ip_header_len;
-	let payload_len = original_len - icmp_offset - 8;
+	let payload_len = echo_payload.len();
 
 	let total_len = 14 + 20 + 8 + payload_len;
 	let mut packet = alloc::vec![0u8; total_len];
@@ -104,10 +92,10 @@ fn send_icmp_reply(
 	packet[20..22].copy_from_slice(&0u16.to_be_bytes());
 	packet[22] = 64;
 	packet[23] = super::ipv4::IP_PROTO_ICMP;
-	packet[26..30].copy_from_slice(&super::OUR_IP);
+	packet[26..30].copy_from_slice(&super::our_ip());
 	packet[30..34].copy_from_slice(dst_ip);
 
-	let ip_checksum = calculate_checksum(&packet[14..34]);
+	let ip_checksum = checksum(&packet[14..34]);
 	packet[24..26].copy_from_slice(&ip_checksum.to_be_bytes());
 
 	// ICMP header
@@ -116,15 +104,9 @@ fn send_icmp_reply(
 	packet[38..40].copy_from_slice(&id.to_be_bytes());
 	packet[40..42].copy_from_slice(&sequence.to_be_bytes());
 
-	if payload_len > 0 {
-		unsafe {
-			let src_payload = original_pkt.add(icmp_offset + 8);
-			packet[42..42 + payload_len]
-				.copy_from_slice(core::slice::from_raw_parts(src_payload, payload_len));
-		}
-	}
+	packet[42..].copy_from_slice(echo_payload);
 
-	let icmp_checksum = calculate_checksum(&packet[34..]);
+	let icmp_checksum = checksum(&packet[34..]);
 	packet[36..38].copy_from_slice(&icmp_checksum.to_be_bytes());
 
 	if let Err(e) = super::send_packet(&packet) {
@@ -142,7 +124,7 @@ pub fn send_ping(dst_ip: [u8; 4], sequence: u16) -> Result<(), NullexError> {
 			let next_hop = if super::is_local_ip(dst_ip) {
 				dst_ip
 			} else {
-				super::GATEWAY_IP
+				super::gateway_ip()
 			};
 
 			serial_println!("[PING] Resolving next hop MAC");
@@ -172,10 +154,10 @@ pub fn send_ping(dst_ip: [u8; 4], sequence: u16) -> Result<(), NullexError> {
 	packet[20..22].copy_from_slice(&0u16.to_be_bytes());
 	packet[22] = 64;
 	packet[23] = super::ipv4::IP_PROTO_ICMP;
-	packet[26..30].copy_from_slice(&super::OUR_IP);
+	packet[26..30].copy_from_slice(&super::our_ip());
 	packet[30..34].copy_from_slice(&dst_ip); // Actual destination!
 
-	let ip_checksum = calculate_checksum(&packet[14..34]);
+	let ip_checksum = checksum(&packet[14..34]);
 	packet[24..26].copy_from_slice(&ip_checksum.to_be_bytes());
 
 	// ICMP
@@ -185,7 +167,7 @@ pub fn send_ping(dst_ip: [u8; 4], sequence: u16) -> Result<(), NullexError> {
 	packet[40..42].copy_from_slice(&sequence.to_be_bytes());
 	packet[42..].copy_from_slice(icmp_data);
 
-	let icmp_checksum = calculate_checksum(&packet[34..]);
+	let icmp_checksum = checksum(&packet[34..]);
 	packet[36..38].copy_from_slice(&icmp_checksum.to_be_bytes());
 
 	super::send_packet(&packet)?;
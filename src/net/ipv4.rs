@@ -1,10 +1,17 @@
 //!
 //! ipv4.rs
-//! 
+//!
 //! IPv4 packet handling logic for the kernel.
-//! 
+//!
 
-use crate::serial_println;
+use core::sync::atomic::Ordering;
+
+use crate::{
+	apic::APIC_TICK_COUNT,
+	error::NullexError,
+	net::checksum::checksum,
+	serial_println
+};
 
 /// ICMP IP Protocol Value
 pub const IP_PROTO_ICMP: u8 = 1;
@@ -13,70 +20,212 @@ pub const IP_PROTO_TCP: u8 = 6;
 /// UDP IP Protocol Value
 pub const IP_PROTO_UDP: u8 = 17;
 
+/// Size of an Ethernet header, the offset of the IPv4 header in a frame.
+const ETH_HEADER_LEN: usize = 14;
+/// Minimum (option-less) IPv4 header length.
+const IPV4_MIN_HEADER_LEN: usize = 20;
+/// "More fragments" bit of the flags/fragment offset field.
+const IPV4_FLAG_MF: u16 = 0x2000;
+/// Fragment offset bits of the flags/fragment offset field, in 8 byte units.
+const IPV4_FRAGMENT_OFFSET_MASK: u16 = 0x1FFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A validated IPv4 header.
+pub struct Ipv4Header {
+	/// Header length in bytes, including options.
+	pub header_len: usize,
+	/// Total length of the datagram (header + payload) in bytes.
+	pub total_len: usize,
+	/// Identification field, used for fragment reassembly.
+	pub identification: u16,
+	/// Flags and fragment offset field.
+	pub flags_fragment: u16,
+	/// Protocol of the payload.
+	pub protocol: u8,
+	/// Source address.
+	pub src: [u8; 4],
+	/// Destination address.
+	pub dst: [u8; 4]
+}
+
+impl Ipv4Header {
+	/// Parses and validates an IPv4 header at the start of `ip`.
+	///
+	/// Checks the version, that the IHL is at least 5 words and fits in both
+	/// the total length and the buffer, and that the header checksum is valid.
+	pub fn parse(ip: &[u8]) -> Result<Ipv4Header, NullexError> {
+		if ip.len() < IPV4_MIN_HEADER_LEN {
+			return Err(NullexError::BufferTooSmall);
+		}
+
+		let version = ip[0] >> 4;
+		if version != 4 {
+			return Err(NullexError::Unsupported);
+		}
+
+		let header_len = (ip[0] & 0x0F) as usize * 4;
+		let total_len = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+		if header_len < IPV4_MIN_HEADER_LEN || header_len > total_len || total_len > ip.len() {
+			return Err(NullexError::InvalidArgument);
+		}
+
+		if checksum(&ip[..header_len]) != 0 {
+			return Err(NullexError::ChecksumMismatch);
+		}
+
+		Ok(Ipv4Header {
+			header_len,
+			total_len,
+			identification: u16::from_be_bytes([ip[4], ip[5]]),
+			flags_fragment: u16::from_be_bytes([ip[6], ip[7]]),
+			protocol: ip[9],
+			src: [ip[12], ip[13], ip[14], ip[15]],
+			dst: [ip[16], ip[17], ip[18], ip[19]]
+		})
+	}
+
+	/// Returns whether the "more fragments" flag is set.
+	pub fn more_fragments(&self) -> bool {
+		self.flags_fragment & IPV4_FLAG_MF != 0
+	}
+
+	/// Returns the offset of this fragment's payload in bytes.
+	pub fn fragment_offset(&self) -> usize {
+		(self.flags_fragment & IPV4_FRAGMENT_OFFSET_MASK) as usize * 8
+	}
+
+	/// Returns whether this datagram is a fragment of a larger one.
+	pub fn is_fragment(&self) -> bool {
+		self.more_fragments() || self.fragment_offset() != 0
+	}
+
+	/// Returns the payload of the datagram described by this header. Any
+	/// Ethernet padding past `total_len` is excluded.
+	pub fn payload<'a>(&self, ip: &'a [u8]) -> &'a [u8] {
+		&ip[self.header_len..self.total_len]
+	}
+}
+
 /// Process incoming IPv4 packets.
 pub fn process_ipv4(pkt: *const u8, len: usize) {
-	if len < 34 {
+	if len < ETH_HEADER_LEN + IPV4_MIN_HEADER_LEN {
 		serial_println!("[IPv4] Packet too short: {} bytes", len);
 		return;
 	}
 
-	unsafe {
-		let ip_start = pkt.add(14);
-
-		let version_ihl = *ip_start;
-		let version = version_ihl >> 4;
-		let ihl = (version_ihl & 0x0F) as usize * 4;
+	let ip =
+		unsafe { core::slice::from_raw_parts(pkt.add(ETH_HEADER_LEN), len - ETH_HEADER_LEN) };
 
-		if version != 4 {
-			serial_println!("[IPv4] Not IPv4: version {}", version);
+	let header = match Ipv4Header::parse(ip) {
+		Ok(header) => header,
+		Err(e) => {
+			serial_println!("[IPv4] Dropping invalid header: {}", e);
 			return;
 		}
+	};
 
-		let protocol = *ip_start.add(9);
-		let src_ip = [
-			*ip_start.add(12),
-			*ip_start.add(13),
-			*ip_start.add(14),
-			*ip_start.add(15)
-		];
-		let dst_ip = [
-			*ip_start.add(16),
-			*ip_start.add(17),
-			*ip_start.add(18),
-			*ip_start.add(19)
-		];
-
-		serial_println!(
-			"[IPv4] src={}.{}.{}.{}, dst={}.{}.{}.{}, proto={}",
-			src_ip[0],
-			src_ip[1],
-			src_ip[2],
-			src_ip[3],
-			dst_ip[0],
-			dst_ip[1],
-			dst_ip[2],
-			dst_ip[3],
-			protocol
-		);
-
-		if dst_ip != super::OUR_IP {
-			serial_println!("[IPv4] Not for us, dropping");
-			return;
+	serial_println!(
+		"[IPv4] src={}.{}.{}.{}, dst={}.{}.{}.{}, proto={}, ihl={}",
+		header.src[0],
+		header.src[1],
+		header.src[2],
+		header.src[3],
+		header.dst[0],
+		header.dst[1],
+		header.dst[2],
+		header.dst[3],
+		header.protocol,
+		header.header_len
+	);
+
+	// DHCP replies are broadcast before we have an address of our own
+	let config = super::config();
+	if header.dst != config.ip && header.dst != [255; 4] && header.dst != config.broadcast() {
+		serial_println!("[IPv4] Not for us, dropping");
+		return;
+	}
+
+	if header.is_fragment() {
+		let now = APIC_TICK_COUNT.load(Ordering::Relaxed);
+		let datagram = super::reassembly::REASSEMBLER
+			.lock()
+			.push(&header, header.payload(ip), now);
+
+		if let Some(datagram) = datagram {
+			serial_println!(
+				"[IPv4] Reassembled datagram id={} ({} bytes)",
+				header.identification,
+				datagram.len()
+			);
+			dispatch(&header, &datagram);
 		}
+		return;
+	}
+
+	dispatch(&header, header.payload(ip));
+}
 
-		match protocol {
-			IP_PROTO_ICMP => {
-				super::icmp::process_icmp(pkt, len, ihl, &src_ip);
-			}
-			IP_PROTO_TCP => {
-				serial_println!("[IPv4] TCP not implemented");
-			}
-			IP_PROTO_UDP => {
-				super::udp::process_udp(pkt, len, ihl, &src_ip);
-			}
-			_ => {
-				serial_println!("[IPv4] Unknown protocol: {}", protocol);
-			}
+/// Hands an IPv4 payload to the handler for its protocol.
+fn dispatch(header: &Ipv4Header, payload: &[u8]) {
+	match header.protocol {
+		IP_PROTO_ICMP => super::icmp::process_icmp(payload, &header.src),
+		IP_PROTO_TCP => {
+			// TCP is driven by smoltcp, which reads frames straight off the device.
+			serial_println!("[IPv4] TCP segment ({} bytes) left to smoltcp", payload.len());
+		}
+		IP_PROTO_UDP => super::udp::process_udp(payload, &header.src, &header.dst),
+		_ => {
+			serial_println!("[IPv4] Unknown protocol: {}", header.protocol);
 		}
 	}
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{error::NullexError, net::ipv4::*, utils::ktest::TestError};
+
+	/// A UDP datagram from 10.0.2.2:53 to 10.0.2.15:1234 with a 4 byte
+	/// (NOP padded) options field and two bytes of Ethernet padding.
+	const UDP_WITH_OPTIONS: [u8; 38] = [
+		0x46, 0x00, 0x00, 0x24, // version/ihl, tos, total length
+		0x00, 0x01, 0x00, 0x00, // identification, flags/fragment
+		0x40, 0x11, 0x5f, 0xb6, // ttl, protocol, checksum
+		10, 0, 2, 2, // src
+		10, 0, 2, 15, // dst
+		0x01, 0x01, 0x01, 0x01, // options
+		0x00, 0x35, 0x04, 0xd2, 0x00, 0x0c, 0x00, 0x00, // udp header
+		b'n', b'u', b'l', b'x', // payload
+		0x00, 0x00 // ethernet padding
+	];
+
+	pub fn test_ipv4_parse_options_offset() -> Result<(), TestError> {
+		let header = Ipv4Header::parse(&UDP_WITH_OPTIONS).map_err(|_| TestError::Error)?;
+		assert_eq!(header.header_len, 24);
+		assert_eq!(header.protocol, IP_PROTO_UDP);
+		assert_eq!(header.src, [10, 0, 2, 2]);
+		assert_eq!(header.dst, [10, 0, 2, 15]);
+
+		let payload = header.payload(&UDP_WITH_OPTIONS);
+		assert_eq!(payload.len(), 12);
+		assert_eq!(&payload[0..2], &[0x00, 0x35]);
+		assert_eq!(&payload[8..], b"nulx");
+		Ok(())
+	}
+	crate::create_test!(test_ipv4_parse_options_offset);
+
+	pub fn test_ipv4_parse_rejects_bad_checksum() -> Result<(), TestError> {
+		let mut pkt = UDP_WITH_OPTIONS;
+		pkt[11] ^= 0xFF;
+		assert_eq!(Ipv4Header::parse(&pkt), Err(NullexError::ChecksumMismatch));
+		Ok(())
+	}
+	crate::create_test!(test_ipv4_parse_rejects_bad_checksum);
+
+	pub fn test_ipv4_parse_rejects_bad_ihl() -> Result<(), TestError> {
+		let mut pkt = UDP_WITH_OPTIONS;
+		pkt[0] = 0x44;
+		assert_eq!(Ipv4Header::parse(&pkt), Err(NullexError::InvalidArgument));
+		Ok(())
+	}
+	crate::create_test!(test_ipv4_parse_rejects_bad_ihl);
+}
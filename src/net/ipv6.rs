@@ -0,0 +1,414 @@
+//!
+//! ipv6.rs
+//!
+//! Minimal IPv6 handling for the kernel: ICMPv6 echo and Neighbor Discovery
+//! on a link-local address derived from our MAC.
+//!
+
+use alloc::vec::Vec;
+
+use crate::{
+	error::NullexError,
+	lazy_static,
+	net::checksum,
+	serial_println,
+	utils::mutex::SpinMutex
+};
+
+/// ICMPv6 next header value
+pub const IP6_PROTO_ICMPV6: u8 = 58;
+
+const IP6_PROTO_HOP_BY_HOP: u8 = 0;
+const IP6_PROTO_ROUTING: u8 = 43;
+const IP6_PROTO_FRAGMENT: u8 = 44;
+const IP6_PROTO_DEST_OPTS: u8 = 60;
+const IP6_PROTO_NO_NEXT: u8 = 59;
+
+const IPV6_HEADER_LEN: usize = 40;
+const ETH_HEADER_LEN: usize = 14;
+/// Hop limit Neighbor Discovery messages MUST be sent and received with.
+const ND_HOP_LIMIT: u8 = 255;
+/// Maximum number of extension headers we walk before giving up.
+const MAX_EXTENSION_HEADERS: usize = 8;
+
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+const ICMPV6_NEIGHBOR_SOLICITATION: u8 = 135;
+const ICMPV6_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+const ND_OPT_SOURCE_LINK_ADDR: u8 = 1;
+const ND_OPT_TARGET_LINK_ADDR: u8 = 2;
+
+const NA_FLAG_SOLICITED: u8 = 0x40;
+const NA_FLAG_OVERRIDE: u8 = 0x20;
+
+/// The link-local all-nodes multicast address (ff02::1).
+const ALL_NODES: [u8; 16] = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+
+lazy_static! {
+	/// Static reference to the Neighbor cache, the IPv6 counterpart of `ARP_CACHE`.
+	pub static ref NEIGHBOR_CACHE: SpinMutex<Vec<([u8; 16], [u8; 6])>> =
+		SpinMutex::new(Vec::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A parsed IPv6 header, with extension headers already skipped.
+pub struct Ipv6Header {
+	/// Protocol of the upper-layer payload.
+	pub next_header: u8,
+	/// Hop limit of the packet.
+	pub hop_limit: u8,
+	/// Source address.
+	pub src: [u8; 16],
+	/// Destination address.
+	pub dst: [u8; 16],
+	/// Offset of the upper-layer payload from the start of the IPv6 header.
+	pub payload_offset: usize,
+	/// Offset one past the end of the payload (40 + payload length).
+	pub payload_end: usize
+}
+
+impl Ipv6Header {
+	/// Parses the IPv6 header at the start of `ip` and walks the extension
+	/// header chain up to the upper-layer protocol.
+	///
+	/// Hop-by-hop, routing and destination option headers are skipped.
+	/// Fragmented packets are rejected as unsupported.
+	pub fn parse(ip: &[u8]) -> Result<Ipv6Header, NullexError> {
+		if ip.len() < IPV6_HEADER_LEN {
+			return Err(NullexError::BufferTooSmall);
+		}
+		if ip[0] >> 4 != 6 {
+			return Err(NullexError::Unsupported);
+		}
+
+		let payload_len = u16::from_be_bytes([ip[4], ip[5]]) as usize;
+		let payload_end = IPV6_HEADER_LEN + payload_len;
+		if payload_end > ip.len() {
+			return Err(NullexError::BufferTooSmall);
+		}
+
+		let mut src = [0u8; 16];
+		let mut dst = [0u8; 16];
+		src.copy_from_slice(&ip[8..24]);
+		dst.copy_from_slice(&ip[24..40]);
+
+		let mut next_header = ip[6];
+		let mut offset = IPV6_HEADER_LEN;
+		for _ in 0..MAX_EXTENSION_HEADERS {
+			match next_header {
+				IP6_PROTO_HOP_BY_HOP | IP6_PROTO_ROUTING | IP6_PROTO_DEST_OPTS => {
+					if offset + 2 > payload_end {
+						return Err(NullexError::BufferTooSmall);
+					}
+					next_header = ip[offset];
+					offset += (ip[offset + 1] as usize + 1) * 8;
+				}
+				IP6_PROTO_FRAGMENT => return Err(NullexError::Unsupported),
+				_ => {
+					if offset > payload_end {
+						return Err(NullexError::BufferTooSmall);
+					}
+					return Ok(Ipv6Header {
+						next_header,
+						hop_limit: ip[7],
+						src,
+						dst,
+						payload_offset: offset,
+						payload_end
+					});
+				}
+			}
+		}
+
+		Err(NullexError::Unsupported)
+	}
+}
+
+/// Derives the EUI-64 based link-local address (fe80::/64) for a MAC.
+pub fn link_local_from_mac(mac: [u8; 6]) -> [u8; 16] {
+	let mut addr = [0u8; 16];
+	addr[0] = 0xfe;
+	addr[1] = 0x80;
+	addr[8] = mac[0] ^ 0x02; // flip the universal/local bit
+	addr[9] = mac[1];
+	addr[10] = mac[2];
+	addr[11] = 0xff;
+	addr[12] = 0xfe;
+	addr[13] = mac[3];
+	addr[14] = mac[4];
+	addr[15] = mac[5];
+	addr
+}
+
+/// Returns the solicited-node multicast address (ff02::1:ffXX:XXXX) for an
+/// address.
+pub fn solicited_node_address(addr: &[u8; 16]) -> [u8; 16] {
+	let mut sn = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, 0, 0, 0];
+	sn[13..16].copy_from_slice(&addr[13..16]);
+	sn
+}
+
+/// Returns the Ethernet multicast MAC (33:33:XX:XX:XX:XX) for an IPv6
+/// multicast address.
+pub fn multicast_mac(addr: &[u8; 16]) -> [u8; 6] {
+	[0x33, 0x33, addr[12], addr[13], addr[14], addr[15]]
+}
+
+/// Returns our link-local address, if we have a MAC.
+pub fn our_link_local() -> Option<[u8; 16]> {
+	super::get_our_mac().map(link_local_from_mac)
+}
+
+/// Formats an IPv6 address (without zero compression).
+pub fn format_ipv6(addr: &[u8; 16]) -> alloc::string::String {
+	use alloc::format;
+	let groups: Vec<_> = addr
+		.chunks(2)
+		.map(|g| format!("{:x}", u16::from_be_bytes([g[0], g[1]])))
+		.collect();
+	groups.join(":")
+}
+
+/// Computes the ICMPv6 checksum over the IPv6 pseudo-header and `icmp`.
+fn icmpv6_checksum(src: &[u8; 16], dst: &[u8; 16], icmp: &[u8]) -> u16 {
+	let pseudo = checksum::pseudo_header_sum_v6(src, dst, IP6_PROTO_ICMPV6, icmp.len() as u32);
+	!checksum::combine(pseudo, checksum::ones_complement_sum(icmp))
+}
+
+/// Process incoming IPv6 packets. `frame` is the whole Ethernet frame.
+pub fn process_ipv6(frame: &[u8]) {
+	if frame.len() < ETH_HEADER_LEN + IPV6_HEADER_LEN {
+		serial_println!("[IPv6] Packet too short: {} bytes", frame.len());
+		return;
+	}
+
+	let mut src_mac = [0u8; 6];
+	src_mac.copy_from_slice(&frame[6..12]);
+	let ip = &frame[ETH_HEADER_LEN..];
+
+	let header = match Ipv6Header::parse(ip) {
+		Ok(header) => header,
+		Err(e) => {
+			serial_println!("[IPv6] Dropping packet: {}", e);
+			return;
+		}
+	};
+
+	let Some(our_addr) = our_link_local() else {
+		return;
+	};
+
+	if header.dst != our_addr
+		&& header.dst != solicited_node_address(&our_addr)
+		&& header.dst != ALL_NODES
+	{
+		serial_println!("[IPv6] Not for us, dropping");
+		return;
+	}
+
+	let payload = &ip[header.payload_offset..header.payload_end];
+	match header.next_header {
+		IP6_PROTO_ICMPV6 => process_icmpv6(&header, payload, src_mac, &our_addr),
+		IP6_PROTO_NO_NEXT => {}
+		proto => serial_println!("[IPv6] Unsupported next header: {}", proto)
+	}
+}
+
+fn process_icmpv6(header: &Ipv6Header, icmp: &[u8], src_mac: [u8; 6], our_addr: &[u8; 16]) {
+	if icmp.len() < 8 {
+		serial_println!("[ICMPv6] Packet too short");
+		return;
+	}
+
+	if icmpv6_checksum(&header.src, &header.dst, icmp) != 0 {
+		serial_println!("[ICMPv6] Bad checksum, dropping");
+		return;
+	}
+
+	match icmp[0] {
+		ICMPV6_ECHO_REQUEST => {
+			serial_println!("[ICMPv6] Echo request from {}", format_ipv6(&header.src));
+			let mut reply = icmp.to_vec();
+			reply[0] = ICMPV6_ECHO_REPLY;
+			send_icmpv6(src_mac, our_addr, &header.src, 64, &mut reply);
+		}
+		ICMPV6_ECHO_REPLY => {
+			serial_println!("[ICMPv6] Echo reply from {}", format_ipv6(&header.src));
+		}
+		ICMPV6_NEIGHBOR_SOLICITATION => {
+			if header.hop_limit != ND_HOP_LIMIT || icmp.len() < 24 {
+				return;
+			}
+			let mut target = [0u8; 16];
+			target.copy_from_slice(&icmp[8..24]);
+
+			// learn the solicitor's MAC unless it is still doing DAD (::)
+			let unspecified = header.src == [0; 16];
+			if !unspecified && let Some(mac) = find_link_addr(&icmp[24..], ND_OPT_SOURCE_LINK_ADDR) {
+				cache_neighbor(header.src, mac);
+			}
+
+			if &target == our_addr {
+				serial_println!("[NDP] Solicitation for us, sending advertisement");
+				send_neighbor_advertisement(src_mac, our_addr, &header.src, !unspecified);
+			}
+		}
+		ICMPV6_NEIGHBOR_ADVERTISEMENT => {
+			if header.hop_limit != ND_HOP_LIMIT || icmp.len() < 24 {
+				return;
+			}
+			let mut target = [0u8; 16];
+			target.copy_from_slice(&icmp[8..24]);
+			let mac = find_link_addr(&icmp[24..], ND_OPT_TARGET_LINK_ADDR).unwrap_or(src_mac);
+			cache_neighbor(target, mac);
+		}
+		other => serial_println!("[ICMPv6] Unhandled type: {}", other)
+	}
+}
+
+/// Finds a link-layer address option of the given type in ND options.
+fn find_link_addr(mut options: &[u8], opt_type: u8) -> Option<[u8; 6]> {
+	while options.len() >= 8 {
+		let len = options[1] as usize * 8;
+		if len == 0 || len > options.len() {
+			return None;
+		}
+		if options[0] == opt_type {
+			let mut mac = [0u8; 6];
+			mac.copy_from_slice(&options[2..8]);
+			return Some(mac);
+		}
+		options = &options[len..];
+	}
+	None
+}
+
+fn cache_neighbor(addr: [u8; 16], mac: [u8; 6]) {
+	let max = super::limits::get().max_arp_entries;
+	let mut cache = NEIGHBOR_CACHE.lock();
+	cache.retain(|(ip, _)| ip != &addr);
+	while !cache.is_empty() && cache.len() >= max {
+		cache.remove(0);
+	}
+	cache.push((addr, mac));
+	serial_println!("[NDP] Cached {}", format_ipv6(&addr));
+}
+
+fn send_neighbor_advertisement(
+	dst_mac: [u8; 6],
+	our_addr: &[u8; 16],
+	dst: &[u8; 16],
+	solicited: bool
+) {
+	let Some(our_mac) = super::get_our_mac() else {
+		return;
+	};
+
+	// answers to DAD probes go to all-nodes
+	let (dst_mac, dst) = if solicited {
+		(dst_mac, *dst)
+	} else {
+		(multicast_mac(&ALL_NODES), ALL_NODES)
+	};
+	let flags = if solicited { NA_FLAG_SOLICITED | NA_FLAG_OVERRIDE } else { NA_FLAG_OVERRIDE };
+
+	let mut icmp = alloc::vec![0u8; 32];
+	icmp[0] = ICMPV6_NEIGHBOR_ADVERTISEMENT;
+	icmp[4] = flags;
+	icmp[8..24].copy_from_slice(our_addr);
+	icmp[24] = ND_OPT_TARGET_LINK_ADDR;
+	icmp[25] = 1; // length in units of 8 bytes
+	icmp[26..32].copy_from_slice(&our_mac);
+
+	send_icmpv6(dst_mac, our_addr, &dst, ND_HOP_LIMIT, &mut icmp);
+}
+
+/// Wraps an ICMPv6 message in IPv6 and Ethernet headers and sends it.
+/// The checksum field of `icmp` is filled in here.
+fn send_icmpv6(dst_mac: [u8; 6], src: &[u8; 16], dst: &[u8; 16], hop_limit: u8, icmp: &mut [u8]) {
+	let Some(our_mac) = super::get_our_mac() else {
+		serial_println!("[ICMPv6] No MAC");
+		return;
+	};
+
+	icmp[2..4].copy_from_slice(&[0, 0]);
+	let checksum = icmpv6_checksum(src, dst, icmp);
+	icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+	let mut packet = alloc::vec![0u8; ETH_HEADER_LEN + IPV6_HEADER_LEN + icmp.len()];
+
+	// ethernet header
+	packet[0..6].copy_from_slice(&dst_mac);
+	packet[6..12].copy_from_slice(&our_mac);
+	packet[12..14].copy_from_slice(&super::ethernet::ETHERTYPE_IPV6.to_be_bytes());
+
+	// IPv6 header
+	packet[14] = 0x60;
+	packet[18..20].copy_from_slice(&(icmp.len() as u16).to_be_bytes());
+	packet[20] = IP6_PROTO_ICMPV6;
+	packet[21] = hop_limit;
+	packet[22..38].copy_from_slice(src);
+	packet[38..54].copy_from_slice(dst);
+	packet[54..].copy_from_slice(icmp);
+
+	if let Err(e) = super::send_packet(&packet) {
+		serial_println!("[ICMPv6] Failed to send: {}", e);
+	}
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{error::NullexError, net::ipv6::*, utils::ktest::TestError};
+
+	/// An ICMPv6 echo request from fe80::1 to ff02::1 behind an empty
+	/// hop-by-hop options header.
+	const ECHO_WITH_HOP_BY_HOP: [u8; 52] = [
+		0x60, 0x00, 0x00, 0x00, // version, traffic class, flow label
+		0x00, 0x0c, 0x00, 0xff, // payload length, next header, hop limit
+		0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, // src
+		0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, // dst
+		IP6_PROTO_ICMPV6, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, // hop-by-hop, PadN
+		ICMPV6_ECHO_REQUEST, 0x00, 0x00, 0x00 // icmpv6 header
+	];
+
+	pub fn test_ipv6_parse_skips_extension_headers() -> Result<(), TestError> {
+		let header = Ipv6Header::parse(&ECHO_WITH_HOP_BY_HOP).map_err(|_| TestError::Error)?;
+		assert_eq!(header.next_header, IP6_PROTO_ICMPV6);
+		assert_eq!(header.hop_limit, ND_HOP_LIMIT);
+		assert_eq!(header.src, ECHO_WITH_HOP_BY_HOP[8..24]);
+		assert_eq!(header.dst, ALL_NODES);
+		assert_eq!(header.payload_offset, 48);
+		assert_eq!(header.payload_end, 52);
+		Ok(())
+	}
+	crate::create_test!(test_ipv6_parse_skips_extension_headers);
+
+	pub fn test_ipv6_parse_rejects_truncated() -> Result<(), TestError> {
+		let pkt = &ECHO_WITH_HOP_BY_HOP;
+		assert_eq!(Ipv6Header::parse(&pkt[..39]), Err(NullexError::BufferTooSmall));
+		// the header is complete, but the payload length runs past the end
+		assert_eq!(Ipv6Header::parse(&pkt[..51]), Err(NullexError::BufferTooSmall));
+		Ok(())
+	}
+	crate::create_test!(test_ipv6_parse_rejects_truncated);
+
+	pub fn test_ipv6_parse_rejects_wrong_version() -> Result<(), TestError> {
+		let mut pkt = ECHO_WITH_HOP_BY_HOP;
+		pkt[0] = 0x45;
+		assert_eq!(Ipv6Header::parse(&pkt), Err(NullexError::Unsupported));
+		Ok(())
+	}
+	crate::create_test!(test_ipv6_parse_rejects_wrong_version);
+
+	pub fn test_link_local_from_mac() -> Result<(), TestError> {
+		// fe80::5054:ff:fe12:3456, with the universal/local bit flipped
+		let addr = link_local_from_mac([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+		assert_eq!(
+			addr,
+			[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0x50, 0x54, 0x00, 0xff, 0xfe, 0x12, 0x34, 0x56]
+		);
+		Ok(())
+	}
+	crate::create_test!(test_link_local_from_mac);
+}
@@ -0,0 +1,229 @@
+//!
+//! limits.rs
+//!
+//! Resource limits for the network stack, so sustained traffic can't grow
+//! its tables without bound.
+//!
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{error::NullexError, serial_println, utils::mutex::SpinMutex};
+
+/// Default maximum number of open sockets (TCP connections and bound UDP
+/// ports).
+pub const DEFAULT_MAX_SOCKETS: usize = 16;
+/// Default maximum number of ARP (and IPv6 neighbor) cache entries.
+pub const DEFAULT_MAX_ARP_ENTRIES: usize = 64;
+/// Default maximum number of datagrams being reassembled at once.
+pub const DEFAULT_MAX_REASSEMBLY_BUFFERS: usize = 8;
+/// Default maximum number of TCP connections that are connecting or
+/// listening but not yet established.
+pub const DEFAULT_MAX_PENDING_CONNECTIONS: usize = 4;
+
+/// The currently configured limits.
+static LIMITS: SpinMutex<NetLimits> = SpinMutex::new(NetLimits::DEFAULT);
+
+/// Number of open sockets.
+static OPEN_SOCKETS: AtomicUsize = AtomicUsize::new(0);
+/// Number of pending TCP connections.
+static PENDING_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Limits enforced by the network stack at its allocation points.
+pub struct NetLimits {
+	/// Maximum number of open sockets. New sockets are rejected past this.
+	pub max_sockets: usize,
+	/// Maximum number of ARP cache entries. The least recently updated entry
+	/// is evicted past this.
+	pub max_arp_entries: usize,
+	/// Maximum number of datagrams being reassembled. The oldest one is
+	/// evicted past this.
+	pub max_reassembly_buffers: usize,
+	/// Maximum number of pending TCP connections. New connects and listens
+	/// are rejected past this.
+	pub max_pending_connections: usize
+}
+
+impl NetLimits {
+	/// The limits the kernel boots with.
+	pub const DEFAULT: NetLimits = NetLimits {
+		max_sockets: DEFAULT_MAX_SOCKETS,
+		max_arp_entries: DEFAULT_MAX_ARP_ENTRIES,
+		max_reassembly_buffers: DEFAULT_MAX_REASSEMBLY_BUFFERS,
+		max_pending_connections: DEFAULT_MAX_PENDING_CONNECTIONS
+	};
+}
+
+impl Default for NetLimits {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A counted resource that is rejected, rather than evicted, at its limit.
+pub enum Resource {
+	/// An open socket.
+	Socket,
+	/// A TCP connection that hasn't been established yet.
+	PendingConnection
+}
+
+impl Resource {
+	fn counter(self) -> &'static AtomicUsize {
+		match self {
+			Resource::Socket => &OPEN_SOCKETS,
+			Resource::PendingConnection => &PENDING_CONNECTIONS
+		}
+	}
+
+	fn limit(self) -> usize {
+		let limits = get();
+		match self {
+			Resource::Socket => limits.max_sockets,
+			Resource::PendingConnection => limits.max_pending_connections
+		}
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Resource::Socket => "sockets",
+			Resource::PendingConnection => "pending connections"
+		}
+	}
+}
+
+/// Returns the currently configured limits.
+pub fn get() -> NetLimits {
+	*LIMITS.lock()
+}
+
+/// Replaces the configured limits. Tables already over a lowered limit are
+/// trimmed the next time they grow.
+pub fn set(limits: NetLimits) {
+	*LIMITS.lock() = limits;
+}
+
+/// Takes one unit of `resource`, failing with `ResourceLimit` if the limit
+/// has been reached.
+pub fn try_acquire(resource: Resource) -> Result<(), NullexError> {
+	let limit = resource.limit();
+	resource
+		.counter()
+		.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < limit).then_some(n + 1))
+		.map(|_| ())
+		.map_err(|_| {
+			serial_println!("[NET] Limit of {} {} reached", limit, resource.name());
+			NullexError::ResourceLimit(resource.name())
+		})
+}
+
+/// Gives back one unit of `resource`.
+pub fn release(resource: Resource) {
+	let _ = resource
+		.counter()
+		.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+}
+
+/// Returns how many units of `resource` are currently in use.
+pub fn in_use(resource: Resource) -> usize {
+	resource.counter().load(Ordering::Acquire)
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{
+		error::NullexError,
+		net::{
+			arp::{self, ARP_CACHE},
+			limits::*,
+			reassembly::Reassembler
+		},
+		utils::ktest::TestError
+	};
+
+	pub fn test_limits_sockets_and_pending() -> Result<(), TestError> {
+		let saved = get();
+		set(NetLimits {
+			max_sockets: in_use(Resource::Socket) + 2,
+			max_pending_connections: in_use(Resource::PendingConnection) + 1,
+			..saved
+		});
+
+		let result = (|| {
+			try_acquire(Resource::Socket).map_err(|_| TestError::Error)?;
+			try_acquire(Resource::Socket).map_err(|_| TestError::Error)?;
+			let rejected = try_acquire(Resource::Socket);
+			release(Resource::Socket);
+			release(Resource::Socket);
+			assert_eq!(rejected, Err(NullexError::ResourceLimit("sockets")));
+
+			try_acquire(Resource::PendingConnection).map_err(|_| TestError::Error)?;
+			let rejected = try_acquire(Resource::PendingConnection);
+			release(Resource::PendingConnection);
+			assert!(rejected.is_err());
+			Ok(())
+		})();
+
+		set(saved);
+		result
+	}
+	crate::create_test!(test_limits_sockets_and_pending);
+
+	pub fn test_limits_arp_evicts_oldest() -> Result<(), TestError> {
+		let saved = get();
+		let saved_cache = ARP_CACHE.lock().clone();
+		ARP_CACHE.lock().clear();
+		set(NetLimits {
+			max_arp_entries: 2,
+			..saved
+		});
+
+		arp::insert([192, 0, 2, 1], [2, 0, 0, 0, 0, 1]);
+		arp::insert([192, 0, 2, 2], [2, 0, 0, 0, 0, 2]);
+		arp::insert([192, 0, 2, 3], [2, 0, 0, 0, 0, 3]);
+
+		let evicted = arp::get_cached([192, 0, 2, 1]);
+		let len = ARP_CACHE.lock().len();
+		let newest = arp::get_cached([192, 0, 2, 3]);
+
+		set(saved);
+		*ARP_CACHE.lock() = saved_cache;
+
+		assert_eq!(len, 2);
+		assert_eq!(evicted, None);
+		assert_eq!(newest, Some([2, 0, 0, 0, 0, 3]));
+		Ok(())
+	}
+	crate::create_test!(test_limits_arp_evicts_oldest);
+
+	pub fn test_limits_reassembly_buffers() -> Result<(), TestError> {
+		use crate::net::ipv4::Ipv4Header;
+
+		let saved = get();
+		set(NetLimits {
+			max_reassembly_buffers: 2,
+			..saved
+		});
+
+		let mut r = Reassembler::new();
+		for id in 0..3u16 {
+			let header = Ipv4Header {
+				header_len: 20,
+				total_len: 0,
+				identification: id,
+				flags_fragment: 0x2000,
+				protocol: 17,
+				src: [10, 0, 2, 2],
+				dst: [10, 0, 2, 15]
+			};
+			r.push(&header, &[0; 8], id as u64);
+		}
+		let pending = r.pending();
+
+		set(saved);
+		assert_eq!(pending, 2);
+		Ok(())
+	}
+	crate::create_test!(test_limits_reassembly_buffers);
+}
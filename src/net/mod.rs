@@ -5,28 +5,130 @@
 //! 
 
 pub mod arp;
+pub mod capture;
+pub mod checksum;
+pub mod dhcp;
 pub mod dns;
 pub mod ethernet;
 pub mod http;
 pub mod icmp;
 pub mod ipv4;
+pub mod ipv6;
+pub mod limits;
+pub mod netcat;
+pub mod reassembly;
 pub mod tcp;
 pub mod udp;
 
-use crate::{drivers::virtio::net::VIRTIO_NET_INSTANCE, error::NullexError, serial_println};
+use x86_64::instructions::interrupts;
 
-/// Our IP
-/// currently manually set based on QEMU config.
-pub const OUR_IP: [u8; 4] = [10, 0, 2, 15];
-/// IP address to the Gateway
-// manually set based on QEMU config.
-pub const GATEWAY_IP: [u8; 4] = [10, 0, 2, 2];
-/// Subnet Mask
-// usually always 255.255.255.0 unless in like corporate.
-pub const SUBNET_MASK: [u8; 4] = [255, 255, 255, 0];
+use crate::{
+	drivers::virtio::net::mac_address,
+	error::NullexError,
+	serial_println,
+	utils::mutex::SpinMutex
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// IPv4 settings of the network interface.
+pub struct NetConfig {
+	/// Our IP.
+	pub ip: [u8; 4],
+	/// IP address of the gateway.
+	pub gateway: [u8; 4],
+	/// Subnet mask.
+	pub subnet_mask: [u8; 4],
+	/// DNS server queries are sent to.
+	pub dns: [u8; 4]
+}
+
+impl NetConfig {
+	/// The addresses handed out by QEMU's user mode networking, used until the
+	/// interface is configured with `ifconfig` or DHCP.
+	pub const QEMU_DEFAULT: NetConfig = NetConfig {
+		ip: [10, 0, 2, 15],
+		gateway: [10, 0, 2, 2],
+		subnet_mask: [255, 255, 255, 0],
+		// 10.0.2.3 is QEMU's DNS proxy, but it never answers ARP, so queries
+		// go out through the gateway to a public resolver instead
+		dns: [8, 8, 8, 8]
+	};
+
+	/// Checks that the mask is contiguous and that our IP is a usable host
+	/// address.
+	pub fn validate(&self) -> Result<(), NullexError> {
+		let mask = u32::from_be_bytes(self.subnet_mask);
+		if mask.leading_ones() + mask.trailing_zeros() != 32 {
+			return Err(NullexError::InvalidArgument);
+		}
+		if self.ip == [0; 4] || self.ip == [255; 4] || self.ip[0] >= 224 {
+			return Err(NullexError::InvalidArgument);
+		}
+		Ok(())
+	}
+
+	/// Returns the length of the subnet prefix (`24` for `255.255.255.0`).
+	pub fn prefix_len(&self) -> u8 {
+		u32::from_be_bytes(self.subnet_mask).leading_ones() as u8
+	}
+
+	/// Returns whether `ip` is on our subnet, i.e. reachable without the
+	/// gateway.
+	pub fn is_local(&self, ip: [u8; 4]) -> bool {
+		(0..4).all(|i| ip[i] & self.subnet_mask[i] == self.ip[i] & self.subnet_mask[i])
+	}
+
+	/// Returns the directed broadcast address of our subnet.
+	pub fn broadcast(&self) -> [u8; 4] {
+		core::array::from_fn(|i| self.ip[i] | !self.subnet_mask[i])
+	}
+}
+
+/// The interface's IPv4 settings. Starts out with QEMU's defaults.
+static NET_CONFIG: SpinMutex<NetConfig> = SpinMutex::new(NetConfig::QEMU_DEFAULT);
+
+/// Returns a copy of the current IPv4 settings.
+pub fn config() -> NetConfig {
+	interrupts::without_interrupts(|| *NET_CONFIG.lock())
+}
+
+/// Replaces the IPv4 settings. The ARP cache is flushed when our IP or the
+/// subnet changes, since its entries may no longer be reachable.
+pub fn set_config(new: NetConfig) -> Result<(), NullexError> {
+	new.validate()?;
+
+	let old = interrupts::without_interrupts(|| core::mem::replace(&mut *NET_CONFIG.lock(), new));
+	if old.ip != new.ip || old.subnet_mask != new.subnet_mask {
+		interrupts::without_interrupts(|| arp::ARP_CACHE.lock().clear());
+	}
+
+	serial_println!(
+		"[NET] Configured {}/{} via {}",
+		format_ip(new.ip),
+		new.prefix_len(),
+		format_ip(new.gateway)
+	);
+	Ok(())
+}
+
+/// Returns our IP.
+pub fn our_ip() -> [u8; 4] {
+	config().ip
+}
+
+/// Returns the gateway's IP.
+pub fn gateway_ip() -> [u8; 4] {
+	config().gateway
+}
 
 /// Main point of receiving and handling packets.
 pub fn receive_packet(pkt: *const u8, len: usize) {
+	if capture::is_enabled() {
+		capture::record(capture::Direction::Rx, unsafe {
+			core::slice::from_raw_parts(pkt, len)
+		});
+	}
+
 	if len < 14 {
 		serial_println!("[NET] Packet too short: {} bytes", len);
 		return;
@@ -35,58 +137,66 @@ pub fn receive_packet(pkt: *const u8, len: usize) {
 	serial_println!("packet");
 
 	unsafe {
-		// ethernet header parse
 		let ethertype = u16::from_be_bytes([*pkt.add(12), *pkt.add(13)]);
-
-		let src_mac = [
-			*pkt.add(6),
-			*pkt.add(7),
-			*pkt.add(8),
-			*pkt.add(9),
-			*pkt.add(10),
-			*pkt.add(11)
-		];
-
 		serial_println!(
 			"[NET] Received packet: {} bytes, ethertype: 0x{:04X}",
 			len,
 			ethertype
 		);
-
-		match ethertype {
-			0x0806 => {
-				serial_println!("[NET] -> ARP packet");
-				arp::process_arp(pkt, len, src_mac);
-			}
-			0x0800 => {
-				serial_println!("[NET] -> IPv4 packet");
-				ipv4::process_ipv4(pkt, len);
-			}
-			_ => {
-				serial_println!("[NET] -> Unknown ethertype: 0x{:04X}", ethertype);
-			}
-		}
 	}
+
+	// the ethernet layer looks through VLAN tags before dispatching
+	ethernet::process_ethernet_frame(pkt, len);
 }
 
 fn send_packet(packet: &[u8]) -> Result<(), NullexError> {
 	crate::drivers::virtio::net::transmit_packet(packet)
 }
 
-fn get_our_mac() -> Option<[u8; 6]> {
-	VIRTIO_NET_INSTANCE
-		.lock()
-		.as_ref()
-		.map(|(net, _)| net.config.mac)
+/// Returns the MAC address used as the source of outgoing frames.
+///
+/// Doesn't lock `VIRTIO_NET_INSTANCE`, which a smoltcp session may hold
+/// while the interrupt handler answers ARP and ICMP requests.
+pub fn get_our_mac() -> Option<[u8; 6]> {
+	mac_address()
 }
 
-fn is_local_ip(ip: [u8; 4]) -> bool {
-	for i in 0..4 {
-		if (ip[i] & SUBNET_MASK[i]) != (OUR_IP[i] & SUBNET_MASK[i]) {
-			return false;
+/// Parses a MAC address written as six colon separated hex octets
+/// (`52:54:00:12:34:56`).
+pub fn parse_mac(s: &str) -> Result<[u8; 6], NullexError> {
+	let mut mac = [0u8; 6];
+	let mut parts = s.split(':');
+
+	for byte in mac.iter_mut() {
+		let part = parts.next().ok_or(NullexError::InvalidArgument)?;
+		if part.len() != 2 {
+			return Err(NullexError::InvalidArgument);
 		}
+		*byte = u8::from_str_radix(part, 16).map_err(|_| NullexError::InvalidArgument)?;
+	}
+
+	if parts.next().is_some() {
+		return Err(NullexError::InvalidArgument);
+	}
+	Ok(mac)
+}
+
+/// Overrides the MAC address used for outgoing frames.
+///
+/// Multicast (group bit set) and all-zero addresses are rejected.
+///
+/// Changing the MAC after peers have cached our old address (e.g. the gateway's
+/// ARP table) means they keep sending to the old one until their cache entry
+/// expires or is flushed.
+pub fn set_our_mac(mac: [u8; 6]) -> Result<(), NullexError> {
+	if mac[0] & 0x01 != 0 || mac == [0; 6] {
+		return Err(NullexError::InvalidArgument);
 	}
-	true
+	crate::drivers::virtio::net::set_mac_address(mac)
+}
+
+fn is_local_ip(ip: [u8; 4]) -> bool {
+	config().is_local(ip)
 }
 
 fn get_next_hop_mac(dst_ip: [u8; 4]) -> Result<[u8; 6], NullexError> {
@@ -97,7 +207,7 @@ fn get_next_hop_mac(dst_ip: [u8; 4]) -> Result<[u8; 6], NullexError> {
 			"[NET] {} is not local, routing through gateway",
 			format_ip(dst_ip)
 		);
-		GATEWAY_IP
+		gateway_ip()
 	};
 
 	// next hop
@@ -117,8 +227,36 @@ fn format_ip(ip: [u8; 4]) -> alloc::string::String {
 /// Initialise the Internet handlers. (DNS currently)
 pub fn init() {
 	dns::init();
+	if let Some(addr) = ipv6::our_link_local() {
+		serial_println!("[IPv6] Link-local address: {}", ipv6::format_ipv6(&addr));
+	}
 }
 
 // Re-exports
 pub use arp::{ARP_CACHE, send_arp_request};
-pub use icmp::send_ping;
\ No newline at end of file
+pub use icmp::send_ping;
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{error::NullexError, net::*, utils::ktest::TestError};
+
+	pub fn test_net_config_subnet() -> Result<(), TestError> {
+		let config = NetConfig {
+			ip: [192, 168, 8, 20],
+			gateway: [192, 168, 8, 1],
+			subnet_mask: [255, 255, 252, 0],
+			dns: [192, 168, 8, 1]
+		};
+		assert_eq!(config.validate(), Ok(()));
+		assert_eq!(config.prefix_len(), 22);
+		assert_eq!(config.broadcast(), [192, 168, 11, 255]);
+		assert!(config.is_local([192, 168, 10, 7]));
+		assert!(!config.is_local([192, 168, 12, 7]));
+
+		let holes = NetConfig { subnet_mask: [255, 0, 255, 0], ..config };
+		assert_eq!(holes.validate(), Err(NullexError::InvalidArgument));
+		let unset = NetConfig { ip: [0; 4], ..config };
+		assert_eq!(unset.validate(), Err(NullexError::InvalidArgument));
+		Ok(())
+	}
+	crate::create_test!(test_net_config_subnet);
+}
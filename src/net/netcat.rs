@@ -0,0 +1,424 @@
+//!
+//! netcat.rs
+//!
+//! Interactive raw TCP/UDP sessions, used by the `nc` shell command.
+//!
+
+use alloc::{collections::VecDeque, string::String, sync::Arc, vec::Vec};
+use core::{
+	future::{Future, poll_fn},
+	net::Ipv4Addr,
+	pin::Pin,
+	sync::atomic::Ordering,
+	task::Poll
+};
+
+use smoltcp::{
+	iface::{Config, Interface, SocketSet},
+	socket::tcp::{Socket, State},
+	time::Instant,
+	wire::{EthernetAddress, IpAddress, IpCidr}
+};
+use x86_64::instructions::interrupts;
+
+use crate::{
+	apic::{self, APIC_TICK_COUNT},
+	drivers::{
+		keyboard::{layouts::ActiveLayout, ps2::Keyboard, scancode::ScancodeSet1},
+		virtio::net::{SmoltcpRx, VIRTIO_NET_INSTANCE, VirtioNet, rx_poll}
+	},
+	error::NullexError,
+	io::keyboard::decode::{DecodedKey, HandleControl},
+	lazy_static,
+	net::{self, tcp::TcpConnection, udp},
+	print, println, serial_println,
+	task::{
+		ProcessState,
+		keyboard::{restore_foreground, set_foreground},
+		timer::{Timer, ms_to_ticks}
+	},
+	utils::mutex::SpinMutex,
+	vga_buffer::console_backspace
+};
+
+/// How long a session sleeps between polls of the network and keyboard.
+const POLL_INTERVAL_MS: u64 = 10;
+/// How long to wait for a TCP connection to be established.
+const CONNECT_TIMEOUT_MS: u64 = 10_000;
+/// How long to wait for the remote to take a typed line before giving up.
+const SEND_TIMEOUT_MS: u64 = 10_000;
+/// How long to wait for the remote to acknowledge our FIN on teardown.
+const CLOSE_TIMEOUT_MS: u64 = 1000;
+/// How many times a UDP send is retried while the next hop MAC is resolved.
+const UDP_ARP_RETRIES: usize = 5;
+/// How long to wait for an ARP reply between UDP send retries.
+const UDP_ARP_WAIT_MS: u64 = 100;
+/// Start of the range local ports are picked from.
+const EPHEMERAL_PORT_BASE: u16 = 49152;
+
+lazy_static! {
+	/// Datagrams received on the UDP port of the running session.
+	static ref UDP_RX: SpinMutex<VecDeque<Vec<u8>>> = SpinMutex::new(VecDeque::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What an `nc` session does.
+pub enum NcMode {
+	/// Connect to a remote TCP port.
+	TcpConnect([u8; 4], u16),
+	/// Wait for a single TCP connection on a local port.
+	TcpListen(u16),
+	/// Send UDP datagrams to a remote port, printing any replies.
+	UdpSend([u8; 4], u16),
+	/// Print UDP datagrams received on a local port.
+	UdpListen(u16)
+}
+
+/// Something the user did at the keyboard.
+enum InputEvent {
+	/// A full line, including the trailing newline.
+	Line(String),
+	/// Ctrl+C was pressed, end the session.
+	Interrupt
+}
+
+/// Line-buffered keyboard input. The session's process holds the
+/// foreground, so its keys arrive on the process's own scancode queue, and
+/// Ctrl+C cancels the process instead of being delivered.
+struct Input {
+	state: Arc<ProcessState>,
+	keyboard: Keyboard<ActiveLayout, ScancodeSet1>,
+	line: String
+}
+
+impl Input {
+	fn new(state: Arc<ProcessState>) -> Input {
+		Input {
+			state,
+			keyboard: Keyboard::new(
+				ScancodeSet1::new(),
+				ActiveLayout,
+				HandleControl::MapLettersToUnicode
+			),
+			line: String::new()
+		}
+	}
+
+	/// Handles every pending scancode, returning as soon as a line is
+	/// complete or the session is interrupted.
+	fn poll(&mut self) -> Option<InputEvent> {
+		if self.state.is_cancelled() {
+			println!("^C");
+			return Some(InputEvent::Interrupt);
+		}
+		let Ok(queue) = self.state.scancode_queue.try_get() else {
+			return None;
+		};
+
+		while let Some(scancode) = queue.pop() {
+			let Ok(Some(event)) = self.keyboard.add_byte(scancode) else {
+				continue;
+			};
+			let Some(key) = self.keyboard.process_keyevent(event) else {
+				continue;
+			};
+
+			match key {
+				DecodedKey::RawKey(_) => {}
+				// other Ctrl combinations map to control codes nobody types on purpose
+				DecodedKey::Unicode(c) if c.is_ascii_control() && c != '\u{8}' && c != '\n' => {}
+				DecodedKey::Unicode('\u{8}') => {
+					if self.line.pop().is_some() {
+						console_backspace();
+					}
+				}
+				DecodedKey::Unicode('\n') => {
+					println!();
+					self.line.push('\n');
+					return Some(InputEvent::Line(core::mem::take(&mut self.line)));
+				}
+				DecodedKey::Unicode(c) => {
+					print!("{}", c);
+					self.line.push(c);
+				}
+			}
+		}
+		None
+	}
+}
+
+/// Runs an interactive session in the calling process until the remote
+/// disconnects or the user presses Ctrl+C. The process takes the keyboard
+/// for the session.
+pub async fn run(state: Arc<ProcessState>, mode: NcMode) -> Result<(), NullexError> {
+	let previous = set_foreground(state.id)?;
+	let input = Input::new(state);
+
+	let result = match mode {
+		NcMode::TcpConnect(..) | NcMode::TcpListen(_) => run_tcp(mode, input).await,
+		NcMode::UdpSend(..) | NcMode::UdpListen(_) => run_udp(mode, input).await
+	};
+
+	restore_foreground(previous);
+	result
+}
+
+/// Sleeps for `ms` milliseconds, or until the process is asked to stop. A
+/// cancelled process is polled until its grace period runs out, so the
+/// session has to notice straight away to get its teardown done.
+async fn pause(state: &ProcessState, ms: u64) {
+	let mut timer = Timer::after_ticks(ms_to_ticks(ms));
+	poll_fn(|cx| {
+		if state.is_cancelled() {
+			return Poll::Ready(());
+		}
+		Pin::new(&mut timer).poll(cx)
+	})
+	.await
+}
+
+fn ticks() -> u64 {
+	APIC_TICK_COUNT.load(Ordering::Relaxed)
+}
+
+/// Converts the APIC timer uptime into a smoltcp timestamp.
+fn now() -> Instant {
+	Instant::from_millis((apic::uptime_ns() / 1_000_000) as i64)
+}
+
+fn ephemeral_port() -> u16 {
+	EPHEMERAL_PORT_BASE + (ticks() % (u16::MAX - EPHEMERAL_PORT_BASE) as u64) as u16
+}
+
+/// Prints received bytes, replacing invalid UTF-8.
+fn print_received(data: &[u8]) {
+	print!("{}", String::from_utf8_lossy(data));
+}
+
+/// Runs `f` on the network device. The device is only locked for the call,
+/// never across an await, so the rest of the kernel can use it in between.
+fn with_device<R>(f: impl FnOnce(&mut VirtioNet) -> R) -> Result<R, NullexError> {
+	let mut instance = VIRTIO_NET_INSTANCE.lock();
+	let (device, _) = instance.as_mut().ok_or(NullexError::MissingVirtIOInstance)?;
+	Ok(f(device))
+}
+
+/// Lets the interface send and receive whatever is pending.
+fn poll_interface(iface: &mut Interface, sockets: &mut SocketSet<'_>) -> Result<(), NullexError> {
+	with_device(|device| TcpConnection::poll(iface, device, sockets, now()))
+}
+
+fn create_interface(device: &mut VirtioNet) -> Result<Interface, NullexError> {
+	let config = Config::new(EthernetAddress(device.config.mac).into());
+	let mut iface = Interface::new(config, device, now());
+
+	let net_config = net::config();
+	let cidr = IpCidr::new(
+		IpAddress::Ipv4(Ipv4Addr::from_octets(net_config.ip)),
+		net_config.prefix_len()
+	);
+	let mut pushed = Ok(());
+	iface.update_ip_addrs(|addrs| {
+		pushed = addrs.push(cidr);
+	});
+	pushed.map_err(|_| NullexError::TcpConnectionFailed)?;
+	iface
+		.routes_mut()
+		.add_default_ipv4_route(Ipv4Addr::from_octets(net_config.gateway))
+		.map_err(|_| NullexError::TcpConnectionFailed)?;
+
+	Ok(iface)
+}
+
+async fn run_tcp(mode: NcMode, mut input: Input) -> Result<(), NullexError> {
+	let mut iface = with_device(create_interface)??;
+	let mut sockets = SocketSet::new(alloc::vec![]);
+	let conn = TcpConnection::new(&mut sockets)?;
+	// keeps the interrupt handler off the frames meant for the interface
+	let _rx = SmoltcpRx::claim();
+
+	let result = tcp_session(mode, &mut iface, &mut sockets, &conn, &mut input).await;
+
+	// clean teardown: send our FIN and give the remote a moment to ack it.
+	// A cancelled session is only polled for a short grace period, so it
+	// sends its FIN without waiting
+	conn.close(&mut sockets);
+	let deadline = ticks() + ms_to_ticks(CLOSE_TIMEOUT_MS);
+	while sockets.get::<Socket>(conn.handle).state() != State::Closed && ticks() < deadline {
+		let _ = poll_interface(&mut iface, &mut sockets);
+		if input.state.is_cancelled() {
+			break;
+		}
+		pause(&input.state, POLL_INTERVAL_MS).await;
+	}
+	sockets.get_mut::<Socket>(conn.handle).abort();
+	let _ = poll_interface(&mut iface, &mut sockets);
+	conn.release(&mut sockets);
+
+	result
+}
+
+async fn tcp_session(
+	mode: NcMode,
+	iface: &mut Interface,
+	sockets: &mut SocketSet<'_>,
+	conn: &TcpConnection,
+	input: &mut Input
+) -> Result<(), NullexError> {
+	match mode {
+		NcMode::TcpConnect(ip, port) => {
+			conn.connect(iface, sockets, ip, port, ephemeral_port())?;
+			println!(
+				"nc: connecting to {}.{}.{}.{}:{} (press Ctrl+C to stop)",
+				ip[0], ip[1], ip[2], ip[3], port
+			);
+		}
+		NcMode::TcpListen(port) => {
+			conn.listen(sockets, port)?;
+			println!("nc: listening on tcp port {} (press Ctrl+C to stop)", port);
+		}
+		_ => return Err(NullexError::InvalidArgument)
+	}
+
+	// wait for the handshake
+	let deadline = ticks() + ms_to_ticks(CONNECT_TIMEOUT_MS);
+	loop {
+		poll_interface(iface, sockets)?;
+		conn.update_pending(sockets);
+
+		match sockets.get::<Socket>(conn.handle).state() {
+			State::Established => break,
+			State::Closed | State::TimeWait => return Err(NullexError::TcpConnectionFailed),
+			_ => {}
+		}
+
+		if let Some(InputEvent::Interrupt) = input.poll() {
+			return Ok(());
+		}
+
+		// a listener waits for as long as the user wants
+		if matches!(mode, NcMode::TcpConnect(..)) && ticks() >= deadline {
+			serial_println!("[NC] Connect timed out");
+			return Err(NullexError::TcpConnectionFailed);
+		}
+		pause(&input.state, POLL_INTERVAL_MS).await;
+	}
+
+	let endpoint = sockets.get::<Socket>(conn.handle).remote_endpoint();
+	if let Some(endpoint) = endpoint {
+		println!("nc: connected to {}", endpoint);
+	}
+
+	loop {
+		poll_interface(iface, sockets)?;
+
+		let data = conn.recv(sockets)?;
+		if !data.is_empty() {
+			print_received(&data);
+		}
+
+		let socket = sockets.get::<Socket>(conn.handle);
+		if !socket.may_recv() || !socket.is_active() {
+			println!("nc: connection closed by remote");
+			return Ok(());
+		}
+
+		match input.poll() {
+			Some(InputEvent::Line(line)) => {
+				let deadline = ticks() + ms_to_ticks(SEND_TIMEOUT_MS);
+				let mut pending = line.as_bytes();
+				loop {
+					let sent = conn.send(sockets, pending)?;
+					pending = &pending[sent..];
+					poll_interface(iface, sockets)?;
+					if pending.is_empty() || input.state.is_cancelled() {
+						break;
+					}
+					if ticks() >= deadline {
+						return Err(NullexError::TcpFailedToSend);
+					}
+					pause(&input.state, POLL_INTERVAL_MS).await;
+				}
+			}
+			Some(InputEvent::Interrupt) => return Ok(()),
+			None => pause(&input.state, POLL_INTERVAL_MS).await
+		}
+	}
+}
+
+/// UDP handler for the session's local port.
+fn udp_rx_handler(payload: &[u8]) {
+	// the handler may run from the RX path, so never spin on the queue here
+	if let Some(mut queue) = UDP_RX.try_lock() {
+		queue.push_back(payload.to_vec());
+	}
+}
+
+async fn run_udp(mode: NcMode, input: Input) -> Result<(), NullexError> {
+	let local_port = match mode {
+		NcMode::UdpListen(port) => {
+			println!("nc: listening on udp port {} (press Ctrl+C to stop)", port);
+			port
+		}
+		NcMode::UdpSend(ip, port) => {
+			println!(
+				"nc: sending to {}.{}.{}.{}:{} over udp (press Ctrl+C to stop)",
+				ip[0], ip[1], ip[2], ip[3], port
+			);
+			ephemeral_port()
+		}
+		_ => return Err(NullexError::InvalidArgument)
+	};
+
+	interrupts::without_interrupts(|| UDP_RX.lock().clear());
+	udp::register_handler(local_port, udp_rx_handler)?;
+
+	let result = udp_session(mode, local_port, input).await;
+
+	udp::unregister_handler(local_port, udp_rx_handler);
+	interrupts::without_interrupts(|| UDP_RX.lock().clear());
+
+	result
+}
+
+async fn udp_session(mode: NcMode, local_port: u16, mut input: Input) -> Result<(), NullexError> {
+	loop {
+		rx_poll();
+
+		let received: Vec<Vec<u8>> =
+			interrupts::without_interrupts(|| UDP_RX.lock().drain(..).collect());
+		for datagram in received.iter() {
+			print_received(datagram);
+		}
+
+		match input.poll() {
+			Some(InputEvent::Line(line)) => match mode {
+				NcMode::UdpSend(ip, port) => {
+					send_udp_line(&input.state, ip, local_port, port, line.as_bytes()).await?
+				}
+				// the UDP layer doesn't tell handlers who sent a datagram
+				_ => println!("nc: listen mode is receive only")
+			},
+			Some(InputEvent::Interrupt) => return Ok(()),
+			None => pause(&input.state, POLL_INTERVAL_MS).await
+		}
+	}
+}
+
+/// Sends a datagram, retrying while the next hop's MAC is being resolved.
+async fn send_udp_line(
+	state: &ProcessState,
+	ip: [u8; 4],
+	src_port: u16,
+	dst_port: u16,
+	data: &[u8]
+) -> Result<(), NullexError> {
+	for _ in 0..UDP_ARP_RETRIES {
+		match udp::send_udp(ip, src_port, dst_port, data) {
+			// give the ARP reply a moment to arrive
+			Err(NullexError::MacNotCached) => pause(state, UDP_ARP_WAIT_MS).await,
+			result => return result
+		}
+	}
+	Err(NullexError::ArpFailed)
+}
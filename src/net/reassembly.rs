@@ -0,0 +1,286 @@
+//!
+//! reassembly.rs
+//!
+//! IPv4 fragment reassembly for the kernel.
+//!
+
+use alloc::vec::Vec;
+
+use crate::{
+	lazy_static,
+	net::{ipv4::Ipv4Header, limits},
+	serial_println,
+	task::timer::ms_to_ticks,
+	utils::mutex::SpinMutex
+};
+
+/// Largest reassembled payload we accept. Datagrams growing past this are
+/// dropped.
+pub const MAX_REASSEMBLED_SIZE: usize = 16 * 1024;
+/// How long an incomplete datagram is kept, in milliseconds.
+pub const REASSEMBLY_TIMEOUT_MS: u64 = 3000;
+
+lazy_static! {
+	/// Static reference to the kernel's IPv4 reassembler.
+	pub static ref REASSEMBLER: SpinMutex<Reassembler> = SpinMutex::new(Reassembler::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The fields identifying which datagram a fragment belongs to (RFC 791).
+struct FragmentKey {
+	src: [u8; 4],
+	dst: [u8; 4],
+	identification: u16,
+	protocol: u8
+}
+
+impl FragmentKey {
+	fn from_header(header: &Ipv4Header) -> FragmentKey {
+		FragmentKey {
+			src: header.src,
+			dst: header.dst,
+			identification: header.identification,
+			protocol: header.protocol
+		}
+	}
+}
+
+/// A datagram that is still missing fragments.
+struct ReassemblyBuffer {
+	key: FragmentKey,
+	/// Payload bytes received so far, indexed by fragment offset.
+	data: Vec<u8>,
+	/// Sorted, non-overlapping `[start, end)` ranges of `data` that are filled.
+	received: Vec<(usize, usize)>,
+	/// Payload length, known once the last fragment (MF clear) arrives.
+	total_len: Option<usize>,
+	/// Tick count when the first fragment arrived.
+	started: u64
+}
+
+impl ReassemblyBuffer {
+	fn new(key: FragmentKey, now: u64) -> ReassemblyBuffer {
+		ReassemblyBuffer {
+			key,
+			data: Vec::new(),
+			received: Vec::new(),
+			total_len: None,
+			started: now
+		}
+	}
+
+	/// Records `[start, end)` as received, merging it with any ranges it
+	/// touches or overlaps.
+	fn mark_received(&mut self, start: usize, end: usize) {
+		let mut start = start;
+		let mut end = end;
+		self.received.retain(|&(s, e)| {
+			if s <= end && start <= e {
+				start = start.min(s);
+				end = end.max(e);
+				false
+			} else {
+				true
+			}
+		});
+		let pos = self.received.partition_point(|&(s, _)| s < start);
+		self.received.insert(pos, (start, end));
+	}
+
+	fn is_complete(&self) -> bool {
+		match self.total_len {
+			Some(total) => self.received.as_slice() == [(0, total)],
+			None => false
+		}
+	}
+}
+
+/// Collects IPv4 fragments until whole datagrams can be delivered.
+pub struct Reassembler {
+	buffers: Vec<ReassemblyBuffer>
+}
+
+impl Reassembler {
+	/// Creates an empty `Reassembler`.
+	pub fn new() -> Reassembler {
+		Reassembler {
+			buffers: Vec::new()
+		}
+	}
+
+	/// Returns how many datagrams are currently being reassembled.
+	pub fn pending(&self) -> usize {
+		self.buffers.len()
+	}
+
+	/// Drops every datagram that has been waiting longer than
+	/// `REASSEMBLY_TIMEOUT_MS`.
+	pub fn expire(&mut self, now: u64) {
+		let timeout = ms_to_ticks(REASSEMBLY_TIMEOUT_MS);
+		self.buffers.retain(|buf| {
+			let alive = now.wrapping_sub(buf.started) <= timeout;
+			if !alive {
+				serial_println!(
+					"[IPv4] Reassembly of id={} timed out",
+					buf.key.identification
+				);
+			}
+			alive
+		});
+	}
+
+	/// Adds a fragment. Fragments may arrive in any order.
+	///
+	/// Returns the reassembled payload once every fragment of the datagram
+	/// has been received.
+	pub fn push(&mut self, header: &Ipv4Header, payload: &[u8], now: u64) -> Option<Vec<u8>> {
+		self.expire(now);
+
+		let key = FragmentKey::from_header(header);
+		let start = header.fragment_offset();
+		let end = start + payload.len();
+
+		// only the last fragment may end off an 8-byte boundary. Dropped
+		// before the lookup, so it can't evict another datagram's buffer
+		if header.more_fragments() && !payload.len().is_multiple_of(8) {
+			serial_println!(
+				"[IPv4] Dropping datagram id={}: fragment {}..{} isn't a multiple of 8 bytes",
+				header.identification,
+				start,
+				end
+			);
+			self.buffers.retain(|buf| buf.key != key);
+			return None;
+		}
+
+		let idx = match self.buffers.iter().position(|buf| buf.key == key) {
+			Some(idx) => idx,
+			None => {
+				// evict the oldest datagrams to make room
+				let max = limits::get().max_reassembly_buffers.max(1);
+				while self.buffers.len() >= max {
+					let oldest = self
+						.buffers
+						.iter()
+						.enumerate()
+						.min_by_key(|(_, buf)| buf.started)
+						.map(|(i, _)| i)?;
+					self.buffers.remove(oldest);
+				}
+				self.buffers.push(ReassemblyBuffer::new(key, now));
+				self.buffers.len() - 1
+			}
+		};
+
+		let buf = &mut self.buffers[idx];
+
+		// overlapping fragments are dropped rather than trusted to agree
+		if buf.received.iter().any(|&(s, e)| s < end && start < e) {
+			serial_println!(
+				"[IPv4] Dropping datagram id={}: fragment {}..{} overlaps",
+				header.identification,
+				start,
+				end
+			);
+			self.buffers.remove(idx);
+			return None;
+		}
+
+		let too_long = buf.total_len.is_some_and(|total| end > total);
+		let bad_last = !header.more_fragments() && buf.received.last().is_some_and(|r| r.1 > end);
+		if end > MAX_REASSEMBLED_SIZE || too_long || bad_last {
+			serial_println!(
+				"[IPv4] Dropping datagram id={}: fragment {}..{} out of bounds",
+				header.identification,
+				start,
+				end
+			);
+			self.buffers.remove(idx);
+			return None;
+		}
+
+		if buf.data.len() < end {
+			buf.data.resize(end, 0);
+		}
+		buf.data[start..end].copy_from_slice(payload);
+		buf.mark_received(start, end);
+
+		if !header.more_fragments() {
+			buf.total_len = Some(end);
+		}
+
+		if buf.is_complete() {
+			return Some(self.buffers.remove(idx).data);
+		}
+
+		None
+	}
+}
+
+impl Default for Reassembler {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{
+		net::{ipv4::Ipv4Header, reassembly::*},
+		utils::ktest::TestError
+	};
+
+	fn fragment_header(offset: usize, more: bool) -> Ipv4Header {
+		Ipv4Header {
+			header_len: 20,
+			total_len: 0,
+			identification: 0x1234,
+			flags_fragment: ((offset / 8) as u16) | if more { 0x2000 } else { 0 },
+			protocol: 17,
+			src: [10, 0, 2, 2],
+			dst: [10, 0, 2, 15]
+		}
+	}
+
+	pub fn test_reassembly_out_of_order() -> Result<(), TestError> {
+		let mut r = Reassembler::new();
+		assert!(r.push(&fragment_header(16, false), &[3; 4], 0).is_none());
+		assert!(r.push(&fragment_header(0, true), &[1; 8], 1).is_none());
+		let datagram = r.push(&fragment_header(8, true), &[2; 8], 2).ok_or(TestError::Error)?;
+
+		assert_eq!(datagram.len(), 20);
+		assert_eq!(&datagram[0..8], &[1; 8]);
+		assert_eq!(&datagram[8..16], &[2; 8]);
+		assert_eq!(&datagram[16..], &[3; 4]);
+		assert_eq!(r.pending(), 0);
+		Ok(())
+	}
+	crate::create_test!(test_reassembly_out_of_order);
+
+	pub fn test_reassembly_size_cap_and_timeout() -> Result<(), TestError> {
+		let mut r = Reassembler::new();
+		assert!(r.push(&fragment_header(MAX_REASSEMBLED_SIZE, true), &[0; 8], 0).is_none());
+		assert_eq!(r.pending(), 0);
+
+		assert!(r.push(&fragment_header(0, true), &[0; 8], 0).is_none());
+		assert_eq!(r.pending(), 1);
+		r.expire(ms_to_ticks(REASSEMBLY_TIMEOUT_MS) + 1);
+		assert_eq!(r.pending(), 0);
+		Ok(())
+	}
+	crate::create_test!(test_reassembly_size_cap_and_timeout);
+
+	pub fn test_reassembly_rejects_bad_fragments() -> Result<(), TestError> {
+		let mut r = Reassembler::new();
+		// a non-final fragment whose length isn't a multiple of 8
+		assert!(r.push(&fragment_header(0, true), &[0; 12], 0).is_none());
+		assert_eq!(r.pending(), 0);
+
+		// a fragment overlapping one already received
+		assert!(r.push(&fragment_header(0, true), &[0; 16], 0).is_none());
+		assert!(r.push(&fragment_header(8, false), &[0; 16], 1).is_none());
+		assert_eq!(r.pending(), 0);
+		Ok(())
+	}
+	crate::create_test!(test_reassembly_rejects_bad_fragments);
+}
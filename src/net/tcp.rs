@@ -1,28 +1,85 @@
-use core::net::Ipv4Addr;
+use core::{cell::Cell, net::Ipv4Addr, sync::atomic::{AtomicU64, Ordering}};
 
-use alloc::{boxed::Box, vec::Vec};
-use smoltcp::{iface::{Interface, SocketHandle, SocketSet}, socket::tcp::{self, Socket, SocketBuffer}, time::Instant, wire::{IpAddress, IpEndpoint}};
+use alloc::vec::Vec;
+use smoltcp::{iface::{Interface, SocketHandle, SocketSet}, socket::tcp::{Socket, SocketBuffer, State}, time::Instant, wire::{IpAddress, IpEndpoint}};
+use x86_64::instructions::interrupts;
 
-use crate::{drivers::virtio::net::VirtioNet, error::NullexError, serial_println};
+use crate::{drivers::virtio::net::VirtioNet, error::NullexError, net::limits::{self, Resource}, serial_println, utils::mutex::SpinMutex};
 
 const TCP_RX_BUFFER_SIZE: usize = 8192;
 const TCP_TX_BUFFER_SIZE: usize = 8192;
 
+/// Source of `TcpConnection` IDs.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// What every live `TcpConnection` last looked like, for `netstat`.
+static CONNECTIONS: SpinMutex<Vec<TcpConnectionInfo>> = SpinMutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A snapshot of a `TcpConnection`, taken whenever its owner last used it.
+pub struct TcpConnectionInfo {
+    id: u64,
+    /// The local endpoint, once connected.
+    pub local: Option<IpEndpoint>,
+    /// The remote endpoint, once connected.
+    pub remote: Option<IpEndpoint>,
+    /// The port listened on, for listening sockets.
+    pub listen_port: Option<u16>,
+    /// The socket's TCP state.
+    pub state: State
+}
+
+/// Returns a snapshot of every open `TcpConnection`.
+pub fn connections() -> Vec<TcpConnectionInfo> {
+    interrupts::without_interrupts(|| CONNECTIONS.lock().clone())
+}
+
 pub struct TcpConnection {
-    pub handle: SocketHandle
+    pub handle: SocketHandle,
+    /// Whether this connection holds a `Resource::PendingConnection`.
+    pending: Cell<bool>,
+    /// Key of this connection in `CONNECTIONS`.
+    id: u64
 }
 
 impl TcpConnection  {
-    pub fn new(sockets: &mut SocketSet<'_>) -> Self {
-        let rx_buf_vec = Box::leak(Box::new(vec![0u8; TCP_RX_BUFFER_SIZE]));
-        let tx_buf_vec = Box::leak(Box::new(vec![0u8; TCP_TX_BUFFER_SIZE]));
-        let rx_buf = SocketBuffer::new(rx_buf_vec.as_mut_slice());
-        let tx_buf = SocketBuffer::new(tx_buf_vec.as_mut_slice());
+    /// Adds a new socket to `sockets`, counting it against the socket limit.
+    /// The socket's buffers are owned by the set and freed by `release`, or
+    /// along with the set. The limits are given back on drop either way.
+    pub fn new(sockets: &mut SocketSet<'_>) -> Result<Self, NullexError> {
+        limits::try_acquire(Resource::Socket)?;
+
+        let rx_buf = SocketBuffer::new(vec![0u8; TCP_RX_BUFFER_SIZE]);
+        let tx_buf = SocketBuffer::new(vec![0u8; TCP_TX_BUFFER_SIZE]);
         let socket = Socket::new(rx_buf, tx_buf);
         let handle = sockets.add(socket);
-        Self {
-            handle
-        }
+        let conn = Self {
+            handle,
+            pending: Cell::new(false),
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed)
+        };
+        conn.publish(sockets);
+        Ok(conn)
+    }
+
+    /// Records the socket's current state in `CONNECTIONS`.
+    fn publish(&self, sockets: &SocketSet<'_>) {
+        let socket = sockets.get::<Socket>(self.handle);
+        let info = TcpConnectionInfo {
+            id: self.id,
+            local: socket.local_endpoint(),
+            remote: socket.remote_endpoint(),
+            listen_port: (socket.state() == State::Listen).then(|| socket.listen_endpoint().port),
+            state: socket.state()
+        };
+
+        interrupts::without_interrupts(|| {
+            let mut connections = CONNECTIONS.lock();
+            match connections.iter_mut().find(|c| c.id == self.id) {
+                Some(entry) => *entry = info,
+                None => connections.push(info)
+            }
+        });
     }
 
     pub fn connect(&self, iface: &mut Interface, sockets: &mut SocketSet<'_>, dst_ip: [u8; 4], dst_port: u16, src_port: u16) -> Result<(), NullexError> {
@@ -31,12 +88,54 @@ impl TcpConnection  {
             dst_port
         );
 
+        self.begin_pending()?;
         let socket = sockets.get_mut::<Socket>(self.handle);
-        socket.connect(iface.context(), remote, src_port)
+        let result = socket.connect(iface.context(), remote, src_port)
             .map_err(|e| {
                 serial_println!("[TCP] Connect error: {:?}", e);
+                self.end_pending();
                 NullexError::TcpConnectionFailed
-            })
+            });
+        self.publish(sockets);
+        result
+    }
+
+    /// Puts the socket into the LISTEN state on `port`.
+    pub fn listen(&self, sockets: &mut SocketSet<'_>, port: u16) -> Result<(), NullexError> {
+        self.begin_pending()?;
+        let socket = sockets.get_mut::<Socket>(self.handle);
+        let result = socket.listen(port)
+            .map_err(|e| {
+                serial_println!("[TCP] Listen error: {:?}", e);
+                self.end_pending();
+                NullexError::TcpConnectionFailed
+            });
+        self.publish(sockets);
+        result
+    }
+
+    /// Stops counting this connection as pending once the handshake has
+    /// finished, either way.
+    pub fn update_pending(&self, sockets: &mut SocketSet<'_>) {
+        match sockets.get::<Socket>(self.handle).state() {
+            State::Listen | State::SynSent | State::SynReceived => {}
+            _ => self.end_pending()
+        }
+        self.publish(sockets);
+    }
+
+    fn begin_pending(&self) -> Result<(), NullexError> {
+        if !self.pending.get() {
+            limits::try_acquire(Resource::PendingConnection)?;
+            self.pending.set(true);
+        }
+        Ok(())
+    }
+
+    fn end_pending(&self) {
+        if self.pending.replace(false) {
+            limits::release(Resource::PendingConnection);
+        }
     }
 
     pub fn is_connected(&self, sockets: &mut SocketSet<'_>) -> bool {
@@ -54,6 +153,7 @@ impl TcpConnection  {
     }
 
     pub fn recv(&self, sockets: &mut SocketSet<'_>) -> Result<Vec<u8>, NullexError> {
+        self.publish(sockets);
         let socket = sockets.get_mut::<Socket>(self.handle);
         if !socket.can_recv() {
             return Ok(vec![]);
@@ -70,9 +170,58 @@ impl TcpConnection  {
 
     pub fn close(&self, sockets: &mut SocketSet<'_>) {
         sockets.get_mut::<Socket>(self.handle).close();
+        self.publish(sockets);
+    }
+
+    /// Removes the socket from `sockets`, freeing its buffers, and gives back
+    /// everything counted against the limits.
+    pub fn release(self, sockets: &mut SocketSet<'_>) {
+        sockets.remove(self.handle);
     }
 
     pub fn poll(iface: &mut Interface, device: &mut VirtioNet, sockets: &mut SocketSet<'_>, timestamp: Instant) {
         iface.poll(timestamp, device, sockets);
     }
-}
\ No newline at end of file
+}
+
+impl Drop for TcpConnection {
+    /// Gives back everything counted against the limits, so a connection
+    /// that's never released, e.g. on an early return, doesn't leak them.
+    fn drop(&mut self) {
+        self.end_pending();
+        limits::release(Resource::Socket);
+        interrupts::without_interrupts(|| CONNECTIONS.lock().retain(|c| c.id != self.id));
+    }
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+    use smoltcp::{iface::SocketSet, socket::tcp::State};
+
+    use crate::{net::tcp::*, utils::ktest::TestError};
+
+    pub fn test_tcp_connections_are_listed_until_released() -> Result<(), TestError> {
+        let mut sockets = SocketSet::new(alloc::vec![]);
+        let conn = TcpConnection::new(&mut sockets).map_err(|_| TestError::Error)?;
+        let id = conn.id;
+        let find = || connections().into_iter().find(|c| c.id == id);
+
+        assert_eq!(find().map(|c| c.state), Some(State::Closed));
+
+        conn.listen(&mut sockets, 4242).map_err(|_| TestError::Error)?;
+        let info = find().ok_or(TestError::Error)?;
+        assert_eq!((info.state, info.listen_port), (State::Listen, Some(4242)));
+        assert_eq!(info.remote, None);
+
+        conn.release(&mut sockets);
+        assert!(find().is_none());
+
+        // one that's only dropped is unlisted too
+        let conn = TcpConnection::new(&mut sockets).map_err(|_| TestError::Error)?;
+        let id = conn.id;
+        drop(conn);
+        assert!(connections().iter().all(|c| c.id != id));
+        Ok(())
+    }
+    crate::create_test!(test_tcp_connections_are_listed_until_released);
+}
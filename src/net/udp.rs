@@ -7,64 +7,105 @@
 use alloc::vec::Vec;
 
 use crate::{
-	error::NullexError, lazy_static, serial_println, utils::{mutex::SpinMutex, net::calculate_checksum}
+	error::NullexError,
+	lazy_static,
+	net::{
+		checksum::{self, checksum},
+		limits::{self, Resource}
+	},
+	serial_println,
+	utils::mutex::SpinMutex
 };
 
 lazy_static! {
 	static ref UDP_HANDLERS: SpinMutex<Vec<(u16, fn(&[u8]))>> = SpinMutex::new(Vec::new());
 }
 
-/// Process incoming UDP packets.
-pub fn process_udp(pkt: *const u8, len: usize, ip_offset: usize, _src_ip: &[u8; 4]) {
-	let udp_offset = 14 + ip_offset;
-
-	if len < udp_offset + 8 {
+/// Process incoming UDP datagrams. `udp` is the IPv4 payload, sent from
+/// `src_ip` to `dst_ip`.
+pub fn process_udp(udp: &[u8], src_ip: &[u8; 4], dst_ip: &[u8; 4]) {
+	if udp.len() < 8 {
 		serial_println!("[UDP] Packet too short");
 		return;
 	}
 
-	unsafe {
-		let udp_start = pkt.add(udp_offset);
-
-		let src_port = u16::from_be_bytes([*udp_start.add(0), *udp_start.add(1)]);
-		let dst_port = u16::from_be_bytes([*udp_start.add(2), *udp_start.add(3)]);
-		let udp_length = u16::from_be_bytes([*udp_start.add(4), *udp_start.add(5)]);
-
-		serial_println!(
-			"[UDP] src_port={}, dst_port={}, len={}",
-			src_port,
-			dst_port,
-			udp_length
-		);
-
-		let payload_len = (udp_length as usize).saturating_sub(8);
-		if payload_len > 0 && len >= udp_offset + 8 + payload_len {
-			let payload_ptr = udp_start.add(8);
-			let payload = core::slice::from_raw_parts(payload_ptr, payload_len);
-
-			// Find handler for this port
-			let handlers = UDP_HANDLERS.lock();
-			let handler_opt = handlers
-				.iter()
-				.find(|(port, _)| *port == dst_port)
-				.or_else(|| handlers.iter().find(|(port, _)| *port == src_port));
-			if let Some((_, handler)) = handler_opt {
-				handler(payload);
-			} else {
-				serial_println!("[UDP] No handler for port {}", dst_port);
-			}
+	let src_port = u16::from_be_bytes([udp[0], udp[1]]);
+	let dst_port = u16::from_be_bytes([udp[2], udp[3]]);
+	let udp_length = u16::from_be_bytes([udp[4], udp[5]]);
+
+	serial_println!(
+		"[UDP] src_port={}, dst_port={}, len={}",
+		src_port,
+		dst_port,
+		udp_length
+	);
+
+	let segment = match udp.get(..udp_length as usize) {
+		Some(segment) if segment.len() >= 8 => segment,
+		_ => {
+			serial_println!("[UDP] Bad length {}, dropping", udp_length);
+			return;
+		}
+	};
+	if !checksum::verify_udp(src_ip, dst_ip, segment) {
+		serial_println!("[UDP] Bad checksum, dropping");
+		return;
+	}
+
+	let payload = &segment[8..];
+	if !payload.is_empty() {
+
+		// Find handler for this port
+		let handlers = UDP_HANDLERS.lock();
+		let handler_opt = handlers
+			.iter()
+			.find(|(port, _)| *port == dst_port)
+			.or_else(|| handlers.iter().find(|(port, _)| *port == src_port));
+		if let Some((_, handler)) = handler_opt {
+			handler(payload);
+		} else {
+			serial_println!("[UDP] No handler for port {}", dst_port);
 		}
 	}
 }
 
-/// Registers a UDP handler for incoming packets.
-pub fn register_handler(port: u16, handler: fn(&[u8])) {
+/// Registers a UDP handler for incoming packets. Every handler counts as an
+/// open socket against `limits::NetLimits::max_sockets`.
+pub fn register_handler(port: u16, handler: fn(&[u8])) -> Result<(), NullexError> {
+	limits::try_acquire(Resource::Socket)?;
 	let mut handlers = UDP_HANDLERS.lock();
 	handlers.push((port, handler));
 	serial_println!("[UDP] Registered handler for port {}", port);
+	Ok(())
+}
+
+/// Removes `handler` from `port`, where `register_handler` put it. Other
+/// handlers on the same port stay.
+pub fn unregister_handler(port: u16, handler: fn(&[u8])) {
+	let mut handlers = UDP_HANDLERS.lock();
+	let position = handlers
+		.iter()
+		.position(|&(p, h)| p == port && core::ptr::fn_addr_eq(h, handler));
+	if let Some(position) = position {
+		handlers.remove(position);
+		limits::release(Resource::Socket);
+		serial_println!("[UDP] Unregistered handler for port {}", port);
+	}
+}
+
+/// Returns the ports with a UDP handler registered, in ascending order.
+pub fn bound_ports() -> Vec<u16> {
+	let mut ports: Vec<u16> = UDP_HANDLERS.lock().iter().map(|(port, _)| *port).collect();
+	ports.sort_unstable();
+	ports.dedup();
+	ports
+}
+
+/// Returns how many UDP handlers are registered.
+pub fn handler_count() -> usize {
+	UDP_HANDLERS.lock().len()
 }
 
-/// Sends a UDP packet to the destination IP
 /// Sends a UDP packet to the destination IP
 pub fn send_udp(
     dst_ip: [u8; 4],
@@ -78,7 +119,7 @@ pub fn send_udp(
             let next_hop = if super::is_local_ip(dst_ip) {
                 dst_ip
             } else {
-                super::GATEWAY_IP
+                super::gateway_ip()
             };
 
             serial_println!(
@@ -90,6 +131,24 @@ pub fn send_udp(
         }
     };
 
+    send_datagram(dst_mac, super::our_ip(), dst_ip, src_port, dst_port, payload)
+}
+
+/// Broadcasts a UDP packet to 255.255.255.255 from 0.0.0.0, for talking to
+/// servers before we have an address (DHCP).
+pub fn send_udp_broadcast(src_port: u16, dst_port: u16, payload: &[u8]) -> Result<(), NullexError> {
+    send_datagram([0xFF; 6], [0; 4], [255; 4], src_port, dst_port, payload)
+}
+
+/// Builds the Ethernet, IPv4 and UDP headers around `payload` and sends it.
+fn send_datagram(
+    dst_mac: [u8; 6],
+    src_ip: [u8; 4],
+    dst_ip: [u8; 4],
+    src_port: u16,
+    dst_port: u16,
+    payload: &[u8]
+) -> Result<(), NullexError> {
     let our_mac = super::get_our_mac().ok_or(NullexError::MissingMacAddress)?;
 
     let total_len = 14 + 20 + 8 + payload.len();
@@ -110,11 +169,11 @@ pub fn send_udp(
     packet[22] = 64;                                             // TTL
     packet[23] = super::ipv4::IP_PROTO_UDP;                      // protocol = UDP
     // checksum at [24..26] — filled in below after calculation
-    packet[26..30].copy_from_slice(&super::OUR_IP);              // src IP
+    packet[26..30].copy_from_slice(&src_ip);                     // src IP
     packet[30..34].copy_from_slice(&dst_ip);                     // dst IP
 
     // IPv4 header checksum (covers bytes 14..34)
-    let ip_checksum = calculate_checksum(&packet[14..34]);
+    let ip_checksum = checksum(&packet[14..34]);
     packet[24..26].copy_from_slice(&ip_checksum.to_be_bytes());
 
     // --- UDP header ---
@@ -128,42 +187,8 @@ pub fn send_udp(
     packet[42..].copy_from_slice(payload);
 
     // --- UDP checksum ---
-    // The checksum covers a pseudo-header + the UDP header + payload.
-    // Pseudo-header layout (12 bytes):
-    //   [0..4]   source IP
-    //   [4..8]   destination IP
-    //   [8]      zero
-    //   [9]      protocol (17 = UDP)
-    //   [10..12] UDP length (header + payload)
-    //
-    // Then the actual UDP segment follows:
-    //   [12..14] src port
-    //   [14..16] dst port
-    //   [16..18] UDP length
-    //   [18..20] checksum (zero during calculation)
-    //   [20..]   payload
-    let udp_checksum = {
-        let pseudo_len = 12 + 8 + payload.len();
-        let mut pseudo = alloc::vec![0u8; pseudo_len];
-
-        // pseudo-header
-        pseudo[0..4].copy_from_slice(&super::OUR_IP);           // src IP
-        pseudo[4..8].copy_from_slice(&dst_ip);                  // dst IP
-        pseudo[8] = 0;                                          // zero byte
-        pseudo[9] = super::ipv4::IP_PROTO_UDP;                  // protocol
-        pseudo[10..12].copy_from_slice(&udp_len.to_be_bytes()); // UDP length
-
-        // UDP header (checksum field left as zero)
-        pseudo[12..14].copy_from_slice(&src_port.to_be_bytes());
-        pseudo[14..16].copy_from_slice(&dst_port.to_be_bytes());
-        pseudo[16..18].copy_from_slice(&udp_len.to_be_bytes());
-        pseudo[18..20].copy_from_slice(&0u16.to_be_bytes());    // checksum = 0
-
-        // payload
-        pseudo[20..].copy_from_slice(payload);
-
-        calculate_checksum(&pseudo)
-    };
+    // covers the IPv4 pseudo-header, the UDP header and the payload
+    let udp_checksum = checksum::udp_checksum(&src_ip, &dst_ip, &packet[34..]);
     packet[40..42].copy_from_slice(&udp_checksum.to_be_bytes());
 
     super::send_packet(&packet)?;
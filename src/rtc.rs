@@ -6,12 +6,13 @@
 use alloc::string::String;
 use core::{
 	fmt,
-	sync::atomic::{AtomicU64, Ordering}
+	sync::atomic::{AtomicU8, AtomicU64, Ordering}
 };
 
 use x86_64::instructions::interrupts;
 
 use crate::{
+	acpi,
 	apic::{PIC1_DATA, PIC2_DATA, send_eoi},
 	common::ports::{inb, io_wait, outb},
 	serial_println
@@ -52,12 +53,20 @@ pub const REG_D: u8 = 0x0D;
 const REG_A_UIP: u8 = 0x80;
 const REG_B_PIE: u8 = 0x40;
 const REG_B_DM: u8 = 0x04;
+const REG_B_24H: u8 = 0x02;
+
+/// Set on the hours register in 12-hour mode for PM times.
+const HOUR_PM: u8 = 0x80;
+
+/// CMOS register holding the century, taken from the FADT. 0 if there is
+/// none, in which case years are assumed to be in the 2000s.
+static CENTURY_REGISTER: AtomicU8 = AtomicU8::new(0);
 
 /// The number of times the RTC interrupt has gone off
 
 pub static RTC_TICKS: AtomicU64 = AtomicU64::new(0);
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 /// A structure representing the time which is returned by the RTC
 pub struct RtcTime {
 	/// The number of seconds
@@ -102,6 +111,36 @@ impl fmt::Display for RtcTime {
 	}
 }
 
+impl RtcTime {
+	/// Day of the week, 0 being Sunday.
+	pub fn weekday(&self) -> u8 {
+		// Sakamoto's method
+		const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+		let month = self.month.clamp(1, 12) as usize;
+		let year = if month < 3 { self.year - 1 } else { self.year };
+		((year + year / 4 - year / 100 + year / 400 + OFFSETS[month - 1] + self.day as u16) % 7)
+			as u8
+	}
+
+	/// Short English name of the day of the week.
+	pub fn weekday_name(&self) -> &'static str {
+		["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"][self.weekday() as usize]
+	}
+}
+
+/// The time registers as read from the CMOS, before decoding.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct RawRtcTime {
+	sec: u8,
+	min: u8,
+	hour: u8,
+	day: u8,
+	month: u8,
+	year: u8,
+	/// Only read if the firmware reports a century register.
+	century: Option<u8>
+}
+
 /// Get RTC tick count
 pub fn rtc_ticks() -> u64 {
 	RTC_TICKS.load(Ordering::Relaxed)
@@ -156,89 +195,88 @@ fn unmask_pic_irq8() {
 	}
 }
 
-/// Returns the (secs, mins, hours, days, months, years) in the RTC clock raw.
-fn read_rtc_raw() -> (u8, u8, u8, u8, u8, u8) {
+/// Reads the CMOS time registers, retrying until two reads in a row agree
+/// so an update can't tear the result.
+fn read_rtc_raw() -> RawRtcTime {
+	let century_register = CENTURY_REGISTER.load(Ordering::Relaxed);
+	let read = || RawRtcTime {
+		sec: cmos_read(REG_SECONDS),
+		min: cmos_read(REG_MINUTES),
+		hour: cmos_read(REG_HOURS),
+		day: cmos_read(REG_DAY),
+		month: cmos_read(REG_MONTH),
+		year: cmos_read(REG_YEAR),
+		century: (century_register != 0).then(|| cmos_read(century_register))
+	};
+
 	loop {
 		// wait for any update in progress to finish
 		while (cmos_read(REG_A) & REG_A_UIP) != 0 {}
-
-		let s1 = cmos_read(REG_SECONDS);
-		let m1 = cmos_read(REG_MINUTES);
-		let h1 = cmos_read(REG_HOURS);
-		let d1 = cmos_read(REG_DAY);
-		let mo1 = cmos_read(REG_MONTH);
-		let y1 = cmos_read(REG_YEAR);
+		let first = read();
 
 		// ensure no update started during the second read
 		while (cmos_read(REG_A) & REG_A_UIP) != 0 {}
+		let second = read();
 
-		let s2 = cmos_read(REG_SECONDS);
-		let m2 = cmos_read(REG_MINUTES);
-		let h2 = cmos_read(REG_HOURS);
-		let d2 = cmos_read(REG_DAY);
-		let mo2 = cmos_read(REG_MONTH);
-		let y2 = cmos_read(REG_YEAR);
-
-		if s1 == s2 && m1 == m2 && h1 == h2 && d1 == d2 && mo1 == mo2 && y1 == y2 {
-			return (s1, m1, h1, d1, mo1, y1);
+		if first == second {
+			return first;
 		}
 		// else try again
 	}
 }
 
-/// Read RTC values to calculate the time/calendar.
-pub fn read_rtc_time() -> RtcTime {
-	let reg_b = cmos_read(REG_B);
-	let bin_mode = (reg_b & REG_B_DM) != 0; // binary_mode. needs bcd -> bin
-	let is_24hr = (reg_b & 0x02) != 0;
-
-	let (s, m, h_raw, d, mo, y) = read_rtc_raw();
-
-	let hour = if is_24hr {
-		h_raw & 0x7F
-	} else {
-		// 12hr. high bit is PM (like AM and PM) flag
-		let pm = (h_raw & 0x80) != 0;
-		let mut h12 = h_raw & 0x7F;
-		if h12 == 12 {
-			// 12AM => 0 || 12 PM => 12
-			if !pm {
-				h12 = 0;
-			}
-		} else if pm {
-			h12 = h12.wrapping_add(12);
-		}
-
-		h12
+/// Decodes raw registers according to the data mode and hour format in
+/// register B.
+fn decode_rtc_time(raw: RawRtcTime, reg_b: u8) -> RtcTime {
+	let bin_mode = (reg_b & REG_B_DM) != 0;
+	let is_24hr = (reg_b & REG_B_24H) != 0;
+	let convert = |v: u8| if bin_mode { v } else { bcd_to_bin(v) };
+
+	// the PM flag sits on top of the (possibly BCD) hour, so take it off
+	// before converting
+	let pm = !is_24hr && (raw.hour & HOUR_PM) != 0;
+	let hour = convert(raw.hour & !HOUR_PM);
+	let hour = match (is_24hr, pm, hour) {
+		(true, _, h) => h,
+		// 12AM => 0 || 12PM => 12
+		(false, false, 12) => 0,
+		(false, true, 12) => 12,
+		(false, true, h) => h + 12,
+		(false, false, h) => h
 	};
 
-	let sec = if bin_mode { s } else { bcd_to_bin(s) };
-	let min = if bin_mode { m } else { bcd_to_bin(m) };
-	let hour = if bin_mode { hour } else { bcd_to_bin(hour) };
-	let day = if bin_mode { d } else { bcd_to_bin(d) };
-	let month = if bin_mode { mo } else { bcd_to_bin(mo) };
-
-	let year_full = if bin_mode {
-		2000u16 + y as u16
-	} else {
-		2000u16 + bcd_to_bin(y) as u16
-	};
+	let century = raw.century.map_or(20, convert) as u16;
 
 	RtcTime {
-		sec,
-		min,
+		sec: convert(raw.sec),
+		min: convert(raw.min),
 		hour,
-		day,
-		month,
-		year: year_full
+		day: convert(raw.day),
+		month: convert(raw.month),
+		year: century * 100 + convert(raw.year) as u16
 	}
 }
 
+/// Returns the current wall-clock time from the RTC.
+pub fn now() -> RtcTime {
+	let reg_b = cmos_read(REG_B);
+	decode_rtc_time(read_rtc_raw(), reg_b)
+}
+
+/// Read RTC values to calculate the time/calendar. Same as `now`.
+pub fn read_rtc_time() -> RtcTime {
+	now()
+}
+
 /// Initializes the Real Time Clock (RTC) 
 pub fn init_rtc() {
 	interrupts::disable();
 	unmask_pic_irq8();
 
+	if let Some(register) = acpi::century_register() {
+		CENTURY_REGISTER.store(register, Ordering::Relaxed);
+	}
+
 	// set rate
 	let prev_a = cmos_read(REG_A);
 	cmos_write(REG_A, (prev_a & 0xF0) | 0x06); // rs = 6
@@ -291,6 +329,42 @@ pub mod tests {
 	}
 	crate::create_test!(test_bcd_to_bin_examples);
 
+	pub fn test_rtc_decode_modes() -> Result<(), TestError> {
+		// 11:05:09 PM on 17/10/2026 in BCD, 12-hour mode, with a century
+		let raw = RawRtcTime {
+			sec: 0x09,
+			min: 0x05,
+			hour: 0x80 | 0x11,
+			day: 0x17,
+			month: 0x10,
+			year: 0x26,
+			century: Some(0x20)
+		};
+		let time = decode_rtc_time(raw, 0);
+		assert_eq!((time.hour, time.min, time.sec), (23, 5, 9));
+		assert_eq!((time.day, time.month, time.year), (17, 10, 2026));
+
+		// 12AM is midnight
+		let time = decode_rtc_time(RawRtcTime { hour: 0x12, ..raw }, 0);
+		assert_eq!(time.hour, 0);
+
+		// the same time in binary, 24-hour mode, without a century register
+		let raw = RawRtcTime {
+			sec: 9,
+			min: 5,
+			hour: 23,
+			day: 17,
+			month: 10,
+			year: 26,
+			century: None
+		};
+		let time = decode_rtc_time(raw, 0x04 | 0x02);
+		assert_eq!((time.hour, time.year), (23, 2026));
+		assert_eq!(time.weekday_name(), "Sat");
+		Ok(())
+	}
+	crate::create_test!(test_rtc_decode_modes);
+
 	pub fn test_rtc_ticks_atomic_accessors() -> Result<(), TestError> {
 		RTC_TICKS.store(0xDEADBEEF, Ordering::Relaxed);
 		assert_eq!(rtc_ticks(), 0xDEADBEEF);
@@ -4,7 +4,7 @@
 //!
 
 use alloc::string::String;
-use core::{arch::asm, fmt, hint::spin_loop, task::Poll};
+use core::{fmt, hint::spin_loop, task::Poll};
 
 use crossbeam_queue::ArrayQueue;
 use futures::{Stream, StreamExt, task::AtomicWaker};
@@ -13,13 +13,16 @@ use x86_64::instructions::interrupts;
 use crate::{
 	bitflags,
 	common::ports::{inb, outb},
+	drivers::keyboard::scancode::CWD,
+	ioapic::IOAPIC,
 	lazy_static,
 	println,
 	serial_print,
 	serial_println,
 	serial_raw_print,
-	task::yield_now,
-	utils::{serial_kfunc::run_serial_command, mutex::SpinMutex, oncecell::spin::OnceCell}
+	task::{keyboard::run_command, yield_now},
+	utils::{mutex::SpinMutex, oncecell::spin::OnceCell},
+	vga_buffer::with_serial_mirror
 };
 
 #[derive(Debug)]
@@ -170,6 +173,11 @@ enum SerialPortError {
 	SerialPortError
 }
 
+/// Capacity of the serial input queue.
+const SERIAL_QUEUE_CAPACITY: usize = 1000;
+/// IRQ line of COM1 on the IOAPIC.
+const COM1_IRQ: u8 = 4;
+
 static SERIAL_SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static SERIAL_WAKER: AtomicWaker = AtomicWaker::new();
 
@@ -177,32 +185,24 @@ pub(crate) fn add_byte(byte: u8) {
 	if let Ok(queue) = SERIAL_SCANCODE_QUEUE.try_get() {
 		if queue.push(byte).is_err() {
 			println!(
-				"WARNING: scancode queue full; dropping keyboard input {}",
+				"WARNING: serial queue full; dropping serial input {}",
 				byte
 			);
 		} else {
 			SERIAL_WAKER.wake();
 		}
 	} else {
-		println!("WARNING: scancode queue uninitialized");
+		println!("WARNING: serial queue uninitialized");
 	}
 }
-// this function is only here because eventually is something like kernel config, like Linux KConfig
-// someone may want serial input. so we keep here for now.
-#[allow(dead_code)]
+
+/// Stream of bytes received on COM1. `init_serial_input` must have run first.
 struct SerialScancodeStream {
 	_private: ()
 }
 
-// this function is only here because eventually is something like kernel config, like Linux KConfig
-// someone may want serial input. so we keep here for now.
-#[allow(dead_code)]
 impl SerialScancodeStream {
 	fn new() -> Self {
-		SERIAL_SCANCODE_QUEUE
-			.try_init_once(|| ArrayQueue::new(1000))
-			.expect("SerialScancodeStream::new should only be called once.");
-
 		Self {
 			_private: ()
 		}
@@ -251,66 +251,80 @@ lazy_static! {
 	};
 }
 
-// this function is only here because eventually is something like kernel config, like Linux KConfig
-// someone may want serial input. so we keep here for now.
-async fn _serial_consumer_loop() -> i32 {
+fn serial_prompt() {
+	serial_print!("serial@nullex: {} $ ", *CWD.lock());
+}
+
+/// The serial console. Reads lines from COM1 and runs them through the same
+/// command dispatcher as the keyboard shell, mirroring their output to the
+/// serial port, so the kernel can be driven headless (`-serial stdio`).
+pub async fn serial_console() -> i32 {
 	let mut bytes = SerialScancodeStream::new();
 	let mut line = String::new();
-	// print serial terminal like ui thing
-	serial_print!("serial@nullex: $ ");
+	// inside an ANSI escape sequence, e.g. an arrow key
+	let mut in_escape = false;
+	let mut last_was_cr = false;
+	serial_prompt();
 
 	while let Some(byte) = bytes.next().await {
-		if byte == 0x0A || byte == 0x0D {
-			if !line.is_empty() {
-				let cmd_line = line.clone();
-				line.clear();
-				yield_now().await;
-				serial_println!();
-				run_serial_command(&cmd_line);
-				serial_print!("serial@nullex: $ ");
-			} else {
-				serial_raw_print!(b"\r\n");
-				serial_print!("serial@nullex: $ ");
-				line.clear();
-			}
+		let after_cr = core::mem::replace(&mut last_was_cr, byte == b'\r');
 
+		if in_escape {
+			// sequences end on a final byte in 0x40..=0x7E; '[' only opens them
+			in_escape = byte == b'[' || !(0x40..=0x7E).contains(&byte);
 			continue;
 		}
 
-		// 7F is the main cause here, 0x08 is js there.
-		if byte == 0x08 || byte == 0x7F {
-			if line.is_empty() {
-				serial_raw_print!(b"\x1B[1C"); // move it back so it cannot delete anything.
+		match byte {
+			// terminals send "\r\n" or either on its own for enter
+			b'\n' if after_cr => {}
+			b'\r' | b'\n' => {
+				serial_println!();
+				if !line.trim().is_empty() {
+					let command_line = core::mem::take(&mut line);
+					// yield so temporary locks are released, like the keyboard shell
+					yield_now().await;
+					with_serial_mirror(|| run_command(&command_line));
+				}
+				line.clear();
+				serial_prompt();
 			}
-
-			line.pop();
-			serial_raw_print!(b"\x08 \x08");
-
-			continue;
+			// ctrl-c
+			0x03 => {
+				serial_println!("^C");
+				line.clear();
+				serial_prompt();
+			}
+			0x08 | 0x7F => {
+				if line.pop().is_some() {
+					// back over the character, blank it and back again
+					serial_raw_print!(b"\x08 \x08");
+				}
+			}
+			0x1B => in_escape = true,
+			byte if byte == b' ' || byte.is_ascii_graphic() => {
+				line.push(byte as char);
+				serial_print!("{}", byte as char);
+			}
+			_ => {}
 		}
-
-		let c = byte as char;
-		line.push(c);
-		serial_print!("{}", c);
 	}
 
 	0
 }
 
-// this function is only here because eventually is something like kernel config, like Linux KConfig
-// someone may want serial input. so we keep here for now.
-fn _init_serial_input() {
-	use x86_64::instructions::port::Port;
+/// Starts taking input on COM1. `SerialPort::init` already enables the UART's
+/// "data received" interrupt, so this sets up the input queue and unmasks
+/// COM1's IRQ on the IOAPIC. Bytes then arrive through the serial interrupt
+/// handler and are read by `serial_console`.
+pub fn init_serial_input() {
+	let _ = SERIAL_SCANCODE_QUEUE.try_init_once(|| ArrayQueue::new(SERIAL_QUEUE_CAPACITY));
 
 	interrupts::without_interrupts(|| {
-		let mut port = Port::<u8>::new(0x3F9);
-		let cur = unsafe { port.read() };
-		let new = cur | 0x01;
-		unsafe { port.write(new) };
+		// make sure the port is set up before its interrupt can fire
+		let _ = SERIAL1.lock();
+		unsafe { IOAPIC.lock().enable_irq(COM1_IRQ) };
 	});
-
-	// unmask IRQ4
-	unsafe { asm!("in al, 0x21", "and al, 0xEF", "out 0x21, al",) };
 }
 
 #[doc(hidden)]
@@ -0,0 +1,169 @@
+//!
+//! smp.rs
+//!
+//! Application processor (AP) bring-up. Every other CPU listed in the MADT
+//! is started with INIT-SIPI-SIPI, runs `ap_trampoline.asm` up into long
+//! mode on the kernel's page tables, reports its APIC ID and then parks.
+//! Nothing is scheduled on them yet.
+//!
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use x86_64::{
+	instructions::{hlt, interrupts},
+	registers::control::Cr3
+};
+
+use crate::{
+	acpi,
+	apic::{self, ICR_INIT, ICR_LEVEL_ASSERT, ICR_STARTUP},
+	error::NullexError,
+	memory::allocate_kernel_stack,
+	println,
+	serial_println,
+	tsc
+};
+
+/// Physical address the trampoline is copied to. A SIPI starts the CPU at
+/// a page below 1 MiB, and the frame allocator never hands this one out.
+/// Must match `AP_TRAMPOLINE_BASE` in `ap_trampoline.asm`.
+pub const AP_TRAMPOLINE_BASE: u64 = 0x8000;
+
+/// Pages of each AP's kernel stack.
+const AP_STACK_PAGES: usize = 8;
+/// How long an AP gets to report in after its SIPIs.
+const AP_STARTUP_TIMEOUT_MS: u64 = 100;
+/// Most spins a wait is allowed, in case the clock isn't moving.
+const WAIT_SPIN_LIMIT: usize = 100_000_000;
+
+unsafe extern "C" {
+	static ap_trampoline_start: u8;
+	static ap_trampoline_end: u8;
+	static ap_trampoline_cr3: u8;
+	static ap_trampoline_stack: u8;
+	static ap_trampoline_entry: u8;
+}
+
+/// CPUs that have reached `ap_main`, plus the BSP.
+static CPUS_ONLINE: AtomicUsize = AtomicUsize::new(1);
+/// Set by the AP being started once it no longer needs the trampoline.
+static AP_CALLED_IN: AtomicBool = AtomicBool::new(false);
+
+/// Returns how many CPUs are running kernel code.
+pub fn cpus_online() -> usize {
+	CPUS_ONLINE.load(Ordering::SeqCst)
+}
+
+/// Busy-waits for `ms` milliseconds, or until `done`. Timed with the TSC,
+/// so it works with interrupts disabled once `tsc::calibrate` has run.
+fn wait_ms(ms: u64, done: impl Fn() -> bool) -> bool {
+	let deadline = tsc::now_ns() + ms * 1_000_000;
+	for _ in 0..WAIT_SPIN_LIMIT {
+		if done() {
+			return true;
+		}
+		if tsc::now_ns() >= deadline {
+			break;
+		}
+		core::hint::spin_loop();
+	}
+	done()
+}
+
+/// Address of `symbol` in the copy of the trampoline.
+fn trampoline_address(symbol: *const u8) -> *mut u64 {
+	let start = &raw const ap_trampoline_start as u64;
+	(AP_TRAMPOLINE_BASE + (symbol as u64 - start)) as *mut u64
+}
+
+/// Copies the trampoline to `AP_TRAMPOLINE_BASE` and points it at the
+/// current page tables.
+fn install_trampoline() -> Result<(), NullexError> {
+	let cr3 = Cr3::read().0.start_address().as_u64();
+	// the trampoline loads CR3 before it's in long mode
+	if cr3 > u32::MAX as u64 {
+		return Err(NullexError::Unsupported);
+	}
+
+	unsafe {
+		let start = &raw const ap_trampoline_start;
+		let len = &raw const ap_trampoline_end as usize - start as usize;
+		// low memory is identity mapped
+		core::ptr::copy_nonoverlapping(start, AP_TRAMPOLINE_BASE as *mut u8, len);
+
+		trampoline_address(&raw const ap_trampoline_cr3).write_volatile(cr3);
+		let entry = ap_main as *const () as u64;
+		trampoline_address(&raw const ap_trampoline_entry).write_volatile(entry);
+	}
+	Ok(())
+}
+
+/// Starts the AP with local APIC ID `apic_id` and waits for it to report in.
+fn start_ap(apic_id: u8) -> Result<(), NullexError> {
+	// the allocator's locks would leave interrupts disabled
+	let stack = interrupts::without_interrupts(|| allocate_kernel_stack(AP_STACK_PAGES))?;
+	let stack_top = stack.top().as_u64();
+	unsafe { trampoline_address(&raw const ap_trampoline_stack).write_volatile(stack_top) };
+	AP_CALLED_IN.store(false, Ordering::SeqCst);
+
+	let called_in = || AP_CALLED_IN.load(Ordering::SeqCst);
+	let vector = (AP_TRAMPOLINE_BASE >> 12) as u32;
+	unsafe { apic::send_ipi(apic_id, ICR_INIT | ICR_LEVEL_ASSERT) };
+	wait_ms(10, || false);
+	for _ in 0..2 {
+		unsafe { apic::send_ipi(apic_id, ICR_STARTUP | ICR_LEVEL_ASSERT | vector) };
+		// a CPU that took the first SIPI ignores the second
+		if wait_ms(1, called_in) {
+			return Ok(());
+		}
+	}
+
+	if wait_ms(AP_STARTUP_TIMEOUT_MS, called_in) {
+		Ok(())
+	} else {
+		// the stack is leaked: the AP may still show up and run on it
+		Err(NullexError::Timeout)
+	}
+}
+
+/// Brings every other CPU in the MADT online and parks it. Needs the
+/// kernel heap, the local APIC and a calibrated TSC.
+pub fn start_aps() {
+	let bsp = unsafe { (apic::read_register(apic::APIC_ID) >> 24) as u8 };
+	let mut aps = interrupts::without_interrupts(acpi::processor_apic_ids);
+	aps.retain(|id| *id != bsp);
+	if aps.is_empty() {
+		serial_println!("[SMP] No other CPUs found");
+		return;
+	}
+
+	if let Err(e) = install_trampoline() {
+		serial_println!("[SMP] Can't start other CPUs: {}", e);
+		return;
+	}
+
+	for apic_id in &aps {
+		if let Err(e) = start_ap(*apic_id) {
+			serial_println!("[SMP] CPU with APIC ID {} didn't start: {}", apic_id, e);
+		}
+	}
+
+	println!("[Info] {} of {} CPUs online", cpus_online(), aps.len() + 1);
+}
+
+/// Where the trampoline leaves each AP, on its own kernel stack with the
+/// kernel's page tables. There's no IDT or TSS for it yet, so it stays
+/// parked with interrupts off.
+extern "C" fn ap_main() -> ! {
+	let apic_id = unsafe { (apic::read_register(apic::APIC_ID) >> 24) as u8 };
+	CPUS_ONLINE.fetch_add(1, Ordering::SeqCst);
+	// the BSP may reuse the trampoline for the next AP from here on
+	AP_CALLED_IN.store(true, Ordering::SeqCst);
+
+	serial_println!("[SMP] CPU with APIC ID {} online", apic_id);
+
+	loop {
+		interrupts::disable();
+		hlt();
+	}
+}
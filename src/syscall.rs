@@ -7,19 +7,22 @@
 //! to me and others without resembling too much of UNIX/Linux
 //!
 
-use alloc::{string::ToString, sync::Arc};
-use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::{string::String, vec, vec::Vec};
+use core::sync::atomic::Ordering;
 
-use futures::task::AtomicWaker;
+use x86_64::{PhysAddr, registers::control::Cr3, structures::paging::PhysFrame};
 
 use crate::{
-	arch::x86_64::user::{KERNEL_CR3, KERNEL_RETURN_ADDR, KERNEL_RETURN_RBP, KERNEL_RETURN_RSP, USER_EXIT_CODE}, fs::{self, resolve_path}, println, serial_println, task::{
+	arch::x86_64::user::{CURRENT_USER_PROCESS, KERNEL_CR3, KERNEL_RETURN_ADDR, KERNEL_RETURN_RBP, KERNEL_RETURN_RSP, USER_EXIT_CODE, switch_to_process}, fs::{self, procfs, ramfs::{FileSystem, Permission}, resolve_path}, print, println, serial_println, task::{
 		OpenFile,
 		Process,
 		ProcessId,
-		ProcessState,
-		executor::{self, CURRENT_PROCESS, EXECUTOR}
-	}, utils::{elf::parse_elf, oncecell::spin::OnceCell}
+		STDERR_FD,
+		STDIN_FD,
+		STDOUT_FD,
+		executor::{self, EXECUTOR},
+		keyboard::stdin
+	}, utils::{elf::{USER_SPACE_END, parse_elf}, process::try_wait_as}
 };
 
 // syscall ids
@@ -36,6 +39,23 @@ const SYS_RUN: u32 = 8;
 const SYS_STOP: u32 = 9;
 const SYS_NAP: u32 = 10;
 const SYS_SIZEF: u32 = 11;
+const SYS_GETPID: u32 = 12;
+const SYS_GETTID: u32 = 13;
+const SYS_GETCWD: u32 = 14;
+const SYS_CHDIR: u32 = 15;
+
+/// Most arguments `run` copies from the caller's argv.
+const RUN_MAX_ARGS: usize = 32;
+/// Longest argument `run` copies, in bytes.
+const RUN_MAX_ARG_LEN: usize = 256;
+
+/// Returned when a file is accessed in a way its permissions don't allow.
+pub const FS_FILE_INVALID_PERMISSION: i32 = -1;
+
+/// `openf` access mode bit: open for reading.
+pub const OPENF_READ: u64 = 1;
+/// `openf` access mode bit: open for writing.
+pub const OPENF_WRITE: u64 = 2;
 
 /// System call handler function. Called when the `syscall` or `int 0x80` instruction
 /// is called.
@@ -89,12 +109,17 @@ pub unsafe fn syscall(
 			}
 		}
 		SYS_SPLIT => sys_split(),
-		SYS_WAITON => sys_waiton(),
+		SYS_WAITON => {
+			let pid = ProcessId::new(arg1);
+			let status_ptr = arg2 as *mut i32;
+			unsafe { sys_waiton(pid, status_ptr) }
+		}
 		SYS_OPENF => {
 			let path_ptr = arg1 as *const u8;
 			let path_len = arg2 as usize;
 			let path = unsafe { core::str::from_raw_parts(path_ptr, path_len) };
-			sys_openf(path)
+			let mode = arg3;
+			sys_openf(path, mode)
 		}
 		SYS_CLOSEF => {
 			let fd = arg1 as u32;
@@ -115,8 +140,15 @@ pub unsafe fn syscall(
 		SYS_RUN => {
 			let path_ptr = arg1 as *const u8;
 			let path_len = arg2 as usize;
+			if !is_user_range(path_ptr as u64, path_len) {
+				serial_println!("sys_run: bad path pointer {:p}", path_ptr);
+				return -1;
+			}
 			let path = unsafe { core::str::from_raw_parts(path_ptr, path_len) };
-			sys_run(path)
+			let Some(args) = (unsafe { read_user_argv(arg3 as *const *const u8) }) else {
+				return -1;
+			};
+			sys_run(path, args)
 		}
 		SYS_STOP => sys_stop(arg1),
 		SYS_NAP => {
@@ -127,6 +159,24 @@ pub unsafe fn syscall(
 			let fd = arg1 as u32;
 			sys_sizef(fd)
 		}
+		SYS_GETPID => sys_getpid(),
+		// a process is a single thread, so its thread id is its pid
+		SYS_GETTID => sys_getpid(),
+		SYS_GETCWD => {
+			let buf_ptr = arg1 as *mut u8;
+			let len = arg2 as usize;
+			unsafe { sys_getcwd(buf_ptr, len) }
+		}
+		SYS_CHDIR => {
+			let path_ptr = arg1 as *const u8;
+			let path_len = arg2 as usize;
+			if !is_user_range(path_ptr as u64, path_len) {
+				serial_println!("sys_chdir: bad path pointer {:p}", path_ptr);
+				return -1;
+			}
+			let path = unsafe { core::str::from_raw_parts(path_ptr, path_len) };
+			sys_chdir(path)
+		}
 		_ => {
 			serial_println!("Invalid syscall ID: {}", syscall_id);
 			-1 // error code for unhandled syscall
@@ -134,41 +184,106 @@ pub unsafe fn syscall(
 	}
 }
 
+/// Forks the calling process. Always fails with -1 for now: only kernel
+/// processes can fork, see `utils::process::fork`, and a user program runs
+/// inside the poll of the process that started it. The number stays taken so
+/// programs built against it keep getting an error.
 fn sys_split() -> i32 {
-	serial_println!("sys_split called");
-	let current_state = {
-		let locked = CURRENT_PROCESS.lock();
-		locked
-			.as_ref()
-			.expect("No current process during sys_split")
-			.clone()
+	serial_println!("sys_split: user programs can't be forked");
+	-1
+}
+
+/// Checks whether the caller's child `pid` has ended, without blocking.
+///
+/// Once it has, its exit code is written to `status_ptr` (if not null), the
+/// child is reaped and its pid returned. Returns 0 while it is still running,
+/// so the caller should yield and try again, and -1 if there is no such
+/// child or `status_ptr` isn't an aligned user address.
+///
+/// # Safety
+/// `status_ptr` needs to be null or a valid pointer or else undefined behaviour
+unsafe fn sys_waiton(pid: ProcessId, status_ptr: *mut i32) -> i32 {
+	// checked before reaping, so a bad pointer doesn't lose the exit code
+	if !status_ptr.is_null()
+		&& (!status_ptr.is_aligned() || !is_user_range(status_ptr as u64, size_of::<i32>()))
+	{
+		serial_println!("sys_waiton: bad status pointer {:p}", status_ptr);
+		return -1;
+	}
+	let Some(caller) = caller_pid() else {
+		return -1;
 	};
-	let future_fn_clone = current_state.future_fn.clone();
-	let mut executor = EXECUTOR.lock();
-	let child_pid = executor.create_pid();
-	let child_state = Arc::new(ProcessState {
-		id: child_pid,
-		is_child: true,
-		future_fn: future_fn_clone,
-		queued: AtomicBool::new(false),
-		scancode_queue: OnceCell::uninit(),
-		waker: AtomicWaker::new()
-	});
-	let child_process = Process::new(child_state).expect("Process created incorrectly.");
-	match executor.spawn_process(child_process) {
-		Ok(()) => child_pid.get() as i32,
-		Err(_) => -1, // Return error code on spawn failure
+
+	match try_wait_as(caller, pid) {
+		Ok(Some(exit_code)) => {
+			if !status_ptr.is_null() {
+				unsafe { status_ptr.write(exit_code) };
+			}
+			pid.get() as i32
+		}
+		Ok(None) => 0,
+		Err(e) => {
+			serial_println!("sys_waiton: {}: {}", pid.get(), e);
+			-1
+		}
 	}
 }
 
-fn sys_waiton() -> i32 {
-	unsafe {
-		if executor::CURRENT_PROCESS_GUARD.is_null() {
-			serial_println!("sys_wait: No current process guard");
-			return -1;
+/// Returns whether the `len` bytes at `addr` all lie in the user half of the
+/// address space.
+fn is_user_range(addr: u64, len: usize) -> bool {
+	addr.checked_add(len as u64).is_some_and(|end| end <= USER_SPACE_END)
+}
+
+/// Returns the id of the calling process: the user process being run, or
+/// else the kernel process being polled.
+fn caller_pid() -> Option<ProcessId> {
+	let user_process = unsafe { CURRENT_USER_PROCESS };
+	if !user_process.is_null() {
+		let process = unsafe { &*user_process };
+		return Some(process.state.id);
+	}
+
+	executor::CURRENT_PROCESS.lock().as_ref().map(|state| state.id)
+}
+
+/// Returns the id of the calling process, see `caller_pid`. -1 if there is
+/// none.
+fn sys_getpid() -> i32 {
+	caller_pid().map_or(-1, |pid| pid.get() as i32)
+}
+
+/// Copies the caller's working directory into `buf_ptr` with a NUL after
+/// it. Returns its length without the NUL, or -1 if it doesn't fit in `len`
+/// bytes or the buffer isn't at a user address.
+///
+/// # Safety
+/// `buf_ptr` needs to be valid for `len` bytes or else undefined behaviour
+unsafe fn sys_getcwd(buf_ptr: *mut u8, len: usize) -> i32 {
+	let cwd = fs::current_dir();
+	if buf_ptr.is_null() || cwd.len() + 1 > len {
+		return -1;
+	}
+	if !is_user_range(buf_ptr as u64, cwd.len() + 1) {
+		serial_println!("sys_getcwd: bad buffer pointer {:p}", buf_ptr);
+		return -1;
+	}
+
+	let buf = unsafe { core::slice::from_raw_parts_mut(buf_ptr, cwd.len() + 1) };
+	buf[..cwd.len()].copy_from_slice(cwd.as_bytes());
+	buf[cwd.len()] = 0;
+	cwd.len() as i32
+}
+
+/// Changes the caller's working directory to `path`. Returns -1 if it isn't
+/// a directory.
+fn sys_chdir(path: &str) -> i32 {
+	match fs::set_current_dir(path) {
+		Ok(()) => 0,
+		Err(e) => {
+			serial_println!("sys_chdir: {}: {:?}", path, e);
+			-1
 		}
-		let _process = &mut *executor::CURRENT_PROCESS_GUARD;
-		0
 	}
 }
 
@@ -176,7 +291,67 @@ fn sys_say(s: &str) {
 	println!("{}", s);
 }
 
-fn sys_openf(path: &str) -> i32 {
+/// Works out what a file opened with `mode` (`OPENF_*` bits) may be used for.
+///
+/// Mode 0, which is what older callers pass, asks for whatever the file
+/// permits. Asking for an access the file doesn't permit fails with
+/// `FS_FILE_INVALID_PERMISSION`.
+fn open_access(fs: &FileSystem, path: &str, mode: u64) -> Result<Permission, i32> {
+	let permission = fs.effective_permission(path).map_err(|_| -1)?;
+
+	let (read, write) = if mode == 0 {
+		(permission.read, permission.write)
+	} else {
+		(mode & OPENF_READ != 0, mode & OPENF_WRITE != 0)
+	};
+
+	if (read && !permission.read) || (write && !permission.write) {
+		return Err(FS_FILE_INVALID_PERMISSION);
+	}
+
+	Ok(Permission {
+		read,
+		write,
+		execute: false
+	})
+}
+
+/// Like `open_access`, for files of the read-only `/proc` tree.
+fn proc_open_access(mode: u64) -> Result<Permission, i32> {
+	if mode & OPENF_WRITE != 0 {
+		return Err(FS_FILE_INVALID_PERMISSION);
+	}
+	Ok(Permission::read())
+}
+
+/// Whether `open_file` may currently be used for `write` (or read) access.
+/// Both the access it was opened with and the file's current permissions
+/// have to allow it.
+fn access_allowed(fs: &FileSystem, open_file: &OpenFile, write: bool) -> bool {
+	let opened_for = if write { open_file.access.write } else { open_file.access.read };
+	if procfs::is_proc_path(&open_file.path) {
+		return opened_for && !write;
+	}
+	let permission = fs.effective_permission(&open_file.path);
+	opened_for && permission.is_ok_and(|p| if write { p.write } else { p.read })
+}
+
+/// Appends `buf` to an open file, checking its permissions first.
+fn write_open_file(fs: &mut FileSystem, open_file: &OpenFile, buf: &[u8]) -> i32 {
+	if !access_allowed(fs, open_file, true) {
+		serial_println!("sys_writef: Permission denied: {}", open_file.path);
+		return FS_FILE_INVALID_PERMISSION;
+	}
+
+	if fs.write_file(open_file.path.as_str(), buf, false).is_ok() {
+		buf.len() as i32 // number of bytes written
+	} else {
+		serial_println!("sys_writef: Write failed: {}", open_file.path);
+		-1 // write failed
+	}
+}
+
+fn sys_openf(path: &str, mode: u64) -> i32 {
 	unsafe {
 		if executor::CURRENT_PROCESS_GUARD.is_null() {
 			serial_println!("sys_openf: No current process guard");
@@ -184,15 +359,32 @@ fn sys_openf(path: &str) -> i32 {
 		}
 		let process = &mut *executor::CURRENT_PROCESS_GUARD;
 		let path_r = resolve_path(path);
-		let exists = fs::with_fs(|fs| fs.get_file(&path_r).is_ok());
+		let exists = if procfs::is_proc_path(&path_r) {
+			procfs::exists(&path_r) && !procfs::is_dir(&path_r)
+		} else {
+			fs::with_fs(|fs| fs.get_file(&path_r).is_ok())
+		};
 		if !exists {
 			serial_println!("sys_openf: File not found: {}", path);
 			return -1;
 		}
+		let access = if procfs::is_proc_path(&path_r) {
+			proc_open_access(mode)
+		} else {
+			fs::with_fs(|fs| open_access(fs, &path_r, mode))
+		};
+		let access = match access {
+			Ok(access) => access,
+			Err(code) => {
+				serial_println!("sys_openf: Permission denied: {} (mode {})", path, mode);
+				return code;
+			}
+		};
 		let fd = process.next_fd;
 		process.open_files.insert(fd, OpenFile {
-			path: path.to_string(),
-			offset: 0
+			path: path_r,
+			offset: 0,
+			access
 		});
 		process.next_fd += 1;
 		fd as i32
@@ -218,6 +410,20 @@ fn sys_closef(fd: u32) -> i32 {
 /// # Safety
 /// `buf_ptr` needs to be a valid pointer or else undefined behaviour
 unsafe fn sys_readf(fd: u32, buf_ptr: *mut u8, len: usize) -> i32 {
+	if buf_ptr.is_null() || !is_user_range(buf_ptr as u64, len) {
+		serial_println!("sys_readf: bad buffer pointer {:p}", buf_ptr);
+		return -1;
+	}
+
+	match fd {
+		STDIN_FD => {
+			let buf = unsafe { core::slice::from_raw_parts_mut(buf_ptr, len) };
+			return stdin::read(buf) as i32;
+		}
+		STDOUT_FD | STDERR_FD => return -1,
+		_ => {}
+	}
+
 	unsafe {
 		if executor::CURRENT_PROCESS_GUARD.is_null() {
 			serial_println!("sys_readf: No current process guard");
@@ -225,8 +431,28 @@ unsafe fn sys_readf(fd: u32, buf_ptr: *mut u8, len: usize) -> i32 {
 		}
 		let process = &mut *executor::CURRENT_PROCESS_GUARD;
 		if let Some(open_file) = process.open_files.get_mut(&fd) {
+			if !fs::with_fs(|fs| access_allowed(fs, open_file, false)) {
+				serial_println!("sys_readf: Permission denied: {}", open_file.path);
+				return FS_FILE_INVALID_PERMISSION;
+			}
 			let path = &open_file.path;
 			let offset = open_file.offset;
+			if procfs::is_proc_path(path) {
+				// generated fresh on every read
+				let Ok(content) = procfs::read(path) else {
+					serial_println!("sys_readf: File not found: {}", path);
+					return -1;
+				};
+				// the file may have shrunk since the last read left `offset`
+				let bytes_to_read = core::cmp::min(len, content.len().saturating_sub(offset));
+				if bytes_to_read == 0 {
+					return 0; // eof
+				}
+				let buf = core::slice::from_raw_parts_mut(buf_ptr, bytes_to_read);
+				buf.copy_from_slice(&content[offset..offset + bytes_to_read]);
+				open_file.offset += bytes_to_read;
+				return bytes_to_read as i32;
+			}
 			fs::with_fs(|fs| {
 				if let Ok(file) = fs.get_file(path.as_str()) {
 					let bytes_to_read =
@@ -261,6 +487,9 @@ fn sys_sizef(fd: u32) -> i32 {
 		let process = &mut *executor::CURRENT_PROCESS_GUARD;
 		if let Some(open_file) = process.open_files.get(&fd) {
 			let path = &open_file.path;
+			if procfs::is_proc_path(path) {
+				return procfs::read(path).map_or(-1, |content| content.len() as i32);
+			}
 			fs::with_fs(|fs| {
 				if !fs.exists(path) || fs.is_dir(path) { return -1isize }
 				return fs.get_file(path).unwrap().content.len().try_into().unwrap()
@@ -275,6 +504,21 @@ fn sys_sizef(fd: u32) -> i32 {
 /// # Safety
 /// `buf_ptr` needs to be a valid pointer or else undefined behaviour
 unsafe fn sys_writef(fd: u32, buf_ptr: *const u8, len: usize) -> i32 {
+	if buf_ptr.is_null() || !is_user_range(buf_ptr as u64, len) {
+		serial_println!("sys_writef: bad buffer pointer {:p}", buf_ptr);
+		return -1;
+	}
+
+	match fd {
+		STDIN_FD => return -1,
+		STDOUT_FD | STDERR_FD => {
+			let buf = unsafe { core::slice::from_raw_parts(buf_ptr, len) };
+			print!("{}", String::from_utf8_lossy(buf));
+			return len as i32;
+		}
+		_ => {}
+	}
+
 	unsafe {
 		if executor::CURRENT_PROCESS_GUARD.is_null() {
 			serial_println!("sys_writef: No current process guard");
@@ -282,16 +526,8 @@ unsafe fn sys_writef(fd: u32, buf_ptr: *const u8, len: usize) -> i32 {
 		}
 		let process = &mut *executor::CURRENT_PROCESS_GUARD;
 		if let Some(open_file) = process.open_files.get(&fd) {
-			let path = &open_file.path;
 			let buf = core::slice::from_raw_parts(buf_ptr, len);
-			fs::with_fs(|fs| {
-				if fs.write_file(path.as_str(), buf, false).is_ok() {
-					len as i32 // number of bytes written
-				} else {
-					serial_println!("sys_writef: Write failed: {}", path);
-					-1 // write failed
-				}
-			})
+			fs::with_fs(|fs| write_open_file(fs, open_file, buf))
 		} else {
 			serial_println!("sys_writef: Invalid file descriptor: {}", fd);
 			-1 // invalid fd
@@ -299,21 +535,147 @@ unsafe fn sys_writef(fd: u32, buf_ptr: *const u8, len: usize) -> i32 {
 	}
 }
 
-fn sys_run(path: &str) -> i32 {
-	let maybe_bytes = fs::with_fs(|fs| fs.get_file(path).ok().map(|f| f.content.clone()));
+/// Copies the NULL-terminated array of C strings at `argv` out of user
+/// memory, or nothing if `argv` is null. Returns `None` if a slot of `argv`
+/// or a byte of one of its strings isn't at a user address.
+unsafe fn read_user_argv(argv: *const *const u8) -> Option<Vec<String>> {
+	let mut args = Vec::new();
+	if argv.is_null() {
+		return Some(args);
+	}
+
+	for i in 0..RUN_MAX_ARGS {
+		let slot = argv.wrapping_add(i);
+		if !slot.is_aligned() || !is_user_range(slot as u64, size_of::<*const u8>()) {
+			serial_println!("sys_run: bad argv pointer {:p}", slot);
+			return None;
+		}
+		let arg = unsafe { *slot };
+		if arg.is_null() {
+			break;
+		}
+		let mut len = 0;
+		while len < RUN_MAX_ARG_LEN {
+			if !is_user_range(arg as u64, len + 1) {
+				serial_println!("sys_run: bad argument pointer {:p}", arg);
+				return None;
+			}
+			if unsafe { *arg.add(len) } == 0 {
+				break;
+			}
+			len += 1;
+		}
+		let bytes = unsafe { core::slice::from_raw_parts(arg, len) };
+		args.push(String::from_utf8_lossy(bytes).into_owned());
+	}
+	Some(args)
+}
+
+/// Replaces the calling user process's image with the ELF at `path` and
+/// resumes it at the new entry point with `args` as its argv (just the path
+/// if empty), keeping its open files. Only returns, with -1, if the file is
+/// missing or not a loadable ELF; the caller keeps running as it was.
+fn sys_run(path: &str, args: Vec<String>) -> i32 {
+	let process = unsafe { CURRENT_USER_PROCESS };
+	if process.is_null() {
+		serial_println!("sys_run: No current user process");
+		return -1;
+	}
+	let process = unsafe { &mut *process };
+
+	// `switch_to_process` never returns, so everything the new image is
+	// built from has to be dropped before it's called
+	if !replace_image(process, path, args) {
+		return -1;
+	}
+	unsafe { switch_to_process(process) }
+}
+
+/// Loads the ELF at `path` into `process` in place of its image, see
+/// `sys_run`. Returns whether it was loaded; if not, `process` is left as
+/// it was.
+fn replace_image(process: &mut Process, path: &str, args: Vec<String>) -> bool {
+	let path = resolve_path(path);
+	let maybe_bytes = fs::with_fs(|fs| fs.get_file(&path).ok().map(|f| f.content.clone()));
 	let elf_bytes = match maybe_bytes {
 		Some(b) => b,
 		None => {
 			serial_println!("sys_run: file not found: {}", path);
-			return -1;
+			return false;
 		}
 	};
-	let _e = parse_elf(&elf_bytes);
-	0
-}
+	// catch a bad ELF before building anything for it
+	if let Err(e) = parse_elf(&elf_bytes) {
+		serial_println!("sys_run: {}: {}", path, e);
+		return false;
+	}
 
+	// build the new image from the kernel's page table, which the old image
+	// can be freed from too
+	let user_cr3 = Cr3::read();
+	unsafe { Cr3::write(PhysFrame::containing_address(PhysAddr::new(KERNEL_CR3)), user_cr3.1) };
+
+	let args: Vec<&str> = if args.is_empty() {
+		vec![path.as_str()]
+	} else {
+		args.iter().map(String::as_str).collect()
+	};
+	match process.exec(&elf_bytes, &args, &[]) {
+		Ok(old) => {
+			if let Some(old) = old {
+				unsafe { old.release() };
+			}
+			serial_println!("sys_run: running {}", path);
+			true
+		}
+		Err(e) => {
+			serial_println!("sys_run: {}: {}", path, e);
+			unsafe { Cr3::write(user_cr3.0, user_cr3.1) };
+			false
+		}
+	}
+}
 
 fn sys_stop(pid: u64) -> i32 {
 	EXECUTOR.lock().end_process(ProcessId::new(pid), -2);
 	0 // placeholder: should terminate the specified process
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::string::ToString;
+
+	use crate::{
+		fs::ramfs::{FileSystem, Permission},
+		syscall::*,
+		task::OpenFile,
+		utils::ktest::TestError
+	};
+
+	pub fn test_syscall_read_only_write_rejected() -> Result<(), TestError> {
+		let mut fs = FileSystem::new();
+		fs.create_dir("/proc", Permission::read()).map_err(|_| TestError::Error)?;
+		fs.create_file("/proc/status", Permission::all()).map_err(|_| TestError::Error)?;
+		fs.create_file("/ro", Permission::read()).map_err(|_| TestError::Error)?;
+
+		// asking for write access up front fails
+		assert_eq!(open_access(&fs, "/proc/status", OPENF_WRITE), Err(FS_FILE_INVALID_PERMISSION));
+		let mode = OPENF_READ | OPENF_WRITE;
+		assert_eq!(open_access(&fs, "/ro", mode), Err(FS_FILE_INVALID_PERMISSION));
+
+		// a legacy open only gets what the file permits
+		let access = open_access(&fs, "/ro", 0).map_err(|_| TestError::Error)?;
+		assert!(access.read && !access.write);
+
+		// a descriptor that somehow claims write access is still refused
+		let open_file = OpenFile {
+			path: "/proc/status".to_string(),
+			offset: 0,
+			access: Permission::all()
+		};
+		assert_eq!(write_open_file(&mut fs, &open_file, b"nope"), FS_FILE_INVALID_PERMISSION);
+		assert!(fs.read_file("/proc/status").map_err(|_| TestError::Error)?.is_empty());
+		Ok(())
+	}
+	crate::create_test!(test_syscall_read_only_write_rejected);
+}
@@ -0,0 +1,249 @@
+//!
+//! src/task/channel.rs
+//!
+//! Bounded async channels for passing values between processes.
+//!
+
+use alloc::sync::Arc;
+use core::{
+	future::poll_fn,
+	sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+	task::Poll
+};
+
+use crossbeam_queue::ArrayQueue;
+use futures::task::AtomicWaker;
+
+#[derive(Debug, PartialEq, Eq)]
+/// Why a value couldn't be sent. The value is handed back either way.
+pub enum SendError<T> {
+	/// The channel is at capacity.
+	Full(T),
+	/// The receiver has been dropped.
+	Closed(T)
+}
+
+impl<T> SendError<T> {
+	/// Takes back the value that wasn't sent.
+	pub fn into_inner(self) -> T {
+		match self {
+			SendError::Full(value) | SendError::Closed(value) => value
+		}
+	}
+}
+
+/// State shared by both ends of a channel.
+struct Shared<T> {
+	queue: ArrayQueue<T>,
+	/// Woken when a value arrives or the last sender goes away.
+	recv_waker: AtomicWaker,
+	/// Woken when space frees up or the receiver goes away.
+	send_waker: AtomicWaker,
+	senders: AtomicUsize,
+	receiver_alive: AtomicBool
+}
+
+/// Creates a channel holding up to `capacity` values.
+///
+/// `Sender` can be cloned to feed one `Receiver` from several processes;
+/// with a single sender it is a plain single-producer/single-consumer queue.
+///
+/// # Panics
+/// Panics if `capacity` is 0.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+	let shared = Arc::new(Shared {
+		queue: ArrayQueue::new(capacity),
+		recv_waker: AtomicWaker::new(),
+		send_waker: AtomicWaker::new(),
+		senders: AtomicUsize::new(1),
+		receiver_alive: AtomicBool::new(true)
+	});
+
+	(
+		Sender {
+			shared: shared.clone()
+		},
+		Receiver {
+			shared
+		}
+	)
+}
+
+/// The sending end of a channel.
+pub struct Sender<T> {
+	shared: Arc<Shared<T>>
+}
+
+impl<T> Sender<T> {
+	/// Sends `value` without waiting. Fails with `SendError::Full` when the
+	/// channel is at capacity, so a fast producer is pushed back on instead of
+	/// growing the queue.
+	pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+		if !self.shared.receiver_alive.load(Ordering::Acquire) {
+			return Err(SendError::Closed(value));
+		}
+
+		self.shared.queue.push(value).map_err(SendError::Full)?;
+		self.shared.recv_waker.wake();
+		Ok(())
+	}
+
+	/// Sends `value`, waiting for space if the channel is full. Fails with
+	/// `SendError::Closed` if the receiver is dropped.
+	///
+	/// Only one sender is woken when space frees up, so clones waiting at the
+	/// same time may wait until the next value is received.
+	pub async fn send_async(&self, value: T) -> Result<(), SendError<T>> {
+		let mut value = Some(value);
+		poll_fn(|cx| {
+			let Some(pending) = value.take() else {
+				return Poll::Ready(Ok(()));
+			};
+			let pending = match self.send(pending) {
+				Err(SendError::Full(pending)) => pending,
+				result => return Poll::Ready(result)
+			};
+
+			self.shared.send_waker.register(cx.waker());
+
+			match self.send(pending) {
+				Err(SendError::Full(pending)) => {
+					value = Some(pending);
+					Poll::Pending
+				}
+				result => {
+					self.shared.send_waker.take();
+					Poll::Ready(result)
+				}
+			}
+		})
+		.await
+	}
+
+	/// Returns whether the receiver has been dropped.
+	pub fn is_closed(&self) -> bool {
+		!self.shared.receiver_alive.load(Ordering::Acquire)
+	}
+}
+
+impl<T> Clone for Sender<T> {
+	fn clone(&self) -> Self {
+		self.shared.senders.fetch_add(1, Ordering::AcqRel);
+		Sender {
+			shared: self.shared.clone()
+		}
+	}
+}
+
+impl<T> Drop for Sender<T> {
+	fn drop(&mut self) {
+		if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+			// let the receiver see the channel has closed
+			self.shared.recv_waker.wake();
+		}
+	}
+}
+
+/// The receiving end of a channel.
+pub struct Receiver<T> {
+	shared: Arc<Shared<T>>
+}
+
+impl<T> Receiver<T> {
+	/// Takes the next value without waiting.
+	pub fn try_recv(&self) -> Option<T> {
+		let value = self.shared.queue.pop();
+		if value.is_some() {
+			self.shared.send_waker.wake();
+		}
+		value
+	}
+
+	/// Waits for the next value. Returns `None` once every sender has been
+	/// dropped and the queue is empty.
+	pub async fn recv(&mut self) -> Option<T> {
+		poll_fn(|cx| {
+			if let Some(value) = self.try_recv() {
+				return Poll::Ready(Some(value));
+			}
+			if self.shared.senders.load(Ordering::Acquire) == 0 {
+				return Poll::Ready(None);
+			}
+
+			self.shared.recv_waker.register(cx.waker());
+
+			match self.try_recv() {
+				Some(value) => {
+					self.shared.recv_waker.take();
+					Poll::Ready(Some(value))
+				}
+				// the last sender may have gone between the checks
+				None if self.shared.senders.load(Ordering::Acquire) == 0 => Poll::Ready(None),
+				None => Poll::Pending
+			}
+		})
+		.await
+	}
+
+	/// Returns how many values are waiting.
+	pub fn len(&self) -> usize {
+		self.shared.queue.len()
+	}
+
+	/// Returns whether no values are waiting.
+	pub fn is_empty(&self) -> bool {
+		self.shared.queue.is_empty()
+	}
+}
+
+impl<T> Drop for Receiver<T> {
+	fn drop(&mut self) {
+		self.shared.receiver_alive.store(false, Ordering::Release);
+		self.shared.send_waker.wake();
+	}
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use core::{
+		future::Future,
+		pin::pin,
+		task::{Context, Poll, Waker}
+	};
+
+	use crate::{task::channel::*, utils::ktest::TestError};
+
+	fn poll_once<F: Future>(future: F) -> Poll<F::Output> {
+		let mut cx = Context::from_waker(Waker::noop());
+		pin!(future).poll(&mut cx)
+	}
+
+	pub fn test_channel_backpressure_and_close() -> Result<(), TestError> {
+		let (sender, mut receiver) = channel(2);
+		let second = sender.clone();
+
+		assert_eq!(sender.send(1), Ok(()));
+		assert_eq!(second.send(2), Ok(()));
+		// at capacity the value is handed back
+		assert_eq!(sender.send(3), Err(SendError::Full(3)));
+		assert!(poll_once(sender.send_async(3)).is_pending());
+
+		assert_eq!(receiver.len(), 2);
+		assert_eq!(poll_once(receiver.recv()), Poll::Ready(Some(1)));
+		assert_eq!(receiver.try_recv(), Some(2));
+		assert!(poll_once(receiver.recv()).is_pending());
+
+		// the channel only closes once every sender is gone
+		drop(sender);
+		assert!(poll_once(receiver.recv()).is_pending());
+		drop(second);
+		assert_eq!(poll_once(receiver.recv()), Poll::Ready(None));
+
+		let (sender, receiver) = channel(1);
+		drop(receiver);
+		assert!(sender.is_closed());
+		assert_eq!(sender.send(4), Err(SendError::Closed(4)));
+		Ok(())
+	}
+	crate::create_test!(test_channel_backpressure_and_close);
+}
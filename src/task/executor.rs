@@ -5,11 +5,14 @@
 //! 
 
 use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
-use core::{sync::atomic::Ordering, task::Waker};
+use core::{
+	sync::atomic::{AtomicUsize, Ordering},
+	task::Waker
+};
 
 use crossbeam_queue::ArrayQueue;
 
-use super::{Process, ProcessId, ProcessState};
+use super::{Priority, Process, ProcessId, ProcessState, keyboard};
 use crate::{error::NullexError, lazy_static, println, serial_println, utils::mutex::SpinMutex};
 
 lazy_static! {
@@ -19,19 +22,95 @@ lazy_static! {
 	pub static ref EXECUTOR: SpinMutex<Executor> = SpinMutex::new(Executor::new());
 }
 
+/// Exit code of a process ended because it was cancelled (128 + SIGINT).
+pub const CANCELLED_EXIT_CODE: i32 = 130;
+
 /// Pointer to the current process.
 pub static mut CURRENT_PROCESS_GUARD: *mut Process = core::ptr::null_mut();
 
+/// Most processes waiting to run at each priority.
+const RUN_QUEUE_CAPACITY: usize = 100;
+
+/// Every this many picks, a lower priority is served first, so busy
+/// high-priority processes can't starve the rest.
+const STARVATION_INTERVAL: usize = 8;
+
+/// Processes waiting to run, in a FIFO queue per `Priority`.
+///
+/// Higher priorities are run first, and processes of the same priority take
+/// turns.
+pub struct RunQueue {
+	levels: [ArrayQueue<ProcessId>; Priority::ALL.len()],
+	/// Picks made so far, to give lower levels their turn.
+	picks: AtomicUsize
+}
+
+impl RunQueue {
+	/// Creates an empty run queue.
+	pub fn new() -> Self {
+		RunQueue {
+			levels: core::array::from_fn(|_| ArrayQueue::new(RUN_QUEUE_CAPACITY)),
+			picks: AtomicUsize::new(0)
+		}
+	}
+
+	/// Queues `pid` to run at `priority`. Gives `pid` back if that level is
+	/// full.
+	pub fn push(&self, pid: ProcessId, priority: Priority) -> Result<(), ProcessId> {
+		self.levels[priority as usize].push(pid)
+	}
+
+	/// Takes the next process to run: the oldest of the highest non-empty
+	/// priority, except that every `STARVATION_INTERVAL`th pick looks at
+	/// the normal and low levels first, in turn.
+	pub fn pop(&self) -> Option<ProcessId> {
+		let pick = self.picks.fetch_add(1, Ordering::Relaxed) + 1;
+		if pick.is_multiple_of(STARVATION_INTERVAL) {
+			let boosted = if (pick / STARVATION_INTERVAL) % 2 == 1 {
+				Priority::Normal
+			} else {
+				Priority::Low
+			};
+			if let Some(pid) = self.levels[boosted as usize].pop() {
+				return Some(pid);
+			}
+		}
+		self.levels.iter().find_map(|level| level.pop())
+	}
+
+	/// Returns whether no process is waiting to run.
+	pub fn is_empty(&self) -> bool {
+		self.levels.iter().all(|level| level.is_empty())
+	}
+}
+
+impl Default for RunQueue {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 /// The process executor of the kernel.
 pub struct Executor {
 	/// Tree map showing all mapped processes.
 	pub processes: BTreeMap<ProcessId, Arc<SpinMutex<Process>>>,
 	/// The queue of all processes waiting to run.
-	pub process_queue: Arc<ArrayQueue<ProcessId>>,
+	pub process_queue: Arc<RunQueue>,
 	/// Cache of all wakers for a process.
 	pub waker_cache: BTreeMap<ProcessId, Waker>,
 	/// Next `ProcessId` to be run.
-	pub next_pid: ProcessId
+	pub next_pid: ProcessId,
+	/// Child processes that have ended but haven't been waited on yet.
+	pub zombies: BTreeMap<ProcessId, Zombie>
+}
+
+/// An ended child whose exit code hasn't been collected by its parent yet.
+#[derive(Debug, Clone, Copy)]
+pub struct Zombie {
+	/// The process that may collect it.
+	pub parent: ProcessId,
+	/// The code the child exited with.
+	pub exit_code: i32
 }
 
 impl Executor {
@@ -39,9 +118,10 @@ impl Executor {
 	pub fn new() -> Self {
 		Executor {
 			processes: BTreeMap::new(),
-			process_queue: Arc::new(ArrayQueue::new(100)),
+			process_queue: Arc::new(RunQueue::new()),
 			waker_cache: BTreeMap::new(),
-			next_pid: ProcessId::new(0)
+			next_pid: ProcessId::new(0),
+			zombies: BTreeMap::new()
 		}
 	}
 
@@ -49,11 +129,12 @@ impl Executor {
 	/// Spawns a new process.
 	pub fn spawn_process(&mut self, process: Process) -> Result<(), NullexError> {
 		let pid = process.state.id;
+		let priority = process.state.priority();
 		let process_arc = Arc::new(SpinMutex::new(process));
 		if self.processes.insert(pid, process_arc).is_some() {
 			return Err(NullexError::ProcessAlreadyExists);
 		}
-		self.process_queue.push(pid).map_err(|_| NullexError::ProcessQueueFull)?;
+		self.process_queue.push(pid, priority).map_err(|_| NullexError::ProcessQueueFull)?;
 		Ok(())
 	}
 
@@ -83,17 +164,50 @@ impl Executor {
 		}
 	}
 
-	/// Ends a running process.
+	/// Ends a running process. A child's exit code is kept until its parent
+	/// collects it with `reap`, or ends itself.
+	///
+	/// The process's `CancellationToken` is cancelled first, so helpers it
+	/// handed the token to can wind down. To give the process itself a chance
+	/// to clean up, use `ProcessState::request_cancel` instead.
+	///
+	/// The process must not be locked by the caller.
 	pub fn end_process(&mut self, pid: ProcessId, exit_code: i32) {
-		let process_arc = self.processes.get(&pid).unwrap();
-		let process = process_arc.lock();
-		let pid_to_remove = pid;
-		drop(process);
-		self.processes.remove(&pid_to_remove);
-		self.waker_cache.remove(&pid_to_remove);
+		let Some(process_arc) = self.processes.get(&pid) else {
+			serial_println!("Process {} is not running", pid.get());
+			return;
+		};
+		// cancelled before removal, so helpers holding the token see it
+		// while the process can still be looked up
+		let state = process_arc.lock().state.clone();
+		state.cancel.cancel();
+
+		self.processes.remove(&pid);
+		self.waker_cache.remove(&pid);
+		keyboard::foreground::release(pid);
+		// nobody is left to wait on the children this process didn't
+		self.zombies.retain(|_, zombie| zombie.parent != pid);
+		if state.is_child
+			&& let Some(parent) = state.parent
+			&& self.processes.contains_key(&parent)
+		{
+			self.zombies.insert(pid, Zombie {
+				parent,
+				exit_code
+			});
+		}
 
 		serial_println!("Process {} exited with code: {}", pid.get(), exit_code);
 	}
+
+	/// Collects the exit code of `parent`'s ended child `pid`, removing its
+	/// zombie entry. Another process's zombie is left alone.
+	pub fn reap(&mut self, parent: ProcessId, pid: ProcessId) -> Option<i32> {
+		if self.zombies.get(&pid)?.parent != parent {
+			return None;
+		}
+		self.zombies.remove(&pid).map(|zombie| zombie.exit_code)
+	}
 }
 
 impl Default for Executor {
@@ -106,8 +220,8 @@ impl Default for Executor {
 pub struct ProcessWaker {
 	/// The `ProcessId` to wake up.
 	pub pid: ProcessId,
-	/// The list of `ProcessId`'s to wake up.
-	pub process_queue: Arc<ArrayQueue<ProcessId>>,
+	/// The queue to put the process back on when it wakes.
+	pub process_queue: Arc<RunQueue>,
 	/// The current state of the process which will be waking up.
 	pub state: Arc<ProcessState>
 }
@@ -117,7 +231,7 @@ impl ProcessWaker {
 	pub fn wake_process(&self) {
 		// use self.state directly no need to lock the process
 		if !self.state.queued.swap(true, Ordering::AcqRel)
-			&& self.process_queue.push(self.pid).is_err()
+			&& self.process_queue.push(self.pid, self.state.priority()).is_err()
 		{
 			serial_println!(
 				"Warning: process_queue full, skipping wake for process {}",
@@ -130,7 +244,7 @@ impl ProcessWaker {
 	/// Creates a new waker for a `ProcessId`
 	pub fn new_waker(
 		pid: ProcessId,
-		process_queue: Arc<ArrayQueue<ProcessId>>,
+		process_queue: Arc<RunQueue>,
 		state: Arc<ProcessState>
 	) -> Waker {
 		Waker::from(Arc::new(ProcessWaker {
@@ -149,4 +263,121 @@ impl Wake for ProcessWaker {
 	fn wake_by_ref(self: &Arc<Self>) {
 		self.wake_process();
 	}
-}
\ No newline at end of file
+}
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::vec::Vec;
+	use core::sync::atomic::Ordering;
+
+	use crate::{
+		apic::APIC_TICK_COUNT,
+		task::{Priority, Process, ProcessId, ProcessState, executor::*},
+		utils::ktest::TestError
+	};
+
+	fn process(id: ProcessId, parent: Option<ProcessId>) -> Result<Process, TestError> {
+		Process::new(ProcessState::for_test(id, parent)).map_err(|_| TestError::Error)
+	}
+
+	pub fn test_executor_reaps_child_exit_codes() -> Result<(), TestError> {
+		let mut executor = Executor::new();
+		let parent = executor.create_pid();
+		let child = executor.create_pid();
+		let other = executor.create_pid();
+		executor.spawn_process(process(parent, None)?).map_err(|_| TestError::Error)?;
+		executor.spawn_process(process(child, Some(parent))?).map_err(|_| TestError::Error)?;
+
+		executor.end_process(child, 42);
+		// ending an unknown process is a no-op
+		executor.end_process(ProcessId::new(99), 1);
+
+		// only the parent can collect the exit code
+		assert_eq!(executor.reap(other, child), None);
+		assert_eq!(executor.reap(parent, child), Some(42));
+		assert_eq!(executor.reap(parent, child), None);
+
+		executor.end_process(parent, 0);
+		assert!(executor.processes.is_empty());
+		// only children leave a zombie behind
+		assert!(executor.zombies.is_empty());
+		Ok(())
+	}
+	crate::create_test!(test_executor_reaps_child_exit_codes);
+
+	pub fn test_executor_frees_zombies_of_ended_parent() -> Result<(), TestError> {
+		let mut executor = Executor::new();
+		let parent = executor.create_pid();
+		let child = executor.create_pid();
+		let orphan = executor.create_pid();
+		executor.spawn_process(process(parent, None)?).map_err(|_| TestError::Error)?;
+		executor.spawn_process(process(child, Some(parent))?).map_err(|_| TestError::Error)?;
+		executor.spawn_process(process(orphan, Some(parent))?).map_err(|_| TestError::Error)?;
+
+		executor.end_process(child, 1);
+		executor.end_process(parent, 0);
+		// its parent is gone, so nobody could wait on it
+		executor.end_process(orphan, 2);
+
+		assert!(executor.zombies.is_empty());
+		Ok(())
+	}
+	crate::create_test!(test_executor_frees_zombies_of_ended_parent);
+
+	pub fn test_cancellation_token() -> Result<(), TestError> {
+		let mut executor = Executor::new();
+		let pid = executor.create_pid();
+		let cancelled = process(pid, None)?;
+		let state = cancelled.state.clone();
+		let token = state.cancel.clone();
+		executor.spawn_process(cancelled).map_err(|_| TestError::Error)?;
+
+		// a request gives the process a grace period before it is ended
+		state.request_cancel();
+		assert!(token.is_cancelled() && state.is_cancelled());
+		assert!(!state.cancel_expired());
+		// and is ended once it's over
+		let now = APIC_TICK_COUNT.load(Ordering::Relaxed);
+		state.cancel_deadline.store(now, Ordering::Release);
+		assert!(state.cancel_expired());
+
+		// ending a process cancels its token for anything holding a clone
+		let other = executor.create_pid();
+		let ended = process(other, None)?;
+		let token = ended.state.cancel.clone();
+		executor.spawn_process(ended).map_err(|_| TestError::Error)?;
+		executor.end_process(other, 0);
+		assert!(token.is_cancelled());
+
+		executor.end_process(pid, CANCELLED_EXIT_CODE);
+		Ok(())
+	}
+	crate::create_test!(test_cancellation_token);
+
+	pub fn test_run_queue_prefers_higher_priorities() -> Result<(), TestError> {
+		let queue = RunQueue::new();
+		let pid = ProcessId::new;
+		queue.push(pid(1), Priority::Low).map_err(|_| TestError::Error)?;
+		queue.push(pid(2), Priority::Normal).map_err(|_| TestError::Error)?;
+		queue.push(pid(3), Priority::High).map_err(|_| TestError::Error)?;
+		queue.push(pid(4), Priority::High).map_err(|_| TestError::Error)?;
+
+		// highest level first, oldest first within a level
+		assert_eq!(queue.pop(), Some(pid(3)));
+		assert_eq!(queue.pop(), Some(pid(4)));
+		assert_eq!(queue.pop(), Some(pid(2)));
+		assert_eq!(queue.pop(), Some(pid(1)));
+		assert!(queue.is_empty());
+
+		// a process kept busy at high priority still lets the others run
+		queue.push(pid(2), Priority::Normal).map_err(|_| TestError::Error)?;
+		queue.push(pid(1), Priority::Low).map_err(|_| TestError::Error)?;
+		let mut order = Vec::new();
+		while order.len() < 3 * STARVATION_INTERVAL && !(order.contains(&1) && order.contains(&2)) {
+			queue.push(pid(3), Priority::High).map_err(|_| TestError::Error)?;
+			order.push(queue.pop().map_or(0, |pid| pid.get()));
+		}
+		assert!(order.contains(&1) && order.contains(&2));
+		Ok(())
+	}
+	crate::create_test!(test_run_queue_prefers_higher_priorities);
+}
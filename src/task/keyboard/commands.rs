@@ -4,17 +4,17 @@
 //! Command handling and definitions module for the kernel.
 //! 
 
-use core::net::Ipv4Addr;
+use core::{fmt, future::Future, net::Ipv4Addr, pin::Pin};
 
 use alloc::{
-	boxed::Box, collections::BTreeMap, string::{String, ToString}, vec::Vec
+	boxed::Box, collections::BTreeMap, string::{String, ToString}, sync::Arc, vec::Vec
 };
 use smoltcp::{iface::{Config, Interface, SocketSet, SocketStorage}, time::Instant, wire::{EthernetAddress, IpAddress, IpCidr}};
 
 use crate::{
-	drivers::{keyboard::scancode::CWD, virtio::net::{VIRTIO_NET_INSTANCE, VirtioNet}}, fs::{self, ramfs::Permission, resolve_path}, lazy_static, net::{GATEWAY_IP, OUR_IP, dns::resolve, http::http_get}, print, println, rtc::read_rtc_time, serial, serial_println, task::{ProcessId, executor::EXECUTOR}, utils::{
-		elf::pelf, logger::{levels::LogLevel, sinks::SYSLOG_SINK, traits::logger_sink::LoggerSink}, mutex::SpinMutex
-	}, vga_buffer::WRITER
+	apic, arch::x86_64::reset, drivers::{keyboard::{layouts::{self, Keymap}, queue::dropped_scancodes}, virtio::net::{VIRTIO_NET_INSTANCE, VirtioNet}}, fs::{self, ramfs::{FsError, Permission}, resolve_path}, io::pci, lazy_static, net::{self, ARP_CACHE, NetConfig, dhcp, dns::resolve, http::http_get}, print, println, rtc::{self, read_rtc_time}, serial, serial_println, task::{Priority, ProcessId, ProcessState, executor::EXECUTOR, keyboard::{env, glob}, timer::sleep_ms, watchdog}, tsc, utils::{
+		elf::{exec, pelf, program_path}, logger::{levels::LogLevel, sinks::{STDOUT_SINK, SYSLOG_SINK}, traits::logger_sink::LoggerSink}, mutex::SpinMutex, process::{cancel, fork, set_priority, spawn_process, wait}
+	}, vga_buffer::{WRITER, capture_output, string_to_color}
 };
 
 lazy_static! {
@@ -22,31 +22,74 @@ lazy_static! {
 	pub static ref CMD_HISTORY: SpinMutex<Vec<String>> = SpinMutex::new(Vec::new());
 	/// Static reference to the current command history index we are at.
 	pub static ref CMD_HISTORY_INDEX: SpinMutex<usize> = SpinMutex::new(0);
+	/// Output of the previous command of a pipeline, for the running command
+	/// to read instead of a file.
+	static ref PIPE_INPUT: SpinMutex<Option<String>> = SpinMutex::new(None);
+	/// Command lines started in the background with `&`, oldest first.
+	static ref BACKGROUND_JOBS: SpinMutex<Vec<Job>> = SpinMutex::new(Vec::new());
 }
 
+/// Most commands kept in the history, the oldest are dropped first.
+const MAX_HISTORY_LEN: usize = 100;
+
+/// File the command history is saved to after every command.
+const HISTORY_FILE: &str = "/logs/.history";
+
+/// Exit code of the child process started by `forktest`.
+const FORKTEST_CHILD_EXIT_CODE: i32 = 7;
+
 /// A type alias for a command function.
 type CommandFunction = fn(args: &[&str]);
 
-#[derive(Clone, Copy, PartialEq)]
-/// Enum representing types of commands.
+/// A type alias for the entry of an application: builds the future its
+/// process runs from the process's state and the command's arguments.
+type ApplicationFunction =
+	fn(state: Arc<ProcessState>, args: Vec<String>) -> Pin<Box<dyn Future<Output = i32>>>;
+
+#[derive(Clone, Copy)]
+/// Enum representing types of commands, and how each is run.
 enum CommandType {
-	Generic,
-	// this might be used in the future.
-	_Application
+	/// A builtin, run inline by the shell.
+	Generic(CommandFunction),
+	/// Runs as its own process, which the shell doesn't wait for.
+	Application(ApplicationFunction)
 }
 
-/// A command structure containing the command name, the function to call, and
-/// help text.
+/// A command structure containing the command name, how to run it, and help
+/// text.
 #[derive(Clone, Copy)]
-#[allow(unused)]
 pub struct Command {
 	name: &'static str,
-	func: CommandFunction,
 	help: &'static str,
-	// this might be used in the future.
 	cmd_type: CommandType
 }
 
+impl Command {
+	/// Runs the command: a builtin inline, an application by spawning its
+	/// process. Returns the process an application was started as.
+	fn run(&self, args: &[&str]) -> Option<ProcessId> {
+		match self.cmd_type {
+			CommandType::Generic(func) => {
+				func(args);
+				None
+			}
+			CommandType::Application(start) => {
+				let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+				match spawn_process(move |state| start(state, args.clone()), false) {
+					Ok(pid) => {
+						println!("{}: started as process {}", self.name, pid.get());
+						Some(pid)
+					}
+					Err(e) => {
+						println!("{}: {}", self.name, e);
+						None
+					}
+				}
+			}
+		}
+	}
+}
+
 lazy_static! {
 	static ref COMMAND_REGISTRY: SpinMutex<BTreeMap<String, Command>> =
 		SpinMutex::new(BTreeMap::new());
@@ -57,152 +100,739 @@ pub fn register_command(cmd: Command) {
 	COMMAND_REGISTRY.lock().insert(cmd.name.to_string(), cmd);
 }
 
-/// Look up and run a command based on input.
+/// Names of all registered commands, in order.
+pub fn command_names() -> Vec<String> {
+	COMMAND_REGISTRY.lock().keys().cloned().collect()
+}
+
+/// A piece of a command line, with quotes and escapes removed and variables
+/// expanded.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	/// A word. `glob` is set if no part of it was quoted or escaped, so
+	/// wildcards in it are expanded.
+	Word { text: String, glob: bool },
+	/// `|`
+	Pipe,
+	/// `>`, or `>>` with `append` set.
+	Redirect { append: bool },
+	/// `&`, running the command line in the background.
+	Background
+}
+
+/// Why a command line couldn't be split into tokens.
+#[derive(Debug, PartialEq)]
+enum ParseError {
+	/// A quote of this kind was never closed.
+	UnterminatedQuote(char),
+	/// A variable was used unset after `set -u`.
+	UnboundVariable(String)
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::UnterminatedQuote(quote) => {
+				write!(f, "syntax error: unterminated {} quote", quote)
+			}
+			Self::UnboundVariable(name) => write!(f, "{}: unbound variable", name)
+		}
+	}
+}
+
+/// The word being read by `tokenize`.
+#[derive(Default)]
+struct WordBuilder {
+	text: String,
+	/// Unquoted or double-quoted text still to have its variables expanded.
+	pending: String,
+	/// Whether a word has started, so `""` still gives an (empty) word.
+	started: bool,
+	quoted: bool
+}
+
+impl WordBuilder {
+	/// Adds a character that `$NAME` is expanded in.
+	fn push_expanded(&mut self, c: char) {
+		self.pending.push(c);
+		self.started = true;
+	}
+
+	/// Adds a character kept as it is, from quotes or an escape.
+	fn push_literal(&mut self, c: char) -> Result<(), ParseError> {
+		self.flush()?;
+		self.text.push(c);
+		self.quoted = true;
+		Ok(())
+	}
+
+	/// Expands the variables in the pending text and adds it to the word.
+	fn flush(&mut self) -> Result<(), ParseError> {
+		let expanded = env::expand(&self.pending).map_err(ParseError::UnboundVariable)?;
+		self.text.push_str(&expanded);
+		self.pending.clear();
+		self.started = true;
+		Ok(())
+	}
+
+	/// Ends the word, adding it to `tokens`. An unquoted word that expanded
+	/// to nothing is dropped.
+	fn finish(&mut self, tokens: &mut Vec<Token>) -> Result<(), ParseError> {
+		if !self.started {
+			return Ok(());
+		}
+		self.flush()?;
+		let word = core::mem::take(self);
+		if word.quoted || !word.text.is_empty() {
+			tokens.push(Token::Word {
+				text: word.text,
+				glob: !word.quoted
+			});
+		}
+		Ok(())
+	}
+}
+
+/// Splits a command line into tokens.
+///
+/// Single quotes keep everything in them literal. Double quotes keep
+/// everything literal but `$NAME`, and inside them a backslash only escapes
+/// `"`, `\` and `$`. Elsewhere a backslash keeps the next character literal.
+/// Unquoted `|`, `>`, `>>` and `&` are operators even without spaces around
+/// them.
+fn tokenize(line: &str) -> Result<Vec<Token>, ParseError> {
+	let mut tokens = Vec::new();
+	let mut word = WordBuilder::default();
+	let mut chars = line.chars().peekable();
+
+	while let Some(c) = chars.next() {
+		match c {
+			c if c.is_whitespace() => word.finish(&mut tokens)?,
+			'|' => {
+				word.finish(&mut tokens)?;
+				tokens.push(Token::Pipe);
+			}
+			'>' => {
+				word.finish(&mut tokens)?;
+				let append = chars.next_if_eq(&'>').is_some();
+				tokens.push(Token::Redirect { append });
+			}
+			'&' => {
+				word.finish(&mut tokens)?;
+				tokens.push(Token::Background);
+			}
+			'\'' => {
+				word.flush()?;
+				word.quoted = true;
+				loop {
+					match chars.next() {
+						Some('\'') => break,
+						Some(c) => word.text.push(c),
+						None => return Err(ParseError::UnterminatedQuote('\''))
+					}
+				}
+			}
+			'"' => {
+				word.flush()?;
+				word.quoted = true;
+				loop {
+					match chars.next() {
+						Some('"') => break,
+						Some('\\') if matches!(chars.peek(), Some('"' | '\\' | '$')) => {
+							let escaped = chars.next().unwrap_or('\\');
+							word.push_literal(escaped)?;
+						}
+						Some(c) => word.push_expanded(c),
+						None => return Err(ParseError::UnterminatedQuote('"'))
+					}
+				}
+				word.flush()?;
+			}
+			// a trailing backslash stands for itself
+			'\\' => word.push_literal(chars.next().unwrap_or('\\'))?,
+			c => word.push_expanded(c)
+		}
+	}
+	word.finish(&mut tokens)?;
+	Ok(tokens)
+}
+
+/// Where the output of a command is written to instead of the console.
+struct Redirect<'a> {
+	path: &'a str,
+	/// `>>` appends to the file, `>` replaces its content.
+	append: bool
+}
+
+/// Splits a trailing `> file` or `>> file` off a command.
+fn split_redirect(tokens: &[Token]) -> (&[Token], Option<Redirect<'_>>) {
+	match tokens {
+		[rest @ .., Token::Redirect { append }, Token::Word { text, .. }] => (rest, Some(Redirect {
+			path: text,
+			append: *append
+		})),
+		_ => (tokens, None)
+	}
+}
+
+/// Writes captured command output to the file `redirect` names, creating it
+/// if needed.
+fn write_redirect(redirect: &Redirect, output: &str) -> Result<(), FsError> {
+	save_file(redirect.path, output, !redirect.append)
+}
+
+/// Writes `content` to the file at `path`, creating it if it doesn't exist.
+/// Replaces what's in the file if `overwrite` is set, appends otherwise.
+fn save_file(path: &str, content: &str, overwrite: bool) -> Result<(), FsError> {
+	let path = resolve_path(path);
+	fs::with_fs(|fs| {
+		if !fs.exists(&path) {
+			fs.create_file(&path, Permission::all())?;
+		}
+		fs.write_file(&path, content.as_bytes(), overwrite)
+	})
+}
+
+/// Look up and run a command based on input. Commands separated by `|`
+/// run in order, each one's output piped into the next.
 pub fn run_command(input: &str) {
-	let parts: Vec<&str> = input.split_whitespace().collect();
-	if parts.is_empty() {
+	if input.trim().is_empty() {
 		return;
 	}
-	let command = parts[0];
-	let args = &parts[1..];
 
-	// copy the command out while holding the lock
-	let cmd_opt = {
-		let registry = COMMAND_REGISTRY.lock();
-		registry.get(command).copied()
+	let input = match recall_history(input.trim()) {
+		Ok(Some(recalled)) => {
+			// show what is being run again
+			println!("{}", recalled);
+			recalled
+		}
+		Ok(None) => input.to_string(),
+		Err(event) => {
+			println!("{}: event not found", event);
+			return;
+		}
+	};
+	record_history(&input);
+
+	let mut tokens = match tokenize(&input) {
+		Ok(tokens) => tokens,
+		Err(e) => {
+			println!("{}", e);
+			return;
+		}
 	};
 
-	{
+	let background = tokens.last() == Some(&Token::Background);
+	if background {
+		tokens.pop();
+	}
+	if tokens.contains(&Token::Background) {
+		println!("syntax error: '&' can only end a command line");
+		return;
+	}
+
+	if background {
+		spawn_job(input.trim_end().trim_end_matches('&').trim_end(), tokens);
+	} else {
+		run_pipeline(&tokens);
+	}
+}
+
+/// Runs the commands of a pipeline in order, each one's output piped into
+/// the next. Returns the processes of the applications it started.
+fn run_pipeline(tokens: &[Token]) -> Vec<ProcessId> {
+	let mut spawned = Vec::new();
+	let stages: Vec<&[Token]> = tokens.split(|token| *token == Token::Pipe).collect();
+	let mut piped = None;
+	for (i, stage) in stages.iter().enumerate() {
+		let pipe_out = i + 1 < stages.len();
+		match run_stage(stage, piped.take(), pipe_out, &mut spawned) {
+			Some(output) => piped = Some(output),
+			// a failed command ends the pipeline
+			None => break
+		}
+	}
+	spawned
+}
+
+/// A command line running in the background.
+struct Job {
+	/// Number `jobs` lists the job under.
+	id: usize,
+	/// The process running the command line.
+	pid: ProcessId,
+	command: String
+}
+
+/// Runs a pipeline in a process of its own, so the shell doesn't wait for
+/// it. The job is listed by `jobs` until it and the applications it started
+/// have ended; its output goes to the console as it's printed.
+fn spawn_job(command: &str, tokens: Vec<Token>) {
+	let spawned = spawn_process(
+		move |_state| {
+			let tokens = tokens.clone();
+			Box::pin(async move {
+				for pid in run_pipeline(&tokens) {
+					// applications aren't children, so this ends with an
+					// error once the process is gone
+					let _ = wait(pid).await;
+				}
+				0
+			}) as Pin<Box<dyn Future<Output = i32>>>
+		},
+		false
+	);
+
+	match spawned {
+		Ok(pid) => {
+			let mut jobs = BACKGROUND_JOBS.lock();
+			let id = jobs.last().map_or(1, |job| job.id + 1);
+			jobs.push(Job {
+				id,
+				pid,
+				command: command.to_string()
+			});
+			println!("[{}] {}", id, pid.get());
+		}
+		Err(e) => println!("{}", e)
+	}
+}
+
+/// Resolves `!N` (entry `N` of `history`) and `!!` (the last entry) to the
+/// command line they stand for. Returns `None` for anything else, or the
+/// event as an error if there is no such entry.
+fn recall_history(input: &str) -> Result<Option<String>, &str> {
+	let Some(event) = input.strip_prefix('!') else {
+		return Ok(None);
+	};
+
+	let history = CMD_HISTORY.lock();
+	let entry = match event {
+		"!" => history.last(),
+		_ => event
+			.parse::<usize>()
+			.ok()
+			.and_then(|n| n.checked_sub(1))
+			.and_then(|i| history.get(i))
+	};
+	entry.cloned().map(Some).ok_or(input)
+}
+
+/// Adds `input` to the history, dropping the oldest entry past
+/// `MAX_HISTORY_LEN`, and saves the history to `HISTORY_FILE`.
+fn record_history(input: &str) {
+	let saved = {
 		let mut history = CMD_HISTORY.lock();
 		history.push(input.to_string());
+		if history.len() > MAX_HISTORY_LEN {
+			history.remove(0);
+		}
 		// reset the history index to the end of the history.
 		*CMD_HISTORY_INDEX.lock() = history.len();
+
+		let mut saved = history.join("\n");
+		saved.push('\n');
+		saved
+	};
+
+	// history is a convenience, so failing to save it isn't reported
+	let _ = fs::with_fs(|fs| {
+		if !fs.exists(HISTORY_FILE) {
+			fs.create_file(HISTORY_FILE, Permission::all())?;
+		}
+		fs.write_file(HISTORY_FILE, saved.as_bytes(), true)
+	});
+}
+
+/// Loads the history saved in `HISTORY_FILE`, e.g. by an earlier boot with
+/// a persisted filesystem.
+fn load_history() {
+	let Ok(saved) = fs::read_file(HISTORY_FILE) else {
+		return;
+	};
+
+	let saved = String::from_utf8_lossy(&saved);
+	let mut history = CMD_HISTORY.lock();
+	history.clear();
+	history.extend(saved.lines().filter(|line| !line.is_empty()).map(str::to_string));
+	let excess = history.len().saturating_sub(MAX_HISTORY_LEN);
+	history.drain(..excess);
+	*CMD_HISTORY_INDEX.lock() = history.len();
+}
+
+/// Runs a single command of a pipeline with `input` piped into it. Its
+/// output is returned if `pipe_out` is set (or it was redirected, leaving
+/// nothing to pipe on), and printed otherwise. An application it starts is
+/// added to `spawned`. Returns `None` if the command couldn't be run.
+fn run_stage(
+	tokens: &[Token],
+	input: Option<String>,
+	pipe_out: bool,
+	spawned: &mut Vec<ProcessId>
+) -> Option<String> {
+	let (tokens, redirect) = split_redirect(tokens);
+	let mut words = Vec::new();
+	for token in tokens {
+		match token {
+			Token::Word { text, glob } => words.push((text.as_str(), *glob)),
+			Token::Redirect { append } => {
+				let op = if *append { ">>" } else { ">" };
+				println!("syntax error: missing file after '{}'", op);
+				return None;
+			}
+			Token::Pipe | Token::Background => unreachable!("stages are split at pipes and jobs")
+		}
 	}
+	let Some(&(command, _)) = words.first() else {
+		println!("syntax error: missing command");
+		return None;
+	};
 
-	if let Some(cmd) = cmd_opt {
-		(cmd.func)(args);
-	} else {
+	// copy the command out while holding the lock
+	let Some(cmd) = COMMAND_REGISTRY.lock().get(command).copied() else {
 		println!("Command not found: {}", command);
+		return None;
+	};
+
+	let Some(args) = glob::expand_args(&words[1..]) else {
+		println!("{}: argument list too long", command);
+		return None;
+	};
+	let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+	*PIPE_INPUT.lock() = input;
+	let (process, output) = if pipe_out || redirect.is_some() {
+		let (process, output) = capture_output(|| cmd.run(&args));
+		(process, Some(output))
+	} else {
+		(cmd.run(&args), None)
+	};
+	spawned.extend(process);
+	// input the command didn't read isn't left for the next one
+	PIPE_INPUT.lock().take();
+
+	match (redirect, output) {
+		(Some(redirect), Some(output)) => {
+			if let Err(e) = write_redirect(&redirect, &output) {
+				println!("{}: {}: {}", command, redirect.path, e);
+			}
+			Some(String::new())
+		}
+		(_, output) => Some(output.unwrap_or_default())
 	}
 }
 
+/// Takes what was piped into the running command, if anything.
+fn take_piped_input() -> Option<String> {
+	PIPE_INPUT.lock().take()
+}
+
 /// Initialize the default commands for the shell.
 pub fn init_commands() {
 	SYSLOG_SINK.log("Initializing Keyboard Commands...\n", LogLevel::Info);
+	load_history();
 	register_command(Command {
 		name: "echo",
-		func: echo,
 		help: "Print arguments",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(echo)
 	});
 	register_command(Command {
 		name: "clear",
-		func: clear,
 		help: "Clear the screen",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(clear)
+	});
+	register_command(Command {
+		name: "color",
+		help: "Set the colors of the console output by name: color <fg> [bg]",
+		cmd_type: CommandType::Generic(color)
+	});
+	register_command(Command {
+		name: "screenshot",
+		help: "Save the text on screen to a file: screenshot <path>",
+		cmd_type: CommandType::Generic(screenshot)
+	});
+	register_command(Command {
+		name: "cursor",
+		help: "Show or hide the cursor, or set its scan lines: cursor <on|off|shape <start> <end>>",
+		cmd_type: CommandType::Generic(cursor)
 	});
 	register_command(Command {
 		name: "help",
-		func: help,
 		help: "Show available commands",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(help)
 	});
 	register_command(Command {
 		name: "ls",
-		func: ls,
-		help: "List directory contents",
-		cmd_type: CommandType::Generic
+		help: "List directory contents (-l for a long listing)",
+		cmd_type: CommandType::Generic(ls)
 	});
 	register_command(Command {
 		name: "cat",
-		func: cat,
 		help: "Display file content",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(cat)
+	});
+	register_command(Command {
+		name: "stat",
+		help: "Show the size, permissions and timestamps of a file",
+		cmd_type: CommandType::Generic(stat)
+	});
+	register_command(Command {
+		name: "set",
+		help: "Set shell variables (set NAME=value, set -u to fail on unset ones)",
+		cmd_type: CommandType::Generic(set)
+	});
+	register_command(Command {
+		name: "unset",
+		help: "Remove shell variables",
+		cmd_type: CommandType::Generic(unset)
+	});
+	register_command(Command {
+		name: "env",
+		help: "List shell variables",
+		cmd_type: CommandType::Generic(env_vars)
+	});
+	register_command(Command {
+		name: "history",
+		help: "Show the numbered command history (!N runs entry N again)",
+		cmd_type: CommandType::Generic(history)
+	});
+	register_command(Command {
+		name: "which",
+		help: "Show whether a command is a builtin or a program file",
+		cmd_type: CommandType::Generic(which)
+	});
+	register_command(Command {
+		name: "jobs",
+		help: "List command lines started in the background with &",
+		cmd_type: CommandType::Generic(jobs)
 	});
 	register_command(Command {
 		name: "cd",
-		func: cd,
 		help: "Change directory",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(cd)
 	});
 	register_command(Command {
 		name: "touch",
-		func: touch,
 		help: "Create an empty file",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(touch)
 	});
 	register_command(Command {
 		name: "mkdir",
-		func: mkdir,
 		help: "Create a directory",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(mkdir)
 	});
 	register_command(Command {
 		name: "rm",
-		func: rm,
 		help: "Remove a file",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(rm)
 	});
 	register_command(Command {
 		name: "rmdir",
-		func: rmdir,
 		help: "Remove a directory",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(rmdir)
+	});
+	register_command(Command {
+		name: "grep",
+		help: "Print lines of a file containing a pattern (grep [-n] [-i] pattern file)",
+		cmd_type: CommandType::Generic(grep)
+	});
+	register_command(Command {
+		name: "head",
+		help: "Print the first lines of a file (head [-n N] file)",
+		cmd_type: CommandType::Generic(head)
+	});
+	register_command(Command {
+		name: "hexdump",
+		help: "Print a file as hex and text (hexdump [-s offset] [-n length] file)",
+		cmd_type: CommandType::Generic(hexdump)
+	});
+	register_command(Command {
+		name: "xxd",
+		help: "Same as hexdump",
+		cmd_type: CommandType::Generic(hexdump)
+	});
+	register_command(Command {
+		name: "tail",
+		help: "Print the last lines of a file (tail [-n N] file)",
+		cmd_type: CommandType::Generic(tail)
+	});
+	register_command(Command {
+		name: "cp",
+		help: "Copy a file, or a directory with -r (cp [-r] source dest)",
+		cmd_type: CommandType::Generic(cp)
+	});
+	register_command(Command {
+		name: "ln",
+		help: "Create a symbolic link (ln -s target linkname)",
+		cmd_type: CommandType::Generic(ln)
 	});
 	register_command(Command {
 		name: "write",
-		func: write_file,
 		help: "Write content to a file",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(write_file)
+	});
+	register_command(Command {
+		name: "sync",
+		help: "Save the filesystem to disk",
+		cmd_type: CommandType::Generic(sync)
+	});
+	register_command(Command {
+		name: "mount",
+		help: "Load the filesystem from disk",
+		cmd_type: CommandType::Generic(mount)
 	});
 	register_command(Command {
 		name: "progs",
-		func: progs,
 		help: "List running processes",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(progs)
 	});
 	register_command(Command {
 		name: "kill",
-		func: kill,
 		help: "Kill a process",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(kill)
+	});
+	register_command(Command {
+		name: "nice",
+		help: "Set how eagerly a process is run: nice <pid> <high|normal|low>",
+		cmd_type: CommandType::Generic(nice)
+	});
+	register_command(Command {
+		name: "watchdog",
+		help: "Show the hung process watchdog, or set whether it cancels them (on|off)",
+		cmd_type: CommandType::Generic(watchdog)
+	});
+	register_command(Command {
+		name: "sleep",
+		help: "Sleep for a number of milliseconds in a background process",
+		cmd_type: CommandType::Application(sleep)
+	});
+	register_command(Command {
+		name: "forktest",
+		help: "Fork a test process, print from both branches and wait on the child",
+		cmd_type: CommandType::Application(forktest)
+	});
+	register_command(Command {
+		name: "date",
+		help: "Show the date and time from the real time clock",
+		cmd_type: CommandType::Generic(date)
+	});
+	register_command(Command {
+		name: "lspci",
+		help: "List PCI devices and whether a driver is bound to them",
+		cmd_type: CommandType::Generic(lspci)
+	});
+	register_command(Command {
+		name: "keymap",
+		help: "Show or switch the keyboard layout (keymap [us|uk])",
+		cmd_type: CommandType::Generic(keymap)
+	});
+	register_command(Command {
+		name: "loglevel",
+		help: "Show or set a log sink's minimum level (loglevel <stdout|syslog> [level])",
+		cmd_type: CommandType::Generic(loglevel)
 	});
 	register_command(Command {
 		name: "time",
-		func: time,
 		help: "Current date and time.",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(time)
 	});
 	register_command(Command {
 		name: "testnet",
-		func: testnet,
 		help: "Test the network.",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(testnet)
 	});
 	register_command(Command {
 		name: "rslv",
-		func: rslv,
 		help: "Resolve a hostname.",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(rslv)
 	});
 	register_command(Command {
 		name: "ping",
-		func: ping,
 		help: "Ping a hostname",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(ping)
 	});
 	register_command(Command {
 		name: "netpoll",
-		func: netpoll,
 		help: "Poll the RX queue",
-		cmd_type: CommandType::Generic
+		cmd_type: CommandType::Generic(netpoll)
+	});
+	register_command(Command {
+		name: "capture",
+		help: "Capture packets (start|stop|dump|clear)",
+		cmd_type: CommandType::Generic(capture)
 	});
-	register_command(Command { name: "pelf", func: pelf, help: "Parse an ELF file", cmd_type: CommandType::Generic });
-	register_command(Command { name: "nget", func: nget, help: "HTTP requests to the WWW.", cmd_type: CommandType::Generic});
+	register_command(Command {
+		name: "netstat",
+		help: "Network status and connections (netstat [-s])",
+		cmd_type: CommandType::Generic(netstat)
+	});
+	register_command(Command {
+		name: "nc",
+		help: "Raw TCP/UDP session (nc [-u] <ip> <port> | nc [-u] -l <port>)",
+		cmd_type: CommandType::Application(nc)
+	});
+	register_command(Command {
+		name: "setmac",
+		help: "Show or override the MAC address",
+		cmd_type: CommandType::Generic(setmac)
+	});
+	register_command(Command {
+		name: "ifconfig",
+		help: "Show or set the IPv4 config (ifconfig [<ip> <gateway> <mask> | dhcp])",
+		cmd_type: CommandType::Generic(ifconfig)
+	});
+	register_command(Command {
+		name: "acpi",
+		help: "Show the ACPI power management registers",
+		cmd_type: CommandType::Generic(acpi)
+	});
+	register_command(Command {
+		name: "irqstat",
+		help: "Show how often each interrupt has fired, and dropped scancodes",
+		cmd_type: CommandType::Generic(irqstat)
+	});
+	register_command(Command {
+		name: "timerhz",
+		help: "Show or set the APIC timer rate in Hz",
+		cmd_type: CommandType::Generic(timerhz)
+	});
+	register_command(Command {
+		name: "reboot",
+		help: "Restart the machine",
+		cmd_type: CommandType::Generic(reboot)
+	});
+	register_command(Command {
+		name: "shutdown",
+		help: "Power off the machine",
+		cmd_type: CommandType::Generic(shutdown)
+	});
+	register_command(Command { name: "pelf", help: "Parse an ELF file", cmd_type: CommandType::Generic(pelf) });
+	register_command(Command {
+		name: "exec",
+		help: "Run a user program by path or by name from /apps",
+		cmd_type: CommandType::Generic(exec)
+	});
+	register_command(Command { name: "nget", help: "HTTP requests to the WWW.", cmd_type: CommandType::Generic(nget)});
 
 	SYSLOG_SINK.log("Done.\n", LogLevel::Info);
 }
 
+fn jobs(_args: &[&str]) {
+	let mut jobs = BACKGROUND_JOBS.lock();
+	let running: Vec<bool> = {
+		let executor = EXECUTOR.lock();
+		jobs.iter().map(|job| executor.processes.contains_key(&job.pid)).collect()
+	};
+
+	for (job, running) in jobs.iter().zip(&running) {
+		let state = if *running { "Running" } else { "Done" };
+		println!("[{}] {:>5}  {:<8} {}", job.id, job.pid.get(), state, job.command);
+	}
+	// finished jobs are reported once
+	let mut running = running.into_iter();
+	jobs.retain(|_| running.next().unwrap_or(false));
+}
+
 fn progs(_args: &[&str]) {
 	if let Some(executor) = EXECUTOR.try_lock() {
 		executor.list_processes();
@@ -219,24 +849,123 @@ fn clear(_args: &[&str]) {
 	WRITER.lock().clear_everything();
 }
 
+fn color(args: &[&str]) {
+	let (fg, bg) = match args {
+		[fg] => (*fg, "black"),
+		[fg, bg] => (*fg, *bg),
+		_ => {
+			println!("Usage: color <fg> [bg]");
+			return;
+		}
+	};
+	match (string_to_color(fg), string_to_color(bg)) {
+		(Ok(fg), Ok(bg)) => WRITER.lock().set_colors(fg, bg),
+		(Err(e), _) => println!("color: '{}': {}", fg, e),
+		(_, Err(e)) => println!("color: '{}': {}", bg, e)
+	}
+}
+
+fn cursor(args: &[&str]) {
+	match args {
+		["on"] => WRITER.lock().set_cursor_visible(true),
+		["off"] => WRITER.lock().set_cursor_visible(false),
+		["shape", start, end] => {
+			let (Ok(start), Ok(end)) = (start.parse::<u8>(), end.parse::<u8>()) else {
+				println!("cursor: scan lines must be numbers from 0 to 15");
+				return;
+			};
+			if let Err(e) = WRITER.lock().set_cursor_shape(start, end) {
+				println!("cursor: {}", e);
+			}
+		}
+		_ => println!("Usage: cursor <on|off|shape <start> <end>>")
+	}
+}
+
 fn help(_args: &[&str]) {
-	println!("Available commands:");
-	for cmd in COMMAND_REGISTRY.lock().values() {
-		println!("{} - {}", cmd.name, cmd.help);
+	let commands: Vec<Command> = COMMAND_REGISTRY.lock().values().copied().collect();
+	let (applications, builtins): (Vec<&Command>, Vec<&Command>) = commands
+		.iter()
+		.partition(|cmd| matches!(cmd.cmd_type, CommandType::Application(_)));
+
+	println!("Commands:");
+	for cmd in builtins {
+		println!("  {} - {}", cmd.name, cmd.help);
+	}
+	println!("Applications (run as their own process):");
+	for cmd in applications {
+		println!("  {} - {}", cmd.name, cmd.help);
 	}
 }
 
 fn ls(args: &[&str]) {
-	let path = resolve_path(if args.is_empty() { "." } else { args[0] });
-	fs::with_fs(|fs| match fs.list_dir(&path) {
-		Ok(entries) => {
-			for entry in entries {
-				print!("{} ", entry);
+	let mut long = false;
+	let mut target = None;
+	for arg in args {
+		match arg.strip_prefix('-') {
+			Some(flags) if !flags.is_empty() => {
+				for flag in flags.chars() {
+					match flag {
+						'l' => long = true,
+						// there are no hidden files to show yet
+						'a' => {}
+						_ => {
+							println!("ls: invalid option -- '{}'", flag);
+							return;
+						}
+					}
+				}
 			}
-			println!();
+			_ => target = Some(*arg)
 		}
-		Err(_) => println!("ls: cannot access '{}'", path)
-	});
+	}
+
+	let path = resolve_path(target.unwrap_or("."));
+	let entries = match fs::list_dir(&path) {
+		Ok(entries) => entries,
+		Err(_) => {
+			println!("ls: cannot access '{}'", path);
+			return;
+		}
+	};
+
+	if !long {
+		for entry in entries {
+			print!("{} ", entry);
+		}
+		println!();
+		return;
+	}
+
+	for entry in entries {
+		let entry_path = format!("{}/{}", path.trim_end_matches('/'), entry);
+		let link = fs::with_fs(|fs| fs.read_link(&entry_path).map(str::to_string).ok());
+		let name = match &link {
+			Some(target) => format!("{} -> {}", entry, target),
+			None => entry
+		};
+		match fs::stat(&entry_path) {
+			Ok(stat) => {
+				let kind = match (&link, stat.is_dir) {
+					(Some(_), _) => 'l',
+					(None, true) => 'd',
+					(None, false) => '-'
+				};
+				let ms = apic::to_hrt(stat.metadata.modified) / 1_000_000;
+				println!(
+					"{}{} {:>8} {:>6}.{:03}s {}",
+					kind,
+					stat.permission,
+					stat.metadata.size,
+					ms / 1000,
+					ms % 1000,
+					name
+				);
+			}
+			// a dangling link has nothing to stat
+			Err(_) => println!("l??? {:>8} {:>11} {}", "?", "?", name)
+		}
+	}
 }
 
 fn cat(args: &[&str]) {
@@ -245,29 +974,126 @@ fn cat(args: &[&str]) {
 		return;
 	}
 	let path = resolve_path(args[0]);
-	fs::with_fs(|fs| match fs.read_file(&path) {
+	match fs::read_file(&path) {
 		Ok(content) => {
-			let s = String::from_utf8_lossy(content);
+			let s = String::from_utf8_lossy(&content);
 			println!("{}", s)
 		}
 		Err(_) => println!("cat: {}: No such file ", path)
-	});
+	}
 }
 
-fn cd(args: &[&str]) {
-	let path = if args.is_empty() {
-		"/".to_string()
+fn stat(args: &[&str]) {
+	if args.is_empty() {
+		println!("stat: missing file operand");
+		return;
+	}
+	let path = resolve_path(args[0]);
+	let stat = match fs::stat(&path) {
+		Ok(stat) => stat,
+		Err(e) => {
+			println!("stat: cannot stat '{}': {}", path, e);
+			return;
+		}
+	};
+
+	// ticks alongside the time since boot they stand for
+	let time = |ticks: u64| {
+		let ms = apic::to_hrt(ticks) / 1_000_000;
+		format!("tick {} ({}.{:03}s)", ticks, ms / 1000, ms % 1000)
+	};
+	let (kind, unit) = if stat.is_dir {
+		("directory", "entries")
 	} else {
-		resolve_path(args[0])
+		("file", "bytes")
 	};
+	println!("  File: {}", path);
+	println!(
+		"  Type: {}  Access: {}  Size: {} {}",
+		kind, stat.permission, stat.metadata.size, unit
+	);
+	println!("Create: {}", time(stat.metadata.created));
+	println!("Modify: {}", time(stat.metadata.modified));
+}
 
-	fs::with_fs(|fs| {
-		if fs.is_dir(&path) {
-			*CWD.lock() = path;
-		} else {
-			println!("cd: no such directory: {}", args[0]);
+fn set(args: &[&str]) {
+	match args {
+		[] => env_vars(args),
+		["-u"] => env::set_error_on_unset(true),
+		["+u"] => env::set_error_on_unset(false),
+		_ => {
+			// the value is everything after the first `=`, spaces included
+			let assignment = args.join(" ");
+			let Some((name, value)) = assignment.split_once('=') else {
+				println!("usage: set NAME=value | set -u | set +u");
+				return;
+			};
+			if !env::set(name, value) {
+				println!("set: '{}': not a valid variable name", name);
+			}
 		}
-	});
+	}
+}
+
+fn unset(args: &[&str]) {
+	if args.is_empty() {
+		println!("unset: missing variable name");
+	}
+	for name in args {
+		env::unset(name);
+	}
+}
+
+fn env_vars(_args: &[&str]) {
+	for (name, value) in env::vars() {
+		println!("{}={}", name, value);
+	}
+}
+
+fn history(_args: &[&str]) {
+	let history = CMD_HISTORY.lock().clone();
+	for (i, command) in history.iter().enumerate() {
+		println!("{:>5}  {}", i + 1, command);
+	}
+}
+
+fn which(args: &[&str]) {
+	if args.is_empty() {
+		println!("usage: which <name>...");
+		return;
+	}
+
+	for name in args {
+		let command = COMMAND_REGISTRY.lock().get(*name).copied();
+		match command.map(|cmd| cmd.cmd_type) {
+			Some(CommandType::Generic(_)) => println!("{}: shell builtin", name),
+			Some(CommandType::Application(_)) => println!("{}: shell application", name),
+			None => {}
+		}
+
+		// programs run with `exec`, found the way it finds them
+		let path = program_path(name);
+		let executable = fs::with_fs(|fs| {
+			fs.effective_permission(&path)
+				.is_ok_and(|permission| permission.execute)
+		});
+		if executable {
+			println!("{}", path);
+		}
+
+		if command.is_none() && !executable {
+			println!("{}: not found", name);
+		}
+	}
+}
+
+fn cd(args: &[&str]) {
+	let path = args.first().copied().unwrap_or("/");
+
+	// changes the shell's `CWD`, or the directory of a process with its own
+	if fs::set_current_dir(path).is_err() {
+		println!("cd: no such directory: {}", path);
+	}
 }
 
 fn touch(args: &[&str]) {
@@ -285,67 +1111,362 @@ fn touch(args: &[&str]) {
 	}
 }
 
-fn mkdir(args: &[&str]) {
-	if args.is_empty() {
-		println!("mkdir: missing operand");
-		return;
-	}
-	for dir in args {
-		let path = resolve_path(dir);
-		fs::with_fs(|fs| {
-			if fs.create_dir(&path, Permission::all()).is_err() {
-				println!("mkdir: cannot create directory '{}'", dir);
-			}
-		});
+fn mkdir(args: &[&str]) {
+	if args.is_empty() {
+		println!("mkdir: missing operand");
+		return;
+	}
+	for dir in args {
+		let path = resolve_path(dir);
+		fs::with_fs(|fs| {
+			if fs.create_dir(&path, Permission::all()).is_err() {
+				println!("mkdir: cannot create directory '{}'", dir);
+			}
+		});
+	}
+}
+
+fn rm(args: &[&str]) {
+	if args.is_empty() {
+		println!("rm: missing operand");
+		return;
+	}
+	for arg in args {
+		let path = resolve_path(arg);
+		fs::with_fs(|fs| {
+			if fs.is_dir(&path) && !fs.is_symlink(&path) {
+				println!("rm: cannot remove '{}': Is a directory", arg);
+			} else {
+				match fs.remove(&path, false, false) {
+					Ok(_) => {}
+					Err(_) => println!("rm: cannot remove '{}': No such file", arg)
+				}
+			}
+		});
+	}
+}
+
+fn rmdir(args: &[&str]) {
+	if args.is_empty() {
+		println!("rmdir: missing operand");
+		return;
+	}
+	let recursive = args.contains(&"-r");
+	let dirs: Vec<&str> = args.iter().filter(|&&arg| arg != "-r").cloned().collect();
+	if dirs.is_empty() {
+		println!("rmdir: missing operand");
+		return;
+	}
+	for dir in dirs {
+		let path = resolve_path(dir);
+		fs::with_fs(|fs| {
+			if fs.is_dir(&path) {
+				match fs.remove(&path, true, recursive) {
+					Ok(_) => {}
+					Err(_) => println!("rmdir: failed to remove '{}'", dir)
+				}
+			} else {
+				println!("rmdir: failed to remove '{}': Not a directory", dir);
+			}
+		});
+	}
+}
+
+fn ln(args: &[&str]) {
+	if args.len() != 3 || args[0] != "-s" {
+		println!("usage: ln -s <target> <linkname> (only symbolic links are supported)");
+		return;
+	}
+
+	let target = args[1];
+	let path = resolve_path(args[2]);
+	fs::with_fs(|fs| {
+		if let Err(e) = fs.create_symlink(&path, target) {
+			println!("ln: cannot create link '{}': {}", args[2], e);
+		}
+	});
+}
+
+/// Options of the `grep` command.
+#[derive(Default)]
+struct GrepOptions {
+	/// Prefix matching lines with their line number (`-n`).
+	line_numbers: bool,
+	/// Ignore ASCII case when matching (`-i`).
+	ignore_case: bool
+}
+
+fn grep(args: &[&str]) {
+	let mut options = GrepOptions::default();
+	let mut operands = Vec::new();
+	for arg in args {
+		match arg.strip_prefix('-').filter(|flags| !flags.is_empty()) {
+			Some(flags) => {
+				for flag in flags.chars() {
+					match flag {
+						'n' => options.line_numbers = true,
+						'i' => options.ignore_case = true,
+						_ => {
+							println!("grep: unknown option '-{}'", flag);
+							return;
+						}
+					}
+				}
+			}
+			None => operands.push(*arg)
+		}
+	}
+
+	let (pattern, file) = match operands[..] {
+		[pattern] => (pattern, None),
+		[pattern, file] => (pattern, Some(file)),
+		_ => {
+			println!("usage: grep [-n] [-i] <pattern> [file]");
+			return;
+		}
+	};
+
+	with_input("grep", file, |content| {
+		grep_lines(content, pattern, &options, |number, line| {
+			if options.line_numbers {
+				println!("{}:{}", number, line);
+			} else {
+				println!("{}", line);
+			}
+		});
+	});
+}
+
+/// Runs `f` on the content of the file at `path`. RAMFS files are read in
+/// place, only `/proc` content has to be generated.
+fn with_file_content<R>(path: &str, f: impl FnOnce(&[u8]) -> R) -> Result<R, FsError> {
+	if fs::procfs::is_proc_path(path) {
+		fs::read_file(path).map(|content| f(&content))
+	} else {
+		fs::with_fs(|fs| fs.read_file(path).map(f))
+	}
+}
+
+/// Runs `f` on the input of `command`: the file at `file` if one is given,
+/// otherwise what was piped into it. Prints an error if there is neither.
+fn with_input<R>(command: &str, file: Option<&str>, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+	let result = match file {
+		Some(file) => with_file_content(&resolve_path(file), f).ok(),
+		None => take_piped_input().map(|input| f(input.as_bytes()))
+	};
+	match (&result, file) {
+		(Some(_), _) => {}
+		(None, Some(file)) => println!("{}: {}: No such file", command, file),
+		(None, None) => println!("{}: missing file operand", command)
+	}
+	result
+}
+
+/// Number of lines `head` and `tail` print by default.
+const DEFAULT_LINE_COUNT: usize = 10;
+
+/// Parses `[-n N] [file]`, the arguments of `head` and `tail`. Without a
+/// file they read what was piped in.
+fn parse_line_count<'a>(name: &str, args: &[&'a str]) -> Option<(usize, Option<&'a str>)> {
+	let parsed = match args {
+		[] => Some((DEFAULT_LINE_COUNT, None)),
+		[file] => Some((DEFAULT_LINE_COUNT, Some(*file))),
+		["-n", count] => count.parse().ok().map(|count| (count, None)),
+		["-n", count, file] => count.parse().ok().map(|count| (count, Some(*file))),
+		_ => None
+	};
+	if parsed.is_none() {
+		println!("usage: {} [-n N] [file]", name);
+	}
+	parsed
+}
+
+/// Prints part of a file, ending the output with a newline even if the
+/// file's last line has none.
+fn print_lines(lines: &[u8]) {
+	print!("{}", String::from_utf8_lossy(lines));
+	if lines.last().is_some_and(|b| *b != b'\n') {
+		println!();
+	}
+}
+
+/// The first `count` lines of `content`.
+fn head_lines(content: &[u8], count: usize) -> &[u8] {
+	if count == 0 {
+		return &[];
+	}
+
+	let end = content
+		.iter()
+		.enumerate()
+		.filter(|(_, b)| **b == b'\n')
+		.nth(count - 1)
+		.map_or(content.len(), |(i, _)| i + 1);
+	&content[..end]
+}
+
+/// The last `count` lines of `content`, found by scanning back from the end.
+fn tail_lines(content: &[u8], count: usize) -> &[u8] {
+	if count == 0 {
+		return &[];
+	}
+
+	// a trailing newline ends the last line rather than starting another
+	let body = content.strip_suffix(b"\n").unwrap_or(content);
+	let mut start = body.len();
+	for _ in 0..count {
+		match body[..start].iter().rposition(|b| *b == b'\n') {
+			Some(newline) => start = newline,
+			// fewer lines than asked for
+			None => return content
+		}
+	}
+	&content[start + 1..]
+}
+
+fn head(args: &[&str]) {
+	let Some((count, file)) = parse_line_count("head", args) else {
+		return;
+	};
+	with_input("head", file, |content| print_lines(head_lines(content, count)));
+}
+
+fn tail(args: &[&str]) {
+	let Some((count, file)) = parse_line_count("tail", args) else {
+		return;
+	};
+	with_input("tail", file, |content| print_lines(tail_lines(content, count)));
+}
+
+/// Bytes shown on each line of `hexdump`.
+const HEXDUMP_WIDTH: usize = 16;
+
+/// Formats `content` the way `hexdump -C` does: a line per 16 bytes with
+/// their offset, the bytes in hex and the printable ones as text, then the
+/// offset the content ends at. `start` is the offset of its first byte.
+fn hexdump_lines(content: &[u8], start: usize) -> Vec<String> {
+	let mut lines = Vec::new();
+	for (i, chunk) in content.chunks(HEXDUMP_WIDTH).enumerate() {
+		let mut line = format!("{:08x}  ", start + i * HEXDUMP_WIDTH);
+		for column in 0..HEXDUMP_WIDTH {
+			match chunk.get(column) {
+				Some(byte) => line.push_str(&format!("{:02x} ", byte)),
+				None => line.push_str("   ")
+			}
+			// split the bytes into two groups of eight
+			if column == HEXDUMP_WIDTH / 2 - 1 {
+				line.push(' ');
+			}
+		}
+		line.push_str(" |");
+		for byte in chunk {
+			line.push(if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' });
+		}
+		line.push('|');
+		lines.push(line);
+	}
+	lines.push(format!("{:08x}", start + content.len()));
+	lines
+}
+
+/// Parses a byte count or offset, in decimal or with a `0x` prefix in hex.
+fn parse_size(value: &str) -> Option<usize> {
+	match value.strip_prefix("0x") {
+		Some(hex) => usize::from_str_radix(hex, 16).ok(),
+		None => value.parse().ok()
 	}
 }
 
-fn rm(args: &[&str]) {
-	if args.is_empty() {
-		println!("rm: missing operand");
-		return;
-	}
-	for arg in args {
-		let path = resolve_path(arg);
-		fs::with_fs(|fs| {
-			if fs.is_dir(&path) {
-				println!("rm: cannot remove '{}': Is a directory", arg);
-			} else {
-				match fs.remove(&path, false, false) {
-					Ok(_) => {}
-					Err(_) => println!("rm: cannot remove '{}': No such file", arg)
+fn hexdump(args: &[&str]) {
+	let mut offset = 0;
+	let mut length = None;
+	let mut file = None;
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		match *arg {
+			flag @ ("-s" | "-n") => {
+				let Some(value) = args.next().and_then(|value| parse_size(value)) else {
+					println!("hexdump: {} needs a number of bytes", flag);
+					return;
+				};
+				if flag == "-s" {
+					offset = value;
+				} else {
+					length = Some(value);
 				}
 			}
-		});
+			path if file.is_none() => file = Some(path),
+			_ => {
+				println!("usage: hexdump [-s offset] [-n length] [file]");
+				return;
+			}
+		}
 	}
+
+	with_input("hexdump", file, |content| {
+		let content = content.get(offset..).unwrap_or_default();
+		let content = &content[..length.map_or(content.len(), |length| length.min(content.len()))];
+		for line in hexdump_lines(content, offset) {
+			println!("{}", line);
+		}
+	});
 }
 
-fn rmdir(args: &[&str]) {
-	if args.is_empty() {
-		println!("rmdir: missing operand");
-		return;
-	}
-	let recursive = args.contains(&"-r");
-	let dirs: Vec<&str> = args.iter().filter(|&&arg| arg != "-r").cloned().collect();
-	if dirs.is_empty() {
-		println!("rmdir: missing operand");
+/// Calls `on_match` with the (1-based) number and text of every line of
+/// `content` containing `pattern`.
+fn grep_lines(
+	content: &[u8],
+	pattern: &str,
+	options: &GrepOptions,
+	mut on_match: impl FnMut(usize, &str)
+) {
+	if content.is_empty() {
 		return;
 	}
-	for dir in dirs {
-		let path = resolve_path(dir);
-		fs::with_fs(|fs| {
-			if fs.is_dir(&path) {
-				match fs.remove(&path, true, recursive) {
-					Ok(_) => {}
-					Err(_) => println!("rmdir: failed to remove '{}'", dir)
+
+	// a trailing newline ends the last line rather than starting another
+	let content = content.strip_suffix(b"\n").unwrap_or(content);
+	let pattern = pattern.as_bytes();
+	for (i, line) in content.split(|b| *b == b'\n').enumerate() {
+		let line = line.strip_suffix(b"\r").unwrap_or(line);
+		let matched = pattern.is_empty()
+			|| line.windows(pattern.len()).any(|window| {
+				if options.ignore_case {
+					window.eq_ignore_ascii_case(pattern)
+				} else {
+					window == pattern
 				}
-			} else {
-				println!("rmdir: failed to remove '{}': Not a directory", dir);
-			}
-		});
+			});
+
+		if matched {
+			on_match(i + 1, &String::from_utf8_lossy(line));
+		}
 	}
 }
 
+fn cp(args: &[&str]) {
+	let (recursive, source, dest) = match args {
+		["-r", source, dest] => (true, *source, *dest),
+		[source, dest] => (false, *source, *dest),
+		_ => {
+			println!("usage: cp [-r] <source> <dest>");
+			return;
+		}
+	};
+
+	let from = resolve_path(source);
+	let to = resolve_path(dest);
+	fs::with_fs(|fs| {
+		if !recursive && fs.is_dir(&from) {
+			println!("cp: -r not specified; omitting directory '{}'", source);
+			return;
+		}
+		if let Err(e) = fs.copy(&from, &to) {
+			println!("cp: cannot copy '{}' to '{}': {}", source, dest, e);
+		}
+	});
+}
+
 fn write_file(args: &[&str]) {
 	if args.len() < 2 {
 		println!("Usage: write <file> <content>");
@@ -360,6 +1481,100 @@ fn write_file(args: &[&str]) {
 	});
 }
 
+fn screenshot(args: &[&str]) {
+	let [path] = args else {
+		println!("Usage: screenshot <path>");
+		return;
+	};
+	let screen = WRITER.lock().copy_vga_buffer().to_text();
+	match save_file(path, &screen, true) {
+		Ok(()) => println!("screenshot: saved to '{}'", path),
+		Err(e) => println!("screenshot: '{}': {}", path, e)
+	}
+}
+
+fn sync(_args: &[&str]) {
+	match fs::persist::sync() {
+		Ok(bytes) => println!("sync: wrote {} bytes to disk", bytes),
+		Err(e) => println!("sync: {}", e)
+	}
+}
+
+fn mount(_args: &[&str]) {
+	match fs::persist::mount() {
+		Ok(()) => println!("mount: filesystem loaded from disk"),
+		Err(e) => println!("mount: {}", e)
+	}
+}
+
+fn acpi(_args: &[&str]) {
+	let fadt = match crate::acpi::fadt() {
+		Ok(fadt) => fadt,
+		Err(e) => {
+			println!("acpi: no usable FADT: {}", e);
+			return;
+		}
+	};
+	let port = |port: Option<u16>| port.map_or("none".to_string(), |port| format!("{:#x}", port));
+
+	println!("FADT revision {}, SCI on IRQ {}", fadt.revision, fadt.sci_interrupt);
+	println!(
+		"ACPI mode: {}",
+		if fadt.acpi_mode_enabled() { "enabled" } else { "disabled" }
+	);
+	println!(
+		"SMI command: {} (enable {:#x}, disable {:#x})",
+		port(fadt.smi_command),
+		fadt.acpi_enable,
+		fadt.acpi_disable
+	);
+	println!("PM1a event: {}, control: {}", port(fadt.pm1a_event), port(fadt.pm1a_control));
+	println!("PM1b event: {}, control: {}", port(fadt.pm1b_event), port(fadt.pm1b_control));
+	match fadt.reset_register {
+		Some((register, value)) => println!("Reset register: {:#x} <- {:#x}", register, value),
+		None => println!("Reset register: none")
+	}
+	match fadt.s5_sleep_types() {
+		Some((a, b)) => println!("S5 sleep types: SLP_TYPa={} SLP_TYPb={}", a, b),
+		None => println!("S5 sleep types: not found in the DSDT")
+	}
+}
+
+fn irqstat(_args: &[&str]) {
+	println!("{:>6} {:>5} {:>8} {:>12}", "VECTOR", "GSI", "HANDLER", "COUNT");
+	for stat in crate::interrupts::irq_stats() {
+		let gsi = stat.gsi.map_or("-".to_string(), |gsi| gsi.to_string());
+		let handler = if stat.has_handler { "yes" } else { "no" };
+		println!("{:>6} {:>5} {:>8} {:>12}", stat.vector, gsi, handler, stat.count);
+	}
+	println!("Dropped scancodes: {}", dropped_scancodes());
+}
+
+fn timerhz(args: &[&str]) {
+	let Some(hz) = args.first() else {
+		println!("{} Hz, up {} ms", crate::apic::timer_hz(), crate::apic::uptime_ns() / 1_000_000);
+		return;
+	};
+	let Ok(hz) = hz.parse::<u64>() else {
+		println!("timerhz: invalid rate '{}'", hz);
+		return;
+	};
+	match crate::apic::set_timer_frequency(hz) {
+		Ok(()) => println!("APIC timer now at {} Hz", hz),
+		Err(e) => println!("timerhz: {}", e)
+	}
+}
+
+fn reboot(_args: &[&str]) {
+	println!("Rebooting...");
+	reset::reboot();
+}
+
+fn shutdown(_args: &[&str]) {
+	println!("Powering off...");
+	reset::shutdown();
+}
+
 fn kill(args: &[&str]) {
 	if args.is_empty() {
 		println!("kill: missing PID");
@@ -374,10 +1589,169 @@ fn kill(args: &[&str]) {
 		}
 	};
 
-	EXECUTOR.lock().end_process(ProcessId::new(pid), -2);
+	// the process gets its grace period to clean up; the executor ends it
+	// once that runs out
+	match cancel(ProcessId::new(pid)) {
+		Ok(()) => serial_println!("Cancelled process {}", pid),
+		Err(e) => println!("kill: {}: {}", pid, e)
+	}
+}
+
+fn nice(args: &[&str]) {
+	let [pid, priority] = args else {
+		println!("Usage: nice <pid> <high|normal|low>");
+		return;
+	};
+	let Ok(pid) = pid.parse::<u64>() else {
+		println!("nice: invalid PID '{}'", pid);
+		return;
+	};
+	let Some(priority) = Priority::from_name(priority) else {
+		println!("nice: invalid priority '{}', expected high, normal or low", priority);
+		return;
+	};
+
+	match set_priority(ProcessId::new(pid), priority) {
+		Ok(()) => println!("Process {} now runs at {} priority", pid, priority.name()),
+		Err(e) => println!("nice: {}", e)
+	}
+}
+
+fn watchdog(args: &[&str]) {
+	match args.first().copied() {
+		None => {}
+		Some("on") => watchdog::set_cancel_hung(true),
+		Some("off") => watchdog::set_cancel_hung(false),
+		Some(arg) => {
+			println!("watchdog: expected 'on' or 'off', got '{}'", arg);
+			return;
+		}
+	}
+	let action = if watchdog::cancels_hung() { "reported and cancelled" } else { "reported" };
+	println!(
+		"Processes not yielding for {} ms are {}",
+		watchdog::WATCHDOG_TIMEOUT_MS,
+		action
+	);
+}
+
+fn forktest(state: Arc<ProcessState>, _args: Vec<String>) -> Pin<Box<dyn Future<Output = i32>>> {
+	Box::pin(async move {
+		// runs once in the parent and again in the child
+		let pid = state.id.get();
+		match fork() {
+			Ok(0) => {
+				println!("forktest: child (pid {}, is_child {})", pid, state.is_child);
+				FORKTEST_CHILD_EXIT_CODE
+			}
+			Ok(child) => {
+				println!("forktest: parent (pid {}) forked child {}", pid, child);
+				match wait(ProcessId::new(child)).await {
+					Ok(code) => println!("forktest: child {} exited with {}", child, code),
+					Err(e) => println!("forktest: wait failed: {}", e)
+				}
+				0
+			}
+			Err(e) => {
+				println!("forktest: fork failed: {}", e);
+				-1
+			}
+		}
+	})
+}
+
+fn sleep(state: Arc<ProcessState>, args: Vec<String>) -> Pin<Box<dyn Future<Output = i32>>> {
+	Box::pin(async move {
+		let Some(ms) = args.first().and_then(|ms| ms.parse::<u64>().ok()) else {
+			println!("usage: sleep <milliseconds>");
+			return 1;
+		};
+
+		let start = tsc::now_ns();
+		sleep_ms(ms).await;
+		let slept = (tsc::now_ns() - start) / 1_000;
+		println!(
+			"sleep: process {} woke after {}.{:03} ms",
+			state.id.get(),
+			slept / 1000,
+			slept % 1000
+		);
+		0
+	})
+}
+
+fn date(_args: &[&str]) {
+	let now = rtc::now();
+	println!(
+		"{} {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+		now.weekday_name(),
+		now.year,
+		now.month,
+		now.day,
+		now.hour,
+		now.min,
+		now.sec
+	);
+}
+
+fn lspci(_args: &[&str]) {
+	let devices = pci::PCI_DEVICES.lock();
+	for device in devices.iter() {
+		let driver = match device.bound_driver() {
+			Some(instance) => format!("driver instance {}", instance),
+			None => "no driver".to_string()
+		};
+		println!(
+			"{} {} [{:02x}{:02x}]: {:04x}:{:04x} ({})",
+			device.bdf,
+			pci::class_name(device.class(), device.subclass()),
+			device.class(),
+			device.subclass(),
+			device.vendor_id(),
+			device.device_id(),
+			driver
+		);
+	}
+}
+
+fn keymap(args: &[&str]) {
+	match args {
+		[] => println!("keymap: {}", layouts::keymap().name()),
+		[name] => match Keymap::from_name(name) {
+			Some(keymap) => {
+				layouts::set_keymap(keymap);
+				println!("keymap: {}", keymap.name());
+			}
+			None => println!("keymap: unknown layout '{}' (us, uk)", name)
+		},
+		_ => println!("Usage: keymap [us|uk]")
+	}
+}
 
-	// Kill process
-	serial_println!("Killed process {}", pid);
+fn loglevel(args: &[&str]) {
+	match args {
+		[sink] => match *sink {
+			"stdout" => println!("stdout: {}", STDOUT_SINK.min_level().name()),
+			"syslog" => println!("syslog: {}", SYSLOG_SINK.min_level().name()),
+			_ => println!("loglevel: unknown sink '{}' (stdout, syslog)", sink)
+		},
+		[sink, level] => {
+			let Some(level) = LogLevel::from_name(level) else {
+				println!("loglevel: unknown level '{}' (debug, info, warn, error, fatal)", level);
+				return;
+			};
+			match *sink {
+				"stdout" => STDOUT_SINK.set_min_level(level),
+				"syslog" => SYSLOG_SINK.set_min_level(level),
+				_ => {
+					println!("loglevel: unknown sink '{}' (stdout, syslog)", sink);
+					return;
+				}
+			}
+			println!("{}: {}", sink, level.name());
+		}
+		_ => println!("Usage: loglevel <stdout|syslog> [debug|info|warn|error|fatal]")
+	}
 }
 
 fn time(_args: &[&str]) {
@@ -387,7 +1761,7 @@ fn time(_args: &[&str]) {
 }
 
 fn testnet(_args: &[&str]) {
-	match crate::net::send_arp_request(crate::net::GATEWAY_IP) {
+	match crate::net::send_arp_request(net::gateway_ip()) {
 		Ok(()) => println!("ARP request sent to gateway"),
 		Err(e) => println!("Failed to send ARP: {}", e)
 	}
@@ -433,6 +1807,249 @@ fn netpoll(_args: &[&str]) {
 	println!("=== Poll Complete ===");
 }
 
+fn capture(args: &[&str]) {
+	use crate::net::capture;
+
+	match args.first().copied() {
+		Some("start") => {
+			capture::start();
+			println!("capture: started (ring holds {} packets)", capture::CAPTURE_RING_SIZE);
+		}
+		Some("stop") => {
+			capture::stop();
+			println!("capture: stopped");
+		}
+		Some("dump") => capture::dump(),
+		Some("clear") => capture::clear(),
+		_ => println!(
+			"usage: capture <start|stop|dump|clear> (currently {})",
+			if capture::is_enabled() { "running" } else { "stopped" }
+		)
+	}
+}
+
+fn netstat(args: &[&str]) {
+	use crate::net::{tcp, udp};
+
+	match args {
+		[] => {}
+		["-s"] => {
+			netstat_limits();
+			return;
+		}
+		_ => {
+			println!("usage: netstat [-s]");
+			return;
+		}
+	}
+
+	let mac = match crate::net::get_our_mac() {
+		Some(mac) => mac
+			.iter()
+			.map(|b| format!("{:02x}", b))
+			.collect::<Vec<_>>()
+			.join(":"),
+		None => "none (no network device)".to_string()
+	};
+	println!("Local IP:    {}", Ipv4Addr::from_octets(net::our_ip()));
+	println!("Local MAC:   {}", mac);
+
+	// each lock is held only long enough to copy out what is printed
+	let arp_entries = ARP_CACHE.lock().len();
+	println!("ARP cache:   {} entries", arp_entries);
+
+	let ports = udp::bound_ports();
+	if ports.is_empty() {
+		println!("UDP ports:   none");
+	} else {
+		let ports: Vec<String> = ports.iter().map(|p| p.to_string()).collect();
+		println!("UDP ports:   {}", ports.join(", "));
+	}
+
+	let connections = tcp::connections();
+	if connections.is_empty() {
+		println!("TCP:         no connections");
+		return;
+	}
+	println!("Proto  Local Address          Remote Address         State");
+	for conn in connections {
+		let local = match (conn.local, conn.listen_port) {
+			(Some(local), _) => local.to_string(),
+			(None, Some(port)) => format!("*:{}", port),
+			(None, None) => "*:*".to_string()
+		};
+		let remote = conn.remote.map_or("*:*".to_string(), |remote| remote.to_string());
+		println!("tcp    {:<22} {:<22} {}", local, remote, conn.state);
+	}
+}
+
+fn netstat_limits() {
+	use crate::net::{
+		ipv6::NEIGHBOR_CACHE,
+		limits::{self, Resource},
+		reassembly::REASSEMBLER,
+		udp
+	};
+
+	let max = limits::get();
+	let arp_entries = ARP_CACHE.lock().len();
+	let neighbor_entries = NEIGHBOR_CACHE.lock().len();
+	let reassembly = REASSEMBLER.lock().pending();
+
+	println!("Resource                 In use / Limit");
+	println!(
+		"sockets                  {:>6} / {}",
+		limits::in_use(Resource::Socket),
+		max.max_sockets
+	);
+	println!("  udp handlers           {:>6}", udp::handler_count());
+	println!(
+		"pending connections      {:>6} / {}",
+		limits::in_use(Resource::PendingConnection),
+		max.max_pending_connections
+	);
+	println!("arp entries              {:>6} / {}", arp_entries, max.max_arp_entries);
+	println!("ipv6 neighbor entries    {:>6} / {}", neighbor_entries, max.max_arp_entries);
+	println!(
+		"reassembly buffers       {:>6} / {}",
+		reassembly,
+		max.max_reassembly_buffers
+	);
+}
+
+fn nc(state: Arc<ProcessState>, args: Vec<String>) -> Pin<Box<dyn Future<Output = i32>>> {
+	use crate::net::netcat::{self, NcMode};
+
+	Box::pin(async move {
+		let udp = args.iter().any(|arg| arg == "-u");
+		let listen = args.iter().any(|arg| arg == "-l");
+		let rest: Vec<&str> =
+			args.iter().map(String::as_str).filter(|&arg| arg != "-u" && arg != "-l").collect();
+
+		let usage = || println!("usage: nc [-u] <ip|hostname> <port> | nc [-u] -l <port>");
+
+		let mode = match (listen, rest.as_slice()) {
+			(true, [port]) => {
+				let Ok(port) = port.parse::<u16>() else {
+					usage();
+					return 1;
+				};
+				if udp { NcMode::UdpListen(port) } else { NcMode::TcpListen(port) }
+			}
+			(false, [host, port]) => {
+				let Ok(port) = port.parse::<u16>() else {
+					usage();
+					return 1;
+				};
+				let ip = match host.parse::<Ipv4Addr>() {
+					Ok(ip) => ip.octets(),
+					Err(_) => match resolve(host) {
+						Ok(ip) => ip,
+						Err(e) => {
+							println!("nc: {}: {}", host, e);
+							return 1;
+						}
+					}
+				};
+				if udp { NcMode::UdpSend(ip, port) } else { NcMode::TcpConnect(ip, port) }
+			}
+			_ => {
+				usage();
+				return 1;
+			}
+		};
+
+		match netcat::run(state, mode).await {
+			Ok(()) => {
+				println!("nc: done");
+				0
+			}
+			Err(e) => {
+				println!("nc: {}", e);
+				1
+			}
+		}
+	})
+}
+
+fn setmac(args: &[&str]) {
+	if args.is_empty() {
+		match crate::net::get_our_mac() {
+			Some(mac) => println!(
+				"MAC: {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+				mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+			),
+			None => println!("setmac: no network device")
+		}
+		return;
+	}
+
+	let mac = match crate::net::parse_mac(args[0]) {
+		Ok(mac) => mac,
+		Err(_) => {
+			println!("setmac: invalid MAC '{}' (expected XX:XX:XX:XX:XX:XX)", args[0]);
+			return;
+		}
+	};
+
+	match crate::net::set_our_mac(mac) {
+		Ok(()) => println!("MAC set to {}. Peers may need to flush their ARP caches.", args[0]),
+		Err(e) => println!("setmac: {}", e)
+	}
+}
+
+fn ifconfig(args: &[&str]) {
+	let config = match args {
+		[] => {
+			let config = net::config();
+			println!(
+				"inet {}/{}  gateway {}  mask {}  dns {}",
+				Ipv4Addr::from_octets(config.ip),
+				config.prefix_len(),
+				Ipv4Addr::from_octets(config.gateway),
+				Ipv4Addr::from_octets(config.subnet_mask),
+				Ipv4Addr::from_octets(config.dns)
+			);
+			return;
+		}
+		["dhcp"] => {
+			match dhcp::configure() {
+				Ok(config) => println!(
+					"ifconfig: leased {}/{} via {}",
+					Ipv4Addr::from_octets(config.ip),
+					config.prefix_len(),
+					Ipv4Addr::from_octets(config.gateway)
+				),
+				Err(e) => println!("ifconfig: dhcp failed: {}", e)
+			}
+			return;
+		}
+		[ip, gateway, mask] => {
+			let (Ok(ip), Ok(gateway), Ok(mask)) =
+				(ip.parse::<Ipv4Addr>(), gateway.parse::<Ipv4Addr>(), mask.parse::<Ipv4Addr>())
+			else {
+				println!("ifconfig: invalid address (expected a.b.c.d)");
+				return;
+			};
+			NetConfig {
+				ip: ip.octets(),
+				gateway: gateway.octets(),
+				subnet_mask: mask.octets(),
+				..net::config()
+			}
+		}
+		_ => {
+			println!("usage: ifconfig [<ip> <gateway> <mask> | dhcp]");
+			return;
+		}
+	};
+
+	match net::set_config(config) {
+		Ok(()) => println!("ifconfig: {}/{} via {}", args[0], config.prefix_len(), args[1]),
+		Err(e) => println!("ifconfig: {}", e)
+	}
+}
+
 fn nget(args: &[&str]) {
     if args.is_empty() || args.len() < 2 {
         println!("usage: nget <METHOD> <URL>");
@@ -461,19 +2078,25 @@ fn nget(args: &[&str]) {
                 let config = Config::new(EthernetAddress(mac).into());
                 let mut iface = Interface::new(config, device, Instant::from_millis(0));
 
+                let net_config = net::config();
+                let our_ip = net_config.ip;
+                let prefix_len = net_config.prefix_len();
                 iface.update_ip_addrs(|addrs| {
                     addrs
-                        .push(IpCidr::new(IpAddress::Ipv4(Ipv4Addr::from_octets(OUR_IP)), 24))
+                        .push(IpCidr::new(
+                            IpAddress::Ipv4(Ipv4Addr::from_octets(our_ip)),
+                            prefix_len
+                        ))
                         .unwrap();
                 });
                 iface
                     .routes_mut()
-                    .add_default_ipv4_route(Ipv4Addr::from_octets(GATEWAY_IP))
+                    .add_default_ipv4_route(Ipv4Addr::from_octets(net_config.gateway))
                     .unwrap();
 
                 serial_println!(
                     "[NGET] Interface ready: {}.{}.{}.{}",
-                    OUR_IP[0], OUR_IP[1], OUR_IP[2], OUR_IP[3]
+                    our_ip[0], our_ip[1], our_ip[2], our_ip[3]
                 );
 
                 let mut sockets = SocketSet::new(vec![]);
@@ -508,4 +2131,217 @@ fn nget(args: &[&str]) {
         }
         _ => println!("[NGET] Method not supported: {}", method),
     }
-}
\ No newline at end of file
+}
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::{string::{String, ToString}, vec::Vec};
+
+	use crate::{task::keyboard::commands::*, utils::ktest::TestError};
+
+	fn matches(content: &[u8], pattern: &str, options: &GrepOptions) -> Vec<(usize, String)> {
+		let mut found = Vec::new();
+		grep_lines(content, pattern, options, |n, line| found.push((n, line.to_string())));
+		found
+	}
+
+	pub fn test_grep_lines() -> Result<(), TestError> {
+		let log = b"boot ok\r\nERROR: disk\nnet up\nerror: arp\n";
+
+		let found = matches(log, "error", &GrepOptions::default());
+		assert_eq!(found, [(4, "error: arp".to_string())]);
+
+		let options = GrepOptions {
+			ignore_case: true,
+			..GrepOptions::default()
+		};
+		let found = matches(log, "error", &options);
+		assert_eq!(found, [(2, "ERROR: disk".to_string()), (4, "error: arp".to_string())]);
+
+		// the trailing newline doesn't add an empty last line
+		assert_eq!(matches(log, "", &options).len(), 4);
+		assert!(matches(b"", "", &options).is_empty());
+		Ok(())
+	}
+	crate::create_test!(test_grep_lines);
+
+	pub fn test_head_and_tail_lines() -> Result<(), TestError> {
+		let text = b"one\ntwo\nthree\n";
+		assert_eq!(head_lines(text, 2), b"one\ntwo\n");
+		assert_eq!(tail_lines(text, 2), b"two\nthree\n");
+		assert_eq!(tail_lines(text, 1), b"three\n");
+
+		// shorter than asked for
+		assert_eq!(head_lines(text, 10), text);
+		assert_eq!(tail_lines(text, 10), text);
+		assert!(head_lines(text, 0).is_empty() && tail_lines(text, 0).is_empty());
+
+		// no trailing newline
+		let text = b"one\ntwo\nthree";
+		assert_eq!(head_lines(text, 3), text);
+		assert_eq!(tail_lines(text, 1), b"three");
+		assert_eq!(tail_lines(text, 2), b"two\nthree");
+		assert!(tail_lines(b"", 5).is_empty());
+		Ok(())
+	}
+	crate::create_test!(test_head_and_tail_lines);
+
+	pub fn test_hexdump_lines() -> Result<(), TestError> {
+		let lines = hexdump_lines(b"Hello, hexdump!\n\x00\x7f", 0x400);
+		assert_eq!(lines, [
+			"00000400  48 65 6c 6c 6f 2c 20 68  65 78 64 75 6d 70 21 0a  |Hello, hexdump!.|",
+			"00000410  00 7f                                             |..|",
+			"00000412"
+		]);
+		assert_eq!(hexdump_lines(b"", 0), ["00000000"]);
+
+		assert_eq!(parse_size("0x400"), Some(1024));
+		assert_eq!(parse_size("16"), Some(16));
+		assert_eq!(parse_size("0xzz"), None);
+		Ok(())
+	}
+	crate::create_test!(test_hexdump_lines);
+
+	fn word(text: &str, glob: bool) -> Token {
+		Token::Word {
+			text: text.to_string(),
+			glob
+		}
+	}
+
+	pub fn test_split_redirect() -> Result<(), TestError> {
+		let tokens = tokenize("echo hi >> out.txt").map_err(|_| TestError::Error)?;
+		let (rest, redirect) = split_redirect(&tokens);
+		assert_eq!(rest, [word("echo", true), word("hi", true)]);
+		assert!(redirect.is_some_and(|r| r.path == "out.txt" && r.append));
+
+		let tokens = tokenize("echo >out.txt").map_err(|_| TestError::Error)?;
+		let (rest, redirect) = split_redirect(&tokens);
+		assert_eq!(rest, [word("echo", true)]);
+		assert!(redirect.is_some_and(|r| r.path == "out.txt" && !r.append));
+
+		// only a trailing redirection counts
+		let tokens = tokenize("echo > a b").map_err(|_| TestError::Error)?;
+		assert!(split_redirect(&tokens).1.is_none());
+		Ok(())
+	}
+	crate::create_test!(test_split_redirect);
+
+	pub fn test_tokenize_quotes_and_escapes() -> Result<(), TestError> {
+		let tokens = tokenize(r#"write /logs/notes "multi word content""#);
+		assert_eq!(tokens, Ok(vec![
+			word("write", true),
+			word("/logs/notes", true),
+			word("multi word content", false)
+		]));
+
+		// quotes join with what's around them, and keep operators literal
+		let tokens = tokenize(r#"echo a'b c'd "x|y" '>' """#);
+		assert_eq!(tokens, Ok(vec![
+			word("echo", true),
+			word("ab cd", false),
+			word("x|y", false),
+			word(">", false),
+			word("", false)
+		]));
+
+		let tokens = tokenize(r#"echo \"hi\" a\ b "say \"q\" \n" \*"#);
+		assert_eq!(tokens, Ok(vec![
+			word("echo", true),
+			word("\"hi\"", false),
+			word("a b", false),
+			word("say \"q\" \\n", false),
+			word("*", false)
+		]));
+
+		// variables expand outside single quotes only
+		env::set("NX_WHO", "world");
+		let tokens = tokenize(r#"echo $NX_WHO "$NX_WHO!" '$NX_WHO' \$NX_WHO $NX_UNSET"#);
+		env::unset("NX_WHO");
+		assert_eq!(tokens, Ok(vec![
+			word("echo", true),
+			word("world", true),
+			word("world!", false),
+			word("$NX_WHO", false),
+			word("$NX_WHO", false)
+		]));
+
+		assert_eq!(tokenize("cat a|grep b>>c"), Ok(vec![
+			word("cat", true),
+			word("a", true),
+			Token::Pipe,
+			word("grep", true),
+			word("b", true),
+			Token::Redirect { append: true },
+			word("c", true)
+		]));
+		assert_eq!(tokenize("sleep 10&"), Ok(vec![
+			word("sleep", true),
+			word("10", true),
+			Token::Background
+		]));
+		assert_eq!(tokenize("echo 'open"), Err(ParseError::UnterminatedQuote('\'')));
+		assert_eq!(tokenize("echo \"open"), Err(ParseError::UnterminatedQuote('"')));
+		Ok(())
+	}
+	crate::create_test!(test_tokenize_quotes_and_escapes);
+
+	pub fn test_pipeline() -> Result<(), TestError> {
+		for (name, func) in [("echo", echo as CommandFunction), ("grep", grep), ("head", head)] {
+			register_command(Command {
+				name,
+				help: "",
+				cmd_type: CommandType::Generic(func)
+			});
+		}
+
+		let ((), output) = capture_output(|| run_command("echo boot error | grep error"));
+		assert_eq!(output, "boot error\n");
+		let ((), output) = capture_output(|| run_command("echo ok | grep error | head"));
+		assert_eq!(output, "");
+		// nothing piped in and no file
+		let ((), output) = capture_output(|| run_command("head -n 1"));
+		assert_eq!(output, "head: missing file operand\n");
+		Ok(())
+	}
+	crate::create_test!(test_pipeline);
+
+	pub fn test_history() -> Result<(), TestError> {
+		// the user's history, put back afterwards
+		let old_history = CMD_HISTORY.lock().clone();
+		let old_index = *CMD_HISTORY_INDEX.lock();
+		let old_file = fs::read_file(HISTORY_FILE).ok();
+
+		for i in 0..MAX_HISTORY_LEN {
+			record_history(&format!("echo {}", i));
+		}
+		record_history("echo last");
+		let len = CMD_HISTORY.lock().len();
+		let first = CMD_HISTORY.lock()[0].clone();
+
+		let last = recall_history("!!");
+		let one = recall_history("!1");
+		let zero = recall_history("!0");
+		let bad = recall_history("!x");
+		let plain = recall_history("ls");
+
+		let saved = fs::read_file(HISTORY_FILE);
+
+		*CMD_HISTORY.lock() = old_history;
+		*CMD_HISTORY_INDEX.lock() = old_index;
+		let _ = fs::with_fs(|fs| match &old_file {
+			Some(content) => fs.write_file(HISTORY_FILE, content, true),
+			None => fs.remove(HISTORY_FILE, false, false)
+		});
+
+		assert_eq!(len, MAX_HISTORY_LEN);
+		assert_eq!(first, "echo 1");
+		assert_eq!(last, Ok(Some("echo last".to_string())));
+		assert_eq!(one, Ok(Some("echo 1".to_string())));
+		assert_eq!(zero, Err("!0"));
+		assert_eq!(bad, Err("!x"));
+		assert_eq!(plain, Ok(None));
+		assert!(saved.map_err(|_| TestError::Error)?.ends_with(b"echo 99\necho last\n"));
+		Ok(())
+	}
+	crate::create_test!(test_history);
+}
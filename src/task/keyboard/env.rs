@@ -0,0 +1,150 @@
+//!
+//! src/task/keyboard/env.rs
+//!
+//! Shell variables and `$NAME` expansion.
+//!
+
+use alloc::{
+	collections::BTreeMap,
+	string::{String, ToString},
+	vec::Vec
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{lazy_static, utils::mutex::SpinMutex};
+
+lazy_static! {
+	/// The shell's variables, by name.
+	static ref ENV_VARS: SpinMutex<BTreeMap<String, String>> = SpinMutex::new(BTreeMap::new());
+}
+
+/// Whether expanding an unset variable is an error (`set -u`) rather than
+/// giving an empty string.
+static ERROR_ON_UNSET: AtomicBool = AtomicBool::new(false);
+
+/// Returns whether `name` can name a variable: a letter or `_`, then
+/// letters, digits and `_`.
+pub fn is_valid_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	chars
+		.next()
+		.is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+		&& chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns the value of the variable `name`.
+pub fn get(name: &str) -> Option<String> {
+	ENV_VARS.lock().get(name).cloned()
+}
+
+/// Sets the variable `name` to `value`. Returns `false` if `name` isn't a
+/// valid variable name.
+pub fn set(name: &str, value: &str) -> bool {
+	if !is_valid_name(name) {
+		return false;
+	}
+	ENV_VARS.lock().insert(name.to_string(), value.to_string());
+	true
+}
+
+/// Removes the variable `name`, returning its value.
+pub fn unset(name: &str) -> Option<String> {
+	ENV_VARS.lock().remove(name)
+}
+
+/// All variables, sorted by name.
+pub fn vars() -> Vec<(String, String)> {
+	ENV_VARS.lock()
+		.iter()
+		.map(|(name, value)| (name.clone(), value.clone()))
+		.collect()
+}
+
+/// All variables as `NAME=value` strings, the way programs get them in
+/// `envp`.
+pub fn envp() -> Vec<String> {
+	ENV_VARS.lock()
+		.iter()
+		.map(|(name, value)| format!("{}={}", name, value))
+		.collect()
+}
+
+/// Sets whether expanding an unset variable is an error.
+pub fn set_error_on_unset(enabled: bool) {
+	ERROR_ON_UNSET.store(enabled, Ordering::Relaxed);
+}
+
+/// Replaces every `$NAME` and `${NAME}` in `word` with the variable's
+/// value. A `$` not followed by a name is kept as is.
+///
+/// Unset variables expand to an empty string, or, after `set -u`, fail the
+/// expansion with the variable's name.
+pub fn expand(word: &str) -> Result<String, String> {
+	let env = ENV_VARS.lock();
+	let mut expanded = String::new();
+	let mut rest = word;
+
+	while let Some(dollar) = rest.find('$') {
+		expanded.push_str(&rest[..dollar]);
+		let after = &rest[dollar + 1..];
+
+		let (name, remainder) = match after.strip_prefix('{') {
+			Some(braced) => match braced.split_once('}') {
+				Some((name, remainder)) if is_valid_name(name) => (name, remainder),
+				_ => ("", after)
+			},
+			None => {
+				let end = after
+					.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+					.unwrap_or(after.len());
+				let name = &after[..end];
+				if is_valid_name(name) {
+					(name, &after[end..])
+				} else {
+					("", after)
+				}
+			}
+		};
+
+		if name.is_empty() {
+			expanded.push('$');
+		} else {
+			match env.get(name) {
+				Some(value) => expanded.push_str(value),
+				None if ERROR_ON_UNSET.load(Ordering::Relaxed) => return Err(name.to_string()),
+				None => {}
+			}
+		}
+		rest = remainder;
+	}
+	expanded.push_str(rest);
+	Ok(expanded)
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{task::keyboard::env::*, utils::ktest::TestError};
+
+	pub fn test_env_expand() -> Result<(), TestError> {
+		assert!(set("NX_LOGS", "/logs"));
+		assert!(!set("1X", "bad"));
+		assert!(!set("", "bad"));
+
+		assert_eq!(expand("$NX_LOGS/syslog"), Ok("/logs/syslog".to_string()));
+		assert_eq!(expand("${NX_LOGS}syslog"), Ok("/logssyslog".to_string()));
+		assert_eq!(expand("cost: $5 $"), Ok("cost: $5 $".to_string()));
+		assert_eq!(expand("${}"), Ok("${}".to_string()));
+		assert_eq!(expand("a${NX_UNSET}b"), Ok("ab".to_string()));
+
+		set_error_on_unset(true);
+		let strict = expand("$NX_UNSET");
+		set_error_on_unset(false);
+		assert_eq!(strict, Err("NX_UNSET".to_string()));
+
+		assert!(envp().contains(&"NX_LOGS=/logs".to_string()));
+		assert_eq!(unset("NX_LOGS"), Some("/logs".to_string()));
+		assert_eq!(get("NX_LOGS"), None);
+		Ok(())
+	}
+	crate::create_test!(test_env_expand);
+}
@@ -0,0 +1,231 @@
+//!
+//! src/task/keyboard/foreground.rs
+//!
+//! Routing of keyboard input to the foreground process.
+//!
+
+use alloc::sync::Arc;
+use core::{
+	future::poll_fn,
+	sync::atomic::{AtomicBool, Ordering},
+	task::Poll
+};
+
+use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+use crate::{
+	drivers::keyboard::queue::{add_scancode, count_dropped_scancode},
+	error::NullexError,
+	serial_println,
+	task::{
+		ProcessId,
+		ProcessState,
+		executor::{CURRENT_PROCESS, EXECUTOR}
+	},
+	utils::mutex::SpinMutex
+};
+
+/// Capacity of a foreground process's scancode queue.
+const FOREGROUND_QUEUE_CAPACITY: usize = 100;
+
+/// Scancode set 1 codes Ctrl+C is recognised from. Right ctrl sends the same
+/// codes behind an 0xE0 prefix.
+const SCANCODE_CTRL_PRESS: u8 = 0x1D;
+const SCANCODE_CTRL_RELEASE: u8 = 0x9D;
+const SCANCODE_C_PRESS: u8 = 0x2E;
+
+/// Whether a ctrl key is held, tracked from the raw scancodes so Ctrl+C works
+/// whatever the foreground process does with its input.
+static CTRL_HELD: AtomicBool = AtomicBool::new(false);
+/// Whether input was dropped since the last scancode got into a foreground
+/// queue, so an overflow is only logged once.
+static FOREGROUND_OVERFLOWING: AtomicBool = AtomicBool::new(false);
+
+/// The process currently receiving keyboard input. `None` means the shell,
+/// which reads the global scancode queue.
+static FOREGROUND: SpinMutex<Option<Arc<ProcessState>>> = SpinMutex::new(None);
+
+/// Gives `pid` the keyboard. Its input arrives on its
+/// `ProcessState.scancode_queue`, which is created if it doesn't exist yet.
+///
+/// Returns the previous foreground process (`None` for the shell), so it can
+/// be handed back with `restore_foreground` when `pid` is done.
+pub fn set_foreground(pid: ProcessId) -> Result<Option<ProcessId>, NullexError> {
+	// the running process is locked by the executor, so a process putting
+	// itself in the foreground is found through `CURRENT_PROCESS` instead
+	let current = CURRENT_PROCESS.lock().clone().filter(|state| state.id == pid);
+	let state = match current {
+		Some(state) => state,
+		None => EXECUTOR
+			.lock()
+			.processes
+			.get(&pid)
+			.map(|process| process.lock().state.clone())
+			.ok_or(NullexError::ProcessNotFound)?
+	};
+
+	state
+		.scancode_queue
+		.try_get_or_init(|| ArrayQueue::new(FOREGROUND_QUEUE_CAPACITY))
+		.map_err(|_| NullexError::ProcessNotFound)?;
+
+	let previous = interrupts::without_interrupts(|| FOREGROUND.lock().replace(state));
+	Ok(previous.map(|state| state.id))
+}
+
+/// Gives the keyboard back to the shell.
+pub fn clear_foreground() {
+	interrupts::without_interrupts(|| *FOREGROUND.lock() = None);
+}
+
+/// Hands the keyboard back to `previous`, as returned by `set_foreground`.
+/// Falls back to the shell if that process has ended in the meantime.
+pub fn restore_foreground(previous: Option<ProcessId>) {
+	match previous {
+		Some(pid) if set_foreground(pid).is_ok() => {}
+		_ => clear_foreground()
+	}
+}
+
+/// Returns the process currently receiving keyboard input, or `None` for the
+/// shell.
+pub fn foreground() -> Option<ProcessId> {
+	interrupts::without_interrupts(|| FOREGROUND.lock().as_ref().map(|state| state.id))
+}
+
+/// Gives the keyboard back to the shell if `pid` holds it. Called when a
+/// process ends.
+pub(crate) fn release(pid: ProcessId) {
+	interrupts::without_interrupts(|| {
+		let mut foreground = FOREGROUND.lock();
+		if foreground.as_ref().is_some_and(|state| state.id == pid) {
+			*foreground = None;
+		}
+	});
+}
+
+/// Delivers a scancode to the foreground process. Called from the keyboard
+/// interrupt handler.
+///
+/// Ctrl+C asks the foreground process to stop instead of being delivered.
+/// The shell is never cancelled; with no foreground process it gets the keys
+/// as usual.
+pub(crate) fn route_scancode(scancode: u8) {
+	match scancode {
+		SCANCODE_CTRL_PRESS => CTRL_HELD.store(true, Ordering::Relaxed),
+		SCANCODE_CTRL_RELEASE => CTRL_HELD.store(false, Ordering::Relaxed),
+		_ => {}
+	}
+
+	// setters only touch FOREGROUND with interrupts disabled, so this can't
+	// spin on a lock held by the code it interrupted
+	let foreground = FOREGROUND.lock();
+	let Some(state) = foreground.as_ref() else {
+		drop(foreground);
+		add_scancode(scancode);
+		return;
+	};
+
+	if scancode == SCANCODE_C_PRESS && CTRL_HELD.load(Ordering::Relaxed) {
+		state.request_cancel();
+		return;
+	}
+
+	match state.scancode_queue.try_get() {
+		Ok(queue) if queue.push(scancode).is_ok() => {
+			FOREGROUND_OVERFLOWING.store(false, Ordering::Relaxed);
+			state.waker.wake();
+		}
+		Ok(_) => {
+			count_dropped_scancode();
+			// printing from the interrupt handler could spin on the console
+			if !FOREGROUND_OVERFLOWING.swap(true, Ordering::Relaxed) {
+				serial_println!("WARNING: foreground queue full; dropping keyboard input");
+			}
+		}
+		Err(_) => {}
+	}
+}
+
+/// Waits for the next scancode routed to `state`'s process. Only yields
+/// anything while that process holds the foreground.
+pub async fn next_scancode(state: &ProcessState) -> u8 {
+	poll_fn(|cx| {
+		let Ok(queue) = state.scancode_queue.try_get() else {
+			state.waker.register(cx.waker());
+			return Poll::Pending;
+		};
+
+		if let Some(scancode) = queue.pop() {
+			return Poll::Ready(scancode);
+		}
+
+		state.waker.register(cx.waker());
+		match queue.pop() {
+			Some(scancode) => {
+				state.waker.take();
+				Poll::Ready(scancode)
+			}
+			None => Poll::Pending
+		}
+	})
+	.await
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::boxed::Box;
+	use core::{future::Future, pin::Pin};
+
+	use crate::{
+		error::NullexError,
+		task::{ProcessId, executor::EXECUTOR, keyboard::foreground::*},
+		utils::{ktest::TestError, process::spawn_process}
+	};
+
+	pub fn test_foreground_defaults_to_shell() -> Result<(), TestError> {
+		clear_foreground();
+		assert_eq!(foreground(), None);
+
+		let missing = ProcessId::new(u64::MAX);
+		assert_eq!(set_foreground(missing), Err(NullexError::ProcessNotFound));
+		assert_eq!(foreground(), None);
+
+		// restoring an ended process falls back to the shell
+		restore_foreground(Some(missing));
+		assert_eq!(foreground(), None);
+		Ok(())
+	}
+	crate::create_test!(test_foreground_defaults_to_shell);
+
+	pub fn test_ctrl_c_cancels_foreground_process() -> Result<(), TestError> {
+		let pid = spawn_process(
+			|_state| Box::pin(async { 0 }) as Pin<Box<dyn Future<Output = i32>>>,
+			false
+		)
+		.map_err(|_| TestError::Error)?;
+		let previous = set_foreground(pid).map_err(|_| TestError::Error)?;
+
+		let state = EXECUTOR
+			.lock()
+			.processes
+			.get(&pid)
+			.map(|process| process.lock().state.clone())
+			.ok_or(TestError::Error)?;
+
+		// a plain 'c' is delivered, ctrl+c cancels
+		route_scancode(0x2E);
+		assert!(!state.is_cancelled());
+		route_scancode(0x1D);
+		route_scancode(0x2E);
+		route_scancode(0x9D);
+		let cancelled = state.is_cancelled();
+
+		restore_foreground(previous);
+		EXECUTOR.lock().end_process(pid, 0);
+		assert!(cancelled);
+		Ok(())
+	}
+	crate::create_test!(test_ctrl_c_cancels_foreground_process);
+}
@@ -0,0 +1,128 @@
+//!
+//! src/task/keyboard/glob.rs
+//!
+//! Wildcard expansion of shell arguments.
+//!
+
+use alloc::{
+	string::{String, ToString},
+	vec,
+	vec::Vec
+};
+
+use crate::fs::{self, resolve_path};
+
+/// Most arguments a command line may expand to, so a pattern over a huge
+/// directory can't flood a command.
+pub const MAX_EXPANDED_ARGS: usize = 256;
+
+/// Returns whether `word` contains a wildcard.
+pub fn is_pattern(word: &str) -> bool {
+	word.contains(['*', '?'])
+}
+
+/// Matches `name` against `pattern`, where `*` matches any run of
+/// characters and `?` any single character.
+///
+/// Only the last `*` is ever backtracked to, so this takes at most
+/// `pattern.len() * name.len()` steps whatever the pattern.
+pub fn matches(pattern: &str, name: &str) -> bool {
+	let pattern: Vec<char> = pattern.chars().collect();
+	let name: Vec<char> = name.chars().collect();
+	let (mut p, mut n) = (0, 0);
+	// the last `*` seen, and where in `name` it stopped matching
+	let mut star = None;
+
+	while n < name.len() {
+		match pattern.get(p) {
+			Some('*') => {
+				star = Some((p, n));
+				p += 1;
+			}
+			Some(c) if *c == '?' || *c == name[n] => {
+				p += 1;
+				n += 1;
+			}
+			// let the last `*` swallow one more character and retry
+			_ => match star {
+				Some((star_p, star_n)) => {
+					star = Some((star_p, star_n + 1));
+					p = star_p + 1;
+					n = star_n + 1;
+				}
+				None => return false
+			}
+		}
+	}
+	pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Expands `word` to the entries of its directory matching its last path
+/// component, in listing order and with the directory kept as written.
+///
+/// A word without wildcards, with wildcards before the last component, or
+/// matching nothing is passed through as is.
+pub fn expand(word: &str) -> Vec<String> {
+	let (dir, pattern) = match word.rfind('/') {
+		Some(slash) => word.split_at(slash + 1),
+		None => ("", word)
+	};
+	if !is_pattern(pattern) || is_pattern(dir) {
+		return vec![word.to_string()];
+	}
+
+	let entries = fs::list_dir(&resolve_path(if dir.is_empty() { "." } else { dir }));
+	let matched: Vec<String> = entries
+		.unwrap_or_default()
+		.into_iter()
+		.filter(|name| matches(pattern, name))
+		.map(|name| format!("{}{}", dir, name))
+		.collect();
+
+	if matched.is_empty() {
+		vec![word.to_string()]
+	} else {
+		matched
+	}
+}
+
+/// Expands the arguments of a command line that are flagged for it, the
+/// others are kept as they are (e.g. because they were quoted). Returns
+/// `None` if they expand to more than `MAX_EXPANDED_ARGS` arguments.
+pub fn expand_args(args: &[(&str, bool)]) -> Option<Vec<String>> {
+	let mut expanded = Vec::new();
+	for &(arg, glob) in args {
+		if glob {
+			expanded.extend(expand(arg));
+		} else {
+			expanded.push(arg.to_string());
+		}
+		if expanded.len() > MAX_EXPANDED_ARGS {
+			return None;
+		}
+	}
+	Some(expanded)
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{task::keyboard::glob::*, utils::ktest::TestError};
+
+	pub fn test_glob_matches() -> Result<(), TestError> {
+		assert!(matches("*.tmp", "a.tmp"));
+		assert!(matches("*.tmp", ".tmp"));
+		assert!(!matches("*.tmp", "a.tmp.bak"));
+		assert!(matches("a?c", "abc"));
+		assert!(!matches("a?c", "ac"));
+		assert!(matches("*a*b*", "xxaxxbxx"));
+		assert!(matches("**", ""));
+		assert!(!matches("", "a"));
+		assert!(matches("sys*", "syslog"));
+
+		// backtracking stays polynomial on many stars
+		let name = "a".repeat(200);
+		assert!(!matches("*a*a*a*a*a*a*a*a*b", &name));
+		Ok(())
+	}
+	crate::create_test!(test_glob_matches);
+}
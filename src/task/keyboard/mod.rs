@@ -5,8 +5,13 @@
 //! 
 
 pub mod commands;
+pub mod env;
+pub mod foreground;
+pub mod glob;
+pub mod stdin;
 
 pub use commands::{Command, init_commands, register_command, run_command};
+pub use foreground::{clear_foreground, foreground, restore_foreground, set_foreground};
 
 // kbd special consts for keys
 const KEYBOARD_BACKSPACE: u8 = 0x0008;
@@ -0,0 +1,120 @@
+//!
+//! src/task/keyboard/stdin.rs
+//!
+//! Console input for fd 0 of user programs.
+//!
+
+use alloc::{collections::VecDeque, string::String};
+
+use x86_64::instructions::interrupts;
+
+use crate::{
+	drivers::keyboard::{
+		layouts::ActiveLayout,
+		ps2::Keyboard,
+		queue::pop_scancode,
+		scancode::ScancodeSet1
+	},
+	io::keyboard::decode::{DecodedKey, HandleControl},
+	lazy_static, print, println,
+	task::{
+		ProcessState,
+		executor::CURRENT_PROCESS,
+		keyboard::foreground::foreground
+	},
+	utils::mutex::SpinMutex,
+	vga_buffer::console_backspace
+};
+
+/// Line-buffered keyboard input. Read off the caller's own scancode queue
+/// while it holds the foreground, and otherwise straight off the global
+/// queue, since the shell task is blocked while a user program runs.
+struct Stdin {
+	keyboard: Keyboard<ActiveLayout, ScancodeSet1>,
+	/// The line being typed, echoed as it goes.
+	line: String,
+	/// Finished lines the program hasn't read yet.
+	pending: VecDeque<u8>
+}
+
+impl Stdin {
+	/// Handles every pending scancode for `caller`, moving a line to `pending`
+	/// once Enter is pressed.
+	fn poll(&mut self, caller: Option<&ProcessState>) {
+		while let Some(scancode) = next_scancode(caller) {
+			let Ok(Some(event)) = self.keyboard.add_byte(scancode) else {
+				continue;
+			};
+			match self.keyboard.process_keyevent(event) {
+				Some(DecodedKey::Unicode('\u{8}')) => {
+					if self.line.pop().is_some() {
+						console_backspace();
+					}
+				}
+				Some(DecodedKey::Unicode('\n')) => {
+					println!();
+					self.pending.extend(self.line.bytes());
+					self.pending.push_back(b'\n');
+					self.line.clear();
+				}
+				Some(DecodedKey::Unicode(c)) => {
+					print!("{}", c);
+					self.line.push(c);
+				}
+				Some(DecodedKey::RawKey(_)) | None => {}
+			}
+		}
+	}
+}
+
+/// Pops the next scancode meant for `caller`: from its own queue if it holds
+/// the foreground, where the keyboard interrupt routes keys to, else from the
+/// global queue.
+fn next_scancode(caller: Option<&ProcessState>) -> Option<u8> {
+	match caller {
+		Some(state) if foreground() == Some(state.id) => state.scancode_queue.try_get().ok()?.pop(),
+		_ => pop_scancode()
+	}
+}
+
+lazy_static! {
+	static ref CONSOLE_STDIN: SpinMutex<Stdin> = SpinMutex::new(Stdin {
+		keyboard: Keyboard::new(ScancodeSet1::new(), ActiveLayout, HandleControl::Ignore),
+		line: String::new(),
+		pending: VecDeque::new()
+	});
+}
+
+/// Blocks until a line has been typed, then copies as much of it as fits
+/// into `buf`, including the newline. What doesn't fit is returned by the
+/// next read. Returns how many bytes were copied.
+///
+/// Waits with interrupts enabled, so it can be called from a syscall, but not
+/// while a `SpinMutex` is locked.
+pub fn read(buf: &mut [u8]) -> usize {
+	if buf.is_empty() {
+		return 0;
+	}
+	loop {
+		// looked up on every pass, since the foreground can change while
+		// waiting
+		let caller = CURRENT_PROCESS.lock().clone();
+		{
+			let mut stdin = CONSOLE_STDIN.lock();
+			stdin.poll(caller.as_deref());
+			if !stdin.pending.is_empty() {
+				let count = buf.len().min(stdin.pending.len());
+				for (dst, src) in buf.iter_mut().zip(stdin.pending.drain(..count)) {
+					*dst = src;
+				}
+				return count;
+			}
+		}
+
+		let enabled = interrupts::are_enabled();
+		interrupts::enable_and_hlt();
+		if !enabled {
+			interrupts::disable();
+		}
+	}
+}
@@ -4,23 +4,39 @@
 //! Module definition for the task handling for the kernel.
 //! 
 
+pub mod channel;
 pub mod executor;
 pub mod keyboard;
+pub mod timer;
+pub mod watchdog;
 
 use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
-use x86_64::{VirtAddr, structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate}};
+use x86_64::{VirtAddr, structures::paging::{FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate}};
 use core::{
-	arch::asm, fmt::Debug, future::Future, pin::Pin, ptr::write_bytes, sync::atomic::AtomicBool, task::{Context, Poll}
+	arch::asm, fmt::Debug, future::Future, pin::Pin, ptr::write_bytes, sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering}, task::{Context, Poll}
 };
 
 use crossbeam_queue::ArrayQueue;
 use futures::task::AtomicWaker;
 use hashbrown::HashMap;
 
-use crate::{PHYS_MEM_OFFSET, allocator::ALLOCATOR_INFO, arch::x86_64::{bootinfo::MemoryRegion, user::setup_user_stack}, error::NullexError, gdt::{INTERRUPT_STACK_SIZE, interrupt_stack_top, user_code_selector, user_data_selector}, memory::{active_level_4_table, phys_to_virt}, serial_println, utils::{elf::{load_segment, parse_elf}, oncecell::spin::OnceCell}};
+use crate::{PHYS_MEM_OFFSET, allocator::ALLOCATOR_INFO, apic::APIC_TICK_COUNT, arch::x86_64::{bootinfo::MemoryRegion, user::setup_user_stack}, error::NullexError, fs::{self, ramfs::Permission}, gdt::{INTERRUPT_STACK_SIZE, interrupt_stack_top, user_code_selector, user_data_selector}, memory::{BootInfoFrameAllocator, active_level_4_table, phys_to_virt}, serial_println, task::timer::ms_to_ticks, utils::{elf::load_elf, oncecell::spin::OnceCell}};
 
 const KERNEL_STACK_PAGES_TO_MAP: usize = 8;
 
+/// File descriptors of the console. Reads from `STDIN_FD` come from the
+/// keyboard, writes to `STDOUT_FD` and `STDERR_FD` go to the screen.
+pub const STDIN_FD: u32 = 0;
+/// See `STDIN_FD`.
+pub const STDOUT_FD: u32 = 1;
+/// See `STDIN_FD`.
+pub const STDERR_FD: u32 = 2;
+/// The first file descriptor handed out for an opened file.
+pub const FIRST_FILE_FD: u32 = 3;
+
+/// How long a cancelled process gets to clean up before the executor ends it.
+pub const CANCEL_GRACE_MS: u64 = 1000;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 /// Wrapper for a process id.
 pub struct ProcessId(u64);
@@ -37,12 +53,52 @@ impl ProcessId {
 	}
 }
 
+/// How eagerly the executor runs a process that is ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Priority {
+	/// Run before anything else, e.g. the keyboard, so input stays
+	/// responsive under load.
+	High,
+	/// The default.
+	Normal,
+	/// Run when nothing else is ready, or when lower levels get their turn.
+	Low
+}
+
+impl Priority {
+	/// Every level, highest first.
+	pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+	/// Returns the level stored as `value` by `as u8`.
+	pub fn from_u8(value: u8) -> Option<Self> {
+		Self::ALL.get(value as usize).copied()
+	}
+
+	/// Returns the level called `name`, e.g. `"high"`.
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.into_iter().find(|priority| priority.name().eq_ignore_ascii_case(name))
+	}
+
+	/// The level's name, in lower case.
+	pub fn name(&self) -> &'static str {
+		match self {
+			Priority::High => "high",
+			Priority::Normal => "normal",
+			Priority::Low => "low"
+		}
+	}
+}
+
 /// Struct to represent an open file in a process
+#[derive(Clone)]
 pub struct OpenFile {
 	/// The path to the open file.
 	pub path: String,
 	/// The current read offset to the open file.
-	pub offset: usize
+	pub offset: usize,
+	/// What the file was opened for. Only `read` and `write` are used.
+	pub access: Permission
 }
 
 #[expect(clippy::type_complexity)]
@@ -52,6 +108,12 @@ pub struct ProcessState {
 	pub id: ProcessId,
 	/// Whether or not the running process is a child of another process.
 	pub is_child: bool,
+	/// The process that spawned or forked this one, the only one that may wait
+	/// on it.
+	pub parent: Option<ProcessId>,
+	/// Set on a child made by `fork` until its re-run reaches the fork it was
+	/// made from, which then returns 0 instead of forking again.
+	pub fork_pending: AtomicBool,
 	/// The function that this process will be running.
 	pub future_fn:
 		Arc<dyn Fn(Arc<ProcessState>) -> Pin<Box<dyn Future<Output = i32>>> + Send + Sync>,
@@ -60,7 +122,92 @@ pub struct ProcessState {
 	/// Scancode queue incase some functions need the keyboard.
 	pub scancode_queue: OnceCell<ArrayQueue<u8>>,
 	/// Waker for functions that need the process now.
-	pub waker: AtomicWaker
+	pub waker: AtomicWaker,
+	/// Cancelled when the process is asked to stop, e.g. by Ctrl+C, or ends.
+	pub cancel: CancellationToken,
+	/// Tick after which a cancelled process is ended by the executor, or 0.
+	pub cancel_deadline: AtomicU64,
+	/// The process's `Priority`, stored `as u8`.
+	pub priority: AtomicU8
+}
+
+impl ProcessState {
+	/// Returns how eagerly the process is run.
+	pub fn priority(&self) -> Priority {
+		Priority::from_u8(self.priority.load(Ordering::Relaxed)).unwrap_or(Priority::Normal)
+	}
+
+	/// Changes how eagerly the process is run, from its next wake on.
+	pub fn set_priority(&self, priority: Priority) {
+		self.priority.store(priority as u8, Ordering::Relaxed);
+	}
+
+	/// Asks the process to stop. Safe to call from interrupt handlers.
+	///
+	/// Futures only notice if they poll the token, e.g. with
+	/// `check_cancelled().await`; the executor ends the process with
+	/// `CANCELLED_EXIT_CODE` once `CANCEL_GRACE_MS` have passed either way.
+	pub fn request_cancel(&self) {
+		if !self.cancel.is_cancelled() {
+			let now = APIC_TICK_COUNT.load(Ordering::Relaxed);
+			self.cancel_deadline.store(now + ms_to_ticks(CANCEL_GRACE_MS), Ordering::Release);
+			self.cancel.cancel();
+		}
+		// the executor registers the process's waker here before every poll
+		self.waker.wake();
+	}
+
+	/// Returns whether the process has been asked to stop.
+	pub fn is_cancelled(&self) -> bool {
+		self.cancel.is_cancelled()
+	}
+
+	/// Returns whether the process was cancelled and its grace period is over.
+	pub fn cancel_expired(&self) -> bool {
+		let deadline = self.cancel_deadline.load(Ordering::Acquire);
+		self.is_cancelled() && APIC_TICK_COUNT.load(Ordering::Relaxed) >= deadline
+	}
+
+	#[cfg(feature = "test")]
+	/// A process `id` that exits with 0 as soon as it is polled, a child of
+	/// `parent` if one is given. For tests that need a process in a table.
+	pub fn for_test(id: ProcessId, parent: Option<ProcessId>) -> Arc<ProcessState> {
+		Arc::new(ProcessState {
+			id,
+			is_child: parent.is_some(),
+			parent,
+			fork_pending: AtomicBool::new(false),
+			future_fn: Arc::new(|_| Box::pin(async { 0 }) as Pin<Box<dyn Future<Output = i32>>>),
+			queued: AtomicBool::new(false),
+			scancode_queue: OnceCell::uninit(),
+			waker: AtomicWaker::new(),
+			cancel: CancellationToken::new(),
+			cancel_deadline: AtomicU64::new(0),
+			priority: AtomicU8::new(Priority::Normal as u8)
+		})
+	}
+}
+
+#[derive(Debug, Clone, Default)]
+/// A shared flag asking a process to stop. Clones observe the same flag, so a
+/// process can hand its token to the helpers it starts.
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+	/// Creates a token that hasn't been cancelled.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Cancels the token and every clone of it.
+	pub fn cancel(&self) {
+		self.0.store(true, Ordering::Release);
+	}
+
+	/// Returns whether the token has been cancelled.
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(Ordering::Acquire)
+	}
 }
 
 /// Structure representing a process running in the kernel.
@@ -77,6 +224,8 @@ pub struct Process {
 	pub open_files: HashMap<u32, OpenFile>,
 	/// The next available file descriptor.
 	pub next_fd: u32,
+	/// The working directory, or `None` to share the shell's `CWD`.
+	pub cwd: Option<String>,
 }
 
 impl Process {
@@ -89,41 +238,75 @@ impl Process {
 			context: UserContext::default(),
 			address_space: None,
 			open_files: HashMap::new(),
-			next_fd: 0 // start file descriptors at 0
+			next_fd: FIRST_FILE_FD,
+			cwd: None
 		})
 	}
 
 	/// Creates a new process from an ELF binary.
 	pub fn from_elf(state: Arc<ProcessState>, elf_bytes: &[u8], args: &[&str], envs: &[&str]) -> Result<Process, NullexError> {
-		let elf = parse_elf(elf_bytes)?;
+		let (address_space, context) = Process::load_image(elf_bytes, args, envs)?;
 
-		let mut address_space = AddressSpace::new()?;
+		let future = (state.future_fn)(state.clone());
 
-		for seg in &elf.segments {
-			load_segment(&mut address_space, elf_bytes, seg)?;
-		}
+		Ok(Process {
+			state,
+			future,
+			context,
+			address_space: Some(address_space),
+			open_files: HashMap::new(),
+			next_fd: FIRST_FILE_FD,
+			// starts where it was started from
+			cwd: Some(fs::current_dir())
+		})
+	}
+
+	/// Replaces the process's user image with the ELF binary in `elf_bytes`:
+	/// a new address space with its segments and a fresh stack, resuming at
+	/// its entry point. The open file table is kept, like POSIX `exec`.
+	///
+	/// Returns the old address space, which the caller releases once it is no
+	/// longer active. On failure the process is left untouched.
+	pub fn exec(
+		&mut self,
+		elf_bytes: &[u8],
+		args: &[&str],
+		envs: &[&str]
+	) -> Result<Option<AddressSpace>, NullexError> {
+		let (address_space, context) = Process::load_image(elf_bytes, args, envs)?;
+		self.context = context;
+		Ok(self.address_space.replace(address_space))
+	}
+
+	/// Builds the address space and initial registers for running an ELF
+	/// binary with `args` and `envs` on its stack.
+	fn load_image(
+		elf_bytes: &[u8],
+		args: &[&str],
+		envs: &[&str]
+	) -> Result<(AddressSpace, UserContext), NullexError> {
+		let mut address_space = AddressSpace::new()?;
+		let entry = match load_elf(&mut address_space, elf_bytes) {
+			Ok(entry) => entry,
+			Err(e) => {
+				unsafe { address_space.release() };
+				return Err(e);
+			}
+		};
 
 		let stack_top = unsafe {
 			setup_user_stack(&mut address_space, args, envs)
 		};
 
 		let mut context = UserContext::default();
-		context.rip = elf.entry;
+		context.rip = entry;
         context.rsp = stack_top;
 		context.cs = user_code_selector() as u64;
 		context.ss = user_data_selector() as u64;
         context.rflags = 0x202;
+		context.set_entry_args(stack_top, args.len());
 
-		let future = (state.future_fn)(state.clone());
-
-		Ok(Process {
-			state,
-			future,
-			context,
-			address_space: Some(address_space),
-			open_files: HashMap::new(),
-			next_fd: 0
-		})
+		Ok((address_space, context))
 	}
 
 	/// Tries to get the final result and signs the task up for a callback if its still pending.
@@ -141,9 +324,12 @@ pub struct UserContext {
 	rax: u64,
 	rbx: u64,
 	rcx: u64,
-	rdx: u64,
-	rsi: u64,
-	rdi: u64,
+	/// RDX register, envp at entry
+	pub rdx: u64,
+	/// RSI register, argv at entry
+	pub rsi: u64,
+	/// RDI register, argc at entry
+	pub rdi: u64,
 	rbp: u64,
 
 	r8: u64,
@@ -172,6 +358,17 @@ pub struct UserContext {
 	pub ss: u64,
 }
 
+impl UserContext {
+	/// Passes argc, argv and envp in `rdi`, `rsi` and `rdx` as well as on the
+	/// stack, so a program's `_start` can take them as plain C arguments.
+	/// `stack_top` is the `rsp` returned by `setup_user_stack`.
+	pub fn set_entry_args(&mut self, stack_top: u64, argc: usize) {
+		self.rdi = argc as u64;
+		self.rsi = stack_top + 8;
+		self.rdx = stack_top + 8 * (argc as u64 + 2);
+	}
+}
+
 /// Structure representing the memory region each `Process` has.
 pub struct AddressSpace {
 	/// Physical frame of the memory region.
@@ -275,6 +472,52 @@ impl AddressSpace {
             regions: Vec::new(),
         })
     }
+
+    /// Frees the user pages, the page tables and the PML4 of this address
+    /// space. Kernel pages mapped into it and the top-level entries shared
+    /// with the kernel's table are left alone.
+    ///
+    /// # Safety
+    /// The kernel's page table must be active, and the address space must not
+    /// be used again.
+    pub unsafe fn release(self) {
+        let mut frame_binding = ALLOCATOR_INFO.frame_allocator.lock();
+        let Some(frame_allocator) = frame_binding.as_mut() else {
+            return;
+        };
+
+        let kernel_pml4 = unsafe { active_level_4_table(*PHYS_MEM_OFFSET.lock()) };
+        let pml4 = unsafe { &*phys_to_virt(self.page_table.start_address()).as_ptr::<PageTable>() };
+
+        for (i, entry) in pml4.iter().enumerate() {
+            if kernel_pml4[i].addr() == entry.addr() {
+                continue;
+            }
+            if let Ok(frame) = entry.frame() {
+                unsafe { free_table(frame_allocator, frame, 3) };
+            }
+        }
+        unsafe { frame_allocator.deallocate_frame(self.page_table) };
+    }
+}
+
+/// Frees the page table in `frame` at `level` (3 for a PDPT, 1 for a page
+/// table), the tables below it and the user pages they map.
+unsafe fn free_table(frame_allocator: &mut BootInfoFrameAllocator, frame: PhysFrame, level: u8) {
+    let table = unsafe { &*phys_to_virt(frame.start_address()).as_ptr::<PageTable>() };
+
+    for entry in table.iter() {
+        // unused entries and huge pages, which only the kernel maps
+        let Ok(next) = entry.frame() else {
+            continue;
+        };
+        if level > 1 {
+            unsafe { free_table(frame_allocator, next, level - 1) };
+        } else if entry.flags().contains(PageTableFlags::USER_ACCESSIBLE) {
+            unsafe { frame_allocator.deallocate_frame(next) };
+        }
+    }
+    unsafe { frame_allocator.deallocate_frame(frame) };
 }
 /// A future that never completes.
 pub struct ForeverPending;
@@ -314,3 +557,23 @@ pub async fn yield_now() {
 	}
 	.await
 }
+
+/// Yields like `yield_now`, then fails with `NullexError::Cancelled` if the
+/// running process has been asked to stop, so long-running futures can use
+/// `check_cancelled().await?` between steps and clean up on the way out.
+///
+/// A process is only cancellable if its future polls this (or its
+/// `CancellationToken`); otherwise it keeps running until the executor
+/// force-ends it after `CANCEL_GRACE_MS`.
+pub async fn check_cancelled() -> Result<(), NullexError> {
+	yield_now().await;
+	let cancelled = executor::CURRENT_PROCESS
+		.lock()
+		.as_ref()
+		.is_some_and(|state| state.is_cancelled());
+	if cancelled {
+		Err(NullexError::Cancelled)
+	} else {
+		Ok(())
+	}
+}
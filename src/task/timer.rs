@@ -0,0 +1,199 @@
+//!
+//! src/task/timer.rs
+//!
+//! Timer futures, woken from the APIC timer interrupt.
+//!
+
+use alloc::collections::BinaryHeap;
+use core::{
+	cmp::{Ordering, Reverse},
+	future::Future,
+	pin::Pin,
+	sync::atomic::{self, AtomicU64},
+	task::{Context, Poll, Waker}
+};
+
+use x86_64::instructions::interrupts;
+
+use crate::{
+	apic::{APIC_TICK_COUNT, timer_hz},
+	utils::mutex::SpinMutex
+};
+
+/// A pending timer. Ordered by deadline, then by registration order.
+struct TimerEntry {
+	deadline: u64,
+	id: u64,
+	waker: Waker
+}
+
+impl PartialEq for TimerEntry {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for TimerEntry {}
+
+impl PartialOrd for TimerEntry {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for TimerEntry {
+	fn cmp(&self, other: &Self) -> Ordering {
+		(self.deadline, self.id).cmp(&(other.deadline, other.id))
+	}
+}
+
+/// Timers ordered with the earliest deadline on top.
+type TimerHeap = BinaryHeap<Reverse<TimerEntry>>;
+
+/// Pending timers. Only locked with interrupts disabled, since the timer
+/// interrupt handler takes it too.
+static TIMERS: SpinMutex<TimerHeap> = SpinMutex::new(BinaryHeap::new());
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(0);
+
+fn now() -> u64 {
+	APIC_TICK_COUNT.load(atomic::Ordering::Relaxed)
+}
+
+/// Converts milliseconds to timer ticks at the current timer rate, rounding
+/// up so a sleep never ends early.
+pub fn ms_to_ticks(ms: u64) -> u64 {
+	ms.saturating_mul(timer_hz()).div_ceil(1000)
+}
+
+/// Wakes `waker` once the tick count reaches `deadline`.
+pub(crate) fn register(deadline: u64, waker: Waker) {
+	let entry = TimerEntry {
+		deadline,
+		id: NEXT_TIMER_ID.fetch_add(1, atomic::Ordering::Relaxed),
+		waker
+	};
+	interrupts::without_interrupts(|| TIMERS.lock().push(Reverse(entry)));
+}
+
+/// Wakes every timer whose deadline is at or before `now`, including several
+/// expiring on the same tick. Called from the APIC timer interrupt handler.
+pub(crate) fn wake_expired(now: u64) {
+	// a handler nested inside a locked section must not spin on the lock; the
+	// timers fire on the next tick instead
+	let Some(mut timers) = TIMERS.try_lock() else {
+		return;
+	};
+	wake_expired_in(&mut timers, now);
+}
+
+/// Wakes and removes every timer in `timers` whose deadline is at or before
+/// `now`.
+fn wake_expired_in(timers: &mut TimerHeap, now: u64) {
+	while timers.peek().is_some_and(|Reverse(entry)| entry.deadline <= now) {
+		if let Some(Reverse(entry)) = timers.pop() {
+			entry.waker.wake();
+		}
+	}
+}
+
+/// A future that completes once the APIC tick count reaches a deadline.
+///
+/// The waiting process isn't polled again until the timer interrupt wakes it,
+/// so sleeping costs nothing while it waits. Dropping a `Timer` early leaves
+/// its entry behind, which later wakes the process once, harmlessly.
+pub struct Timer {
+	deadline: u64,
+	/// The waker registered for `deadline`, if any.
+	registered: Option<Waker>
+}
+
+impl Timer {
+	/// A timer that fires once the tick count reaches `deadline`.
+	pub fn at(deadline: u64) -> Timer {
+		Timer {
+			deadline,
+			registered: None
+		}
+	}
+
+	/// A timer that fires `ticks` timer ticks from now.
+	pub fn after_ticks(ticks: u64) -> Timer {
+		Timer::at(now().saturating_add(ticks))
+	}
+
+	/// Returns the tick this timer fires on.
+	pub fn deadline(&self) -> u64 {
+		self.deadline
+	}
+}
+
+impl Future for Timer {
+	type Output = ();
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+		if now() >= self.deadline {
+			return Poll::Ready(());
+		}
+
+		// only register again if we are being polled by someone else
+		if !self.registered.as_ref().is_some_and(|waker| waker.will_wake(cx.waker())) {
+			register(self.deadline, cx.waker().clone());
+			self.registered = Some(cx.waker().clone());
+		}
+
+		Poll::Pending
+	}
+}
+
+/// Sleeps for at least `ms` milliseconds.
+pub async fn sleep_ms(ms: u64) {
+	Timer::after_ticks(ms_to_ticks(ms)).await
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::{sync::Arc, task::Wake};
+	use core::{
+		sync::atomic::{AtomicUsize, Ordering},
+		task::Waker
+	};
+
+	use crate::{task::timer::*, utils::ktest::TestError};
+
+	struct CountingWaker(AtomicUsize);
+
+	impl Wake for CountingWaker {
+		fn wake(self: Arc<Self>) {
+			self.0.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	pub fn test_timers_wake_in_deadline_order() -> Result<(), TestError> {
+		// a heap of its own, so the kernel's real timers are left alone
+		let mut timers = TimerHeap::new();
+		let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+		let waker = Waker::from(counter.clone());
+		for (id, deadline) in [12, 11, 11].into_iter().enumerate() {
+			timers.push(Reverse(TimerEntry {
+				deadline,
+				id: id as u64,
+				waker: waker.clone()
+			}));
+		}
+
+		wake_expired_in(&mut timers, 10);
+		assert_eq!(counter.0.load(Ordering::Relaxed), 0);
+		// both timers sharing a tick fire together
+		wake_expired_in(&mut timers, 11);
+		assert_eq!(counter.0.load(Ordering::Relaxed), 2);
+		wake_expired_in(&mut timers, 12);
+		assert_eq!(counter.0.load(Ordering::Relaxed), 3);
+		assert!(timers.is_empty());
+
+		assert_eq!(ms_to_ticks(1000), timer_hz());
+		assert_eq!(ms_to_ticks(1), timer_hz().div_ceil(1000));
+		assert_eq!(ms_to_ticks(0), 0);
+		Ok(())
+	}
+	crate::create_test!(test_timers_wake_in_deadline_order);
+}
@@ -0,0 +1,109 @@
+//!
+//! src/task/watchdog.rs
+//!
+//! Detects a process that keeps the executor from making progress.
+//!
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::{
+	apic::APIC_TICK_COUNT,
+	serial_println,
+	task::{executor::CURRENT_PROCESS, timer::ms_to_ticks}
+};
+
+/// How long the executor may go without finishing a poll before the
+/// running process is reported as hung.
+pub const WATCHDOG_TIMEOUT_MS: u64 = 5000;
+
+/// Tick the executor last made progress at, or 0 before it has started.
+static LAST_PROGRESS: AtomicU64 = AtomicU64::new(0);
+/// Whether the current stall has been reported, so it's reported once.
+static REPORTED: AtomicBool = AtomicBool::new(false);
+/// Whether a hung process is also cancelled, not only reported.
+static CANCEL_HUNG: AtomicBool = AtomicBool::new(false);
+
+/// Records that the executor made progress. Called by the executor loop
+/// before every poll and after every idle wake-up; the first call arms the
+/// watchdog.
+pub fn feed() {
+	// 0 means unarmed, so the first tick counts as 1
+	let now = APIC_TICK_COUNT.load(Ordering::Relaxed).max(1);
+	LAST_PROGRESS.store(now, Ordering::Relaxed);
+	REPORTED.store(false, Ordering::Relaxed);
+}
+
+/// Returns whether hung processes are cancelled.
+pub fn cancels_hung() -> bool {
+	CANCEL_HUNG.load(Ordering::Relaxed)
+}
+
+/// Sets whether a hung process is cancelled besides being reported.
+pub fn set_cancel_hung(enabled: bool) {
+	CANCEL_HUNG.store(enabled, Ordering::Relaxed);
+}
+
+/// Reports the running process if the executor hasn't made progress for
+/// `WATCHDOG_TIMEOUT_MS`. Called from the APIC timer interrupt handler.
+/// Returns whether a stall was reported.
+///
+/// Processes are polled with interrupts enabled, so this runs while a
+/// process spins. `SpinMutex::lock` disables them, so a poll that took a
+/// lock without `without_interrupts` isn't seen until it yields.
+///
+/// A process spinning inside its poll can't be pre-empted, so cancelling
+/// only asks it to stop: it ends if it checks its cancellation token, or
+/// once it yields and its grace period is over.
+pub(crate) fn check(now: u64) -> bool {
+	let last = LAST_PROGRESS.load(Ordering::Relaxed);
+	if last == 0 || now.saturating_sub(last) < ms_to_ticks(WATCHDOG_TIMEOUT_MS) {
+		return false;
+	}
+	if REPORTED.swap(true, Ordering::Relaxed) {
+		return false;
+	}
+
+	// the executor only holds the lock around polls, never during one
+	let state = CURRENT_PROCESS.try_lock().and_then(|current| current.clone());
+	match state {
+		Some(state) => {
+			serial_println!(
+				"[WATCHDOG] Process {} has not yielded for {} ticks",
+				state.id.get(),
+				now - last
+			);
+			if cancels_hung() {
+				serial_println!("[WATCHDOG] Cancelling process {}", state.id.get());
+				state.request_cancel();
+			}
+		}
+		None => serial_println!("[WATCHDOG] Executor has made no progress for {} ticks", now - last)
+	}
+	true
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{task::watchdog::*, utils::ktest::TestError};
+
+	pub fn test_watchdog_reports_stall_once() -> Result<(), TestError> {
+		feed();
+		let last = LAST_PROGRESS.load(Ordering::Relaxed);
+		let timeout = ms_to_ticks(WATCHDOG_TIMEOUT_MS);
+
+		let early = check(last + timeout - 1);
+		let stalled = check(last + timeout);
+		let again = check(last + timeout + 1);
+		feed();
+		let after_feed = check(LAST_PROGRESS.load(Ordering::Relaxed) + timeout);
+
+		// leave it unarmed until the executor starts
+		LAST_PROGRESS.store(0, Ordering::Relaxed);
+		assert!(!early);
+		assert!(stalled);
+		assert!(!again);
+		assert!(after_feed);
+		Ok(())
+	}
+	crate::create_test!(test_watchdog_reports_stall_once);
+}
@@ -0,0 +1,151 @@
+//!
+//! tsc.rs
+//!
+//! Monotonic nanosecond clock on the Time Stamp Counter, calibrated against
+//! the APIC timer. Without an invariant TSC it falls back to the APIC tick
+//! count.
+//!
+
+use core::{
+	arch::x86_64::{__cpuid, _rdtsc},
+	sync::atomic::{AtomicU64, Ordering}
+};
+
+use crate::{
+	apic::{self, APIC_TICK_COUNT},
+	serial_println,
+	task::timer::ms_to_ticks
+};
+
+/// TSC frequency in Hz, or 0 if the TSC isn't used.
+pub static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+/// TSC value at calibration.
+static TSC_BASE: AtomicU64 = AtomicU64::new(0);
+/// Uptime in nanoseconds at calibration, so both clocks agree.
+static TSC_BASE_NS: AtomicU64 = AtomicU64::new(0);
+
+/// How long the TSC is measured against the APIC timer.
+const CALIBRATION_MS: u64 = 50;
+/// How much longer than expected calibration waits for an APIC tick before
+/// deciding the timer isn't running.
+const TICK_TIMEOUT_MS: u64 = 100;
+/// Faster than any real TSC, to turn a timeout into a cycle count before the
+/// TSC is calibrated. A slower TSC only makes the timeout longer.
+const MAX_TSC_HZ: u64 = 10_000_000_000;
+
+const CPUID_EXTENDED_MAX: u32 = 0x8000_0000;
+const CPUID_POWER_MANAGEMENT: u32 = 0x8000_0007;
+/// EDX bit of leaf 0x80000007: the TSC runs at a constant rate in every
+/// P-, C- and T-state.
+const INVARIANT_TSC: u32 = 1 << 8;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Returns whether the CPU reports an invariant TSC.
+pub fn invariant_tsc() -> bool {
+	__cpuid(CPUID_EXTENDED_MAX).eax >= CPUID_POWER_MANAGEMENT
+		&& __cpuid(CPUID_POWER_MANAGEMENT).edx & INVARIANT_TSC != 0
+}
+
+/// Reads the TSC.
+pub fn rdtsc() -> u64 {
+	unsafe { _rdtsc() }
+}
+
+/// Converts `cycles` of a `hz` TSC to nanoseconds.
+pub fn cycles_to_ns(cycles: u64, hz: u64) -> u64 {
+	(cycles as u128 * NANOS_PER_SECOND as u128 / hz as u128) as u64
+}
+
+/// Spins until the APIC tick count moves past `tick`, returning the new count,
+/// or `None` if it hasn't after at least `timeout_ms`.
+fn wait_for_tick_after(tick: u64, timeout_ms: u64) -> Option<u64> {
+	let deadline = rdtsc().saturating_add(timeout_ms * (MAX_TSC_HZ / 1000));
+	loop {
+		let now = APIC_TICK_COUNT.load(Ordering::Relaxed);
+		if now > tick {
+			return Some(now);
+		}
+		if rdtsc() >= deadline {
+			return None;
+		}
+		core::hint::spin_loop();
+	}
+}
+
+/// Measures the TSC frequency over a few APIC timer ticks. Needs the APIC
+/// timer running and interrupts enabled. Leaves `now_ns` on the tick counter
+/// if the TSC isn't invariant or the timer doesn't tick.
+pub fn calibrate() {
+	if !invariant_tsc() {
+		serial_println!("[TSC] Not invariant, timekeeping stays on the APIC timer");
+		return;
+	}
+
+	// start and stop right on tick edges
+	let Some(start_tick) =
+		wait_for_tick_after(APIC_TICK_COUNT.load(Ordering::Relaxed), TICK_TIMEOUT_MS)
+	else {
+		serial_println!("[TSC] APIC timer isn't ticking, staying on the APIC tick counter");
+		return;
+	};
+	let start = rdtsc();
+	let Some(end_tick) = wait_for_tick_after(
+		start_tick + ms_to_ticks(CALIBRATION_MS) - 1,
+		CALIBRATION_MS + TICK_TIMEOUT_MS
+	) else {
+		serial_println!("[TSC] APIC timer stopped ticking, staying on the APIC tick counter");
+		return;
+	};
+	let end = rdtsc();
+
+	let elapsed_ns = apic::to_hrt(end_tick) - apic::to_hrt(start_tick);
+	let hz = ((end - start) as u128 * NANOS_PER_SECOND as u128 / elapsed_ns as u128) as u64;
+	if hz == 0 {
+		serial_println!("[TSC] Calibration measured no cycles, staying on the APIC timer");
+		return;
+	}
+
+	TSC_BASE.store(end, Ordering::Relaxed);
+	TSC_BASE_NS.store(apic::to_hrt(end_tick), Ordering::Relaxed);
+	TSC_HZ.store(hz, Ordering::Release);
+	serial_println!("[TSC] Invariant TSC at {} kHz", hz / 1000);
+}
+
+/// Nanoseconds since boot on a monotonic clock. Uses the TSC once it's
+/// calibrated, otherwise the APIC tick count.
+pub fn now_ns() -> u64 {
+	let hz = TSC_HZ.load(Ordering::Acquire);
+	if hz == 0 {
+		return apic::uptime_ns();
+	}
+
+	let cycles = rdtsc().saturating_sub(TSC_BASE.load(Ordering::Relaxed));
+	TSC_BASE_NS.load(Ordering::Relaxed) + cycles_to_ns(cycles, hz)
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::{tsc::*, utils::ktest::TestError};
+
+	pub fn test_now_ns_is_monotonic() -> Result<(), TestError> {
+		assert_eq!(cycles_to_ns(3_000_000_000, 3_000_000_000), NANOS_PER_SECOND);
+		assert_eq!(cycles_to_ns(1_500, 3_000_000_000), 500);
+
+		let mut last = now_ns();
+		for _ in 0..1000 {
+			let now = now_ns();
+			assert!(now >= last);
+			last = now;
+		}
+		Ok(())
+	}
+	crate::create_test!(test_now_ns_is_monotonic);
+
+	pub fn test_wait_for_tick_times_out() -> Result<(), TestError> {
+		// the tick count never gets past u64::MAX
+		assert_eq!(wait_for_tick_after(u64::MAX, 1), None);
+		Ok(())
+	}
+	crate::create_test!(test_wait_for_tick_times_out);
+}
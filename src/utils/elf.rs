@@ -6,17 +6,23 @@
 
 // https://codebrowser.dev/linux/include/elf.h.html
 
-use core::{arch::asm, ptr::{copy_nonoverlapping, write_bytes}};
+use core::{arch::asm, ptr::{copy_nonoverlapping, read_unaligned, write_bytes}};
 
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{format, string::String, sync::Arc, vec::Vec};
 use x86_64::{VirtAddr, registers::control::{Cr3, Cr3Flags}, structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, page::PageRange}};
 
-use crate::{allocator::ALLOCATOR_INFO, arch::x86_64::user::{enter_user_process, setup_user_stack}, error::NullexError, fs::{self, resolve_path}, memory::{map_range, phys_to_virt}, println, serial_println, task::{AddressSpace, Process, ProcessState, UserContext}, utils::process::{spawn_process, spawn_user_process}};
+use crate::{allocator::ALLOCATOR_INFO, arch::x86_64::user::{enter_user_process, setup_user_stack}, error::NullexError, fs::{self, resolve_path}, memory::{map_range, phys_to_virt}, println, serial_println, task::{AddressSpace, Process, ProcessState, UserContext, keyboard::env}, utils::process::{spawn_process, spawn_user_process}};
 
 const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
 
 const EI_NIDENT: usize = 16;
 
+/// First address past the user half of the address space.
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// Directory `exec` looks programs up in by name.
+pub const PROGRAM_DIR: &str = "/apps";
+
 pub(crate) const HELLO_ELF: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/build/userspace/hello/hello.elf"));
 //pub const BARE_ELF: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/build/userspace/bare/bare.elf"));
 
@@ -36,6 +42,14 @@ type ElfSection = u16;
 
 type ElfVersym = ElfHalf;
 
+const EI_CLASS: usize = 4;
+const EI_DATA: usize = 5;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+
+const ET_EXEC: ElfHalf = 2;
+const EM_X86_64: ElfHalf = 62;
+
 const PT_LOAD: ElfWord = 1;
 
 const PF_X: u32 = 0x1;
@@ -110,46 +124,68 @@ pub struct ElfImage {
 }
 
 /// Parse an ELF file.
+///
+/// Only 64-bit little-endian x86_64 executables are accepted, and every
+/// PT_LOAD segment has to lie inside the file and in user memory.
 pub fn parse_elf(bytes: &[u8]) -> Result<ElfImage, NullexError> {
-	if bytes.len() < core::mem::size_of::<Elf64Ehdr>() {
+	if bytes.len() < core::mem::size_of::<Elf64Ehdr>() || bytes[0..4] != ELF_MAGIC {
 		return Err(NullexError::ElfMagicIncorrect);
 	}
 
-	let e_header = unsafe { &*(bytes.as_ptr() as *const Elf64Ehdr) };
+	let e_header = unsafe { read_unaligned(bytes.as_ptr() as *const Elf64Ehdr) };
 
-	if e_header.e_ident[0..4] != ELF_MAGIC {
-		println!("invalid elf magic number");
-		return Err(NullexError::ElfMagicIncorrect);
+	if e_header.e_ident[EI_CLASS] != ELFCLASS64 || e_header.e_ident[EI_DATA] != ELFDATA2LSB {
+		return Err(NullexError::ElfUnsupportedClass);
+	}
+	if e_header.e_machine != EM_X86_64 {
+		return Err(NullexError::ElfWrongMachine(e_header.e_machine));
+	}
+	if e_header.e_type != ET_EXEC {
+		return Err(NullexError::ElfNotExecutable(e_header.e_type));
+	}
+
+	let phentsize = e_header.e_phentsize as usize;
+	if e_header.e_phnum > 0 && phentsize < core::mem::size_of::<Elf64Phdr>() {
+		return Err(NullexError::ElfMalformed("program header entries too small"));
+	}
+	let table_size = e_header.e_phnum as u64 * phentsize as u64;
+	if e_header.e_phoff.checked_add(table_size).is_none_or(|end| end > bytes.len() as u64) {
+		return Err(NullexError::ElfMalformed("program headers past end of file"));
 	}
 
 	let mut load_segs: Vec<LoadSegment> = Vec::new();
 
-	for i in 0..e_header.e_phnum {
-		let start = (e_header.e_phoff + (i * e_header.e_phentsize) as u64) as usize;
-		let end = start + e_header.e_phentsize as usize;
+	for i in 0..e_header.e_phnum as usize {
+		let start = e_header.e_phoff as usize + i * phentsize;
+		let phdr = unsafe { read_unaligned(bytes.as_ptr().add(start) as *const Elf64Phdr) };
 
-		if end <= bytes.len() {
-			let mut phdr = Elf64Phdr::default();
-			let phdr_size = core::mem::size_of::<Elf64Phdr>();
+		if phdr.p_type != PT_LOAD {
+			continue;
+		}
+		if phdr.p_filesz > phdr.p_memsz {
+			return Err(NullexError::ElfMalformed("segment larger in the file than in memory"));
+		}
+		if phdr.p_offset.checked_add(phdr.p_filesz).is_none_or(|end| end > bytes.len() as u64) {
+			return Err(NullexError::ElfMalformed("segment past end of file"));
+		}
+		if phdr.p_vaddr.checked_add(phdr.p_memsz).is_none_or(|end| end > USER_SPACE_END) {
+			return Err(NullexError::ElfMalformed("segment outside of user memory"));
+		}
 
-			unsafe {
-				core::ptr::copy_nonoverlapping(
-					bytes.as_ptr().add(start),
-					&mut phdr as *mut Elf64Phdr as *mut u8,
-					phdr_size.min(e_header.e_phentsize as usize),
-				);
-			}
+		load_segs.push(LoadSegment {
+			vaddr: phdr.p_vaddr,
+			offset: phdr.p_offset,
+			filesz: phdr.p_filesz,
+			memsz: phdr.p_memsz,
+			flags: phdr.p_flags,
+		});
+	}
 
-			if phdr.p_type == PT_LOAD {
-				load_segs.push(LoadSegment {
-					vaddr: phdr.p_vaddr,
-					offset: phdr.p_offset,
-					filesz: phdr.p_filesz,
-					memsz: phdr.p_memsz,
-					flags: phdr.p_flags,
-				});
-			}
-		}
+	let entry_executable = load_segs.iter().any(|seg| {
+		seg.flags & PF_X != 0 && (seg.vaddr..seg.vaddr + seg.memsz).contains(&e_header.e_entry)
+	});
+	if !entry_executable {
+		return Err(NullexError::ElfMalformed("entry point not in an executable segment"));
 	}
 
 	Ok(ElfImage {
@@ -158,39 +194,87 @@ pub fn parse_elf(bytes: &[u8]) -> Result<ElfImage, NullexError> {
 	})
 }
 
-/// Parse ELF command for the kernel.
-pub fn pelf(args: &[&str]) {
-	if args.is_empty() {
-		println!("pelf: missing file.");
-		return;
+/// Parses `elf_bytes` and maps its PT_LOAD segments into `address_space`,
+/// writable and executable as their flags say. Returns the entry point.
+pub fn load_elf(address_space: &mut AddressSpace, elf_bytes: &[u8]) -> Result<u64, NullexError> {
+	let elf = parse_elf(elf_bytes)?;
+
+	for seg in &elf.segments {
+		load_segment(address_space, elf_bytes, seg)?;
 	}
 
-	let path = resolve_path(args[0]);
+	Ok(elf.entry)
+}
+
+/// Finds the file for `program`. A bare name is looked up in `PROGRAM_DIR`,
+/// with or without an `.elf` extension; anything else is a path.
+pub fn program_path(program: &str) -> String {
+	if !program.contains('/') {
+		let candidates = [
+			format!("{}/{}", PROGRAM_DIR, program),
+			format!("{}/{}.elf", PROGRAM_DIR, program)
+		];
+		let found = fs::with_fs(|fs| candidates.into_iter().find(|path| fs.exists(path)));
+		if let Some(path) = found {
+			return path;
+		}
+	}
+	resolve_path(program)
+}
 
+/// Loads the ELF at `path` into a new user process and runs it until it
+/// exits, with the shell's variables as its environment. `cmd` prefixes the
+/// error messages.
+fn run_elf(cmd: &str, path: &str, args: &[&str]) {
+	let envp = env::envp();
+	let envs: Vec<&str> = envp.iter().map(String::as_str).collect();
 	let process = fs::with_fs(|fs| {
-		match fs.read_file(path.as_str()) {
-			Ok(bytes) => spawn_user_process(bytes, args, &[""]),
-			Err(_) => {
-				println!("pelf: file not found: {}", args[0]);
-				return Err(NullexError::FileNotFound);
-			}
+		match fs.read_file(path) {
+			Ok(bytes) => spawn_user_process(bytes, args, &envs),
+			Err(_) => Err(NullexError::FileNotFound)
 		}
 	});
 
 	match process {
-		Ok(proc) => {
+		Ok(mut proc) => {
 			serial_println!("[INFO] Entering User Process..");
 
 			unsafe {
-				enter_user_process(&proc);
+				enter_user_process(&mut proc);
 			}
 
 			let code = crate::arch::x86_64::user::USER_EXIT_CODE
 				.load(core::sync::atomic::Ordering::SeqCst);
 			println!("Process exited with code {}", code);
+
+			// `halt` switched back to the kernel's page table
+			if let Some(address_space) = proc.address_space.take() {
+				unsafe { address_space.release() };
+			}
 		}
-		Err(_) => println!("pelf: failed to spawn process"),
+		Err(NullexError::FileNotFound) => println!("{}: file not found: {}", cmd, path),
+		Err(e) => println!("{}: {}", cmd, e),
+	}
+}
+
+/// Parse ELF command for the kernel.
+pub fn pelf(args: &[&str]) {
+	if args.is_empty() {
+		println!("pelf: missing file.");
+		return;
+	}
+
+	run_elf("pelf", &resolve_path(args[0]), args);
+}
+
+/// Runs a user program by path, or by name from `PROGRAM_DIR`.
+pub fn exec(args: &[&str]) {
+	if args.is_empty() {
+		println!("usage: exec <program> [args...]");
+		return;
 	}
+
+	run_elf("exec", &program_path(args[0]), args);
 }
 
 /// Load a ELF binary segment into memory.
@@ -287,4 +371,63 @@ pub fn load_segment(
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::{vec, vec::Vec};
+
+	use crate::{error::NullexError, utils::{elf::*, ktest::TestError}};
+
+	/// A one-segment executable whose code segment holds the entry point.
+	fn tiny_elf() -> Vec<u8> {
+		let mut bytes = vec![0u8; 64 + 56 + 16];
+		bytes[0..4].copy_from_slice(&ELF_MAGIC);
+		bytes[EI_CLASS] = ELFCLASS64;
+		bytes[EI_DATA] = ELFDATA2LSB;
+		bytes[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+		bytes[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+		bytes[24..32].copy_from_slice(&0x40_1000u64.to_le_bytes());
+		bytes[32..40].copy_from_slice(&64u64.to_le_bytes());
+		bytes[54..56].copy_from_slice(&56u16.to_le_bytes());
+		bytes[56..58].copy_from_slice(&1u16.to_le_bytes());
+
+		bytes[64..68].copy_from_slice(&PT_LOAD.to_le_bytes());
+		bytes[68..72].copy_from_slice(&(PF_R | PF_X).to_le_bytes());
+		bytes[72..80].copy_from_slice(&120u64.to_le_bytes());
+		bytes[80..88].copy_from_slice(&0x40_1000u64.to_le_bytes());
+		bytes[96..104].copy_from_slice(&16u64.to_le_bytes());
+		bytes[104..112].copy_from_slice(&0x100u64.to_le_bytes());
+		bytes
+	}
+
+	pub fn test_parse_elf_validates_headers() -> Result<(), TestError> {
+		let image = parse_elf(&tiny_elf()).map_err(|_| TestError::Error)?;
+		assert_eq!(image.entry, 0x40_1000);
+		assert_eq!(image.segments.len(), 1);
+
+		let mut aarch64 = tiny_elf();
+		aarch64[18..20].copy_from_slice(&0xb7u16.to_le_bytes());
+		assert_eq!(parse_elf(&aarch64).err(), Some(NullexError::ElfWrongMachine(0xb7)));
+
+		// ET_DYN
+		let mut shared = tiny_elf();
+		shared[16..18].copy_from_slice(&3u16.to_le_bytes());
+		assert_eq!(parse_elf(&shared).err(), Some(NullexError::ElfNotExecutable(3)));
+
+		let mut elf32 = tiny_elf();
+		elf32[EI_CLASS] = 1;
+		assert_eq!(parse_elf(&elf32).err(), Some(NullexError::ElfUnsupportedClass));
+
+		let truncated = tiny_elf();
+		assert!(matches!(parse_elf(&truncated[..100]), Err(NullexError::ElfMalformed(_))));
+
+		let mut kernel_half = tiny_elf();
+		kernel_half[80..88].copy_from_slice(&0xffff_8000_0000_0000u64.to_le_bytes());
+		assert!(matches!(parse_elf(&kernel_half), Err(NullexError::ElfMalformed(_))));
+
+		assert_eq!(parse_elf(b"not an elf").err(), Some(NullexError::ElfMagicIncorrect));
+		Ok(())
+	}
+	crate::create_test!(test_parse_elf_validates_headers);
+}
@@ -4,9 +4,13 @@
 //! Kernel testing framework module for nullex.
 //! 
 
-use core::{slice::from_raw_parts, str::from_utf8_unchecked};
+use core::{fmt, slice::from_raw_parts, str::from_utf8_unchecked};
 
-use crate::{println, serial_println};
+use crate::{
+	println,
+	serial_println,
+	utils::{multiboot2::KernelCmdline, mutex::SpinMutex}
+};
 
 #[cfg(feature = "test")]
 include!(concat!(env!("OUT_DIR"), "/tests_registry.rs"));
@@ -21,13 +25,26 @@ pub enum TestError {
  
 type TestFn = fn() -> Result<(), TestError>;
 
+/// Guest exit code of a passing test run. `qemu_exit` writes it to QEMU's
+/// isa-debug-exit port, which exits with host status `(code << 1) | 1`; the
+/// Makefile maps that back to the guest code.
+pub const QEMU_EXIT_SUCCESS: u32 = 0;
+/// Guest exit code of a failed test run.
+pub const QEMU_EXIT_FAILURE: u32 = 1;
+
+/// Name of the test `run_all_tests` is running, for failure reports.
+static CURRENT_TEST: SpinMutex<Option<&'static str>> = SpinMutex::new(None);
+
 #[repr(C)]
 /// Structure representing all data needed for locating
 /// and running tests.
 pub struct TestDescriptor {
-	name_ptr: *const u8,
-	name_len: usize,
-	func: TestFn
+	/// Pointer to the test's name, filled in by `create_test!`.
+	pub name_ptr: *const u8,
+	/// Length of the test's name in bytes.
+	pub name_len: usize,
+	/// The test function.
+	pub func: TestFn
 }
 
 unsafe impl Send for TestDescriptor {}
@@ -108,6 +125,129 @@ macro_rules! create_test {
 	};
 }
 
+/// Reports a failed `assert_kernel!`/`assert_eq_kernel!` and ends the run.
+///
+/// With the `test` feature QEMU is exited with `QEMU_EXIT_FAILURE`, so CI sees
+/// the failure instead of a hang. Otherwise this panics.
+#[doc(hidden)]
+pub fn assertion_failed(file: &str, line: u32, expr: &str, detail: Option<fmt::Arguments>) -> ! {
+	let test = CURRENT_TEST.try_lock().and_then(|test| *test).unwrap_or("<none>");
+
+	println!("assertion failed in {} at {}:{}: {}", test, file, line, expr);
+	serial_println!("assertion failed in {} at {}:{}: {}", test, file, line, expr);
+	if let Some(detail) = detail {
+		println!("  {}", detail);
+		serial_println!("  {}", detail);
+	}
+
+	#[cfg(feature = "test")]
+	{
+		println!("test result: FAILED");
+		serial_println!("test result: FAILED");
+		crate::qemu_exit(QEMU_EXIT_FAILURE);
+	}
+
+	#[cfg(not(feature = "test"))]
+	panic!("assertion failed: {}", expr);
+}
+
+#[macro_export]
+/// Asserts that a condition holds in a kernel test.
+///
+/// On failure the test name, file, line and expression (plus the optional
+/// message) are printed to serial and VGA, and QEMU exits with the failure
+/// code.
+///
+/// ```ignore
+/// assert_kernel!(queue.is_empty());
+/// assert_kernel!(len <= MAX, "len was {}", len);
+/// ```
+macro_rules! assert_kernel {
+	($cond:expr $(,)?) => {
+		if !$cond {
+			$crate::utils::ktest::assertion_failed(file!(), line!(), stringify!($cond), None);
+		}
+	};
+	($cond:expr, $($arg:tt)+) => {
+		if !$cond {
+			$crate::utils::ktest::assertion_failed(
+				file!(),
+				line!(),
+				stringify!($cond),
+				Some(format_args!($($arg)+))
+			);
+		}
+	};
+}
+
+#[macro_export]
+/// Asserts that two expressions are equal in a kernel test, printing both
+/// values on failure. See `assert_kernel!`.
+///
+/// ```ignore
+/// assert_eq_kernel!(checksum(&header), 0);
+/// ```
+macro_rules! assert_eq_kernel {
+	($left:expr, $right:expr $(,)?) => {
+		match (&$left, &$right) {
+			(left, right) => {
+				if !(*left == *right) {
+					$crate::utils::ktest::assertion_failed(
+						file!(),
+						line!(),
+						concat!(stringify!($left), " == ", stringify!($right)),
+						Some(format_args!("left: {:?}, right: {:?}", left, right))
+					);
+				}
+			}
+		}
+	};
+	($left:expr, $right:expr, $($arg:tt)+) => {
+		match (&$left, &$right) {
+			(left, right) => {
+				if !(*left == *right) {
+					$crate::utils::ktest::assertion_failed(
+						file!(),
+						line!(),
+						concat!(stringify!($left), " == ", stringify!($right)),
+						Some(format_args!(
+							"left: {:?}, right: {:?}: {}",
+							left,
+							right,
+							format_args!($($arg)+)
+						))
+					);
+				}
+			}
+		}
+	};
+}
+
+#[macro_export]
+/// Defines and registers a kernel test in one go.
+///
+/// The function is written without a return type; it passes if it returns,
+/// and fails through `assert_kernel!`/`assert_eq_kernel!` (or a panic).
+/// `build.rs` picks these up the same way as `create_test!` registrations.
+///
+/// ```ignore
+/// kernel_test! {
+///     fn test_addition() {
+///         assert_eq_kernel!(2 + 2, 4);
+///     }
+/// }
+/// ```
+macro_rules! kernel_test {
+	($(#[$meta:meta])* fn $name:ident() $body:block) => {
+		$(#[$meta])*
+		pub fn $name() -> Result<(), $crate::utils::ktest::TestError> {
+			$body
+			Ok(())
+		}
+		$crate::create_test! { $name }
+	};
+}
+
 unsafe extern "C" {
 	/// The starting address where the kernel tests are stored.
 	unsafe static __start_kernel_tests: u8;
@@ -115,9 +255,32 @@ unsafe extern "C" {
 	unsafe static __stop_kernel_tests: u8;
 }
 
-/// Runs all tests that have been generated.
+/// Kernel command line argument selecting which tests run (`ktest=fs,vfs`).
+pub const FILTER_ARG: &str = "ktest";
+
+/// Returns the test filter: the `ktest=` kernel command line argument, or
+/// else the `KTEST_FILTER` environment variable at build time.
+pub fn test_filter(cmdline: &KernelCmdline) -> Option<&str> {
+	cmdline
+		.get(FILTER_ARG)
+		.or(option_env!("KTEST_FILTER"))
+		.filter(|filter| !filter.is_empty())
+}
+
+/// Returns whether the test `name` is selected by `filter`, a comma separated
+/// list of substrings. No filter selects every test.
+pub fn matches_filter(name: &str, filter: Option<&str>) -> bool {
+	filter.is_none_or(|filter| {
+		filter
+			.split(',')
+			.any(|part| !part.is_empty() && name.contains(part))
+	})
+}
+
+/// Runs all tests that have been generated, or only those whose name matches
+/// `filter` (see `matches_filter`).
 /// Can only run on `#cfg[feature = "test"]`
-pub fn run_all_tests() {
+pub fn run_all_tests(filter: Option<&str>) {
 	#[cfg(feature = "test")]
 	{
 		use crate::{
@@ -128,21 +291,43 @@ pub fn run_all_tests() {
 		// deref the wrapper newtype to get the array of pointers
 		let ptrs = &__kernel_test_registry_refs.0;
 
-		println!("Running {} tests...", ptrs.len());
-		serial_println!("Running {} tests...", ptrs.len());
+		let total = ptrs.len();
+		let selected = ptrs
+			.iter()
+			.filter(|ptr| matches_filter(unsafe { &***ptr }.name(), filter))
+			.count();
+		match filter {
+			Some(filter) => {
+				println!("Running {} of {} tests (filter '{}')...", selected, total, filter);
+				serial_println!("Running {} of {} tests (filter '{}')...", selected, total, filter);
+			}
+			None => {
+				println!("Running {} tests...", total);
+				serial_println!("Running {} tests...", total);
+			}
+		}
 
 		let mut passed = 0;
 		let mut failed = 0;
+		let mut skipped = 0;
 
 		for (i, ptr) in ptrs.iter().enumerate() {
 			// deref the pointer to get the TestDescriptor
 			let desc = unsafe { &**ptr };
 			let name = desc.name();
 
+			if !matches_filter(name, filter) {
+				serial_println!("test {} ({})... skipped", i + 1, name);
+				skipped += 1;
+				continue;
+			}
+
 			println!("test {} ({})... ", i + 1, name);
 			serial_println!("test {} ({})... ", i + 1, name);
 
+			*CURRENT_TEST.lock() = Some(name);
 			let result = (desc.func)();
+			*CURRENT_TEST.lock() = None;
 			match result {
 				Ok(_) => {
 					println!("ok");
@@ -157,23 +342,53 @@ pub fn run_all_tests() {
 			}
 		}
 
-		println!("\n{} passed, {} failed", passed, failed);
-		serial_println!("\n{} passed, {} failed", passed, failed);
+		println!("\n{} passed, {} failed, {} skipped", passed, failed, skipped);
+		serial_println!("\n{} passed, {} failed, {} skipped", passed, failed, skipped);
 
 		if failed > 0 {
 			println!("test result: FAILED");
 			serial_println!("test result: FAILED");
-			qemu_exit(1);
+			qemu_exit(QEMU_EXIT_FAILURE);
 		} else {
 			println!("test result: ok");
 			serial_println!("test result: ok");
-			qemu_exit(0)
+			qemu_exit(QEMU_EXIT_SUCCESS)
 		}
 	}
 
 	#[cfg(not(feature = "test"))]
 	{
+		let _ = filter;
 		println!("Tests not compiled (feature 'test' not enabled)");
 		serial_println!("Tests not compiled (feature 'test' not enabled)");
 	}
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::utils::ktest::*;
+
+	crate::kernel_test! {
+		/// Passing assertions fall through to the end of the test.
+		fn test_kernel_assertions_pass() {
+			assert_kernel!(CURRENT_TEST.lock().is_some());
+			assert_kernel!(QEMU_EXIT_SUCCESS != QEMU_EXIT_FAILURE, "exit codes must differ");
+			assert_eq_kernel!(2 + 2, 4);
+			assert_eq_kernel!("nullex".len(), 6, "length of {}", "nullex");
+		}
+	}
+
+	crate::kernel_test! {
+		fn test_filter_selects_by_substring() {
+			assert_kernel!(matches_filter("test_fs_create", None));
+			assert_kernel!(matches_filter("test_fs_create", Some("fs")));
+			assert_kernel!(matches_filter("test_vfs_mount", Some("net,vfs")));
+			assert_kernel!(!matches_filter("test_net_config", Some("fs")));
+			// empty parts don't match everything
+			assert_kernel!(!matches_filter("test_net_config", Some("fs,")));
+
+			let cmdline = KernelCmdline::new(b"ktest=fs");
+			assert_eq_kernel!(test_filter(&cmdline), Some("fs"));
+		}
+	}
+}
@@ -7,24 +7,43 @@
 use alloc::string::String;
 
 use super::{levels::LogLevel, traits::log_formatter::LogFormatter};
+use crate::apic::uptime_ns;
 
 /// Structure representing the default logging formatter
 pub struct DefaultFormatter {
-	show_level: bool
+	show_level: bool,
+	show_timestamp: bool
 }
 
 impl DefaultFormatter {
 	/// Creates a new `DefaultFormatter`
 	pub fn new(show_level: bool) -> Self {
+		Self::with_timestamp(show_level, false)
+	}
+
+	/// Creates a new `DefaultFormatter` that can also prefix every line with
+	/// the time since boot, like `dmesg` does (`[   12.345]`).
+	pub fn with_timestamp(show_level: bool, show_timestamp: bool) -> Self {
 		Self {
-			show_level
+			show_level,
+			show_timestamp
 		}
 	}
 }
 
+/// Formats nanoseconds since boot as seconds with millisecond precision.
+fn format_timestamp(nanos: u64) -> String {
+	let seconds = nanos / 1_000_000_000;
+	let millis = nanos % 1_000_000_000 / 1_000_000;
+	format!("[{:>5}.{:03}] ", seconds, millis)
+}
+
 impl LogFormatter for DefaultFormatter {
 	fn format(&self, level: LogLevel, message: &str) -> String {
 		let mut formatted_message = String::new();
+		if self.show_timestamp {
+			formatted_message.push_str(&format_timestamp(uptime_ns()));
+		}
 		if self.show_level {
 			formatted_message.push_str(&format!("[{:#?}] ", level));
 		}
@@ -32,3 +51,23 @@ impl LogFormatter for DefaultFormatter {
 		formatted_message
 	}
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::utils::{
+		ktest::TestError,
+		logger::{format::*, levels::LogLevel, traits::log_formatter::LogFormatter}
+	};
+
+	pub fn test_format_timestamp() -> Result<(), TestError> {
+		assert_eq!(format_timestamp(0), "[    0.000] ");
+		assert_eq!(format_timestamp(12_500_000_000), "[   12.500] ");
+
+		let plain = DefaultFormatter::new(false).format(LogLevel::Info, "boot");
+		assert_eq!(plain, "boot");
+		let stamped = DefaultFormatter::with_timestamp(false, true).format(LogLevel::Info, "boot");
+		assert!(stamped.starts_with('[') && stamped.ends_with("] boot"));
+		Ok(())
+	}
+	crate::create_test!(test_format_timestamp);
+}
@@ -4,8 +4,10 @@
 //! Definitions for the different types of Logging Levels for the kernel's logging framework
 //! 
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-/// Enum representing all supported log levels.
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+/// Enum representing all supported log levels, from least to most severe.
 pub enum LogLevel {
 	/// Debug
 	Debug,
@@ -18,3 +20,51 @@ pub enum LogLevel {
 	/// Fatal (like kernel panics)
 	Fatal
 }
+
+impl LogLevel {
+	const ALL: [LogLevel; 5] = [
+		LogLevel::Debug,
+		LogLevel::Info,
+		LogLevel::Warn,
+		LogLevel::Error,
+		LogLevel::Fatal
+	];
+
+	/// Lowercase name of the level, as used by the `loglevel` command.
+	pub fn name(self) -> &'static str {
+		match self {
+			LogLevel::Debug => "debug",
+			LogLevel::Info => "info",
+			LogLevel::Warn => "warn",
+			LogLevel::Error => "error",
+			LogLevel::Fatal => "fatal"
+		}
+	}
+
+	/// Parses a level from its name, ignoring case.
+	pub fn from_name(name: &str) -> Option<LogLevel> {
+		Self::ALL
+			.into_iter()
+			.find(|level| level.name().eq_ignore_ascii_case(name))
+	}
+}
+
+/// A `LogLevel` that can be shared and changed at runtime.
+pub struct AtomicLogLevel(AtomicU8);
+
+impl AtomicLogLevel {
+	/// Creates a new `AtomicLogLevel` holding `level`.
+	pub const fn new(level: LogLevel) -> Self {
+		Self(AtomicU8::new(level as u8))
+	}
+
+	/// Returns the current level.
+	pub fn load(&self) -> LogLevel {
+		LogLevel::ALL[self.0.load(Ordering::Relaxed) as usize]
+	}
+
+	/// Replaces the current level.
+	pub fn store(&self, level: LogLevel) {
+		self.0.store(level as u8, Ordering::Relaxed);
+	}
+}
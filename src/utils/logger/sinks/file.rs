@@ -0,0 +1,184 @@
+//!
+//! file.rs
+//!
+//! File sink logic for the kernel. Appends log lines to a RAMFS file.
+//!
+
+use alloc::{
+	boxed::Box,
+	string::{String, ToString},
+	vec::Vec
+};
+
+use x86_64::instructions::interrupts;
+
+use crate::{
+	fs::{
+		FS,
+		ramfs::{FileSystem, Permission}
+	},
+	utils::{
+		logger::{
+			levels::{AtomicLogLevel, LogLevel},
+			traits::{log_formatter::LogFormatter, logger_sink::LoggerSink}
+		},
+		mutex::SpinMutex
+	}
+};
+
+/// How many lines are held back while the filesystem is unavailable. Anything
+/// past this is dropped.
+const MAX_PENDING_LINES: usize = 256;
+
+/// The File sink. Appends each formatted message as a line to a RAMFS file,
+/// creating it (and its parent directory) on first use.
+///
+/// Messages logged before `fs::init_fs` has run, or while the filesystem is
+/// locked, are buffered and written out on the next `flush` or log call.
+pub struct FileSink {
+	/// The formatting strategy used.
+	pub formatter: Box<dyn LogFormatter>,
+	/// The file lines are appended to.
+	path: String,
+	/// Messages below this level are dropped.
+	min_level: AtomicLogLevel,
+	/// Lines that couldn't be written yet.
+	pending: SpinMutex<Vec<String>>
+}
+
+impl FileSink {
+	/// Creates a new `FileSink` appending to `path` with the formatting strategy
+	pub fn new(path: &str, formatter: Box<dyn LogFormatter>) -> Self {
+		Self {
+			formatter,
+			path: path.to_string(),
+			min_level: AtomicLogLevel::new(LogLevel::Debug),
+			pending: SpinMutex::new(Vec::new())
+		}
+	}
+
+	/// Returns the path of the file this sink appends to.
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+
+	/// Writes out any lines buffered while the filesystem was unavailable.
+	pub fn flush(&self) {
+		self.write(None);
+	}
+
+	/// Appends `line` (if any) after the buffered lines, or buffers it if the
+	/// filesystem can't be used right now.
+	fn write(&self, line: Option<String>) {
+		// the logger may be called from inside `with_fs`, so never spin on FS
+		let mut fs = FS.try_lock();
+		let Some(fs) = fs.as_mut().and_then(|fs| fs.as_mut()) else {
+			if let Some(line) = line {
+				interrupts::without_interrupts(|| {
+					let mut pending = self.pending.lock();
+					if pending.len() < MAX_PENDING_LINES {
+						pending.push(line);
+					}
+				});
+			}
+			return;
+		};
+
+		let pending = interrupts::without_interrupts(|| core::mem::take(&mut *self.pending.lock()));
+		for line in pending.iter().chain(line.as_ref()) {
+			self.append(fs, line);
+		}
+	}
+
+	fn append(&self, fs: &mut FileSystem, line: &str) {
+		if !fs.exists(&self.path) {
+			if let Some((parent, _)) = self.path.rsplit_once('/')
+				&& !parent.is_empty()
+				&& !fs.exists(parent)
+			{
+				let _ = fs.create_dir(parent, Permission::all());
+			}
+			let _ = fs.create_file(&self.path, Permission::all());
+		}
+
+		let _ = fs.write_file(&self.path, line.as_bytes(), false);
+		if !line.ends_with('\n') {
+			let _ = fs.write_file(&self.path, b"\n", false);
+		}
+	}
+}
+
+impl LoggerSink for FileSink {
+	fn min_level(&self) -> LogLevel {
+		self.min_level.load()
+	}
+
+	fn set_min_level(&self, level: LogLevel) {
+		self.min_level.store(level);
+	}
+
+	fn log(&self, message: &str, level: LogLevel) {
+		if !self.enabled(level) {
+			return;
+		}
+		self.write(Some(self.formatter.format(level, message)));
+	}
+
+	fn log_async(
+		&self,
+		message: &str,
+		level: LogLevel
+	) -> impl core::future::Future<Output = ()> + Send {
+		let formatted_message = self.enabled(level).then(|| self.formatter.format(level, message));
+		async move {
+			if formatted_message.is_some() {
+				self.write(formatted_message);
+			}
+		}
+	}
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::boxed::Box;
+
+	use crate::{
+		fs::{self, FS, ramfs::FileSystem},
+		utils::{
+			ktest::TestError,
+			logger::{
+				format::DefaultFormatter,
+				levels::LogLevel,
+				sinks::file::*,
+				traits::logger_sink::LoggerSink
+			}
+		}
+	};
+
+	pub fn test_file_sink_buffers_until_fs_available() -> Result<(), TestError> {
+		if FS.lock().is_none() {
+			fs::init_fs(FileSystem::new());
+		}
+
+		let sink = FileSink::new("/file_sink_test/kernel.log", Box::new(DefaultFormatter::new(false)));
+		sink.set_min_level(LogLevel::Info);
+
+		// with the filesystem busy, lines are held back
+		let busy = FS.lock();
+		sink.log("early", LogLevel::Info);
+		sink.log("filtered", LogLevel::Debug);
+		drop(busy);
+		sink.log("late\n", LogLevel::Warn);
+
+		let content = fs::with_fs(|fs| {
+			let content = fs.read_file(sink.path()).map(|c| c.to_vec());
+			let _ = fs.remove("/file_sink_test", true, true);
+			content
+		})
+		.map_err(|_| TestError::Error)?;
+
+		assert_eq!(content.as_slice(), b"early\nlate\n");
+		Ok(())
+	}
+	crate::create_test!(test_file_sink_buffers_until_fs_available);
+}
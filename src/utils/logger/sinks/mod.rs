@@ -4,6 +4,7 @@
 //! All sink definitions for the kernel's logging framework
 //! 
 
+pub mod file;
 pub mod stdout;
 pub mod syslog;
 
@@ -13,13 +14,19 @@ use crate::{
 	lazy_static,
 	utils::logger::{
 		format::DefaultFormatter,
-		sinks::{stdout::StdOutSink, syslog::SyslogSink}
+		sinks::{file::FileSink, stdout::StdOutSink, syslog::SyslogSink}
 	}
 };
 
 lazy_static! {
 	/// Static reference to the Standard Output Sink
-	pub static ref STDOUT_SINK: StdOutSink = StdOutSink::new(Box::new(DefaultFormatter::new(true)));
-	/// Static reference to the System Logging Sink
-	pub static ref SYSLOG_SINK: SyslogSink = SyslogSink::new(Box::new(DefaultFormatter::new(true)));
+	pub static ref STDOUT_SINK: StdOutSink =
+		StdOutSink::new(Box::new(DefaultFormatter::with_timestamp(true, true)));
+	/// Static reference to the kernel log file sink, `/logs/kernel.log`
+	pub static ref FILE_SINK: FileSink =
+		FileSink::new("/logs/kernel.log", Box::new(DefaultFormatter::with_timestamp(true, true)));
+	/// Static reference to the System Logging Sink, mirrored to `FILE_SINK`
+	pub static ref SYSLOG_SINK: SyslogSink =
+		SyslogSink::new(Box::new(DefaultFormatter::with_timestamp(true, true)))
+			.mirrored_to(&FILE_SINK);
 }
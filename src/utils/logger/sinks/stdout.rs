@@ -1,38 +1,172 @@
 //!
 //! stdout.rs
-//! 
+//!
 //! Standard Output (stdio) sink logic for the kernel.
 //!
 
-use alloc::boxed::Box;
+use alloc::{
+	boxed::Box,
+	string::{String, ToString},
+	vec::Vec
+};
+
+use x86_64::instructions::interrupts;
 
 use crate::{
-	println,
-	utils::logger::{
-		levels::LogLevel,
-		traits::{log_formatter::LogFormatter, logger_sink::LoggerSink}
-	}
+	fs::{FS, ramfs::Permission},
+	print_colours, serial_println,
+	utils::{
+		logger::{
+			levels::{AtomicLogLevel, LogLevel},
+			traits::{log_formatter::LogFormatter, logger_sink::LoggerSink}
+		},
+		mutex::SpinMutex
+	},
+	vga_buffer::Color
 };
 
-/// The Standard Output sink. Logs to the screen (stdio)
+/// Foreground and background VGA colours of each level, indexed by
+/// `LogLevel`.
+const LEVEL_COLOURS: [(Color, Color); 5] = [
+	(Color::LightGray, Color::Black),
+	(Color::White, Color::Black),
+	(Color::Yellow, Color::Black),
+	(Color::LightRed, Color::Black),
+	(Color::White, Color::Red)
+];
+
+/// How many lines for a `StdOutTarget::File` are held back while the
+/// filesystem is locked. Anything past this is dropped.
+const MAX_PENDING_LINES: usize = 256;
+
+/// Returns the VGA colours messages of `level` are printed in.
+pub fn level_colours(level: LogLevel) -> (Color, Color) {
+	LEVEL_COLOURS[level as usize]
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// Where a `StdOutSink` writes its output.
+pub enum StdOutTarget {
+	/// The VGA text buffer only.
+	Vga,
+	/// The serial port only, for headless runs.
+	Serial,
+	/// Both the VGA text buffer and the serial port.
+	#[default]
+	Both,
+	/// Appends to a RAMFS file at the given path, creating it if needed.
+	File(String)
+}
+
+impl StdOutTarget {
+	/// Returns whether this target writes to the VGA text buffer.
+	pub fn writes_vga(&self) -> bool {
+		matches!(self, StdOutTarget::Vga | StdOutTarget::Both)
+	}
+
+	/// Returns whether this target writes to the serial port.
+	pub fn writes_serial(&self) -> bool {
+		matches!(self, StdOutTarget::Serial | StdOutTarget::Both)
+	}
+}
+
+/// The Standard Output sink. Logs to the screen (stdio), the serial port, or
+/// a file, depending on its `StdOutTarget`.
 pub struct StdOutSink {
 	/// The formatting strategy used.
-	pub formatter: Box<dyn LogFormatter>
+	pub formatter: Box<dyn LogFormatter>,
+	/// Where output currently goes.
+	target: SpinMutex<StdOutTarget>,
+	/// Messages below this level are dropped.
+	min_level: AtomicLogLevel,
+	/// Lines that couldn't be written to a file target yet, with its path.
+	pending: SpinMutex<Vec<(String, String)>>
 }
 
 impl StdOutSink {
-	/// Creates a new Standard Output sink (`StdOutSink`) with the provided logging strategy
+	/// Creates a new Standard Output sink (`StdOutSink`) with the provided logging strategy,
+	/// writing to both VGA and serial.
 	pub fn new(formatter: Box<dyn LogFormatter>) -> Self {
 		Self {
-			formatter
+			formatter,
+			target: SpinMutex::new(StdOutTarget::default()),
+			min_level: AtomicLogLevel::new(LogLevel::Debug),
+			pending: SpinMutex::new(Vec::new())
+		}
+	}
+
+	/// Redirects all further output to `target`.
+	///
+	/// The switch happens with interrupts disabled, so a message logged from an
+	/// interrupt handler never sees a half-updated target.
+	pub fn set_target(&self, target: StdOutTarget) {
+		interrupts::without_interrupts(|| *self.target.lock() = target);
+	}
+
+	/// Returns the current output target.
+	pub fn target(&self) -> StdOutTarget {
+		interrupts::without_interrupts(|| self.target.lock().clone())
+	}
+
+	/// Writes an already formatted message of `level` to the current target.
+	fn write(&self, message: &str, level: LogLevel) {
+		// copy the target out so no lock is held while writing
+		let target = self.target();
+
+		if target.writes_vga() {
+			let (fg, bg) = level_colours(level);
+			print_colours!((message, fg, bg), ("\n", Color::White));
+		}
+		if target.writes_serial() {
+			serial_println!("{}", message);
+		}
+		if let StdOutTarget::File(path) = target {
+			self.write_file(&path, message);
+		}
+	}
+
+	/// Appends `message` as a line to the file at `path`, after the lines
+	/// buffered earlier, or buffers it if the filesystem is locked.
+	fn write_file(&self, path: &str, message: &str) {
+		// the logger may be called from inside `with_fs`, so never spin on FS
+		let mut fs = FS.try_lock();
+		let Some(fs) = fs.as_mut().and_then(|fs| fs.as_mut()) else {
+			interrupts::without_interrupts(|| {
+				let mut pending = self.pending.lock();
+				if pending.len() < MAX_PENDING_LINES {
+					pending.push((path.to_string(), message.to_string()));
+				}
+			});
+			return;
+		};
+
+		let pending = interrupts::without_interrupts(|| core::mem::take(&mut *self.pending.lock()));
+		let lines = pending.iter().map(|(path, line)| (path.as_str(), line.as_str()));
+		for (path, line) in lines.chain([(path, message)]) {
+			if !fs.exists(path) {
+				let _ = fs.create_file(path, Permission::all());
+			}
+			let _ = fs.write_file(path, line.as_bytes(), false);
+			let _ = fs.write_file(path, b"\n", false);
 		}
 	}
 }
 
 impl LoggerSink for StdOutSink {
+	fn min_level(&self) -> LogLevel {
+		self.min_level.load()
+	}
+
+	fn set_min_level(&self, level: LogLevel) {
+		self.min_level.store(level);
+	}
+
 	fn log(&self, message: &str, level: LogLevel) {
+		if !self.enabled(level) {
+			return;
+		}
 		let formatted_message = self.formatter.format(level, message);
-		println!("{}", formatted_message);
+		self.write(&formatted_message, level);
 	}
 
 	fn log_async(
@@ -40,9 +174,134 @@ impl LoggerSink for StdOutSink {
 		message: &str,
 		level: LogLevel
 	) -> impl core::future::Future<Output = ()> + Send {
-		let formatted_message = self.formatter.format(level, message);
+		let formatted_message = self.enabled(level).then(|| self.formatter.format(level, message));
 		async move {
-			println!("{}", formatted_message);
+			if let Some(formatted_message) = formatted_message {
+				self.write(&formatted_message, level);
+			}
+		}
+	}
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use alloc::boxed::Box;
+
+	use crate::{
+		fs::{self, FS, ramfs::FileSystem},
+		utils::{
+			ktest::TestError,
+			logger::{
+				format::DefaultFormatter,
+				levels::LogLevel,
+				sinks::stdout::*,
+				traits::logger_sink::LoggerSink
+			}
+		}
+	};
+
+	pub fn test_stdout_target_selection() -> Result<(), TestError> {
+		let sink = StdOutSink::new(Box::new(DefaultFormatter::new(false)));
+		assert_eq!(sink.target(), StdOutTarget::Both);
+		assert!(sink.target().writes_vga() && sink.target().writes_serial());
+
+		sink.set_target(StdOutTarget::Serial);
+		assert!(!sink.target().writes_vga() && sink.target().writes_serial());
+
+		sink.set_target(StdOutTarget::Vga);
+		assert!(sink.target().writes_vga() && !sink.target().writes_serial());
+		Ok(())
+	}
+	crate::create_test!(test_stdout_target_selection);
+
+	pub fn test_level_colours() -> Result<(), TestError> {
+		assert_eq!(level_colours(LogLevel::Debug), (Color::LightGray, Color::Black));
+		assert_eq!(level_colours(LogLevel::Info), (Color::White, Color::Black));
+		assert_eq!(level_colours(LogLevel::Warn), (Color::Yellow, Color::Black));
+		assert_eq!(level_colours(LogLevel::Error), (Color::LightRed, Color::Black));
+		assert_eq!(level_colours(LogLevel::Fatal).1, Color::Red);
+		Ok(())
+	}
+	crate::create_test!(test_level_colours);
+
+	pub fn test_stdout_file_target_receives_output() -> Result<(), TestError> {
+		if FS.lock().is_none() {
+			fs::init_fs(FileSystem::new());
 		}
+
+		let path = "/stdout_test";
+		let sink = StdOutSink::new(Box::new(DefaultFormatter::new(false)));
+		sink.set_target(StdOutTarget::File(path.into()));
+		sink.log("first", LogLevel::Info);
+		sink.log("second", LogLevel::Info);
+
+		let content = fs::with_fs(|fs| {
+			let content = fs.read_file(path).map(|c| c.to_vec());
+			let _ = fs.remove(path, false, false);
+			content
+		})
+		.map_err(|_| TestError::Error)?;
+
+		assert_eq!(content.as_slice(), b"first\nsecond\n");
+		Ok(())
+	}
+	crate::create_test!(test_stdout_file_target_receives_output);
+
+	pub fn test_stdout_file_target_buffers_while_fs_locked() -> Result<(), TestError> {
+		if FS.lock().is_none() {
+			fs::init_fs(FileSystem::new());
+		}
+
+		let path = "/stdout_locked_test";
+		let sink = StdOutSink::new(Box::new(DefaultFormatter::new(false)));
+		sink.set_target(StdOutTarget::File(path.into()));
+		// as if logging from inside `with_fs`
+		let buffered = {
+			let _fs = FS.lock();
+			sink.log("held", LogLevel::Info);
+			sink.pending.lock().len() == 1
+		};
+		sink.log("after", LogLevel::Info);
+
+		let content = fs::with_fs(|fs| {
+			let content = fs.read_file(path).map(|c| c.to_vec());
+			let _ = fs.remove(path, false, false);
+			content
+		})
+		.map_err(|_| TestError::Error)?;
+
+		assert!(buffered);
+		assert_eq!(content.as_slice(), b"held\nafter\n");
+		Ok(())
+	}
+	crate::create_test!(test_stdout_file_target_buffers_while_fs_locked);
+
+	pub fn test_stdout_min_level_filters() -> Result<(), TestError> {
+		if FS.lock().is_none() {
+			fs::init_fs(FileSystem::new());
+		}
+
+		let path = "/stdout_level_test";
+		let sink = StdOutSink::new(Box::new(DefaultFormatter::new(false)));
+		sink.set_target(StdOutTarget::File(path.into()));
+		sink.set_min_level(LogLevel::Warn);
+		assert!(!sink.enabled(LogLevel::Info) && sink.enabled(LogLevel::Error));
+
+		sink.log("quiet", LogLevel::Debug);
+		sink.log("quiet", LogLevel::Info);
+		sink.log("loud", LogLevel::Warn);
+
+		let content = fs::with_fs(|fs| {
+			let content = fs.read_file(path).map(|c| c.to_vec());
+			let _ = fs.remove(path, false, false);
+			content
+		})
+		.map_err(|_| TestError::Error)?;
+
+		assert_eq!(content.as_slice(), b"loud\n");
+		assert_eq!(LogLevel::from_name("WARN"), Some(LogLevel::Warn));
+		assert_eq!(LogLevel::from_name("verbose"), None);
+		Ok(())
 	}
+	crate::create_test!(test_stdout_min_level_filters);
 }
@@ -7,9 +7,10 @@
 use alloc::boxed::Box;
 
 use crate::{
-	fs::{self, ramfs::Permission},
+	fs::{FS, ramfs::Permission},
 	utils::logger::{
-		levels::LogLevel,
+		levels::{AtomicLogLevel, LogLevel},
+		sinks::file::FileSink,
 		traits::{log_formatter::LogFormatter, logger_sink::LoggerSink}
 	}
 };
@@ -18,30 +19,67 @@ use crate::{
 /// The SysLog sink. Logs to files inside of `/logs/syslog`
 pub struct SyslogSink {
 	/// The formatting strategy used.
-	pub formatter: Box<dyn LogFormatter>
+	pub formatter: Box<dyn LogFormatter>,
+	/// Messages below this level are dropped.
+	min_level: AtomicLogLevel,
+	/// A file sink every message is also forwarded to.
+	mirror: Option<&'static FileSink>
 }
 
 impl SyslogSink {
 	/// Creates a new `SyslogSink` with the formatting strategy
 	pub fn new(formatter: Box<dyn LogFormatter>) -> Self {
 		Self {
-			formatter
+			formatter,
+			min_level: AtomicLogLevel::new(LogLevel::Debug),
+			mirror: None
 		}
 	}
+
+	/// Appends an already formatted message to `/logs/syslog`. Messages logged
+	/// while the filesystem doesn't exist or is in use only reach the mirror,
+	/// which buffers them.
+	fn write(&self, message: &str) {
+		// the logger may be called from inside `with_fs`, so never spin on FS
+		let mut fs = FS.try_lock();
+		let Some(fs) = fs.as_mut().and_then(|fs| fs.as_mut()) else {
+			return;
+		};
+		if !fs.exists("/logs") {
+			let _ = fs.create_dir("/logs", Permission::all());
+		}
+		if !fs.exists("/logs/syslog") {
+			let _ = fs.create_file("/logs/syslog", Permission::all());
+		}
+		let _ = fs.write_file("/logs/syslog", message.as_bytes(), false);
+	}
+
+	/// Also forwards every message to `sink`, which applies its own formatter
+	/// and level filter.
+	pub fn mirrored_to(mut self, sink: &'static FileSink) -> Self {
+		self.mirror = Some(sink);
+		self
+	}
 }
 
 impl LoggerSink for SyslogSink {
+	fn min_level(&self) -> LogLevel {
+		self.min_level.load()
+	}
+
+	fn set_min_level(&self, level: LogLevel) {
+		self.min_level.store(level);
+	}
+
 	fn log(&self, message: &str, level: LogLevel) {
+		if let Some(mirror) = self.mirror {
+			mirror.log(message, level);
+		}
+		if !self.enabled(level) {
+			return;
+		}
 		let formatted_message = self.formatter.format(level, message);
-		fs::with_fs(|fs| {
-			if !fs.exists("/logs") {
-				let _ = fs.create_dir("/logs", Permission::all());
-			}
-			if !fs.exists("/logs/syslog") {
-				let _ = fs.create_file("/logs/syslog", Permission::all());
-			}
-			let _ = fs.write_file("/logs/syslog", formatted_message.as_bytes(), false);
-		})
+		self.write(&formatted_message);
 	}
 
 	fn log_async(
@@ -49,17 +87,16 @@ impl LoggerSink for SyslogSink {
 		message: &str,
 		level: LogLevel
 	) -> impl core::future::Future<Output = ()> + Send {
-		let formatted_message = self.formatter.format(level, message);
+		let mirrored = self.mirror.map(|mirror| mirror.log_async(message, level));
+		let formatted_message = self.enabled(level).then(|| self.formatter.format(level, message));
 		async move {
-			fs::with_fs(|fs| {
-				if !fs.exists("/logs") {
-					let _ = fs.create_dir("/logs", Permission::all());
-				}
-				if !fs.exists("/logs/syslog") {
-					let _ = fs.create_file("/logs/syslog", Permission::all());
-				}
-				let _ = fs.write_file("/logs/syslog", formatted_message.as_bytes(), false);
-			})
+			if let Some(mirrored) = mirrored {
+				mirrored.await;
+			}
+			let Some(formatted_message) = formatted_message else {
+				return;
+			};
+			self.write(&formatted_message);
 		}
 	}
 }
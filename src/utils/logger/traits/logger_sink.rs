@@ -8,6 +8,16 @@ use crate::utils::logger::levels::LogLevel;
 
 /// Trait representing all functions that a logging sink will need to implement.
 pub trait LoggerSink {
+	/// Returns the least severe level this sink still logs.
+	fn min_level(&self) -> LogLevel;
+	/// Sets the least severe level this sink still logs. Anything below it
+	/// is dropped before being formatted.
+	fn set_min_level(&self, level: LogLevel);
+	/// Whether a message of `level` passes this sink's threshold.
+	fn enabled(&self, level: LogLevel) -> bool {
+		level >= self.min_level()
+	}
+
 	/// Log a message of a certain level.
 	fn log(&self, message: &str, level: LogLevel);
 	/// Asynchronously Log a message of a certain level.
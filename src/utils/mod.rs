@@ -16,10 +16,6 @@ pub mod cpu_utils;
 #[allow(non_camel_case_types)]
 pub mod elf;
 pub mod endian;
-#[deprecated]
-#[allow(unused)]
-#[allow(deprecated)]
-pub mod serial_kfunc;
 pub mod ktest;
 #[allow(missing_docs)]
 #[allow(unused)]
@@ -28,9 +24,9 @@ pub mod logger;
 #[allow(unused)]
 pub mod multiboot2;
 pub mod mutex;
-pub mod net;
 #[allow(missing_docs)]
 pub mod oncecell;
+pub mod panic;
 pub mod process;
 #[allow(missing_docs)]
 pub mod spin;
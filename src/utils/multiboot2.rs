@@ -9,14 +9,15 @@
 
 use core::{ptr::read_unaligned, u64};
 
-use x86_64::PhysAddr;
+use x86_64::{PhysAddr, instructions::interrupts};
 
 use crate::{
 	acpi::RSDT,
 	arch::x86_64::bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType},
 	memory::phys_to_virt,
 	println,
-	serial_println
+	serial_println,
+	utils::mutex::SpinMutex
 };
 
 const MULTIBOOT_SEARCH: u32 = 32768;
@@ -172,7 +173,7 @@ struct MultibootHeaderTagRelocatable {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 struct MultibootColour {
 	red: u8,
 	green: u8,
@@ -293,7 +294,8 @@ struct MultibootTagFramebufferCommon {
 #[derive(Debug, Copy, Clone)]
 struct FramebufferPalette {
 	framebuffer_palette_num_colors: u16,
-	framebuffer_palette: *const MultibootColour
+	// followed by `framebuffer_palette_num_colors` entries
+	framebuffer_palette: [MultibootColour; 0]
 }
 
 #[repr(C)]
@@ -434,6 +436,158 @@ struct MultibootInfoHeader {
 	reserved: u32
 }
 
+/// Longest kernel command line kept. Anything past it is cut off.
+const CMDLINE_MAX: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+/// The kernel command line passed by the boot loader, copied out of the
+/// multiboot information so it outlives it.
+///
+/// Arguments are separated by whitespace and are either `key=value` pairs or
+/// bare flags (`quiet`).
+pub struct KernelCmdline {
+	bytes: [u8; CMDLINE_MAX],
+	len: usize
+}
+
+impl KernelCmdline {
+	/// Creates an empty command line.
+	pub const fn empty() -> Self {
+		Self {
+			bytes: [0; CMDLINE_MAX],
+			len: 0
+		}
+	}
+
+	/// Copies `bytes` (up to a NUL, if any) into a new command line.
+	pub fn new(bytes: &[u8]) -> Self {
+		let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+		let mut cmdline = Self::empty();
+		cmdline.len = end.min(CMDLINE_MAX);
+		cmdline.bytes[..cmdline.len].copy_from_slice(&bytes[..cmdline.len]);
+		cmdline
+	}
+
+	/// Returns the command line, or an empty string if it isn't valid UTF-8.
+	pub fn as_str(&self) -> &str {
+		core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+	}
+
+	/// Returns the value of the first `key=value` argument named `key`.
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.as_str()
+			.split_whitespace()
+			.filter_map(|arg| arg.split_once('='))
+			.find(|(k, _)| *k == key)
+			.map(|(_, value)| value)
+	}
+
+	/// Returns whether the bare flag `name` was passed.
+	pub fn has_flag(&self, name: &str) -> bool {
+		self.as_str().split_whitespace().any(|arg| arg == name)
+	}
+}
+
+/// The command line the kernel was booted with. Empty until
+/// `parse_multiboot2` has run, or if the boot loader didn't pass one.
+static KERNEL_CMDLINE: SpinMutex<KernelCmdline> = SpinMutex::new(KernelCmdline::empty());
+
+/// Returns the command line the kernel was booted with.
+pub fn kernel_cmdline() -> KernelCmdline {
+	interrupts::without_interrupts(|| *KERNEL_CMDLINE.lock())
+}
+
+/// Most palette entries an indexed framebuffer can have.
+const PALETTE_MAX: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How a framebuffer pixel encodes its colour.
+// parsed before the heap is up, so the palette can't be boxed
+#[allow(clippy::large_enum_variant)]
+pub enum PixelFormat {
+	/// Direct colour. Each channel is a `(field position, mask size)` pair in
+	/// bits.
+	Rgb {
+		/// The red channel
+		red: (u8, u8),
+		/// The green channel
+		green: (u8, u8),
+		/// The blue channel
+		blue: (u8, u8)
+	},
+	/// Each pixel is an index into `palette`, of which the first `len`
+	/// entries are valid.
+	Indexed {
+		/// The palette as `[red, green, blue]` entries
+		palette: [[u8; 3]; PALETTE_MAX],
+		/// Number of valid palette entries
+		len: usize
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A linear framebuffer set up by the boot loader.
+pub struct FramebufferInfo {
+	/// Physical address of the first pixel
+	pub addr: u64,
+	/// Bytes per scanline
+	pub pitch: u32,
+	/// Width in pixels
+	pub width: u32,
+	/// Height in pixels
+	pub height: u32,
+	/// Bits per pixel
+	pub bpp: u8,
+	/// Pixel encoding
+	pub format: PixelFormat
+}
+
+impl FramebufferInfo {
+	/// Reads a framebuffer tag. Returns `None` for EGA text mode, which is
+	/// driven through the VGA text buffer instead.
+	unsafe fn from_tag(tagfb: *const MultibootTagFramebuffer) -> Option<FramebufferInfo> {
+		let common = unsafe { &(*tagfb).common };
+		let format = match common.framebuffer_type {
+			MULTIBOOT_FRAMEBUFFER_TYPE_RGB => {
+				let fields = unsafe { (*tagfb).details.rgb_fields };
+				PixelFormat::Rgb {
+					red: (fields.framebuffer_red_field_position, fields.framebuffer_red_mask_size),
+					green: (
+						fields.framebuffer_green_field_position,
+						fields.framebuffer_green_mask_size
+					),
+					blue: (
+						fields.framebuffer_blue_field_position,
+						fields.framebuffer_blue_mask_size
+					)
+				}
+			}
+			MULTIBOOT_FRAMEBUFFER_TYPE_INDEXED => {
+				let info = unsafe { &(*tagfb).details.palette };
+				let len = (info.framebuffer_palette_num_colors as usize).min(PALETTE_MAX);
+				let entries = info.framebuffer_palette.as_ptr();
+
+				let mut palette = [[0u8; 3]; PALETTE_MAX];
+				for (i, entry) in palette.iter_mut().enumerate().take(len) {
+					let colour = unsafe { read_unaligned(entries.add(i)) };
+					*entry = [colour.red, colour.green, colour.blue];
+				}
+				PixelFormat::Indexed { palette, len }
+			}
+			_ => return None
+		};
+
+		Some(FramebufferInfo {
+			addr: common.framebuffer_addr,
+			pitch: common.framebuffer_pitch,
+			width: common.framebuffer_width,
+			height: common.framebuffer_height,
+			bpp: common.framebuffer_bpp,
+			format
+		})
+	}
+}
+
 /// Structure representing the boot-time information 
 /// provided to us by Multiboot2
 pub struct BootInformation {
@@ -443,7 +597,11 @@ pub struct BootInformation {
 	pub memory_map: MemoryMap,
 
 	/// The Root System Description Pointer
-	pub rsdp: usize
+	pub rsdp: usize,
+	/// The kernel command line
+	pub cmdline: KernelCmdline,
+	/// The linear framebuffer, if the boot loader set up a graphics mode
+	pub framebuffer: Option<FramebufferInfo>
 }
 
 impl BootInformation {
@@ -451,7 +609,9 @@ impl BootInformation {
 		Self {
 			physical_memory_offset: 0,
 			memory_map: MemoryMap::new(),
-			rsdp: 0
+			rsdp: 0,
+			cmdline: KernelCmdline::empty(),
+			framebuffer: None
 		}
 	}
 }
@@ -487,7 +647,11 @@ pub unsafe fn parse_multiboot2(mbi_addr: usize) -> BootInformation {
 			match (*tag).r#type {
 				MULTIBOOT_TAG_TYPE_CMDLINE => {
 					let str = tag as *const MultibootTagString;
-					println!("Command line = {:?}", (*str).string)
+					let len = ((*tag).size as usize).saturating_sub(8);
+					let bytes = core::slice::from_raw_parts((*str).string.as_ptr(), len);
+					bi.cmdline = KernelCmdline::new(bytes);
+					*KERNEL_CMDLINE.lock() = bi.cmdline;
+					println!("Command line = {:?}", bi.cmdline.as_str())
 				}
 				MULTIBOOT_TAG_TYPE_BOOT_LOADER_NAME => {
 					let str = tag as *const MultibootTagString;
@@ -552,102 +716,13 @@ pub unsafe fn parse_multiboot2(mbi_addr: usize) -> BootInformation {
 				}
 				MULTIBOOT_TAG_TYPE_FRAMEBUFFER => {
 					let tagfb = tag as *const MultibootTagFramebuffer;
-					let fb = (*tagfb).common.framebuffer_addr as *const u8;
-					let mut colour: u32 = 0;
-					let mut i: u32 = 0;
-
-					match (*tagfb).common.framebuffer_type {
-						MULTIBOOT_FRAMEBUFFER_TYPE_INDEXED => {
-							let palette_info = (*tagfb).details.palette;
-							let num = palette_info.framebuffer_palette_num_colors as usize;
-							let pal_ptr = palette_info.framebuffer_palette;
-
-							if !pal_ptr.is_null() && num > 0 {
-								let mut best_distance: u32 = u32::MAX;
-								let mut best_index: usize = 0;
-
-								for idx in 0..num {
-									let col: &MultibootColour = &*pal_ptr.add(idx);
-									println!(
-										"palette[{}] = r={} g={} b={}",
-										idx, col.red, col.green, col.blue
-									);
-
-									// distance = (0xff - palette[i].blue) * (0xff -
-									// palette[i].blue)
-									//          + palette[i].red * palette[i].red
-									//          + palette[i].green * palette[i].green;
-									let b_inv = 0xffu32 - (col.blue as u32);
-									let r = col.red as u32;
-									let g = col.green as u32;
-									let distance = b_inv
-										.wrapping_mul(b_inv)
-										.wrapping_add(r.wrapping_mul(r))
-										.wrapping_add(g.wrapping_mul(g));
-
-									if distance < best_distance {
-										best_distance = distance;
-										best_index = idx;
-									}
-								}
-
-								colour = best_index as u32;
-							}
-						}
-						MULTIBOOT_FRAMEBUFFER_TYPE_RGB => {
-							colour =
-								((1 << (*tagfb).details.rgb_fields.framebuffer_blue_mask_size) - 1)
-									<< (*tagfb).details.rgb_fields.framebuffer_blue_field_position;
-						}
-						MULTIBOOT_FRAMEBUFFER_TYPE_EGA_TEXT => {
-							colour = '\\' as u32 | 0x0100;
-						}
-						_ => {
-							colour = 0xffffffff;
-						}
-					}
-
-					// loops through width and height, could use two for loops, the kernel.c code
-					// says otherwise.
-					while i < (*tagfb).common.framebuffer_width
-						&& i < (*tagfb).common.framebuffer_height
-					{
-						match (*tagfb).common.framebuffer_bpp {
-							8 => {
-								let pixel = fb.wrapping_add(
-									((*tagfb).common.framebuffer_pitch * i + i)
-										.try_into()
-										.unwrap()
-								) as *mut u8;
-								*pixel = colour as u8;
-							}
-							16 => {
-								let pixel = fb.wrapping_add(
-									((*tagfb).common.framebuffer_pitch * i + 2 * i)
-										.try_into()
-										.unwrap()
-								) as *mut u16;
-								*pixel = colour as u16;
-							}
-							24 => {
-								let pixel = fb.wrapping_add(
-									((*tagfb).common.framebuffer_pitch * i + 3 * i)
-										.try_into()
-										.unwrap()
-								) as *mut u32;
-								*pixel = (colour & 0xffffff) | (*pixel & 0xff000000)
-							}
-							32 => {
-								let pixel = fb.wrapping_add(
-									((*tagfb).common.framebuffer_pitch * i + 4 * i)
-										.try_into()
-										.unwrap()
-								) as *mut u32;
-								*pixel = colour;
-							}
-							_ => {}
-						}
-						i += 1;
+					bi.framebuffer = FramebufferInfo::from_tag(tagfb);
+					match bi.framebuffer {
+						Some(fb) => serial_println!(
+							"[MULTIBOOT2] framebuffer {}x{}x{} at {:#x}",
+							fb.width, fb.height, fb.bpp, fb.addr
+						),
+						None => serial_println!("[MULTIBOOT2] EGA text framebuffer")
 					}
 				}
 
@@ -715,3 +790,25 @@ pub unsafe fn compute_phys_map_offset() -> u64 {
 		text_vaddr.wrapping_sub(phys_base)
 	}
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::utils::{ktest::TestError, multiboot2::*};
+
+	pub fn test_command_line_arguments() -> Result<(), TestError> {
+		let cmdline = KernelCmdline::new(b"quiet ktest=fs,vfs  log=debug\0garbage");
+		assert_eq!(cmdline.as_str(), "quiet ktest=fs,vfs  log=debug");
+		assert_eq!(cmdline.get("ktest"), Some("fs,vfs"));
+		assert_eq!(cmdline.get("log"), Some("debug"));
+		assert_eq!(cmdline.get("quiet"), None);
+		assert!(cmdline.has_flag("quiet"));
+		assert!(!cmdline.has_flag("ktest"));
+
+		// overlong command lines are cut off
+		let long = KernelCmdline::new(&[b'a'; CMDLINE_MAX + 10]);
+		assert_eq!(long.as_str().len(), CMDLINE_MAX);
+		assert_eq!(KernelCmdline::empty().get("ktest"), None);
+		Ok(())
+	}
+	crate::create_test!(test_command_line_arguments);
+}
@@ -3,6 +3,9 @@
 //! 
 //! An implementation for a Mutually Exclusive thread-safe type.
 //! 
+//! Debug builds remember where each mutex was locked and panic with a
+//! deadlock report when `lock()` spins for too long. Release builds spin
+//! forever, with no bookkeeping.
 //! 
 
 use core::{
@@ -10,12 +13,23 @@ use core::{
 	mem::MaybeUninit,
 	sync::atomic::{AtomicBool, Ordering}
 };
+#[cfg(debug_assertions)]
+use core::{panic::Location, ptr::null_mut, sync::atomic::AtomicPtr};
 
 use x86_64::instructions::interrupts;
 
+/// Spins `lock()` gives up after in debug builds. Far longer than any lock is
+/// legitimately held; with a single CPU a mutex that stays locked this long
+/// is held by the code `lock()` interrupted, which will never run again.
+#[cfg(debug_assertions)]
+const DEADLOCK_SPINS: u64 = 50_000_000;
+
 /// A Mutual Exclusion Object to prevent race conditions.
 pub struct SpinMutex<T> {
 	locked: AtomicBool,
+	/// Where the current holder locked the mutex.
+	#[cfg(debug_assertions)]
+	holder: AtomicPtr<Location<'static>>,
 	data: UnsafeCell<T>
 }
 
@@ -27,33 +41,51 @@ impl<T> SpinMutex<T> {
 	pub const fn new(data: T) -> Self {
 		SpinMutex {
 			locked: AtomicBool::new(false),
+			#[cfg(debug_assertions)]
+			holder: AtomicPtr::new(null_mut()),
 			data: UnsafeCell::new(data)
 		}
 	}
 
 	/// Locks the current `SpinMutex`
+	///
+	/// In debug builds, panics with the holder's and the caller's location if
+	/// the mutex stays locked for `DEADLOCK_SPINS` spins.
+	#[track_caller]
 	pub fn lock(&self) -> SpinMutexGuard<'_, T> {
 		// fixed deadlock where ISR and other parts of code
 		// tried to get data at the same time
 		interrupts::disable();
 
+		#[cfg(debug_assertions)]
+		let mut spins: u64 = 0;
 		while self.locked.swap(true, Ordering::Acquire) {
+			#[cfg(debug_assertions)]
+			{
+				spins += 1;
+				if spins == DEADLOCK_SPINS {
+					self.deadlock();
+				}
+			}
 			interrupts::enable();
 			core::hint::spin_loop();
 			interrupts::disable();
 		}
+		self.acquired();
 		SpinMutexGuard {
 			mutex: self
 		}
 	}
 
 	/// Tries to lock the current `SpinMutex`
+	#[track_caller]
 	pub fn try_lock(&self) -> Option<SpinMutexGuard<'_, T>> {
 		if self
 			.locked
 			.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
 			.is_ok()
 		{
+			self.acquired();
 			Some(SpinMutexGuard {
 				mutex: self
 			})
@@ -62,6 +94,34 @@ impl<T> SpinMutex<T> {
 		}
 	}
 
+	/// Records the caller as the holder.
+	#[track_caller]
+	#[inline(always)]
+	fn acquired(&self) {
+		#[cfg(debug_assertions)]
+		self.holder.store(Location::caller() as *const _ as *mut _, Ordering::Relaxed);
+	}
+
+	/// Returns where the mutex was last locked. Only tracked in debug builds.
+	#[cfg(debug_assertions)]
+	pub fn holder(&self) -> Option<&'static Location<'static>> {
+		unsafe { self.holder.load(Ordering::Relaxed).as_ref() }
+	}
+
+	#[cfg(debug_assertions)]
+	#[track_caller]
+	#[cold]
+	fn deadlock(&self) -> ! {
+		match self.holder() {
+			Some(holder) => panic!(
+				"deadlock: lock() at {} spun on a mutex held since {}",
+				Location::caller(),
+				holder
+			),
+			None => panic!("deadlock: lock() at {} spun on a mutex", Location::caller())
+		}
+	}
+
 	/// Forces the SpinMutex to unlock, regardless if another thread is trying to use it.
 	pub unsafe fn force_unlock(&self) {
 		self.locked.store(false, Ordering::Release);
@@ -80,6 +140,8 @@ impl<T> SpinMutex<Option<T>> {
 	const fn none() -> Self {
 		SpinMutex {
 			locked: AtomicBool::new(false),
+			#[cfg(debug_assertions)]
+			holder: AtomicPtr::new(null_mut()),
 			data: UnsafeCell::new(None)
 		}
 	}
@@ -91,6 +153,8 @@ impl<T> SpinMutex<MaybeUninit<T>> {
 	const fn uninit() -> Self {
 		SpinMutex {
 			locked: AtomicBool::new(false),
+			#[cfg(debug_assertions)]
+			holder: AtomicPtr::new(null_mut()),
 			data: UnsafeCell::new(MaybeUninit::uninit())
 		}
 	}
@@ -128,3 +192,22 @@ impl<'a, T> Drop for SpinMutexGuard<'a, T> {
 		self.mutex.locked.store(false, Ordering::Release);
 	}
 }
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::utils::{ktest::TestError, mutex::*};
+
+	pub fn test_lock_and_try_lock() -> Result<(), TestError> {
+		let mutex = SpinMutex::new(1);
+		{
+			let mut guard = mutex.lock();
+			*guard += 1;
+			assert!(mutex.try_lock().is_none());
+			#[cfg(debug_assertions)]
+			assert_eq!(mutex.holder().map(|l| l.file()), Some(file!()));
+		}
+		assert_eq!(mutex.try_lock().map(|guard| *guard), Some(2));
+		Ok(())
+	}
+	crate::create_test!(test_lock_and_try_lock);
+}
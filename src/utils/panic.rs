@@ -0,0 +1,229 @@
+//!
+//! panic.rs
+//!
+//! The crash report printed by the panic handler: general purpose and control
+//! registers, then the return addresses found by walking the saved RBP chain.
+//! The kernel is built with frame pointers, so the chain is there to walk.
+//!
+
+use core::{
+	arch::asm,
+	fmt,
+	panic::PanicInfo,
+	sync::atomic::{AtomicBool, Ordering}
+};
+
+use x86_64::{
+	VirtAddr,
+	instructions::interrupts,
+	registers::control::{Cr2, Cr3},
+	structures::paging::{OffsetPageTable, Translate}
+};
+
+use crate::{
+	PHYS_MEM_OFFSET,
+	memory::active_level_4_table,
+	println,
+	serial::SERIAL1,
+	serial_println,
+	vga_buffer::WRITER
+};
+
+/// Most frames printed, so a corrupted chain that loops can't hang the report.
+const MAX_FRAMES: usize = 32;
+
+/// Set by the first panic. A panic while reporting only prints its message.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Prints to both the VGA console and serial.
+macro_rules! report {
+	($($arg:tt)*) => {{
+		println!($($arg)*);
+		serial_println!($($arg)*);
+	}};
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+/// General purpose registers and RFLAGS as they were when captured.
+pub struct Registers {
+	rax: u64,
+	rbx: u64,
+	rcx: u64,
+	rdx: u64,
+	rsi: u64,
+	rdi: u64,
+	rbp: u64,
+	rsp: u64,
+	r8: u64,
+	r9: u64,
+	r10: u64,
+	r11: u64,
+	r12: u64,
+	r13: u64,
+	r14: u64,
+	r15: u64,
+	rflags: u64
+}
+
+impl Registers {
+	/// Captures the registers of the caller. One register holds the
+	/// destination pointer by the time it's stored, so its value is only as
+	/// good as the compiler left it.
+	#[inline(always)]
+	pub fn capture() -> Registers {
+		let mut regs = Registers::default();
+		let rflags: u64;
+		unsafe {
+			asm!(
+				"mov [{r} + 0x00], rax",
+				"mov [{r} + 0x08], rbx",
+				"mov [{r} + 0x10], rcx",
+				"mov [{r} + 0x18], rdx",
+				"mov [{r} + 0x20], rsi",
+				"mov [{r} + 0x28], rdi",
+				"mov [{r} + 0x30], rbp",
+				"mov [{r} + 0x38], rsp",
+				"mov [{r} + 0x40], r8",
+				"mov [{r} + 0x48], r9",
+				"mov [{r} + 0x50], r10",
+				"mov [{r} + 0x58], r11",
+				"mov [{r} + 0x60], r12",
+				"mov [{r} + 0x68], r13",
+				"mov [{r} + 0x70], r14",
+				"mov [{r} + 0x78], r15",
+				r = in(reg) &mut regs as *mut Registers,
+				options(nostack, preserves_flags)
+			);
+			asm!("pushfq", "pop {}", out(reg) rflags, options(preserves_flags));
+		}
+		regs.rflags = rflags;
+		regs
+	}
+}
+
+impl fmt::Display for Registers {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "RAX={:016x} RBX={:016x} RCX={:016x}", self.rax, self.rbx, self.rcx)?;
+		writeln!(f, "RDX={:016x} RSI={:016x} RDI={:016x}", self.rdx, self.rsi, self.rdi)?;
+		writeln!(f, "RBP={:016x} RSP={:016x} R8 ={:016x}", self.rbp, self.rsp, self.r8)?;
+		writeln!(f, "R9 ={:016x} R10={:016x} R11={:016x}", self.r9, self.r10, self.r11)?;
+		writeln!(f, "R12={:016x} R13={:016x} R14={:016x}", self.r12, self.r13, self.r14)?;
+		write!(f, "R15={:016x} RFLAGS={:016x}", self.r15, self.rflags)
+	}
+}
+
+/// Returns whether `addr` is canonical and mapped in the active page table.
+/// Never spins: if the physical memory offset is locked, nothing is mapped.
+fn is_mapped(addr: u64) -> bool {
+	let Ok(addr) = VirtAddr::try_new(addr) else {
+		return false;
+	};
+	let Some(pmo) = PHYS_MEM_OFFSET.try_lock().map(|pmo| *pmo) else {
+		return false;
+	};
+	if pmo.is_null() {
+		return false;
+	}
+
+	let table = unsafe { OffsetPageTable::new(active_level_4_table(pmo), pmo) };
+	table.translate_addr(addr).is_some()
+}
+
+/// Walks the RBP chain starting at `rbp`, calling `f` with each return
+/// address. Stops at a null, misaligned or unmapped frame pointer, at a frame
+/// that doesn't move up the stack, or after `MAX_FRAMES` frames.
+pub fn walk_frames(mut rbp: u64, mut f: impl FnMut(usize, u64)) {
+	for depth in 0..MAX_FRAMES {
+		// the saved RBP and return address are 16 bytes at `rbp`
+		if rbp == 0 || !rbp.is_multiple_of(8) || !is_mapped(rbp) || !is_mapped(rbp + 15) {
+			return;
+		}
+
+		let (next, ret) = unsafe {
+			(
+				core::ptr::read_volatile(rbp as *const u64),
+				core::ptr::read_volatile((rbp + 8) as *const u64)
+			)
+		};
+		if ret == 0 {
+			return;
+		}
+		f(depth, ret);
+
+		if next <= rbp {
+			return;
+		}
+		rbp = next;
+	}
+}
+
+/// Prints the crash report for `info`. Called by the panic handler with the
+/// registers it captured on entry.
+pub fn report(info: &PanicInfo, regs: &Registers) {
+	interrupts::disable();
+
+	if PANICKING.swap(true, Ordering::SeqCst) {
+		unsafe { SERIAL1.force_unlock() };
+		serial_println!("PANIC while reporting a panic: {}", info);
+		return;
+	}
+
+	// the panic may have happened with either console locked, and nothing
+	// else runs from here on
+	if WRITER.try_lock().is_none() {
+		unsafe { WRITER.force_unlock() };
+	}
+	if SERIAL1.try_lock().is_none() {
+		unsafe { SERIAL1.force_unlock() };
+	}
+
+	report!("KERNEL PANIC: {}", info);
+	report!("{}", regs);
+	report!("CR2={:016x} CR3={:016x}", Cr2::read_raw(), Cr3::read().0.start_address().as_u64());
+
+	report!("Backtrace:");
+	let mut frames = 0;
+	walk_frames(regs.rbp, |depth, ret| {
+		report!("  #{:<2} {:#018x}", depth, ret);
+		frames += 1;
+	});
+	if frames == 0 {
+		report!("  (no frames)");
+	}
+}
+
+#[cfg(feature = "test")]
+pub mod tests {
+	use crate::utils::{ktest::TestError, panic::*};
+
+	#[inline(never)]
+	fn frames_from_here() -> usize {
+		let regs = Registers::capture();
+		let mut frames = 0;
+		walk_frames(regs.rbp, |_, _| frames += 1);
+		frames
+	}
+
+	pub fn test_walk_frames_stops_on_bad_chain() -> Result<(), TestError> {
+		// at least this test and its caller are on the chain
+		assert!(frames_from_here() >= 2);
+
+		let mut visited = 0;
+		walk_frames(0, |_, _| visited += 1);
+		walk_frames(0x1001, |_, _| visited += 1);
+		walk_frames(0xdead_beef_0000_0000, |_, _| visited += 1);
+		assert_eq!(visited, 0);
+
+		// a frame pointing at itself is reported once, not forever
+		let frame: [u64; 2] = [0, 0x1234];
+		let mut looped = [0u64; 2];
+		looped[1] = 0x5678;
+		looped[0] = looped.as_ptr() as u64;
+		walk_frames(frame.as_ptr() as u64, |_, _| visited += 1);
+		walk_frames(looped.as_ptr() as u64, |_, _| visited += 1);
+		assert_eq!(visited, 2);
+		Ok(())
+	}
+	crate::create_test!(test_walk_frames_stops_on_bad_chain);
+}
@@ -6,12 +6,12 @@
 
 use alloc::{boxed::Box, sync::Arc};
 use crossbeam_queue::ArrayQueue;
-use core::{future::Future, pin::Pin, sync::atomic::{AtomicBool, Ordering}};
+use core::{future::Future, pin::Pin, sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering}};
 
 use futures::task::AtomicWaker;
 
 use crate::{
-	apic::{APIC_TICK_COUNT, APIC_TPS}, error::NullexError, task::{Process, ProcessId, ProcessState, executor::EXECUTOR, yield_now}, utils::oncecell::cell::OnceCell
+	apic::APIC_TICK_COUNT, error::NullexError, task::{CancellationToken, Priority, Process, ProcessId, ProcessState, executor::{self, CURRENT_PROCESS, EXECUTOR}, timer::ms_to_ticks, yield_now}, utils::oncecell::cell::OnceCell
 };
 
 /// Spawns a process using the provided future function.
@@ -39,6 +39,9 @@ pub fn spawn_process<F>(future_fn: F, is_child: bool) -> Result<ProcessId, Nulle
 where
 	F: Fn(Arc<ProcessState>) -> Pin<Box<dyn Future<Output = i32>>> + Send + Sync + 'static
 {
+	// the process spawning this one, if any, is its parent
+	let parent = CURRENT_PROCESS.lock().as_ref().map(|state| state.id);
+
 	// lock the executor and create a new PID.
 	let mut executor = EXECUTOR.lock();
 	let pid = executor.create_pid();
@@ -47,10 +50,15 @@ where
 	let state = Arc::new(ProcessState {
 		id: pid,
 		is_child,
+		parent,
+		fork_pending: AtomicBool::new(false),
 		future_fn: Arc::new(future_fn),
 		queued: AtomicBool::new(false),
 		scancode_queue: OnceCell::uninit(),
-		waker: AtomicWaker::new()
+		waker: AtomicWaker::new(),
+		cancel: CancellationToken::new(),
+		cancel_deadline: AtomicU64::new(0),
+		priority: AtomicU8::new(Priority::Normal as u8)
 	});
 
 	// construct the process.
@@ -60,6 +68,139 @@ where
 	Ok(pid)
 }
 
+/// Forks the currently running process.
+///
+/// A process is a future, and a future that is part way through can't be
+/// duplicated, so fork works by running the parent's code again:
+///
+/// - the child is a new process that runs the parent's `future_fn` from the
+///   start, with `is_child` set and a copy of the parent's open file table;
+/// - in the parent, `fork` returns the child's pid;
+/// - in the child, the first `fork` it reaches returns 0. Any later call
+///   forks as usual.
+///
+/// Everything before the fork runs once in each process, so it should be
+/// free of side effects that can't happen twice.
+///
+/// Only kernel processes can fork. A user program runs inside the poll of the
+/// process that started it, so the `split` syscall fails for it.
+pub fn fork() -> Result<u64, NullexError> {
+	let parent_state = CURRENT_PROCESS.lock().clone().ok_or(NullexError::NoCurrentProcess)?;
+
+	// this is the child's re-run arriving at the fork it was created by
+	if parent_state.fork_pending.swap(false, Ordering::AcqRel) {
+		return Ok(0);
+	}
+
+	let mut executor = EXECUTOR.lock();
+	let child_pid = executor.create_pid();
+	let child_state = Arc::new(ProcessState {
+		id: child_pid,
+		is_child: true,
+		parent: Some(parent_state.id),
+		fork_pending: AtomicBool::new(true),
+		future_fn: parent_state.future_fn.clone(),
+		queued: AtomicBool::new(false),
+		scancode_queue: OnceCell::uninit(),
+		waker: AtomicWaker::new(),
+		cancel: CancellationToken::new(),
+		cancel_deadline: AtomicU64::new(0),
+		priority: AtomicU8::new(parent_state.priority() as u8)
+	});
+
+	let mut child = Process::new(child_state)?;
+	// the parent is locked by the executor while it runs, so go through the
+	// guard rather than the process table
+	unsafe {
+		if let Some(parent) = executor::CURRENT_PROCESS_GUARD.as_ref()
+			&& parent.state.id == parent_state.id
+		{
+			child.open_files = parent.open_files.clone();
+			child.next_fd = parent.next_fd;
+			child.cwd = parent.cwd.clone();
+		}
+	}
+
+	executor.spawn_process(child)?;
+	Ok(child_pid.get())
+}
+
+/// Returns whether the running process has been asked to stop, e.g. by
+/// Ctrl+C. Long-running futures should check this between steps.
+pub fn cancel_requested() -> bool {
+	CURRENT_PROCESS.lock().as_ref().is_some_and(|state| state.is_cancelled())
+}
+
+/// Returns the state of the running process `pid`.
+fn process_state(pid: ProcessId) -> Result<Arc<ProcessState>, NullexError> {
+	// the running process is locked by the executor, so a process looking
+	// itself up is found through `CURRENT_PROCESS` instead
+	let current = CURRENT_PROCESS.lock().clone().filter(|state| state.id == pid);
+	match current {
+		Some(state) => Ok(state),
+		None => EXECUTOR
+			.lock()
+			.processes
+			.get(&pid)
+			.map(|process| process.lock().state.clone())
+			.ok_or(NullexError::ProcessNotFound)
+	}
+}
+
+/// Asks the process `pid` to stop. See `ProcessState::request_cancel`.
+pub fn cancel(pid: ProcessId) -> Result<(), NullexError> {
+	process_state(pid)?.request_cancel();
+	Ok(())
+}
+
+/// Changes how eagerly the process `pid` is run. See `Priority`.
+pub fn set_priority(pid: ProcessId, priority: Priority) -> Result<(), NullexError> {
+	process_state(pid)?.set_priority(priority);
+	Ok(())
+}
+
+/// Checks on the running process's child `pid` without blocking.
+///
+/// Returns its exit code once it has ended, reaping it, or `None` while it is
+/// still running. Fails with `ProcessNotFound` if `pid` is neither running
+/// nor an unreaped child, and with `NotChild` if it isn't the caller's.
+pub fn try_wait(pid: ProcessId) -> Result<Option<i32>, NullexError> {
+	let caller = CURRENT_PROCESS
+		.lock()
+		.as_ref()
+		.map(|state| state.id)
+		.ok_or(NullexError::NoCurrentProcess)?;
+	try_wait_as(caller, pid)
+}
+
+/// Like `try_wait`, on behalf of the process `parent`.
+pub fn try_wait_as(parent: ProcessId, pid: ProcessId) -> Result<Option<i32>, NullexError> {
+	let mut executor = EXECUTOR.lock();
+	if let Some(exit_code) = executor.reap(parent, pid) {
+		return Ok(Some(exit_code));
+	}
+	if executor.zombies.contains_key(&pid) || pid == parent {
+		return Err(NullexError::NotChild);
+	}
+	// a process other than the caller isn't locked by the executor
+	match executor.processes.get(&pid) {
+		Some(process) if process.lock().state.parent == Some(parent) => Ok(None),
+		Some(_) => Err(NullexError::NotChild),
+		None => Err(NullexError::ProcessNotFound)
+	}
+}
+
+/// Waits for the child `pid` to end, yielding to other processes until it
+/// does, and returns its exit code.
+pub async fn wait(pid: ProcessId) -> Result<i32, NullexError> {
+	loop {
+		if let Some(exit_code) = try_wait(pid)? {
+			return Ok(exit_code);
+		}
+		yield_now().await;
+	}
+}
+
 /// Spawns a new user process with restricted permissions.
 pub fn spawn_user_process(bytes: &[u8], args: &[&str], envs: &[&str]) -> Result<Process, NullexError> {
 	let mut executor = EXECUTOR.lock();
@@ -68,10 +209,15 @@ pub fn spawn_user_process(bytes: &[u8], args: &[&str], envs: &[&str]) -> Result<
 	let state = Arc::new(ProcessState {
         id: pid,
         is_child: false,
+        parent: None,
+        fork_pending: AtomicBool::new(false),
         future_fn: Arc::new(|_| Box::pin(async { 0 })),
         queued: AtomicBool::new(false),
         scancode_queue: OnceCell::new(ArrayQueue::new(1)),
         waker: AtomicWaker::new(),
+        cancel: CancellationToken::new(),
+        cancel_deadline: AtomicU64::new(0),
+        priority: AtomicU8::new(Priority::Normal as u8),
     });
 
     Process::from_elf(state, bytes, args, envs)
@@ -81,10 +227,8 @@ pub fn spawn_user_process(bytes: &[u8], args: &[&str], envs: &[&str]) -> Result<
 /// # Safety
 /// Should NEVER be used in kernel space. only like a API for syscalls and user space later.
 async unsafe fn sleep(ms: u64) {
-    let tps = APIC_TPS.load(Ordering::Relaxed);
     let now = APIC_TICK_COUNT.load(Ordering::Relaxed);
-    let ticks = (ms * tps) / 1000;
-    let then = now + ticks;
+    let then = now + ms_to_ticks(ms);
 
     while APIC_TICK_COUNT.load(Ordering::Relaxed) < then {
         yield_now().await;
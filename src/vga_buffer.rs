@@ -8,12 +8,21 @@
 //! 
 //! 
 
-use core::fmt;
+use core::{
+	fmt,
+	sync::atomic::{AtomicBool, Ordering}
+};
+
+use x86_64::instructions::{interrupts, port::Port};
 
-use x86_64::instructions::port::Port;
+use alloc::{boxed::Box, string::String};
 
 use crate::{
+	drivers::framebuffer::FramebufferConsole,
+	error::NullexError,
 	lazy_static,
+	serial::SERIAL1,
+	task::{ProcessId, executor::CURRENT_PROCESS},
 	utils::{mutex::SpinMutex, volatile::Volatile}
 };
 
@@ -34,9 +43,181 @@ lazy_static! {
 		current_row: 0,
 		color_code: ColorCode::new(Color::White, Color::Black),
 		buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+		framebuffer: None,
+		back_buffer: None,
+		cursor_visible: true
 	});
 }
 
+/// Index and data ports of the VGA CRT controller.
+const CRTC_INDEX: u16 = 0x3D4;
+const CRTC_DATA: u16 = 0x3D5;
+/// CRTC registers of the text cursor: its first and last scan line, and
+/// the high and low byte of its cell.
+const CRTC_CURSOR_START: u8 = 0x0A;
+const CRTC_CURSOR_END: u8 = 0x0B;
+const CRTC_CURSOR_HIGH: u8 = 0x0E;
+const CRTC_CURSOR_LOW: u8 = 0x0F;
+/// Bit of `CRTC_CURSOR_START` that hides the cursor.
+const CURSOR_DISABLE: u8 = 1 << 5;
+/// Bits of the cursor scan line registers holding the scan line.
+const CURSOR_SCAN_LINE_MASK: u8 = 0x1F;
+/// Scan lines in a text cell; the cursor spans some of them.
+const CELL_SCAN_LINES: u8 = 16;
+
+fn crtc_read(index: u8) -> u8 {
+	unsafe {
+		Port::<u8>::new(CRTC_INDEX).write(index);
+		Port::<u8>::new(CRTC_DATA).read()
+	}
+}
+
+fn crtc_write(index: u8, value: u8) {
+	unsafe {
+		Port::<u8>::new(CRTC_INDEX).write(index);
+		Port::<u8>::new(CRTC_DATA).write(value);
+	}
+}
+
+/// Bytes of output that can wait for `WRITER` before more is dropped.
+const DEFERRED_CAPACITY: usize = 2048;
+
+/// Output printed while `WRITER` was already locked, e.g. by a print that
+/// called into code which prints, so printing never has to wait for
+/// `WRITER`. The next print writes it out ahead of its own output, and a
+/// print holding `WRITER` also writes out what was deferred meanwhile.
+static DEFERRED: SpinMutex<DeferredOutput> = SpinMutex::new(DeferredOutput::new());
+
+struct DeferredOutput {
+	bytes: [u8; DEFERRED_CAPACITY],
+	len: usize,
+	/// Bytes that didn't fit since the last flush.
+	dropped: usize
+}
+
+impl DeferredOutput {
+	const fn new() -> Self {
+		Self {
+			bytes: [0; DEFERRED_CAPACITY],
+			len: 0,
+			dropped: 0
+		}
+	}
+}
+
+impl fmt::Write for DeferredOutput {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		let fits = s.len().min(DEFERRED_CAPACITY - self.len);
+		self.bytes[self.len..self.len + fits].copy_from_slice(&s.as_bytes()[..fits]);
+		self.len += fits;
+		self.dropped += s.len() - fits;
+		Ok(())
+	}
+}
+
+/// Writes out deferred output. Called with `WRITER` locked.
+fn flush_deferred(writer: &mut Writer) {
+	loop {
+		let pending = {
+			let mut deferred = DEFERRED.lock();
+			if deferred.len == 0 && deferred.dropped == 0 {
+				return;
+			}
+			let pending = (deferred.bytes, deferred.len, deferred.dropped);
+			deferred.len = 0;
+			deferred.dropped = 0;
+			pending
+		};
+
+		let (bytes, len, dropped) = pending;
+		for byte in &bytes[..len] {
+			match byte {
+				0x20..=0x7e | b'\n' => writer.write_byte(*byte),
+				_ => writer.write_byte(0xfe)
+			}
+		}
+		if dropped > 0 {
+			use core::fmt::Write;
+			let _ = writeln!(writer, "[{} bytes of output dropped]", dropped);
+		}
+	}
+}
+
+/// Runs `f` with `WRITER` locked, writing out deferred output before it, so
+/// output keeps its order, and again after it for anything `f` deferred.
+/// If `WRITER` is already locked, `defer` is given the deferred output to
+/// write to instead.
+fn with_writer(f: impl FnOnce(&mut Writer), defer: impl FnOnce(&mut DeferredOutput)) {
+	interrupts::without_interrupts(|| match WRITER.try_lock() {
+		Some(mut writer) => {
+			flush_deferred(&mut writer);
+			f(&mut writer);
+			flush_deferred(&mut writer);
+		}
+		// a print from inside deferring has nowhere to go
+		None => {
+			if let Some(mut deferred) = DEFERRED.try_lock() {
+				defer(&mut deferred);
+			}
+		}
+	})
+}
+
+/// While set, everything printed to the VGA buffer is also sent to the serial
+/// port. The serial console sets it while it runs a command.
+static SERIAL_MIRROR: AtomicBool = AtomicBool::new(false);
+
+/// Runs `f` with all VGA output mirrored to the serial port.
+pub fn with_serial_mirror<R>(f: impl FnOnce() -> R) -> R {
+	let previous = SERIAL_MIRROR.swap(true, Ordering::Relaxed);
+	let result = f();
+	SERIAL_MIRROR.store(previous, Ordering::Relaxed);
+	result
+}
+
+/// Output of a process being captured instead of printed.
+struct Capture {
+	/// The process whose output is captured, `None` outside of any process.
+	owner: Option<ProcessId>,
+	output: String
+}
+
+/// The capture set up by `capture_output`, if any.
+static CAPTURE: SpinMutex<Option<Capture>> = SpinMutex::new(None);
+
+/// Runs `f`, collecting everything the current process prints meanwhile
+/// instead of showing it. Other processes keep printing to the console.
+pub fn capture_output<R>(f: impl FnOnce() -> R) -> (R, String) {
+	let owner = CURRENT_PROCESS.lock().as_ref().map(|state| state.id);
+	let capture = Capture {
+		owner,
+		output: String::new()
+	};
+	let previous = CAPTURE.lock().replace(capture);
+	let result = f();
+	let capture = core::mem::replace(&mut *CAPTURE.lock(), previous);
+	(result, capture.map(|capture| capture.output).unwrap_or_default())
+}
+
+/// Appends `args` to the capture if the current process's output is being
+/// captured. Returns whether it was.
+fn capture(args: fmt::Arguments) -> bool {
+	use core::fmt::Write;
+	// a print from inside capturing, or while the current process is being
+	// switched, goes to the console
+	let Some(mut capture) = CAPTURE.try_lock() else {
+		return false;
+	};
+	let Some(capture) = capture.as_mut() else {
+		return false;
+	};
+	let current = match CURRENT_PROCESS.try_lock() {
+		Some(current) => current.as_ref().map(|state| state.id),
+		None => return false
+	};
+	current == capture.owner && capture.output.write_fmt(args).is_ok()
+}
+
 /// The standard color palette in VGA text mode.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -75,6 +256,64 @@ pub enum Color {
 	White = 15
 }
 
+impl Color {
+	/// All 16 colors, in palette order.
+	pub const ALL: [Color; 16] = [
+		Color::Black,
+		Color::Blue,
+		Color::Green,
+		Color::Cyan,
+		Color::Red,
+		Color::Magenta,
+		Color::Brown,
+		Color::LightGray,
+		Color::DarkGray,
+		Color::LightBlue,
+		Color::LightGreen,
+		Color::LightCyan,
+		Color::LightRed,
+		Color::Pink,
+		Color::Yellow,
+		Color::White
+	];
+
+	/// The color's name as `string_to_color` takes it, e.g. `"lightgreen"`.
+	pub fn name(&self) -> &'static str {
+		match self {
+			Color::Black => "black",
+			Color::Blue => "blue",
+			Color::Green => "green",
+			Color::Cyan => "cyan",
+			Color::Red => "red",
+			Color::Magenta => "magenta",
+			Color::Brown => "brown",
+			Color::LightGray => "lightgray",
+			Color::DarkGray => "darkgray",
+			Color::LightBlue => "lightblue",
+			Color::LightGreen => "lightgreen",
+			Color::LightCyan => "lightcyan",
+			Color::LightRed => "lightred",
+			Color::Pink => "pink",
+			Color::Yellow => "yellow",
+			Color::White => "white"
+		}
+	}
+}
+
+/// Returns the color called `name`, e.g. `"green"`. Case, `-` and `_` are
+/// ignored, so `"Light_Blue"` is `Color::LightBlue`.
+pub fn string_to_color(name: &str) -> Result<Color, NullexError> {
+	let normalized = || {
+		name.chars()
+			.filter(|c| !matches!(c, '-' | '_'))
+			.map(|c| c.to_ascii_lowercase())
+	};
+	Color::ALL
+		.into_iter()
+		.find(|color| normalized().eq(color.name().chars()))
+		.ok_or(NullexError::UnknownColor)
+}
+
 /// A combination of a foreground and a background color.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
@@ -113,8 +352,8 @@ impl ScreenChar {
 	}
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+pub(crate) const BUFFER_HEIGHT: usize = 25;
+pub(crate) const BUFFER_WIDTH: usize = 80;
 
 /// A VGA Text Buffer
 #[derive(Clone, Debug)]
@@ -124,47 +363,160 @@ pub struct Buffer {
 	chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT]
 }
 
+impl Buffer {
+	/// The screen as text, a line per row with trailing spaces stripped.
+	/// Glyphs are turned back into characters with `cp437_to_char`.
+	pub fn to_text(&self) -> String {
+		let mut text = String::new();
+		for row in self.chars.iter() {
+			let line: String = row
+				.iter()
+				.map(|cell| cp437_to_char(cell.read().ascii_character))
+				.collect();
+			text.push_str(line.trim_end_matches(' '));
+			text.push('\n');
+		}
+		text
+	}
+
+	/// A buffer of blank characters.
+	#[cfg(feature = "test")]
+	fn blank() -> Buffer {
+		let blank_row = || core::array::from_fn(|_| Volatile::new(ScreenChar::blank()));
+		Buffer {
+			chars: core::array::from_fn(|_| blank_row())
+		}
+	}
+}
+
 /// A writer type that allows writing ASCII bytes and strings to an underlying
 /// `Buffer`.
 pub struct Writer {
 	column_position: usize,
 	current_row: usize,
 	pub(self) color_code: ColorCode,
-	buffer: &'static mut Buffer
+	buffer: &'static mut Buffer,
+	/// Set when the console is on a linear framebuffer. `buffer` is then a
+	/// copy in memory that the framebuffer is drawn from.
+	framebuffer: Option<FramebufferConsole>,
+	/// Set while double buffering. Writes then go here, and only reach the
+	/// screen on `flush`.
+	back_buffer: Option<Box<Buffer>>,
+	/// Whether the cursor is shown.
+	cursor_visible: bool
 }
 
 impl Writer {
+	/// Writes a character to the cell at `row`, `col`: to the back buffer
+	/// while double buffering, to the screen otherwise.
+	fn put(&mut self, row: usize, col: usize, character: ScreenChar) {
+		match self.back_buffer.as_mut() {
+			Some(back) => back.chars[row][col].write(character),
+			None => self.draw(row, col, character)
+		}
+	}
+
+	/// Writes a character to the cell at `row`, `col` of the screen, and
+	/// draws it on the framebuffer if there is one.
+	fn draw(&mut self, row: usize, col: usize, character: ScreenChar) {
+		self.buffer.chars[row][col].write(character);
+		if let Some(fb) = self.framebuffer.as_mut() {
+			let ColorCode(code) = character.color_code;
+			fb.draw_char(row, col, character.ascii_character, code & 0xf, code >> 4);
+		}
+	}
+
+	/// Reads the character in the cell at `row`, `col`, as last written.
+	fn cell(&self, row: usize, col: usize) -> ScreenChar {
+		self.back_buffer.as_deref().unwrap_or(self.buffer).chars[row][col].read()
+	}
+
+	/// Turns double buffering on or off. While it's on, output is kept off
+	/// the screen until `flush`, so a full redraw doesn't flicker. Turning
+	/// it off flushes what was written.
+	pub(crate) fn set_double_buffered(&mut self, enabled: bool) {
+		match (enabled, self.back_buffer.is_some()) {
+			(true, false) => self.back_buffer = Some(Box::new(self.buffer.clone())),
+			(false, true) => {
+				self.flush();
+				self.back_buffer = None;
+			}
+			_ => {}
+		}
+	}
+
+	/// Copies the cells of the back buffer that differ from the screen onto
+	/// it. Returns how many cells were redrawn. Does nothing unless double
+	/// buffering.
+	pub(crate) fn flush(&mut self) -> usize {
+		let Some(back) = self.back_buffer.take() else {
+			return 0;
+		};
+		let mut redrawn = 0;
+		for row in 0..BUFFER_HEIGHT {
+			for col in 0..BUFFER_WIDTH {
+				let character = back.chars[row][col].read();
+				if self.buffer.chars[row][col].read() != character {
+					self.draw(row, col, character);
+					redrawn += 1;
+				}
+			}
+		}
+		self.back_buffer = Some(back);
+		redrawn
+	}
+
+	/// Moves the console to a framebuffer, redrawing what's on screen there.
+	/// The VGA text buffer isn't written to afterwards.
+	pub(crate) fn attach_framebuffer(&mut self, framebuffer: FramebufferConsole) {
+		self.buffer = Box::leak(Box::new(self.buffer.clone()));
+		self.framebuffer = Some(framebuffer);
+		for row in 0..BUFFER_HEIGHT {
+			for col in 0..BUFFER_WIDTH {
+				let character = self.buffer.chars[row][col].read();
+				self.put(row, col, character);
+			}
+		}
+		self.update_cursor();
+	}
+
 	/// Writes an ASCII byte to the buffer.
 	fn write_byte(&mut self, byte: u8) {
 		match byte {
 			b'\n' => {
 				self.new_line();
 			}
-			byte => {
-				if self.column_position >= BUFFER_WIDTH {
-					self.new_line();
-				}
+			byte => self.write_raw_byte(byte)
+		}
+	}
 
-				// write at the current row (top -> down)
-				let row = self.current_row;
-				let col = self.column_position;
+	/// Writes the code page 437 glyph `byte` stands for, without treating
+	/// newlines or other control bytes specially, e.g. to draw box borders.
+	pub(crate) fn write_raw_byte(&mut self, byte: u8) {
+		if self.column_position >= BUFFER_WIDTH {
+			self.new_line();
+		}
 
-				self.buffer.chars[row][col].write(ScreenChar::new(byte as char, self.color_code));
+		// write at the current row (top -> down)
+		let row = self.current_row;
+		let col = self.column_position;
 
-				// advance column & update hardware cursor immediately
-				self.column_position += 1;
-				self.update_cursor();
-			}
-		}
+		self.put(row, col, ScreenChar::new(byte as char, self.color_code));
+
+		// advance column & update hardware cursor immediately
+		self.column_position += 1;
+		self.update_cursor();
 	}
 
-	/// Writes the given ASCII string to the buffer.
+	/// Writes the given string to the buffer. Characters outside printable
+	/// ASCII are written as their code page 437 glyph if `cp437` knows one,
+	/// and as a block otherwise.
 	fn write_string(&mut self, s: &str) {
-		for byte in s.bytes() {
-			match byte {
-				// printable ASCII byte or newline
-				0x20..=0x7e | b'\n' => self.write_byte(byte),
-				_ => self.write_byte(0xfe)
+		for c in s.chars() {
+			match c {
+				// printable ASCII or newline
+				' '..='~' | '\n' => self.write_byte(c as u8),
+				c => self.write_raw_byte(cp437(c).unwrap_or(0xfe))
 			}
 		}
 	}
@@ -177,8 +529,11 @@ impl Writer {
 			// scroll up
 			for row in 1..BUFFER_HEIGHT {
 				for col in 0..BUFFER_WIDTH {
-					let character = self.buffer.chars[row][col].read();
-					self.buffer.chars[row - 1][col].write(character);
+					let character = self.cell(row, col);
+					// skip unchanged cells, redrawing them is slow on a framebuffer
+					if self.cell(row - 1, col) != character {
+						self.put(row - 1, col, character);
+					}
 				}
 			}
 			// clear last line
@@ -194,7 +549,7 @@ impl Writer {
 	fn clear_row(&mut self, row: usize) {
 		let blank = ScreenChar::blank();
 		for col in 0..BUFFER_WIDTH {
-			self.buffer.chars[row][col].write(blank);
+			self.put(row, col, blank);
 		}
 	}
 
@@ -203,7 +558,7 @@ impl Writer {
 		let blank = ScreenChar::blank();
 		for row in 0..BUFFER_HEIGHT {
 			for col in 0..BUFFER_WIDTH {
-				self.buffer.chars[row][col].write(blank);
+				self.put(row, col, blank);
 			}
 		}
 		// reset cursor to top-left after clearing
@@ -213,26 +568,62 @@ impl Writer {
 	}
 
 	/// Update the VGA cursor to move on a character write.
-	fn update_cursor(&self) {
+	fn update_cursor(&mut self) {
+		if let Some(fb) = self.framebuffer.as_mut() {
+			let ColorCode(code) = self.color_code;
+			// moving the bar off the grid only erases it
+			let (row, col) = if self.cursor_visible {
+				(self.current_row, self.column_position)
+			} else {
+				(BUFFER_HEIGHT, 0)
+			};
+			if let Some((row, col)) = fb.move_cursor(row, col, code) {
+				let character = self.buffer.chars[row][col].read();
+				self.draw(row, col, character);
+			}
+			return;
+		}
+
 		// hardware cursor position = row * width + col
 		let position = (self.current_row * BUFFER_WIDTH) + self.column_position;
+		crtc_write(CRTC_CURSOR_LOW, (position & 0xFF) as u8);
+		crtc_write(CRTC_CURSOR_HIGH, ((position >> 8) & 0xFF) as u8);
+	}
 
-		let mut port_3d4 = Port::<u8>::new(0x3D4);
-		let mut port_3d5 = Port::<u8>::new(0x3D5);
-		unsafe {
-			port_3d4.write(0x0F);
-			port_3d5.write((position & 0xFF) as u8);
-			port_3d4.write(0x0E);
-			port_3d5.write(((position >> 8) & 0xFF) as u8);
+	/// Shows or hides the cursor, e.g. to keep it from jumping around during
+	/// a full-screen redraw.
+	pub(crate) fn set_cursor_visible(&mut self, visible: bool) {
+		self.cursor_visible = visible;
+		if self.framebuffer.is_none() {
+			let start = crtc_read(CRTC_CURSOR_START);
+			let start = if visible { start & !CURSOR_DISABLE } else { start | CURSOR_DISABLE };
+			crtc_write(CRTC_CURSOR_START, start);
 		}
+		self.update_cursor();
+	}
+
+	/// Sets the scan lines of the text cell the cursor covers, from `start`
+	/// to `end` (0 is the top, 15 the bottom), e.g. 0 to 15 for a block.
+	/// Only VGA text mode has a cursor shape; the framebuffer's is fixed.
+	pub(crate) fn set_cursor_shape(&mut self, start: u8, end: u8) -> Result<(), NullexError> {
+		if start > end || end >= CELL_SCAN_LINES {
+			return Err(NullexError::InvalidArgument);
+		}
+		if self.framebuffer.is_none() {
+			// keep the disable and skew bits above the scan line
+			let start_reg = crtc_read(CRTC_CURSOR_START) & !CURSOR_SCAN_LINE_MASK;
+			crtc_write(CRTC_CURSOR_START, start_reg | start);
+			let end_reg = crtc_read(CRTC_CURSOR_END) & !CURSOR_SCAN_LINE_MASK;
+			crtc_write(CRTC_CURSOR_END, end_reg | end);
+		}
+		Ok(())
 	}
 
 	/// Copies the VGA Buffer into memory for restoration.<br>
 	/// Good for applications (TUI's) where they use fullscreen and then 
 	/// want to revert back to the original terminal screen.
-	#[allow(dead_code)]
 	pub(crate) fn copy_vga_buffer(&self) -> Buffer {
-		self.buffer.clone()
+		self.back_buffer.as_deref().unwrap_or(self.buffer).clone()
 	}
 
 	/// Restores the VGA Buffer from memory. 
@@ -243,7 +634,7 @@ impl Writer {
 		for y in 0..BUFFER_HEIGHT {
 			for x in 0..BUFFER_WIDTH {
 				let ch = prev.chars[y][x].read();
-				self.buffer.chars[y][x].write(ch);
+				self.put(y, x, ch);
 			}
 		}
 	}
@@ -272,10 +663,15 @@ impl Writer {
 		}
 
 		// write blank at the new cursor position and update the cursor
-		self.buffer.chars[self.current_row][self.column_position].write(blank);
+		self.put(self.current_row, self.column_position, blank);
 		self.update_cursor();
 	}
 
+	/// Sets the colors output is written in from now on.
+	pub(crate) fn set_colors(&mut self, fg: Color, bg: Color) {
+		self.color_code = ColorCode::new(fg, bg);
+	}
+
 	/// Run a closure with a temporary color, restoring the previous color
 	/// afterwards.
 	fn with_color<F: FnOnce(&mut Self)>(&mut self, fg: Color, bg: Color, f: F) {
@@ -304,6 +700,73 @@ impl Writer {
 	}
 }
 
+/// Non-ASCII characters the VGA font can draw, with their code page 437
+/// byte: box drawing, blocks, arrows and a few symbols.
+const CP437_CHARS: [(char, u8); 44] = [
+	('─', 0xc4),
+	('│', 0xb3),
+	('┌', 0xda),
+	('┐', 0xbf),
+	('└', 0xc0),
+	('┘', 0xd9),
+	('├', 0xc3),
+	('┤', 0xb4),
+	('┬', 0xc2),
+	('┴', 0xc1),
+	('┼', 0xc5),
+	('═', 0xcd),
+	('║', 0xba),
+	('╔', 0xc9),
+	('╗', 0xbb),
+	('╚', 0xc8),
+	('╝', 0xbc),
+	('╠', 0xcc),
+	('╣', 0xb9),
+	('╦', 0xcb),
+	('╩', 0xca),
+	('╬', 0xce),
+	('░', 0xb0),
+	('▒', 0xb1),
+	('▓', 0xb2),
+	('█', 0xdb),
+	('▀', 0xdf),
+	('▄', 0xdc),
+	('■', 0xfe),
+	('↑', 0x18),
+	('↓', 0x19),
+	('→', 0x1a),
+	('←', 0x1b),
+	('↕', 0x12),
+	('↔', 0x1d),
+	('▲', 0x1e),
+	('▼', 0x1f),
+	('►', 0x10),
+	('◄', 0x11),
+	('•', 0x07),
+	('·', 0xfa),
+	('°', 0xf8),
+	('±', 0xf1),
+	('√', 0xfb)
+];
+
+/// Returns the code page 437 byte of a non-ASCII character the VGA font can
+/// draw, see `CP437_CHARS`.
+pub fn cp437(c: char) -> Option<u8> {
+	CP437_CHARS.iter().find(|(known, _)| *known == c).map(|(_, byte)| *byte)
+}
+
+/// Returns the character a code page 437 byte on screen shows: itself for
+/// printable ASCII, the matching `CP437_CHARS` entry, or `?`.
+pub fn cp437_to_char(byte: u8) -> char {
+	match byte {
+		0x20..=0x7e => byte as char,
+		_ => CP437_CHARS
+			.iter()
+			.find(|(_, known)| *known == byte)
+			.map_or('?', |(c, _)| *c)
+	}
+}
+
 /// Wrapper for the backspace() function of `Writer`
 pub fn console_backspace() {
 	WRITER.lock().backspace();
@@ -335,13 +798,84 @@ macro_rules! println {
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
 	use core::fmt::Write;
-	WRITER.lock().write_fmt(args).unwrap();
+	if capture(args) {
+		return;
+	}
+	with_writer(
+		|writer| {
+			let _ = writer.write_fmt(args);
+		},
+		|deferred| {
+			let _ = deferred.write_fmt(args);
+		}
+	);
+	if SERIAL_MIRROR.load(Ordering::Relaxed) {
+		crate::serial::_print(args);
+	}
 }
 
 #[doc(hidden)]
 pub fn _print_segments(segments: &[(&str, Color, Color)]) {
-	let mut w = WRITER.lock();
-	w.write_segments(segments);
+	use core::fmt::Write;
+	// captured output has no colours
+	if segments
+		.iter()
+		.all(|(text, _, _)| capture(format_args!("{}", text)))
+	{
+		return;
+	}
+	with_writer(
+		|writer| writer.write_segments(segments),
+		// deferred output is written in the current colour
+		|deferred| {
+			for (text, _, _) in segments {
+				let _ = deferred.write_str(text);
+			}
+		}
+	);
+	if SERIAL_MIRROR.load(Ordering::Relaxed) {
+		for (text, _, _) in segments {
+			crate::serial::_print(format_args!("{}", text));
+		}
+	}
+}
+
+/// Like `print!`, but never waits for a lock, so it's safe from interrupt
+/// handlers and the panic path. Prints to serial instead if `WRITER` is
+/// held.
+#[macro_export]
+macro_rules! try_print {
+    ($($arg:tt)*) => ($crate::vga_buffer::_try_print(format_args!($($arg)*)));
+}
+
+/// Like `println!`, but never waits for a lock. See `try_print!`.
+#[macro_export]
+macro_rules! try_println {
+    () => ($crate::try_print!("\n"));
+    ($($arg:tt)*) => ($crate::try_print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Prints `args` to the VGA buffer if `WRITER` is free, otherwise to serial.
+/// Output is dropped if both are held. Returns whether it was printed.
+#[doc(hidden)]
+pub fn _try_print(args: fmt::Arguments) -> bool {
+	use core::fmt::Write;
+	interrupts::without_interrupts(|| {
+		let on_vga = match WRITER.try_lock() {
+			Some(mut writer) => {
+				let _ = writer.write_fmt(args);
+				true
+			}
+			None => false
+		};
+		if on_vga && !SERIAL_MIRROR.load(Ordering::Relaxed) {
+			return true;
+		}
+		match SERIAL1.try_lock() {
+			Some(mut serial) => serial.write_fmt(args).is_ok() || on_vga,
+			None => on_vga
+		}
+	})
 }
 
 /// Print multiple colored segments.
@@ -391,6 +925,8 @@ pub mod prelude {
 
 #[cfg(feature = "test")]
 pub mod tests {
+	use alloc::vec::Vec;
+
 	use crate::{utils::ktest::TestError, vga_buffer::prelude::*};
 
 	pub fn test_screenchar_blank_and_buffer_blank() -> Result<(), TestError> {
@@ -412,4 +948,153 @@ pub mod tests {
 		Ok(())
 	}
 	crate::create_test!(test_color_code_creation);
+
+	pub fn test_print_while_writer_locked_is_deferred() -> Result<(), TestError> {
+		x86_64::instructions::interrupts::without_interrupts(|| {
+			let writer = WRITER.lock();
+			let position = writer.copy_cursor_position();
+			// would deadlock if printing waited for the lock
+			crate::print!("deferred");
+			assert_eq!(writer.copy_cursor_position(), position);
+			assert_eq!(DEFERRED.lock().len, "deferred".len());
+		});
+
+		// the next print writes it out ahead of its own output
+		crate::println!("-after");
+		assert_eq!(DEFERRED.lock().len, 0);
+		let text = x86_64::instructions::interrupts::without_interrupts(|| {
+			WRITER.lock().buffer.to_text()
+		});
+		assert!(text.contains("deferred-after"));
+		Ok(())
+	}
+	crate::create_test!(test_print_while_writer_locked_is_deferred);
+
+	pub fn test_try_print_never_waits() -> Result<(), TestError> {
+		use crate::serial::SERIAL1;
+
+		x86_64::instructions::interrupts::without_interrupts(|| {
+			let writer = WRITER.lock();
+			let position = writer.copy_cursor_position();
+			// falls back to serial
+			assert!(crate::try_print!(""));
+			assert_eq!(writer.copy_cursor_position(), position);
+
+			let _serial = SERIAL1.lock();
+			assert!(!crate::try_print!("dropped"));
+			assert_eq!(writer.copy_cursor_position(), position);
+		});
+		Ok(())
+	}
+	crate::create_test!(test_try_print_never_waits);
+
+	pub fn test_capture_output() -> Result<(), TestError> {
+		let position = WRITER.lock().copy_cursor_position();
+		let (value, output) = capture_output(|| {
+			crate::println!("captured {}", 1);
+			crate::print_colours!(("no colour", Color::Green));
+			let ((), inner) = capture_output(|| crate::print!("inner"));
+			assert_eq!(inner, "inner");
+			7
+		});
+		assert_eq!(value, 7);
+		assert_eq!(output, "captured 1\nno colour");
+		assert_eq!(WRITER.lock().copy_cursor_position(), position);
+		assert!(CAPTURE.lock().is_none());
+		Ok(())
+	}
+	crate::create_test!(test_capture_output);
+
+	/// A writer drawing into a blank buffer in memory instead of the screen.
+	fn offscreen_writer() -> Writer {
+		Writer {
+			column_position: 0,
+			current_row: 0,
+			color_code: ColorCode::new(Color::White, Color::Black),
+			buffer: Box::leak(Box::new(Buffer::blank())),
+			framebuffer: None,
+			back_buffer: None,
+			cursor_visible: true
+		}
+	}
+
+	pub fn test_double_buffer_flushes_changed_cells() -> Result<(), TestError> {
+		let mut writer = offscreen_writer();
+		let screen = |writer: &Writer| writer.buffer.chars[0][0].read().ascii_character;
+
+		writer.set_double_buffered(true);
+		writer.write_string("hi");
+		assert_eq!(screen(&writer), b' ');
+		assert_eq!(writer.flush(), 2);
+		assert_eq!(screen(&writer), b'h');
+
+		// redrawing the same frame leaves the screen alone
+		writer.clear_everything();
+		writer.write_string("hi");
+		assert_eq!(writer.flush(), 0);
+
+		writer.write_string("!");
+		writer.set_double_buffered(false);
+		assert_eq!(writer.buffer.chars[0][2].read().ascii_character, b'!');
+		writer.write_string("?");
+		assert_eq!(writer.buffer.chars[0][3].read().ascii_character, b'?');
+		Ok(())
+	}
+	crate::create_test!(test_double_buffer_flushes_changed_cells);
+
+	pub fn test_cursor_shape_rejects_bad_scan_lines() -> Result<(), TestError> {
+		let mut writer = WRITER.lock();
+		assert!(writer.set_cursor_shape(10, 4).is_err());
+		assert!(writer.set_cursor_shape(0, CELL_SCAN_LINES).is_err());
+		Ok(())
+	}
+	crate::create_test!(test_cursor_shape_rejects_bad_scan_lines);
+
+	pub fn test_write_string_maps_cp437() -> Result<(), TestError> {
+		let mut writer = offscreen_writer();
+		writer.write_string("┌─┐ a→é");
+		writer.write_raw_byte(b'\n');
+		let row: Vec<u8> = (0..8)
+			.map(|col| writer.buffer.chars[0][col].read().ascii_character)
+			.collect();
+		assert_eq!(row, [0xda, 0xc4, 0xbf, b' ', b'a', 0x1a, 0xfe, b'\n']);
+		assert_eq!(writer.copy_cursor_position(), (0, 8));
+		Ok(())
+	}
+	crate::create_test!(test_write_string_maps_cp437);
+
+	pub fn test_cp437_chars_have_framebuffer_glyphs() -> Result<(), TestError> {
+		for (c, byte) in CP437_CHARS {
+			if crate::drivers::framebuffer::font::known_glyph(byte).is_none() {
+				crate::serial_println!("no framebuffer glyph for {} ({:#04x})", c, byte);
+				return Err(TestError::Error);
+			}
+		}
+		Ok(())
+	}
+	crate::create_test!(test_cp437_chars_have_framebuffer_glyphs);
+
+	pub fn test_string_to_color() -> Result<(), TestError> {
+		for color in Color::ALL {
+			assert_eq!(string_to_color(color.name()), Ok(color));
+		}
+		assert_eq!(string_to_color("Light_Blue"), Ok(Color::LightBlue));
+		assert_eq!(string_to_color("dark-gray"), Ok(Color::DarkGray));
+		assert_eq!(string_to_color("purple"), Err(NullexError::UnknownColor));
+		Ok(())
+	}
+	crate::create_test!(test_string_to_color);
+
+	pub fn test_buffer_to_text() -> Result<(), TestError> {
+		let mut writer = offscreen_writer();
+		writer.write_string("┌─┐  \n│x│\n");
+		let text = writer.copy_vga_buffer().to_text();
+		let mut lines = text.lines();
+		assert_eq!(lines.next(), Some("┌─┐"));
+		assert_eq!(lines.next(), Some("│x│"));
+		assert_eq!(lines.next(), Some(""));
+		assert_eq!(text.lines().count(), BUFFER_HEIGHT);
+		Ok(())
+	}
+	crate::create_test!(test_buffer_to_text);
 }